@@ -0,0 +1,241 @@
+//! Live Data Alarms
+//!
+//! Threshold rules watched by the acquisition thread, e.g. exhaust gas
+//! temperature before the turbo above 750 °C for 2 s, or battery voltage
+//! below 11.8 V. A rule watches one channel, read from the ECU or virtual
+//! (a rail pressure deviation is a virtual channel), must see the threshold
+//! exceeded for its minimum duration before it is raised, and clears once
+//! the value is back past the threshold by its hysteresis.
+//!
+//! Raising and clearing emit `live-data:alarm` and are saved as markers in
+//! the running recording (`recording`). The rules are kept as JSON in the
+//! settings table; their format and the raise/clear logic are shared with
+//! the daemon (`bmw_diag_core::alarms`).
+
+use crate::db_commands::DbState;
+use crate::live_stream::{ChannelKind, LiveSample};
+use crate::virtual_channels;
+use bmw_diag_core::alarms::{validate, AlarmRule, AlarmState, Severity, Watch};
+use serde::Serialize;
+use std::collections::HashMap;
+use tauri::State;
+
+/// Settings key holding the rules
+pub const SETTINGS_KEY: &str = "alarm_rules";
+
+/// Payload of `live-data:alarm`
+#[derive(Debug, Clone, Serialize)]
+pub struct AlarmEvent {
+    pub stream_id: u64,
+    /// Rule key
+    pub rule: String,
+    pub name: String,
+    pub severity: Severity,
+    pub state: AlarmState,
+    /// Name of the sample that raised or cleared the alarm
+    pub channel: String,
+    pub value: f64,
+    pub unit: String,
+    pub threshold: f64,
+    /// Value furthest past the threshold since it was exceeded
+    pub peak: f64,
+    /// When the threshold was first exceeded, ms since the Unix epoch
+    pub since: u64,
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+impl AlarmEvent {
+    /// Kind of the recording marker
+    pub fn marker_kind(&self) -> &'static str {
+        match self.state {
+            AlarmState::Raised => "alarm_raised",
+            AlarmState::Cleared => "alarm_cleared",
+        }
+    }
+}
+
+/// Alarm rules of a running stream
+#[derive(Debug, Default)]
+pub struct Alarms {
+    watches: Vec<Watch>,
+    /// Indexes into `watches` by the channel they watch
+    by_channel: HashMap<(ChannelKind, u16), Vec<usize>>,
+}
+
+impl Alarms {
+    /// Set up the rules on the `streamed` channels and the stream's
+    /// virtual channels (keys in sample `id` order)
+    ///
+    /// Returns the keys of the rules left out because the stream does not
+    /// carry their channel.
+    pub fn new(
+        rules: Vec<AlarmRule>,
+        streamed: &[(ChannelKind, u16)],
+        virtual_keys: &[String],
+    ) -> (Self, Vec<String>) {
+        let mut names = virtual_channels::channel_names(streamed);
+        for (id, key) in virtual_keys.iter().enumerate() {
+            names.insert(key.clone(), (ChannelKind::Virtual, id as u16));
+        }
+
+        let mut this = Self::default();
+        let mut skipped = Vec::new();
+        for rule in rules {
+            match names.get(&rule.channel) {
+                Some(&channel) => {
+                    this.by_channel
+                        .entry(channel)
+                        .or_default()
+                        .push(this.watches.len());
+                    this.watches.push(Watch::new(rule));
+                }
+                None => skipped.push(rule.key),
+            }
+        }
+        (this, skipped)
+    }
+
+    /// Keys of the rules watched
+    pub fn keys(&self) -> Vec<String> {
+        self.watches.iter().map(|w| w.rule().key.clone()).collect()
+    }
+
+    /// Take in a batch of samples, return the alarms raised or cleared
+    pub fn update(&mut self, samples: &[LiveSample]) -> Vec<AlarmEvent> {
+        let mut events = Vec::new();
+        for sample in samples {
+            let Some(indexes) = self.by_channel.get(&(sample.kind, sample.id)) else {
+                continue;
+            };
+            for &index in indexes {
+                let watch = &mut self.watches[index];
+                let Some(state) = watch.step(sample.timestamp, sample.value) else {
+                    continue;
+                };
+                let rule = watch.rule();
+                events.push(AlarmEvent {
+                    stream_id: 0,
+                    rule: rule.key.clone(),
+                    name: rule.name.clone(),
+                    severity: rule.severity,
+                    state,
+                    channel: sample.name.clone(),
+                    value: sample.value,
+                    unit: sample.unit.clone(),
+                    threshold: rule.threshold,
+                    peak: watch.peak(),
+                    since: watch.since(),
+                    timestamp: sample.timestamp,
+                });
+            }
+        }
+        events
+    }
+}
+
+/// Saved rules, empty if none were saved
+pub fn load(db: &DbState) -> Result<Vec<AlarmRule>, String> {
+    let guard = db.0.lock().map_err(|e| format!("Lock error: {}", e))?;
+    let database = guard.as_ref().ok_or("Database not initialized")?;
+    match database
+        .get_setting(SETTINGS_KEY)
+        .map_err(|e| format!("Database error: {}", e))?
+    {
+        Some(json) => {
+            serde_json::from_str(&json).map_err(|e| format!("Invalid alarm settings: {}", e))
+        }
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Get the saved alarm rules
+#[tauri::command]
+pub fn get_alarm_rules(db: State<DbState>) -> Result<Vec<AlarmRule>, String> {
+    load(&db)
+}
+
+/// Replace the saved alarm rules
+///
+/// Takes effect with the next `start_live_stream`.
+#[tauri::command]
+pub fn set_alarm_rules(db: State<DbState>, rules: Vec<AlarmRule>) -> Result<(), String> {
+    validate(&rules)?;
+    let json = serde_json::to_string(&rules).map_err(|e| e.to_string())?;
+
+    let guard = db.0.lock().map_err(|e| format!("Lock error: {}", e))?;
+    let database = guard.as_ref().ok_or("Database not initialized")?;
+    database
+        .set_setting(SETTINGS_KEY, &json)
+        .map_err(|e| format!("Database error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bmw_diag_core::alarms::Condition;
+
+    const BATTERY: u16 = 0x42;
+
+    fn rule(key: &str, channel: &str, condition: Condition, threshold: f64) -> AlarmRule {
+        AlarmRule {
+            key: key.to_string(),
+            name: key.to_string(),
+            channel: channel.to_string(),
+            condition,
+            threshold,
+            hysteresis: 0.0,
+            min_duration_ms: 0,
+            severity: Severity::Warning,
+        }
+    }
+
+    fn sample(kind: ChannelKind, id: u16, value: f64, timestamp: u64) -> LiveSample {
+        LiveSample {
+            stream_id: 0,
+            seq: 0,
+            kind,
+            id,
+            name: String::new(),
+            value,
+            unit: String::new(),
+            raw: Vec::new(),
+            timestamp,
+        }
+    }
+
+    fn states(events: &[AlarmEvent]) -> Vec<(&str, AlarmState)> {
+        events.iter().map(|e| (e.rule.as_str(), e.state)).collect()
+    }
+
+    #[test]
+    fn test_below_and_virtual_channels() {
+        let (mut alarms, skipped) = Alarms::new(
+            vec![
+                rule("BATTERY_LOW", "PID_42", Condition::Below, 11.8),
+                rule("RAIL_DEV", "RAIL_DEV_ABS", Condition::Above, 100.0),
+                rule("BOOST_DEV", "BOOST_DEV_ABS", Condition::Above, 200.0),
+            ],
+            &[(ChannelKind::Pid, BATTERY)],
+            &["RAIL_DEV".to_string(), "RAIL_DEV_ABS".to_string()],
+        );
+        assert_eq!(skipped, vec!["BOOST_DEV"]);
+        assert_eq!(alarms.keys(), vec!["BATTERY_LOW", "RAIL_DEV"]);
+
+        let events = alarms.update(&[
+            sample(ChannelKind::Pid, BATTERY, 11.5, 100),
+            sample(ChannelKind::Virtual, 0, 150.0, 100),
+            sample(ChannelKind::Virtual, 1, 150.0, 100),
+        ]);
+        assert_eq!(
+            states(&events),
+            vec![
+                ("BATTERY_LOW", AlarmState::Raised),
+                ("RAIL_DEV", AlarmState::Raised)
+            ]
+        );
+
+        let events = alarms.update(&[sample(ChannelKind::Pid, BATTERY, 11.8, 200)]);
+        assert_eq!(states(&events), vec![("BATTERY_LOW", AlarmState::Cleared)]);
+    }
+}
@@ -5,6 +5,10 @@
 //! Note: UDS and KWP service constants are centralized in `constants.rs`.
 //! This module re-exports them for backward compatibility.
 
+use crate::channels;
+use crate::dtc_info::DtcEnvironment;
+use bmw_diag_core::dtc_service::DtcRecord;
+use bmw_diag_core::fault_codes::FaultText;
 use serde::{Deserialize, Serialize};
 
 // Note: UDS and KWP constants are available in crate::constants module
@@ -21,6 +25,13 @@ pub struct EcuInfo {
     pub protocol: Protocol,
 }
 
+impl EcuInfo {
+    /// D-CAN (request, response) IDs, if the ECU is on D-CAN
+    pub fn can_ids(&self) -> Option<(u32, u32)> {
+        self.can_tx_id.zip(self.can_rx_id)
+    }
+}
+
 /// Communication protocol
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub enum Protocol {
@@ -36,6 +47,22 @@ pub struct Dtc {
     pub status: DtcStatus,
     pub description: Option<String>,
     pub raw_bytes: Vec<u8>,
+    /// BMW fault code as reported by the ECU: 4 hex digits on KWP
+    /// (e.g. "2A82"), 6 on UDS
+    #[serde(default)]
+    pub bmw_code: String,
+    /// SAE equivalent from the fault database, if the fault has one
+    #[serde(default)]
+    pub sae_code: Option<String>,
+    /// Fault texts in all languages from the fault database
+    #[serde(default)]
+    pub texts: Option<FaultText>,
+    /// ECU variant of the database entry that matched
+    #[serde(default)]
+    pub fault_variant: Option<String>,
+    /// KWP environmental conditions, when read with ReadStatusOfDTC
+    #[serde(default)]
+    pub environment: Option<DtcEnvironment>,
 }
 
 /// DTC Status byte flags
@@ -87,10 +114,61 @@ impl Dtc {
             status: DtcStatus::from_byte(status),
             description: None,
             raw_bytes: bytes[..3].to_vec(),
+            bmw_code: format!("{:02X}{:02X}", dtc_high, dtc_low),
+            sae_code: None,
+            texts: None,
+            fault_variant: None,
+            environment: None,
+        })
+    }
+
+    /// Parse a UDS DTC (4 bytes: DTC high, middle, low, status)
+    ///
+    /// The low byte is the failure type; `bmw_code` keeps all three bytes.
+    pub fn from_uds_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 4 {
+            return None;
+        }
+
+        Some(Self {
+            code: Self::bytes_to_code(bytes[0], bytes[1]),
+            status: DtcStatus::from_byte(bytes[3]),
+            description: None,
+            raw_bytes: bytes[..4].to_vec(),
+            bmw_code: format!("{:02X}{:02X}{:02X}", bytes[0], bytes[1], bytes[2]),
+            sae_code: None,
+            texts: None,
+            fault_variant: None,
+            environment: None,
         })
     }
 
+    /// Build a DTC from a record of the DTC read service
+    ///
+    /// `status.raw` keeps the byte sent by the ECU; the flags are decoded
+    /// from the UDS layout, so KWP and UDS faults compare equal.
+    pub fn from_record(record: &DtcRecord) -> Self {
+        let short_code = record.short_code();
+        let mut status = DtcStatus::from_byte(record.uds_status());
+        status.raw = record.status;
+
+        Self {
+            code: Self::bytes_to_code((short_code >> 8) as u8, short_code as u8),
+            status,
+            description: None,
+            raw_bytes: record.raw_bytes(),
+            bmw_code: format!("{:0width$X}", record.code, width = record.code_len * 2),
+            sae_code: None,
+            texts: None,
+            fault_variant: None,
+            environment: None,
+        }
+    }
+
     /// Convert two bytes to DTC code string
+    ///
+    /// This is the SAE J2012 reading of the bytes. BMW KWP ECUs report BMW
+    /// fault codes instead, see `bmw_code` and `crate::fault_codes`.
     fn bytes_to_code(high: u8, low: u8) -> String {
         // First 2 bits determine category
         let category = match (high >> 6) & 0x03 {
@@ -154,27 +232,7 @@ pub mod nrc {
     pub const GENERAL_PROGRAMMING_FAILURE: u8 = 0x72;
     pub const SERVICE_NOT_SUPPORTED_IN_ACTIVE_SESSION: u8 = 0x7F;
 
-    pub fn description(code: u8) -> &'static str {
-        match code {
-            0x10 => "General reject",
-            0x11 => "Service not supported",
-            0x12 => "Sub-function not supported",
-            0x13 => "Incorrect message length or invalid format",
-            0x21 => "Busy - repeat request",
-            0x22 => "Conditions not correct",
-            0x24 => "Request sequence error",
-            0x31 => "Request out of range",
-            0x33 => "Security access denied",
-            0x35 => "Invalid key",
-            0x36 => "Exceeded number of attempts",
-            0x37 => "Required time delay not expired",
-            0x70 => "Upload/download not accepted",
-            0x71 => "Transfer data suspended",
-            0x72 => "General programming failure",
-            0x7F => "Service not supported in active session",
-            _ => "Unknown error",
-        }
-    }
+    pub use bmw_diag_core::nrc::description;
 }
 
 /// BMW E60 ECU definitions
@@ -282,6 +340,49 @@ pub fn e60_ecus() -> Vec<EcuInfo> {
     ]
 }
 
+/// IDs of the E60 ECUs reachable at a K-Line address
+///
+/// DME and DDE share 0x12, so more than one ID can be returned.
+pub fn ecu_ids_for_kline_address(address: u8) -> Vec<String> {
+    e60_ecus()
+        .into_iter()
+        .filter(|ecu| ecu.kline_address == Some(address))
+        .map(|ecu| ecu.id)
+        .collect()
+}
+
+/// Group ECUs that share their addresses (DME/DDE), keeping list order
+///
+/// The CAN response ID is compared too: several ECUs are requested through
+/// the functional ID 0x6F1 and only differ in where they answer.
+pub fn ecu_groups(ecus: Vec<EcuInfo>, names: Option<&[String]>) -> Vec<Vec<EcuInfo>> {
+    let mut groups: Vec<Vec<EcuInfo>> = Vec::new();
+
+    for ecu in ecus {
+        if let Some(names) = names {
+            if !names.iter().any(|name| name.eq_ignore_ascii_case(&ecu.id)) {
+                continue;
+            }
+        }
+        let same_ecu = |other: &EcuInfo| {
+            other.kline_address == ecu.kline_address
+                && other.can_tx_id == ecu.can_tx_id
+                && other.can_rx_id == ecu.can_rx_id
+        };
+        match groups.iter_mut().find(|group| same_ecu(&group[0])) {
+            Some(group) => group.push(ecu),
+            None => groups.push(vec![ecu]),
+        }
+    }
+
+    groups
+}
+
+/// ID of a group of ECUs sharing their addresses, e.g. "DME/DDE"
+pub fn group_id(group: &[EcuInfo]) -> String {
+    group.iter().map(|ecu| ecu.id.as_str()).collect::<Vec<_>>().join("/")
+}
+
 /// Common OBD-II PIDs
 #[allow(dead_code)]  // Public API for OBD-II compatibility
 pub fn common_pids() -> Vec<Pid> {
@@ -753,555 +854,45 @@ pub struct DieselPidDefinition {
 }
 
 /// Get all diesel PID definitions for E60 520d
+///
+/// Built from the DDE channel table (`data/channels/dde_diesel.json` plus
+/// any override files).
 pub fn get_diesel_pid_definitions() -> Vec<DieselPidDefinition> {
-    vec![
-        // === FUEL SYSTEM ===
-        DieselPidDefinition {
-            did: diesel_dids::FUEL_RAIL_PRESSURE,
-            name: "Presion Rail Combustible".to_string(),
-            short_name: "Rail".to_string(),
-            description: "Presion del common rail en bar".to_string(),
-            unit: "bar".to_string(),
-            min: 0.0,
-            max: 2000.0,
-            category: "fuel_system".to_string(),
-            formula: "(A*256+B) * 0.1".to_string(),
-            warning_low: Some(200.0),
-            warning_high: Some(1800.0),
-            critical_low: Some(150.0),
-            critical_high: Some(1900.0),
-        },
-        DieselPidDefinition {
-            did: diesel_dids::FUEL_RAIL_PRESSURE_DESIRED,
-            name: "Presion Rail Deseada".to_string(),
-            short_name: "Rail Obj".to_string(),
-            description: "Presion objetivo del rail".to_string(),
-            unit: "bar".to_string(),
-            min: 0.0,
-            max: 2000.0,
-            category: "fuel_system".to_string(),
-            formula: "(A*256+B) * 0.1".to_string(),
-            warning_low: None,
-            warning_high: None,
-            critical_low: None,
-            critical_high: None,
-        },
-        DieselPidDefinition {
-            did: diesel_dids::INJECTION_QUANTITY,
-            name: "Caudal Inyeccion".to_string(),
-            short_name: "Inyeccion".to_string(),
-            description: "Cantidad de combustible inyectado por carrera".to_string(),
-            unit: "mg/str".to_string(),
-            min: 0.0,
-            max: 100.0,
-            category: "fuel_system".to_string(),
-            formula: "(A*256+B) * 0.01".to_string(),
-            warning_low: None,
-            warning_high: Some(80.0),
-            critical_low: None,
-            critical_high: Some(90.0),
-        },
-        DieselPidDefinition {
-            did: diesel_dids::INJECTOR_CORRECTION_CYL1,
-            name: "Correccion Inyector Cil.1".to_string(),
-            short_name: "Inj1".to_string(),
-            description: "Desviacion del inyector cilindro 1".to_string(),
-            unit: "mg".to_string(),
-            min: -5.0,
-            max: 5.0,
-            category: "fuel_system".to_string(),
-            formula: "(A-128) * 0.1".to_string(),
-            warning_low: Some(-3.0),
-            warning_high: Some(3.0),
-            critical_low: Some(-4.0),
-            critical_high: Some(4.0),
-        },
-        DieselPidDefinition {
-            did: diesel_dids::INJECTOR_CORRECTION_CYL2,
-            name: "Correccion Inyector Cil.2".to_string(),
-            short_name: "Inj2".to_string(),
-            description: "Desviacion del inyector cilindro 2".to_string(),
-            unit: "mg".to_string(),
-            min: -5.0,
-            max: 5.0,
-            category: "fuel_system".to_string(),
-            formula: "(A-128) * 0.1".to_string(),
-            warning_low: Some(-3.0),
-            warning_high: Some(3.0),
-            critical_low: Some(-4.0),
-            critical_high: Some(4.0),
-        },
-        DieselPidDefinition {
-            did: diesel_dids::INJECTOR_CORRECTION_CYL3,
-            name: "Correccion Inyector Cil.3".to_string(),
-            short_name: "Inj3".to_string(),
-            description: "Desviacion del inyector cilindro 3".to_string(),
-            unit: "mg".to_string(),
-            min: -5.0,
-            max: 5.0,
-            category: "fuel_system".to_string(),
-            formula: "(A-128) * 0.1".to_string(),
-            warning_low: Some(-3.0),
-            warning_high: Some(3.0),
-            critical_low: Some(-4.0),
-            critical_high: Some(4.0),
-        },
-        DieselPidDefinition {
-            did: diesel_dids::INJECTOR_CORRECTION_CYL4,
-            name: "Correccion Inyector Cil.4".to_string(),
-            short_name: "Inj4".to_string(),
-            description: "Desviacion del inyector cilindro 4".to_string(),
-            unit: "mg".to_string(),
-            min: -5.0,
-            max: 5.0,
-            category: "fuel_system".to_string(),
-            formula: "(A-128) * 0.1".to_string(),
-            warning_low: Some(-3.0),
-            warning_high: Some(3.0),
-            critical_low: Some(-4.0),
-            critical_high: Some(4.0),
-        },
-
-        // === TURBO ===
-        DieselPidDefinition {
-            did: diesel_dids::BOOST_PRESSURE_ACTUAL,
-            name: "Presion Turbo Actual".to_string(),
-            short_name: "Boost".to_string(),
-            description: "Presion de sobrealimentacion actual".to_string(),
-            unit: "mbar".to_string(),
-            min: 0.0,
-            max: 2500.0,
-            category: "turbo".to_string(),
-            formula: "A*256+B".to_string(),
-            warning_low: None,
-            warning_high: Some(2200.0),
-            critical_low: None,
-            critical_high: Some(2400.0),
-        },
-        DieselPidDefinition {
-            did: diesel_dids::BOOST_PRESSURE_DESIRED,
-            name: "Presion Turbo Objetivo".to_string(),
-            short_name: "Boost Obj".to_string(),
-            description: "Presion de sobrealimentacion deseada".to_string(),
-            unit: "mbar".to_string(),
-            min: 0.0,
-            max: 2500.0,
-            category: "turbo".to_string(),
-            formula: "A*256+B".to_string(),
-            warning_low: None,
-            warning_high: None,
-            critical_low: None,
-            critical_high: None,
-        },
-        DieselPidDefinition {
-            did: diesel_dids::VNT_POSITION_ACTUAL,
-            name: "Posicion VNT Actual".to_string(),
-            short_name: "VNT".to_string(),
-            description: "Posicion del actuador de geometria variable".to_string(),
-            unit: "%".to_string(),
-            min: 0.0,
-            max: 100.0,
-            category: "turbo".to_string(),
-            formula: "A * 100 / 255".to_string(),
-            warning_low: None,
-            warning_high: None,
-            critical_low: None,
-            critical_high: None,
-        },
-
-        // === EGR ===
-        DieselPidDefinition {
-            did: diesel_dids::EGR_POSITION_ACTUAL,
-            name: "Posicion EGR Actual".to_string(),
-            short_name: "EGR".to_string(),
-            description: "Apertura de la valvula EGR".to_string(),
-            unit: "%".to_string(),
-            min: 0.0,
-            max: 100.0,
-            category: "egr".to_string(),
-            formula: "A * 100 / 255".to_string(),
-            warning_low: None,
-            warning_high: None,
-            critical_low: None,
-            critical_high: None,
-        },
-        DieselPidDefinition {
-            did: diesel_dids::EGR_POSITION_DESIRED,
-            name: "Posicion EGR Objetivo".to_string(),
-            short_name: "EGR Obj".to_string(),
-            description: "Posicion objetivo de la valvula EGR".to_string(),
-            unit: "%".to_string(),
-            min: 0.0,
-            max: 100.0,
-            category: "egr".to_string(),
-            formula: "A * 100 / 255".to_string(),
-            warning_low: None,
-            warning_high: None,
-            critical_low: None,
-            critical_high: None,
-        },
-        DieselPidDefinition {
-            did: diesel_dids::EGR_MASS_FLOW,
-            name: "Caudal Masico EGR".to_string(),
-            short_name: "EGR Flow".to_string(),
-            description: "Flujo de gases recirculados".to_string(),
-            unit: "kg/h".to_string(),
-            min: 0.0,
-            max: 500.0,
-            category: "egr".to_string(),
-            formula: "(A*256+B) * 0.1".to_string(),
-            warning_low: None,
-            warning_high: None,
-            critical_low: None,
-            critical_high: None,
-        },
-
-        // === TEMPERATURAS ESCAPE ===
-        DieselPidDefinition {
-            did: diesel_dids::EXHAUST_TEMP_PRE_TURBO,
-            name: "Temp Escape Pre-Turbo".to_string(),
-            short_name: "T.PreTurbo".to_string(),
-            description: "Temperatura gases antes del turbo".to_string(),
-            unit: "°C".to_string(),
-            min: -40.0,
-            max: 900.0,
-            category: "temperatures".to_string(),
-            formula: "(A*256+B) * 0.1 - 40".to_string(),
-            warning_low: None,
-            warning_high: Some(750.0),
-            critical_low: None,
-            critical_high: Some(850.0),
-        },
-        DieselPidDefinition {
-            did: diesel_dids::EXHAUST_TEMP_DPF_INLET,
-            name: "Temp Entrada DPF".to_string(),
-            short_name: "T.DPF In".to_string(),
-            description: "Temperatura gases entrada filtro particulas".to_string(),
-            unit: "°C".to_string(),
-            min: -40.0,
-            max: 800.0,
-            category: "temperatures".to_string(),
-            formula: "(A*256+B) * 0.1 - 40".to_string(),
-            warning_low: None,
-            warning_high: Some(650.0),
-            critical_low: None,
-            critical_high: Some(700.0),
-        },
-        DieselPidDefinition {
-            did: diesel_dids::EXHAUST_TEMP_DPF_OUTLET,
-            name: "Temp Salida DPF".to_string(),
-            short_name: "T.DPF Out".to_string(),
-            description: "Temperatura gases salida filtro particulas".to_string(),
-            unit: "°C".to_string(),
-            min: -40.0,
-            max: 800.0,
-            category: "temperatures".to_string(),
-            formula: "(A*256+B) * 0.1 - 40".to_string(),
-            warning_low: None,
-            warning_high: Some(600.0),
-            critical_low: None,
-            critical_high: Some(650.0),
-        },
-
-        // === DPF ===
-        DieselPidDefinition {
-            did: diesel_dids::DPF_SOOT_LOADING,
-            name: "Carga Hollin DPF".to_string(),
-            short_name: "Hollin".to_string(),
-            description: "Porcentaje de carga de hollin en el DPF".to_string(),
-            unit: "%".to_string(),
-            min: 0.0,
-            max: 100.0,
-            category: "dpf".to_string(),
-            formula: "A * 100 / 255".to_string(),
-            warning_low: None,
-            warning_high: Some(70.0),
-            critical_low: None,
-            critical_high: Some(85.0),
-        },
-        DieselPidDefinition {
-            did: diesel_dids::DPF_DIFFERENTIAL_PRESSURE,
-            name: "Presion Diferencial DPF".to_string(),
-            short_name: "dP DPF".to_string(),
-            description: "Diferencia de presion a traves del DPF".to_string(),
-            unit: "mbar".to_string(),
-            min: 0.0,
-            max: 500.0,
-            category: "dpf".to_string(),
-            formula: "A*256+B".to_string(),
-            warning_low: None,
-            warning_high: Some(300.0),
-            critical_low: None,
-            critical_high: Some(400.0),
-        },
-        DieselPidDefinition {
-            did: diesel_dids::DPF_ASH_LOADING,
-            name: "Carga Cenizas DPF".to_string(),
-            short_name: "Cenizas".to_string(),
-            description: "Gramos de ceniza acumulada en el DPF".to_string(),
-            unit: "g".to_string(),
-            min: 0.0,
-            max: 200.0,
-            category: "dpf".to_string(),
-            formula: "(A*256+B) * 0.1".to_string(),
-            warning_low: None,
-            warning_high: Some(100.0),
-            critical_low: None,
-            critical_high: Some(150.0),
-        },
-        DieselPidDefinition {
-            did: diesel_dids::DPF_DISTANCE_SINCE_REGEN,
-            name: "Distancia desde Regen".to_string(),
-            short_name: "Km Regen".to_string(),
-            description: "Kilometros recorridos desde ultima regeneracion".to_string(),
-            unit: "km".to_string(),
-            min: 0.0,
-            max: 1000.0,
-            category: "dpf".to_string(),
-            formula: "A*256+B".to_string(),
-            warning_low: None,
-            warning_high: Some(500.0),
-            critical_low: None,
-            critical_high: Some(700.0),
-        },
-        DieselPidDefinition {
-            did: diesel_dids::DPF_REGEN_COUNT,
-            name: "Contador Regeneraciones".to_string(),
-            short_name: "Regens".to_string(),
-            description: "Numero total de regeneraciones realizadas".to_string(),
-            unit: "".to_string(),
-            min: 0.0,
-            max: 10000.0,
-            category: "dpf".to_string(),
-            formula: "A*256+B".to_string(),
-            warning_low: None,
-            warning_high: None,
-            critical_low: None,
-            critical_high: None,
-        },
-
-        // === BUJIAS CALENTAMIENTO ===
-        DieselPidDefinition {
-            did: diesel_dids::GLOW_PLUG_STATUS,
-            name: "Estado Bujias".to_string(),
-            short_name: "Bujias".to_string(),
-            description: "Estado de las bujias de calentamiento (bitmask)".to_string(),
-            unit: "".to_string(),
-            min: 0.0,
-            max: 255.0,
-            category: "glow_plugs".to_string(),
-            formula: "A".to_string(),
-            warning_low: None,
-            warning_high: None,
-            critical_low: None,
-            critical_high: None,
-        },
-
-        // === MOTOR ===
-        DieselPidDefinition {
-            did: diesel_dids::ENGINE_RPM,
-            name: "RPM Motor".to_string(),
-            short_name: "RPM".to_string(),
-            description: "Velocidad del motor".to_string(),
-            unit: "rpm".to_string(),
-            min: 0.0,
-            max: 6000.0,
-            category: "engine".to_string(),
-            formula: "A*256+B".to_string(),
-            warning_low: None,
-            warning_high: Some(5000.0),
-            critical_low: None,
-            critical_high: Some(5500.0),
-        },
-        DieselPidDefinition {
-            did: diesel_dids::ENGINE_LOAD,
-            name: "Carga Motor".to_string(),
-            short_name: "Carga".to_string(),
-            description: "Porcentaje de carga del motor".to_string(),
-            unit: "%".to_string(),
-            min: 0.0,
-            max: 100.0,
-            category: "engine".to_string(),
-            formula: "A * 100 / 255".to_string(),
-            warning_low: None,
-            warning_high: None,
-            critical_low: None,
-            critical_high: None,
-        },
-        DieselPidDefinition {
-            did: diesel_dids::ACCELERATOR_PEDAL_POS1,
-            name: "Posicion Acelerador".to_string(),
-            short_name: "Acel".to_string(),
-            description: "Posicion del pedal acelerador".to_string(),
-            unit: "%".to_string(),
-            min: 0.0,
-            max: 100.0,
-            category: "engine".to_string(),
-            formula: "A * 100 / 255".to_string(),
-            warning_low: None,
-            warning_high: None,
-            critical_low: None,
-            critical_high: None,
-        },
+    let db = channels::read_global();
+    db.channels(channels::DDE, channels::SERVICE_DATA_ID)
+        .map(|channel| {
+            let def = &channel.def;
+            DieselPidDefinition {
+                did: channel.id,
+                name: def.name.preferred().unwrap_or(def.key.as_str()).to_string(),
+                short_name: def.short_name.clone(),
+                description: def.description.preferred().unwrap_or_default().to_string(),
+                unit: def.unit.clone(),
+                min: def.min,
+                max: def.max,
+                category: def.category.clone(),
+                formula: channel.formula_text(),
+                warning_low: def.warning_low,
+                warning_high: def.warning_high,
+                critical_low: def.critical_low,
+                critical_high: def.critical_high,
+            }
+        })
+        .collect()
+}
 
-        // === ELECTRICO ===
-        DieselPidDefinition {
-            did: diesel_dids::BATTERY_VOLTAGE,
-            name: "Tension Bateria".to_string(),
-            short_name: "Bateria".to_string(),
-            description: "Voltaje de la bateria".to_string(),
-            unit: "V".to_string(),
-            min: 0.0,
-            max: 20.0,
-            category: "electrical".to_string(),
-            formula: "(A*256+B) * 0.001".to_string(),
-            warning_low: Some(11.5),
-            warning_high: Some(15.0),
-            critical_low: Some(10.5),
-            critical_high: Some(16.0),
-        },
-        DieselPidDefinition {
-            did: diesel_dids::COOLANT_TEMPERATURE,
-            name: "Temperatura Refrigerante".to_string(),
-            short_name: "T.Refrig".to_string(),
-            description: "Temperatura del liquido refrigerante".to_string(),
-            unit: "°C".to_string(),
-            min: -40.0,
-            max: 150.0,
-            category: "engine".to_string(),
-            formula: "A - 40".to_string(),
-            warning_low: Some(60.0),
-            warning_high: Some(105.0),
-            critical_low: Some(40.0),
-            critical_high: Some(115.0),
-        },
-        DieselPidDefinition {
-            did: diesel_dids::OIL_TEMPERATURE,
-            name: "Temperatura Aceite".to_string(),
-            short_name: "T.Aceite".to_string(),
-            description: "Temperatura del aceite motor".to_string(),
-            unit: "°C".to_string(),
-            min: -40.0,
-            max: 180.0,
-            category: "engine".to_string(),
-            formula: "A - 40".to_string(),
-            warning_low: Some(60.0),
-            warning_high: Some(130.0),
-            critical_low: Some(40.0),
-            critical_high: Some(150.0),
-        },
-        DieselPidDefinition {
-            did: diesel_dids::FUEL_TEMPERATURE,
-            name: "Temperatura Combustible".to_string(),
-            short_name: "T.Fuel".to_string(),
-            description: "Temperatura del diesel".to_string(),
-            unit: "°C".to_string(),
-            min: -40.0,
-            max: 100.0,
-            category: "fuel_system".to_string(),
-            formula: "A - 40".to_string(),
-            warning_low: None,
-            warning_high: Some(60.0),
-            critical_low: None,
-            critical_high: Some(70.0),
-        },
-        DieselPidDefinition {
-            did: diesel_dids::AIR_MASS_FLOW,
-            name: "Caudal Masico Aire".to_string(),
-            short_name: "MAF".to_string(),
-            description: "Flujo de aire medido por el sensor MAF".to_string(),
-            unit: "kg/h".to_string(),
-            min: 0.0,
-            max: 1000.0,
-            category: "engine".to_string(),
-            formula: "(A*256+B) * 0.1".to_string(),
-            warning_low: None,
-            warning_high: None,
-            critical_low: None,
-            critical_high: None,
-        },
-    ]
+/// Data length of a diesel DID in bytes, from its channel definition
+pub fn diesel_did_length(did: u16) -> Option<usize> {
+    channels::data_length(channels::DDE, channels::SERVICE_DATA_ID, did)
 }
 
 /// Calculate value from raw DID response bytes
+///
+/// Unknown DIDs, and data the channel formula cannot scale, give the
+/// first byte as a raw value.
 pub fn calculate_diesel_did_value(did: u16, data: &[u8]) -> Option<(f64, String, String)> {
-    if data.is_empty() {
-        return None;
-    }
-
-    let a = data[0] as f64;
-    let b = data.get(1).copied().unwrap_or(0) as f64;
-    let ab = a * 256.0 + b;
-
-    let (value, unit, name) = match did {
-        // Fuel rail pressure (bar)
-        0x394A => (ab * 0.1, "bar".to_string(), "Rail Pressure".to_string()),
-        0x394B => (ab * 0.1, "bar".to_string(), "Rail Pressure Desired".to_string()),
-
-        // Injection quantity (mg/stroke)
-        0x394C => (ab * 0.01, "mg/str".to_string(), "Injection Qty".to_string()),
-        0x394D => (ab * 0.01, "mg/str".to_string(), "Pilot Injection".to_string()),
-
-        // Injector corrections (signed, mg)
-        0x3950..=0x3953 => {
-            let cyl = (did - 0x394F) as u8;
-            ((a - 128.0) * 0.1, "mg".to_string(), format!("Inj Corr Cyl{}", cyl))
-        },
-
-        // EGR position (%)
-        0x3960 => (a * 100.0 / 255.0, "%".to_string(), "EGR Position".to_string()),
-        0x3961 => (a * 100.0 / 255.0, "%".to_string(), "EGR Desired".to_string()),
-        0x3962 => (ab * 0.1, "kg/h".to_string(), "EGR Mass Flow".to_string()),
-
-        // Boost pressure (mbar)
-        0x3970 => (ab, "mbar".to_string(), "Boost Actual".to_string()),
-        0x3971 => (ab, "mbar".to_string(), "Boost Desired".to_string()),
-
-        // VNT position (%)
-        0x3972 => (a * 100.0 / 255.0, "%".to_string(), "VNT Position".to_string()),
-        0x3973 => (a * 100.0 / 255.0, "%".to_string(), "VNT Desired".to_string()),
-
-        // Air mass (kg/h)
-        0x3980 => (ab * 0.1, "kg/h".to_string(), "Air Mass Flow".to_string()),
-
-        // Exhaust temperatures (°C)
-        0x3990..=0x3994 => {
-            let names = ["Pre-Turbo", "Post-Turbo", "DPF Inlet", "DPF Outlet", "Pre-Cat"];
-            let idx = (did - 0x3990) as usize;
-            (ab * 0.1 - 40.0, "°C".to_string(), format!("Exhaust {}", names.get(idx).unwrap_or(&"Temp")))
-        },
-
-        // DPF values
-        0x39A0 => (ab, "mbar".to_string(), "DPF Diff Pressure".to_string()),
-        0x39A1 => (a * 100.0 / 255.0, "%".to_string(), "DPF Soot Loading".to_string()),
-        0x39A2 => (ab * 0.1, "g".to_string(), "DPF Ash Loading".to_string()),
-        0x39A3 => (a, "".to_string(), "DPF Regen Status".to_string()),
-        0x39A4 => (ab, "km".to_string(), "Dist Since Regen".to_string()),
-        0x39A5 => (ab, "".to_string(), "Regen Count".to_string()),
-
-        // Glow plugs
-        0x39B0 => (a, "".to_string(), "Glow Plug Status".to_string()),
-        0x39B1 => (a, "s".to_string(), "Glow Time Remain".to_string()),
-
-        // Pedal/load
-        0x39C0 => (a * 100.0 / 255.0, "%".to_string(), "Accel Pedal".to_string()),
-        0x39C4 => (a * 100.0 / 255.0, "%".to_string(), "Engine Load".to_string()),
-
-        // Electrical
-        0x39D0 => (ab * 0.001, "V".to_string(), "Battery Voltage".to_string()),
-        0x39D2 => (a - 40.0, "°C".to_string(), "Fuel Temp".to_string()),
-        0x39D3 => (a - 40.0, "°C".to_string(), "Coolant Temp".to_string()),
-        0x39D4 => (a - 40.0, "°C".to_string(), "Oil Temp".to_string()),
-        0x39D5 => (ab * 0.01, "bar".to_string(), "Oil Pressure".to_string()),
-
-        // Engine
-        0x39E0 => (ab, "rpm".to_string(), "Engine RPM".to_string()),
-        0x39E1 => (a, "km/h".to_string(), "Vehicle Speed".to_string()),
-        0x39E4 => (ab * 0.01, "L/h".to_string(), "Fuel Consumption".to_string()),
-
-        // Unknown DID
-        _ => (a, "raw".to_string(), format!("DID 0x{:04X}", did)),
-    };
-
-    Some((value, unit, name))
+    let first = *data.first()?;
+    channels::decode(channels::DDE, channels::SERVICE_DATA_ID, did, data).or_else(|| {
+        Some((first as f64, "raw".to_string(), format!("DID 0x{:04X}", did)))
+    })
 }
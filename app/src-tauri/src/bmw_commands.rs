@@ -5,8 +5,18 @@
 use crate::bmw::{self, Dtc, EcuInfo};
 use crate::constants::addresses;
 use crate::dcan::DCanHandler;
+use crate::dtc_info;
+use crate::fault_codes;
 use crate::kline::KLineHandler;
+use crate::profiles::{self, DpfRoutine};
+use crate::database::NewDtcClearEvent;
+use crate::db_commands::DbState;
 use crate::serial::SerialState;
+use bmw_diag_core::dtc_service::{
+    self, ClearSummary, DtcDialect, DtcGroup, DtcRecord, DtcTransport,
+};
+use bmw_diag_core::obd_service::{self, EmissionsReport, ObdVehicleInfo};
+use bmw_diag_core::routine::RoutineStatus;
 use serde::{Deserialize, Serialize};
 use tauri::State;
 
@@ -28,9 +38,16 @@ pub struct DtcReadResult {
 }
 
 /// Get list of known BMW E60 ECUs
+///
+/// With a `vehicle_id` only the ECUs of the vehicle's profile are listed
+/// (DDE or DME, not both).
 #[tauri::command]
-pub fn bmw_get_ecus() -> Vec<EcuInfo> {
-    bmw::e60_ecus()
+pub fn bmw_get_ecus(db: State<DbState>, vehicle_id: Option<i64>) -> Result<Vec<EcuInfo>, String> {
+    let ecus = bmw::e60_ecus();
+    Ok(match profiles::for_optional_vehicle(&db, vehicle_id)? {
+        Some(profile) => ecus.into_iter().filter(|ecu| profile.has_ecu(&ecu.id)).collect(),
+        None => ecus,
+    })
 }
 
 /// Switch to K-Line mode
@@ -126,71 +143,117 @@ pub fn bmw_kline_request(
 pub fn bmw_read_dtcs_kline(
     state: State<SerialState>,
     target_address: Option<u8>,
+    ecu_variant: Option<String>,
 ) -> Result<DtcReadResult, String> {
     let target = target_address.unwrap_or(addresses::DME_DDE);
     let source = addresses::TESTER;
+    let ecu_ids = bmw::ecu_ids_for_kline_address(target);
 
-    state.with_port(|port| {
-        // Try UDS style first (0x19 with sub-function 0x02 = reportDTCByStatusMask)
-        let request = vec![0x19, 0x02, 0xFF]; // Read all DTCs with any status
+    let result = state.with_port(|port| Ok(read_dtcs_kline(port, target, source)))?;
 
-        match KLineHandler::send_request(port, target, source, &request) {
-            Ok(response) => {
-                if response.first() == Some(&0x59) {
-                    // Positive response
-                    let dtcs = parse_uds_dtc_response(&response);
-                    Ok(DtcReadResult {
-                        success: true,
-                        count: dtcs.len(),
-                        dtcs,
-                        message: "DTCs read successfully (UDS)".to_string(),
-                    })
-                } else if response.first() == Some(&0x7F) {
-                    // Negative response, try KWP2000 style
-                    let kwp_request = vec![0x18, 0x00, 0xFF, 0x00]; // ReadDTCByStatus
-                    match KLineHandler::send_request(port, target, source, &kwp_request) {
-                        Ok(kwp_response) => {
-                            if kwp_response.first() == Some(&0x58) {
-                                let dtcs = parse_kwp_dtc_response(&kwp_response);
-                                Ok(DtcReadResult {
-                                    success: true,
-                                    count: dtcs.len(),
-                                    dtcs,
-                                    message: "DTCs read successfully (KWP2000)".to_string(),
-                                })
-                            } else {
-                                Ok(DtcReadResult {
-                                    success: false,
-                                    count: 0,
-                                    dtcs: vec![],
-                                    message: format!("Unexpected KWP response: {:02X?}", kwp_response),
-                                })
-                            }
-                        }
-                        Err(e) => Ok(DtcReadResult {
-                            success: false,
-                            count: 0,
-                            dtcs: vec![],
-                            message: format!("KWP2000 request failed: {}", e),
-                        }),
-                    }
-                } else {
-                    Ok(DtcReadResult {
-                        success: false,
-                        count: 0,
-                        dtcs: vec![],
-                        message: format!("Unexpected response: {:02X?}", response),
-                    })
-                }
+    let ecu_ids: Vec<&str> = ecu_ids.iter().map(String::as_str).collect();
+    Ok(with_fault_texts(result, &ecu_ids, ecu_variant.as_deref()))
+}
+
+/// K-Line access to one ECU for the DTC read service
+pub(crate) struct KLineDtcTransport<'a> {
+    pub(crate) port: &'a mut Box<dyn serialport::SerialPort>,
+    pub(crate) target: u8,
+    pub(crate) source: u8,
+}
+
+impl DtcTransport for KLineDtcTransport<'_> {
+    fn request(&mut self, data: &[u8]) -> Result<Vec<u8>, String> {
+        KLineHandler::send_request(self.port, self.target, self.source, data)
+    }
+
+    fn receive(&mut self) -> Result<Vec<u8>, String> {
+        KLineHandler::receive_response(self.port)
+    }
+}
+
+/// D-CAN access to one ECU for the DTC read service
+pub(crate) struct DCanDtcTransport<'a> {
+    pub(crate) port: &'a mut Box<dyn serialport::SerialPort>,
+    pub(crate) tx_id: u32,
+    pub(crate) rx_id: u32,
+}
+
+impl DtcTransport for DCanDtcTransport<'_> {
+    fn request(&mut self, data: &[u8]) -> Result<Vec<u8>, String> {
+        DCanHandler::send_message(self.port, self.tx_id, self.rx_id, data)
+    }
+
+    fn receive(&mut self) -> Result<Vec<u8>, String> {
+        DCanHandler::receive_message(self.port, self.rx_id)
+    }
+}
+
+/// Read DTCs via K-Line in the ECU dialect (KWP2000, UDS as fallback)
+fn read_dtcs_kline(
+    port: &mut Box<dyn serialport::SerialPort>,
+    target: u8,
+    source: u8,
+) -> DtcReadResult {
+    let mut transport = KLineDtcTransport { port, target, source };
+
+    match dtc_service::read_dtcs(&mut transport, DtcDialect::for_transport(true)) {
+        Ok(readout) => {
+            let dtcs: Vec<Dtc> = readout.records.iter().map(Dtc::from_record).collect();
+            let dialect = match readout.dialect {
+                DtcDialect::Kwp2000 => "KWP2000",
+                DtcDialect::Uds => "UDS",
+            };
+            DtcReadResult {
+                success: true,
+                count: dtcs.len(),
+                dtcs,
+                message: format!("DTCs read successfully ({})", dialect),
             }
-            Err(e) => Ok(DtcReadResult {
-                success: false,
-                count: 0,
-                dtcs: vec![],
-                message: format!("Request failed: {}", e),
-            }),
         }
-    })
+        Err(e) => DtcReadResult {
+            success: false,
+            count: 0,
+            dtcs: vec![],
+            message: format!("Read DTCs failed: {}", e),
+        },
+    }
+}
+
+/// Read DTCs via K-Line with the environmental conditions of each fault
+///
+/// Reads the fault list like `bmw_read_dtcs_kline`, then sends KWP
+/// ReadStatusOfDTC (0x17) per fault. Faults whose ECU does not answer
+/// 0x17 are returned without `environment`.
+#[tauri::command]
+pub fn bmw_read_dtc_environment_kline(
+    state: State<SerialState>,
+    target_address: Option<u8>,
+    ecu_variant: Option<String>,
+) -> Result<DtcReadResult, String> {
+    let target = target_address.unwrap_or(addresses::DME_DDE);
+    let source = addresses::TESTER;
+    let ecu_ids = bmw::ecu_ids_for_kline_address(target);
+
+    let result = state.with_port(|port| {
+        let mut result = read_dtcs_kline(port, target, source);
+
+        for dtc in result.dtcs.iter_mut().filter(|dtc| dtc.raw_bytes.len() == 3) {
+            let code = u16::from_be_bytes([dtc.raw_bytes[0], dtc.raw_bytes[1]]);
+            let request = dtc_info::kwp_status_request(code);
+
+            match KLineHandler::send_request(port, target, source, &request)
+                .and_then(|response| dtc_info::parse_kwp_dtc_status(&response))
+            {
+                Ok((_, environment)) => dtc.environment = Some(environment),
+                Err(e) => log::warn!("No environment data for fault {:04X}: {}", code, e),
+            }
+        }
+        Ok(result)
+    })?;
+
+    let ecu_ids: Vec<&str> = ecu_ids.iter().map(String::as_str).collect();
+    Ok(with_fault_texts(result, &ecu_ids, ecu_variant.as_deref()))
 }
 
 /// Clear DTCs from ECU via K-Line
@@ -203,29 +266,120 @@ pub fn bmw_clear_dtcs_kline(
     let source = addresses::TESTER;
 
     state.with_port(|port| {
-        // UDS ClearDiagnosticInformation (0x14) with group = all (0xFFFFFF)
-        let request = vec![0x14, 0xFF, 0xFF, 0xFF];
+        let mut transport = KLineDtcTransport { port, target, source };
 
-        match KLineHandler::send_request(port, target, source, &request) {
-            Ok(response) => {
-                if response.first() == Some(&0x54) {
-                    Ok("DTCs cleared successfully".to_string())
-                } else if response.first() == Some(&0x7F) {
-                    let nrc = response.get(2).copied().unwrap_or(0);
-                    Err(format!(
-                        "Clear failed: {} (0x{:02X})",
-                        bmw::nrc::description(nrc),
-                        nrc
-                    ))
-                } else {
-                    Err(format!("Unexpected response: {:02X?}", response))
-                }
-            }
-            Err(e) => Err(format!("Request failed: {}", e)),
-        }
+        dtc_service::clear_dtcs(&mut transport, DtcDialect::for_transport(true), DtcGroup::All)
+            .map(|_| "DTCs cleared successfully".to_string())
+            .map_err(|e| format!("Clear failed: {}", e))
     })
 }
 
+/// Read the OBD-II emissions readiness report via K-Line
+///
+/// MIL, readiness monitors and the Mode 03/07/0A fault lists of the
+/// engine ECU, for an inspection pre-check.
+#[tauri::command]
+pub fn bmw_emissions_readiness_kline(
+    state: State<SerialState>,
+    target_address: Option<u8>,
+) -> Result<EmissionsReport, String> {
+    let target = target_address.unwrap_or(addresses::DME_DDE);
+    let source = addresses::TESTER;
+
+    state.with_port(|port| {
+        let mut transport = KLineDtcTransport { port, target, source };
+        obd_service::read_emissions_report(&mut transport)
+            .map_err(|e| format!("Readiness read failed: {}", e))
+    })
+}
+
+/// Clear emission DTCs via K-Line (OBD Mode 04)
+///
+/// Also resets every readiness monitor to incomplete.
+#[tauri::command]
+pub fn bmw_clear_emissions_dtcs_kline(
+    state: State<SerialState>,
+    target_address: Option<u8>,
+) -> Result<String, String> {
+    let target = target_address.unwrap_or(addresses::DME_DDE);
+    let source = addresses::TESTER;
+
+    state.with_port(|port| {
+        let mut transport = KLineDtcTransport { port, target, source };
+        obd_service::clear_emissions_dtcs(&mut transport)
+            .map(|_| "Emission DTCs cleared, readiness monitors reset".to_string())
+            .map_err(|e| format!("Clear failed: {}", e))
+    })
+}
+
+/// Read OBD-II Mode 09 vehicle information via K-Line
+///
+/// VIN, calibration IDs, CVNs, ECU name and in-use performance counters.
+/// With `session_id` the result is stored with that session.
+#[tauri::command]
+pub fn obd_read_vehicle_info_kline(
+    state: State<SerialState>,
+    db: State<DbState>,
+    target_address: Option<u8>,
+    session_id: Option<i64>,
+) -> Result<ObdVehicleInfo, String> {
+    let target = target_address.unwrap_or(addresses::DME_DDE);
+    let source = addresses::TESTER;
+
+    let info = state.with_port(|port| {
+        let mut transport = KLineDtcTransport { port, target, source };
+        obd_service::read_vehicle_info(&mut transport)
+            .map_err(|e| format!("Vehicle info read failed: {}", e))
+    })?;
+
+    if let Some(session_id) = session_id {
+        store_vehicle_info(&db, session_id, &info);
+    }
+    Ok(info)
+}
+
+/// Read OBD-II Mode 09 vehicle information from an ECU via D-CAN
+#[tauri::command]
+pub fn obd_read_vehicle_info_dcan(
+    state: State<SerialState>,
+    db: State<DbState>,
+    ecu_name: String,
+    session_id: Option<i64>,
+) -> Result<ObdVehicleInfo, String> {
+    let (tx_id, rx_id) = can_ids::for_ecu(&ecu_name)
+        .ok_or_else(|| format!("Unknown ECU for D-CAN: {}", ecu_name))?;
+
+    let info = state.with_port(|port| {
+        DCanHandler::switch_to_dcan_mode(port)?;
+        let mut transport = DCanDtcTransport { port, tx_id, rx_id };
+        obd_service::read_vehicle_info(&mut transport)
+            .map_err(|e| format!("Vehicle info read failed: {}", e))
+    })?;
+
+    if let Some(session_id) = session_id {
+        store_vehicle_info(&db, session_id, &info);
+    }
+    Ok(info)
+}
+
+/// Attach Mode 09 information to a stored session
+fn store_vehicle_info(db: &DbState, session_id: i64, info: &ObdVehicleInfo) {
+    let stored = serde_json::to_value(info)
+        .map_err(|e| e.to_string())
+        .and_then(|value| {
+            let guard = db.0.lock().map_err(|e| e.to_string())?;
+            match guard.as_ref() {
+                Some(db) => db
+                    .set_session_vehicle_info(session_id, &value)
+                    .map_err(|e| e.to_string()),
+                None => Err("Database not initialized".to_string()),
+            }
+        });
+    if let Err(e) = stored {
+        log::warn!("Failed to store vehicle info for session {}: {}", session_id, e);
+    }
+}
+
 /// Read ECU identification
 #[tauri::command]
 pub fn bmw_read_ecu_id(
@@ -281,51 +435,38 @@ pub fn bmw_tester_present(
 
 // Helper functions
 
-fn parse_uds_dtc_response(response: &[u8]) -> Vec<Dtc> {
-    let mut dtcs = Vec::new();
-
-    if response.len() < 3 {
-        return dtcs;
-    }
-
-    // Skip service ID (0x59), sub-function, and status mask availability
-    let data = &response[3..];
-
-    // Each DTC is 3 bytes: DTC_HI, DTC_LO, STATUS
-    for chunk in data.chunks(3) {
-        if let Some(dtc) = Dtc::from_bytes(chunk) {
-            dtcs.push(dtc);
-        }
-    }
-
-    dtcs
+/// Fill in BMW fault texts for the DTCs of a read result
+fn with_fault_texts(
+    mut result: DtcReadResult,
+    ecu_ids: &[&str],
+    ecu_variant: Option<&str>,
+) -> DtcReadResult {
+    fault_codes::enrich_dtcs(&mut result.dtcs, ecu_ids, ecu_variant);
+    result
 }
 
-fn parse_kwp_dtc_response(response: &[u8]) -> Vec<Dtc> {
-    let mut dtcs = Vec::new();
-
-    if response.len() < 2 {
-        return dtcs;
-    }
-
-    // Skip service ID (0x58) and count
-    let data = &response[2..];
+/// Fault text database info
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FaultDbInfo {
+    pub versions: Vec<String>,
+    pub entries: usize,
+}
 
-    // Format varies by ECU, common is: DTC_HI, DTC_LO, STATUS
-    for chunk in data.chunks(3) {
-        if let Some(dtc) = Dtc::from_bytes(chunk) {
-            dtcs.push(dtc);
-        }
+/// Get loaded fault text database versions
+#[tauri::command]
+pub fn bmw_fault_db_info() -> FaultDbInfo {
+    let db = fault_codes::global().read().unwrap_or_else(|e| e.into_inner());
+    FaultDbInfo {
+        versions: db.versions().to_vec(),
+        entries: db.len(),
     }
-
-    dtcs
 }
 
 // ============================================================================
 // DPF (Diesel Particulate Filter) Commands
 // ============================================================================
 
-use crate::bmw::{dpf_routines, dpf_dids, security, routine, DpfRoutineResult, DpfStatus};
+use crate::bmw::{dpf_dids, security, routine, DpfRoutineResult, DpfStatus};
 
 /// Session control result
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -537,74 +678,83 @@ fn execute_dpf_routine(
     }
 }
 
-/// Reset DPF soot/ash loading counter
-#[tauri::command]
-pub fn bmw_dpf_reset_ash(
-    state: State<SerialState>,
+/// Run a DPF routine with the IDs of the vehicle's profile
+///
+/// The IDs are tried in order until the ECU accepts one. Without a
+/// `vehicle_id` the default (E60 520d) profile is used.
+fn run_dpf_routine(
+    state: &SerialState,
+    db: &DbState,
     target_address: Option<u8>,
+    vehicle_id: Option<i64>,
+    routine: DpfRoutine,
+    sub_function: u8,
 ) -> Result<DpfRoutineResult, String> {
-    let target = target_address.unwrap_or(addresses::DME_DDE);
+    let profile = profiles::for_vehicle(db, vehicle_id)?.profile;
+    let routine_ids = profile
+        .dpf_routine(routine)
+        .ok_or_else(|| format!("{} has no particulate filter", profile.def.name))?;
+    let target = target_address
+        .or_else(|| profile.engine_address())
+        .unwrap_or(addresses::DME_DDE);
     let source = addresses::TESTER;
-
-    log::info!("Resetting DPF ash counter on ECU 0x{:02X}", target);
+    log::info!(
+        "DPF routine {:?} on ECU 0x{:02X} ({} profile)",
+        routine,
+        target,
+        profile.def.key
+    );
 
     state.with_port(|port| {
-        // Try primary routine ID first
-        let result = execute_dpf_routine(port, target, source, dpf_routines::RESET_ASH_LOADING, routine::START)?;
-
-        if !result.success {
-            log::info!("Primary routine failed, trying alternative ID");
-            return execute_dpf_routine(port, target, source, dpf_routines::alt::RESET_ASH, routine::START);
+        let mut result = None;
+        for (index, &routine_id) in routine_ids.iter().enumerate() {
+            if index > 0 {
+                log::info!("Routine 0x{:04X} failed, trying alternative ID", routine_ids[index - 1]);
+            }
+            let attempt = execute_dpf_routine(port, target, source, routine_id, sub_function)?;
+            if attempt.success {
+                return Ok(attempt);
+            }
+            result = Some(attempt);
         }
-
-        Ok(result)
+        result.ok_or_else(|| "No routine ID configured".to_string())
     })
 }
 
+/// Reset DPF soot/ash loading counter
+#[tauri::command]
+pub fn bmw_dpf_reset_ash(
+    state: State<SerialState>,
+    db: State<DbState>,
+    target_address: Option<u8>,
+    vehicle_id: Option<i64>,
+) -> Result<DpfRoutineResult, String> {
+    log::info!("Resetting DPF ash counter");
+    run_dpf_routine(&state, &db, target_address, vehicle_id, DpfRoutine::ResetAsh, routine::START)
+}
+
 /// Reset DPF learned/adaptation values
 #[tauri::command]
 pub fn bmw_dpf_reset_learned(
     state: State<SerialState>,
+    db: State<DbState>,
     target_address: Option<u8>,
+    vehicle_id: Option<i64>,
 ) -> Result<DpfRoutineResult, String> {
-    let target = target_address.unwrap_or(addresses::DME_DDE);
-    let source = addresses::TESTER;
-
-    log::info!("Resetting DPF learned values on ECU 0x{:02X}", target);
-
-    state.with_port(|port| {
-        let result = execute_dpf_routine(port, target, source, dpf_routines::RESET_LEARNED_VALUES, routine::START)?;
-
-        if !result.success {
-            log::info!("Primary routine failed, trying alternative ID");
-            return execute_dpf_routine(port, target, source, dpf_routines::alt::RESET_ADAPTATION, routine::START);
-        }
-
-        Ok(result)
-    })
+    log::info!("Resetting DPF learned values");
+    run_dpf_routine(&state, &db, target_address, vehicle_id, DpfRoutine::ResetLearned, routine::START)
 }
 
 /// Register new DPF installed
 #[tauri::command]
 pub fn bmw_dpf_new_installed(
     state: State<SerialState>,
+    db: State<DbState>,
     target_address: Option<u8>,
+    vehicle_id: Option<i64>,
 ) -> Result<DpfRoutineResult, String> {
-    let target = target_address.unwrap_or(addresses::DME_DDE);
-    let source = addresses::TESTER;
-
-    log::info!("Registering new DPF on ECU 0x{:02X}", target);
-
-    state.with_port(|port| {
-        let result = execute_dpf_routine(port, target, source, dpf_routines::NEW_DPF_INSTALLED, routine::START)?;
-
-        if !result.success {
-            log::info!("Primary routine failed, trying alternative ID");
-            return execute_dpf_routine(port, target, source, dpf_routines::alt::NEW_DPF, routine::START);
-        }
-
-        Ok(result)
-    })
+    log::info!("Registering new DPF");
+    run_dpf_routine(&state, &db, target_address, vehicle_id, DpfRoutine::NewDpf, routine::START)
 }
 
 /// Start forced DPF regeneration
@@ -612,49 +762,42 @@ pub fn bmw_dpf_new_installed(
 #[tauri::command]
 pub fn bmw_dpf_start_regen(
     state: State<SerialState>,
+    db: State<DbState>,
     target_address: Option<u8>,
+    vehicle_id: Option<i64>,
 ) -> Result<DpfRoutineResult, String> {
-    let target = target_address.unwrap_or(addresses::DME_DDE);
-    let source = addresses::TESTER;
-
-    log::warn!("Starting forced DPF regeneration on ECU 0x{:02X}", target);
+    log::warn!("Starting forced DPF regeneration");
     log::warn!("WARNING: Ensure vehicle is stationary and engine is running!");
-
-    state.with_port(|port| {
-        let result = execute_dpf_routine(port, target, source, dpf_routines::START_FORCED_REGEN, routine::START)?;
-
-        if !result.success {
-            log::info!("Primary routine failed, trying alternative ID");
-            return execute_dpf_routine(port, target, source, dpf_routines::alt::FORCED_REGEN, routine::START);
-        }
-
-        Ok(result)
-    })
+    run_dpf_routine(&state, &db, target_address, vehicle_id, DpfRoutine::StartRegen, routine::START)
 }
 
 /// Stop forced DPF regeneration
 #[tauri::command]
 pub fn bmw_dpf_stop_regen(
     state: State<SerialState>,
+    db: State<DbState>,
     target_address: Option<u8>,
+    vehicle_id: Option<i64>,
 ) -> Result<DpfRoutineResult, String> {
-    let target = target_address.unwrap_or(addresses::DME_DDE);
-    let source = addresses::TESTER;
-
-    log::info!("Stopping forced DPF regeneration on ECU 0x{:02X}", target);
-
-    state.with_port(|port| {
-        execute_dpf_routine(port, target, source, dpf_routines::STOP_FORCED_REGEN, routine::STOP)
-    })
+    log::info!("Stopping forced DPF regeneration");
+    run_dpf_routine(&state, &db, target_address, vehicle_id, DpfRoutine::StopRegen, routine::STOP)
 }
 
 /// Read DPF status information
 #[tauri::command]
 pub fn bmw_dpf_read_status(
     state: State<SerialState>,
+    db: State<DbState>,
     target_address: Option<u8>,
+    vehicle_id: Option<i64>,
 ) -> Result<DpfStatus, String> {
-    let target = target_address.unwrap_or(addresses::DME_DDE);
+    let profile = profiles::for_vehicle(&db, vehicle_id)?.profile;
+    if profile.def.dpf.is_none() {
+        return Err(format!("{} has no particulate filter", profile.def.name));
+    }
+    let target = target_address
+        .or_else(|| profile.engine_address())
+        .unwrap_or(addresses::DME_DDE);
     let source = addresses::TESTER;
 
     log::info!("Reading DPF status from ECU 0x{:02X}", target);
@@ -778,39 +921,16 @@ pub fn bmw_routine_control(
             request.extend_from_slice(&extra_data);
         }
 
-        match KLineHandler::send_request(port, target, source, &request) {
-            Ok(response) => {
-                if response.first() == Some(&0x71) {
-                    Ok(DpfRoutineResult {
-                        success: true,
-                        routine_id,
-                        status: "OK".to_string(),
-                        data: response[3..].to_vec(),
-                    })
-                } else if response.first() == Some(&0x7F) {
-                    let nrc = response.get(2).copied().unwrap_or(0);
-                    Ok(DpfRoutineResult {
-                        success: false,
-                        routine_id,
-                        status: format!("{} (0x{:02X})", bmw::nrc::description(nrc), nrc),
-                        data: vec![],
-                    })
-                } else {
-                    Ok(DpfRoutineResult {
-                        success: false,
-                        routine_id,
-                        status: format!("Unexpected: {:02X?}", response),
-                        data: vec![],
-                    })
-                }
-            }
-            Err(e) => Ok(DpfRoutineResult {
-                success: false,
-                routine_id,
-                status: format!("Failed: {}", e),
-                data: vec![],
-            }),
-        }
+        let result = match KLineHandler::send_request(port, target, source, &request) {
+            Ok(response) => RoutineStatus::from_response(&response),
+            Err(e) => RoutineStatus::failed(&e),
+        };
+        Ok(DpfRoutineResult {
+            success: result.success,
+            routine_id,
+            status: result.status,
+            data: result.data,
+        })
     })
 }
 
@@ -841,7 +961,7 @@ pub struct DscSensorStatus {
 /// Read DTCs from DSC module
 #[tauri::command]
 pub fn bmw_dsc_read_dtcs(state: State<SerialState>) -> Result<DtcReadResult, String> {
-    bmw_read_dtcs_kline(state, Some(addresses::DSC))
+    bmw_read_dtcs_kline(state, Some(addresses::DSC), None)
 }
 
 /// Read wheel speed sensors from DSC
@@ -1013,7 +1133,7 @@ pub struct VehicleInfo {
 /// Read DTCs from instrument cluster
 #[tauri::command]
 pub fn bmw_kombi_read_dtcs(state: State<SerialState>) -> Result<DtcReadResult, String> {
-    bmw_read_dtcs_kline(state, Some(addresses::KOMBI))
+    bmw_read_dtcs_kline(state, Some(addresses::KOMBI), None)
 }
 
 /// Read service intervals from KOMBI
@@ -1218,7 +1338,7 @@ pub struct LampStatus {
 /// Read DTCs from FRM
 #[tauri::command]
 pub fn bmw_frm_read_dtcs(state: State<SerialState>) -> Result<DtcReadResult, String> {
-    bmw_read_dtcs_kline(state, Some(addresses::FRM))
+    bmw_read_dtcs_kline(state, Some(addresses::FRM), None)
 }
 
 /// Read lamp failure status from FRM
@@ -1332,7 +1452,7 @@ pub struct EgsStatus {
 /// Read DTCs from EGS
 #[tauri::command]
 pub fn bmw_egs_read_dtcs(state: State<SerialState>) -> Result<DtcReadResult, String> {
-    bmw_read_dtcs_kline(state, Some(addresses::EGS))
+    bmw_read_dtcs_kline(state, Some(addresses::EGS), None)
 }
 
 /// Read EGS transmission status
@@ -1438,13 +1558,16 @@ pub fn bmw_egs_reset_adaptations(state: State<SerialState>) -> Result<DpfRoutine
 // D-CAN Specific Commands (for ECUs that prefer/require CAN)
 // ============================================================================
 
-use crate::dcan::{can_ids, detect_ecu_protocol};
+use crate::constants::uds;
+use crate::dcan::{can_ids, detect_ecu_protocol, detect_protocol};
+use crate::dtc_info::{DtcCount, DtcExtendedData, DtcSnapshot, SnapshotIdentification};
 
 /// Read DTCs via D-CAN
 #[tauri::command]
 pub fn bmw_read_dtcs_dcan(
     state: State<SerialState>,
     ecu_name: String,
+    ecu_variant: Option<String>,
 ) -> Result<DtcReadResult, String> {
     let (tx_id, rx_id) = can_ids::for_ecu(&ecu_name)
         .ok_or_else(|| format!("Unknown ECU for D-CAN: {}", ecu_name))?;
@@ -1469,6 +1592,7 @@ pub fn bmw_read_dtcs_dcan(
             }),
         }
     })
+    .map(|result| with_fault_texts(result, &[ecu_name.as_str()], ecu_variant.as_deref()))
 }
 
 /// Auto-detect protocol and read DTCs
@@ -1477,6 +1601,7 @@ pub fn bmw_read_dtcs_auto(
     state: State<SerialState>,
     ecu_name: String,
     kline_address: Option<u8>,
+    ecu_variant: Option<String>,
 ) -> Result<DtcReadResult, String> {
     let mut manager = state.lock_manager()?;
     let port = manager
@@ -1486,7 +1611,7 @@ pub fn bmw_read_dtcs_auto(
     // Detect protocol
     let protocol = detect_ecu_protocol(port, &ecu_name)?;
 
-    match protocol.as_str() {
+    let result = match protocol.as_str() {
         "D-CAN" => {
             let (tx_id, rx_id) = can_ids::for_ecu(&ecu_name)
                 .ok_or_else(|| format!("Unknown ECU: {}", ecu_name))?;
@@ -1521,28 +1646,12 @@ pub fn bmw_read_dtcs_auto(
             });
             let source = addresses::TESTER;
 
-            // UDS ReadDTCInformation
-            let request = vec![0x19, 0x02, 0xFF];
-            match KLineHandler::send_request(port, target, source, &request) {
-                Ok(response) if response.first() == Some(&0x59) => {
-                    let dtcs = parse_uds_dtc_response(&response);
-                    Ok(DtcReadResult {
-                        success: true,
-                        count: dtcs.len(),
-                        dtcs,
-                        message: format!("DTCs read from {} via K-Line", ecu_name),
-                    })
-                }
-                _ => Ok(DtcReadResult {
-                    success: false,
-                    count: 0,
-                    dtcs: vec![],
-                    message: "Failed to read DTCs via K-Line".to_string(),
-                }),
-            }
+            Ok(read_dtcs_kline(port, target, source))
         }
         _ => Err(format!("Unknown protocol: {}", protocol)),
-    }
+    }?;
+
+    Ok(with_fault_texts(result, &[ecu_name.as_str()], ecu_variant.as_deref()))
 }
 
 /// Detect ECU protocol (K-Line or D-CAN)
@@ -1570,6 +1679,109 @@ pub fn bmw_read_did_dcan(
     })
 }
 
+/// Count DTCs matching a status mask via D-CAN (0x19 0x01)
+#[tauri::command]
+pub fn bmw_dtc_count_dcan(
+    state: State<SerialState>,
+    ecu_name: String,
+    status_mask: Option<u8>,
+) -> Result<DtcCount, String> {
+    let (tx_id, rx_id) = can_ids::for_ecu(&ecu_name)
+        .ok_or_else(|| format!("Unknown ECU for D-CAN: {}", ecu_name))?;
+    let status_mask = status_mask.unwrap_or(uds::dtc::STATUS_MASK_ALL);
+
+    state.with_port(|port| {
+        DCanHandler::switch_to_dcan_mode(port)?;
+        DCanHandler::read_dtc_count(port, tx_id, rx_id, status_mask)
+    })
+}
+
+/// List DTCs with stored snapshots via D-CAN (0x19 0x03)
+#[tauri::command]
+pub fn bmw_dtc_snapshot_ids_dcan(
+    state: State<SerialState>,
+    ecu_name: String,
+) -> Result<Vec<SnapshotIdentification>, String> {
+    let (tx_id, rx_id) = can_ids::for_ecu(&ecu_name)
+        .ok_or_else(|| format!("Unknown ECU for D-CAN: {}", ecu_name))?;
+
+    state.with_port(|port| {
+        DCanHandler::switch_to_dcan_mode(port)?;
+        DCanHandler::read_snapshot_identification(port, tx_id, rx_id)
+    })
+}
+
+/// Read the snapshot records of a DTC via D-CAN (0x19 0x04)
+///
+/// `dtc` is the 3-byte DTC; all records are read unless `record_number` is given.
+#[tauri::command]
+pub fn bmw_dtc_snapshot_dcan(
+    state: State<SerialState>,
+    ecu_name: String,
+    dtc: u32,
+    record_number: Option<u8>,
+) -> Result<DtcSnapshot, String> {
+    let (tx_id, rx_id) = can_ids::for_ecu(&ecu_name)
+        .ok_or_else(|| format!("Unknown ECU for D-CAN: {}", ecu_name))?;
+    let record_number = record_number.unwrap_or(dtc_info::ALL_RECORDS);
+
+    state.with_port(|port| {
+        DCanHandler::switch_to_dcan_mode(port)?;
+        DCanHandler::read_snapshot_records(port, tx_id, rx_id, dtc, record_number)
+    })
+}
+
+/// Read the extended data of a DTC via D-CAN (0x19 0x06)
+///
+/// Occurrence and aging counters plus first/last occurrence mileage.
+#[tauri::command]
+pub fn bmw_dtc_extended_data_dcan(
+    state: State<SerialState>,
+    ecu_name: String,
+    dtc: u32,
+    record_number: Option<u8>,
+) -> Result<DtcExtendedData, String> {
+    let (tx_id, rx_id) = can_ids::for_ecu(&ecu_name)
+        .ok_or_else(|| format!("Unknown ECU for D-CAN: {}", ecu_name))?;
+    let record_number = record_number.unwrap_or(dtc_info::ALL_RECORDS);
+
+    state.with_port(|port| {
+        DCanHandler::switch_to_dcan_mode(port)?;
+        DCanHandler::read_extended_data(port, tx_id, rx_id, dtc, record_number)
+    })
+}
+
+/// Read every DTC the ECU supports via D-CAN (0x19 0x0A)
+#[tauri::command]
+pub fn bmw_dtc_supported_dcan(
+    state: State<SerialState>,
+    ecu_name: String,
+    ecu_variant: Option<String>,
+) -> Result<DtcReadResult, String> {
+    let (tx_id, rx_id) = can_ids::for_ecu(&ecu_name)
+        .ok_or_else(|| format!("Unknown ECU for D-CAN: {}", ecu_name))?;
+
+    state.with_port(|port| {
+        DCanHandler::switch_to_dcan_mode(port)?;
+
+        match DCanHandler::read_supported_dtcs(port, tx_id, rx_id) {
+            Ok(dtcs) => Ok(DtcReadResult {
+                success: true,
+                count: dtcs.len(),
+                dtcs,
+                message: format!("Supported DTCs read from {} via D-CAN", ecu_name),
+            }),
+            Err(e) => Ok(DtcReadResult {
+                success: false,
+                count: 0,
+                dtcs: vec![],
+                message: e,
+            }),
+        }
+    })
+    .map(|result| with_fault_texts(result, &[ecu_name.as_str()], ecu_variant.as_deref()))
+}
+
 /// Start session via D-CAN
 #[tauri::command]
 pub fn bmw_start_session_dcan(
@@ -1631,9 +1843,15 @@ pub fn bmw_routine_control_dcan(
 }
 
 /// Auto-detect and read DTCs from all known ECUs
+///
+/// With a `vehicle_id` only the ECUs of the vehicle's profile are read.
 #[tauri::command]
-pub fn bmw_read_all_dtcs(state: State<SerialState>) -> Result<Vec<(String, DtcReadResult)>, String> {
-    let ecus = bmw::e60_ecus();
+pub fn bmw_read_all_dtcs(
+    state: State<SerialState>,
+    db: State<DbState>,
+    vehicle_id: Option<i64>,
+) -> Result<Vec<(String, DtcReadResult)>, String> {
+    let ecus = bmw_get_ecus(db, vehicle_id)?;
     let mut all_results = Vec::new();
     let source = addresses::TESTER;
 
@@ -1649,27 +1867,7 @@ pub fn bmw_read_all_dtcs(state: State<SerialState>) -> Result<Vec<(String, DtcRe
 
                 // Try to init communication with this ECU first
                 match KLineHandler::init_fast(port, target, source) {
-                    Ok(_) => {
-                        // Read DTCs
-                        let request = vec![0x19, 0x02, 0xFF];
-                        match KLineHandler::send_request(port, target, source, &request) {
-                            Ok(response) if response.first() == Some(&0x59) => {
-                                let dtcs = parse_uds_dtc_response(&response);
-                                DtcReadResult {
-                                    success: true,
-                                    count: dtcs.len(),
-                                    dtcs,
-                                    message: "OK".to_string(),
-                                }
-                            }
-                            _ => DtcReadResult {
-                                success: false,
-                                count: 0,
-                                dtcs: vec![],
-                                message: "No response".to_string(),
-                            },
-                        }
-                    }
+                    Ok(_) => read_dtcs_kline(port, target, source),
                     Err(_) => DtcReadResult {
                         success: false,
                         count: 0,
@@ -1679,6 +1877,7 @@ pub fn bmw_read_all_dtcs(state: State<SerialState>) -> Result<Vec<(String, DtcRe
                 }
             };
 
+            let result = with_fault_texts(result, &[ecu.id.as_str()], None);
             all_results.push((ecu.id.clone(), result));
 
             // Delay between ECUs
@@ -1688,3 +1887,164 @@ pub fn bmw_read_all_dtcs(state: State<SerialState>) -> Result<Vec<(String, DtcRe
 
     Ok(all_results)
 }
+
+/// Verified clear result for one ECU
+#[derive(Debug, Clone, Serialize)]
+pub struct EcuClearResult {
+    /// ECU ID; ECUs sharing an address are cleared once, e.g. "DME/DDE"
+    pub ecu_id: String,
+    pub protocol: Option<String>,
+    pub success: bool,
+    pub summary: Option<ClearSummary>,
+    pub message: String,
+}
+
+/// Clear a DTC group and re-read to report what came back
+///
+/// Runs on `ecu_names` (default: every known ECU) over D-CAN or K-Line,
+/// whichever the ECU answers on. ECUs sharing their addresses (DME/DDE)
+/// are cleared once, reported as e.g. "DME/DDE". With a `session_id` each
+/// clear is stored in the session history.
+#[tauri::command]
+pub fn bmw_clear_dtcs_verified(
+    state: State<SerialState>,
+    db: State<DbState>,
+    group: DtcGroup,
+    ecu_names: Option<Vec<String>>,
+    session_id: Option<i64>,
+) -> Result<Vec<EcuClearResult>, String> {
+    let targets = clear_targets(ecu_names.as_deref());
+    if targets.is_empty() {
+        return Err("No matching ECU".to_string());
+    }
+
+    let mut results = Vec::new();
+    for (ecu_id, ecus) in targets {
+        let outcome = {
+            let mut manager = state.lock_manager()?;
+            let port = manager
+                .get_port_mut()
+                .ok_or_else(|| "Not connected".to_string())?;
+            clear_ecu_verified(port, &ecus[0], group)
+        };
+
+        let result = match outcome {
+            Ok((protocol, summary)) => EcuClearResult {
+                ecu_id: ecu_id.clone(),
+                message: format!(
+                    "{} cleared, {} returned, {} pending",
+                    summary.cleared.len(),
+                    summary.returned.len(),
+                    summary.pending.len()
+                ),
+                protocol: Some(protocol),
+                success: true,
+                summary: Some(summary),
+            },
+            Err(e) => EcuClearResult {
+                ecu_id: ecu_id.clone(),
+                protocol: None,
+                success: false,
+                summary: None,
+                message: e,
+            },
+        };
+
+        if let (Some(session_id), Some(summary)) = (session_id, &result.summary) {
+            record_clear_event(&db, session_id, &ecu_id, summary);
+        }
+        results.push(result);
+
+        // Delay between ECUs
+        std::thread::sleep(std::time::Duration::from_millis(200));
+    }
+
+    Ok(results)
+}
+
+/// ECUs to clear, one entry per physical ECU: (group ID, its ECUs)
+fn clear_targets(ecu_names: Option<&[String]>) -> Vec<(String, Vec<EcuInfo>)> {
+    bmw::ecu_groups(bmw::e60_ecus(), ecu_names)
+        .into_iter()
+        .map(|group| (bmw::group_id(&group), group))
+        .collect()
+}
+
+/// Clear and verify one ECU, returns the protocol used
+fn clear_ecu_verified(
+    port: &mut Box<dyn serialport::SerialPort>,
+    ecu: &EcuInfo,
+    group: DtcGroup,
+) -> Result<(String, ClearSummary), String> {
+    let protocol = detect_protocol(port, ecu)?;
+
+    let summary = match (protocol.as_str(), ecu.can_ids(), ecu.kline_address) {
+        ("D-CAN", Some((tx_id, rx_id)), _) => {
+            let mut transport = DCanDtcTransport { port, tx_id, rx_id };
+            dtc_service::clear_and_verify(&mut transport, DtcDialect::for_transport(false), group)
+        }
+        ("K-Line", _, Some(target)) => {
+            let source = addresses::TESTER;
+            let mut transport = KLineDtcTransport { port, target, source };
+            dtc_service::clear_and_verify(&mut transport, DtcDialect::for_transport(true), group)
+        }
+        _ => Err(format!("No {} address for {}", protocol, ecu.id)),
+    }?;
+
+    Ok((protocol, summary))
+}
+
+/// Store a verified clear in the session history
+fn record_clear_event(db: &DbState, session_id: i64, ecu_id: &str, summary: &ClearSummary) {
+    let codes = |records: &[DtcRecord]| -> Vec<String> {
+        records.iter().map(|r| Dtc::from_record(r).bmw_code).collect()
+    };
+    let dtc_group = match summary.group {
+        DtcGroup::Code(code) => format!("code {:X}", code),
+        group => format!("{:?}", group).to_lowercase(),
+    };
+    let event = NewDtcClearEvent {
+        session_id,
+        ecu_id: ecu_id.to_string(),
+        dtc_group,
+        cleared: codes(&summary.cleared),
+        returned: codes(&summary.returned),
+        pending: codes(&summary.pending),
+    };
+
+    let stored = db
+        .0
+        .lock()
+        .map_err(|e| e.to_string())
+        .and_then(|guard| match guard.as_ref() {
+            Some(db) => db.add_dtc_clear_event(&event).map_err(|e| e.to_string()),
+            None => Err("Database not initialized".to_string()),
+        });
+    if let Err(e) = stored {
+        log::warn!("Failed to store DTC clear event: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clear_targets_share_addresses() {
+        let names: Vec<String> = ["DME", "dde", "IHKA", "PDC"].map(String::from).to_vec();
+        let targets = clear_targets(Some(&names));
+        let ids: Vec<&str> = targets.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(ids, ["DME/DDE", "IHKA", "PDC"]);
+
+        // Cleared through the addresses of the ECU definitions
+        let ihka = &targets[1].1[0];
+        assert_eq!((ihka.kline_address, ihka.can_ids()), (Some(0x5B), None));
+        let pdc = &targets[2].1[0];
+        assert_eq!((pdc.kline_address, pdc.can_ids()), (None, Some((0x6F1, 0x672))));
+
+        // Every ECU once, the engine ECU a single target
+        let all = clear_targets(None);
+        assert_eq!(all.len(), bmw::e60_ecus().len() - 1);
+        assert_eq!(all.iter().filter(|(id, _)| id.contains("DDE")).count(), 1);
+    }
+}
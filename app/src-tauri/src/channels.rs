@@ -0,0 +1,36 @@
+//! Live Data Channel Definitions
+//!
+//! The channel tables of `bmw_diag_core::channels` as used by the app: one
+//! process-wide database with the bundled tables, plus the JSON files from
+//! the app data dir loaded at startup.
+
+use bmw_diag_core::channels::{Channel, ChannelDb};
+use std::sync::{OnceLock, RwLock, RwLockReadGuard};
+
+pub use bmw_diag_core::channels::{DDE, OBD, SERVICE_DATA_ID, SERVICE_MODE_01};
+
+/// Process-wide database, initialized with the bundled tables
+pub fn global() -> &'static RwLock<ChannelDb> {
+    static DB: OnceLock<RwLock<ChannelDb>> = OnceLock::new();
+    DB.get_or_init(|| RwLock::new(ChannelDb::bundled()))
+}
+
+/// Read access to the process-wide database
+pub fn read_global() -> RwLockReadGuard<'static, ChannelDb> {
+    global().read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Decode with the global database: (value, unit, English name)
+pub fn decode(ecu: &str, service: u8, id: u16, data: &[u8]) -> Option<(f64, String, String)> {
+    let db = read_global();
+    let channel = db.get(ecu, service, id)?;
+    let value = channel.decode(data)?;
+    Some((value, channel.def.unit.clone(), channel.label()))
+}
+
+/// Data bytes of a channel in the global database
+pub fn data_length(ecu: &str, service: u8, id: u16) -> Option<usize> {
+    read_global()
+        .get(ecu, service, id)
+        .map(Channel::data_length)
+}
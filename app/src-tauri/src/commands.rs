@@ -1,3 +1,4 @@
+use crate::live_stream::LiveStreamState;
 use crate::serial::{ConnectionState, PortInfo, SerialManager, SerialState};
 use serde::{Deserialize, Serialize};
 use tauri::State;
@@ -71,9 +72,17 @@ pub fn serial_connect(
 
 /// Disconnect from the current port
 #[tauri::command]
-pub fn serial_disconnect(state: State<SerialState>) -> Result<ConnectionStatus, String> {
+pub fn serial_disconnect(
+    state: State<SerialState>,
+    stream: State<LiveStreamState>,
+) -> Result<ConnectionStatus, String> {
     log::info!("Disconnecting...");
 
+    // Let a live stream end its periodic transmission while the port is open
+    if stream.stop()? {
+        log::info!("Stopped live stream before disconnecting");
+    }
+
     let mut manager = state
         .0
         .lock()
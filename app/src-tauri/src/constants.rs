@@ -101,8 +101,11 @@ pub mod uds {
 
     // DTC sub-functions
     pub mod dtc {
+        pub const REPORT_NUMBER_BY_STATUS_MASK: u8 = 0x01;
         pub const REPORT_BY_STATUS_MASK: u8 = 0x02;
+        pub const REPORT_SNAPSHOT_IDENTIFICATION: u8 = 0x03;
         pub const REPORT_SNAPSHOT_BY_DTC: u8 = 0x04;
+        pub const REPORT_EXTENDED_DATA_BY_DTC: u8 = 0x06;
         pub const REPORT_SUPPORTED: u8 = 0x0A;
         pub const STATUS_MASK_ALL: u8 = 0xFF;
     }
@@ -227,6 +230,9 @@ pub mod limits {
     /// Maximum DIDs per single request
     pub const MAX_DIDS_PER_REQUEST: usize = 16;
 
+    /// Maximum DIDs packed into one dynamically defined identifier
+    pub const MAX_DIDS_PER_DYNAMIC_ID: usize = 64;
+
     /// Maximum routine data size in bytes
     pub const MAX_ROUTINE_DATA_SIZE: usize = 256;
 
@@ -235,6 +241,9 @@ pub mod limits {
 
     /// Maximum DTCs to read at once
     pub const MAX_DTCS_PER_READ: usize = 100;
+
+    /// Maximum rows of a resampled live data export
+    pub const MAX_EXPORT_ROWS: usize = 1_000_000;
 }
 
 // ============================================================================
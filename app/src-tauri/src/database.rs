@@ -2,12 +2,16 @@
 //!
 //! Provides SQLite-based storage for vehicles, diagnostic sessions, DTCs, and settings.
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, Utc};
 use rusqlite::{params, Connection, Result as SqlResult};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use std::sync::Mutex;
 
+/// Most buckets per channel `get_live_data_buckets` returns
+pub const MAX_LIVE_DATA_BUCKETS: u32 = 10_000;
+
 /// Database connection wrapper
 pub struct Database {
     conn: Mutex<Connection>,
@@ -54,6 +58,10 @@ pub struct DiagnosticSession {
     pub protocol: String,
     pub mileage_km: Option<i32>,
     pub notes: Option<String>,
+    /// OBD Mode 09 vehicle information read during the session
+    pub vehicle_info: Option<serde_json::Value>,
+    /// Quick test this session was recorded by
+    pub vehicle_test_id: Option<i64>,
     pub created_at: DateTime<Utc>,
 }
 
@@ -78,6 +86,8 @@ pub struct StoredDtc {
     pub description: Option<String>,
     pub is_pending: bool,
     pub is_confirmed: bool,
+    /// Environmental conditions recorded by the ECU (KWP ReadStatusOfDTC)
+    pub environment: Option<serde_json::Value>,
     pub created_at: DateTime<Utc>,
 }
 
@@ -90,6 +100,95 @@ pub struct NewDtc {
     pub description: Option<String>,
     pub is_pending: bool,
     pub is_confirmed: bool,
+    #[serde(default)]
+    pub environment: Option<serde_json::Value>,
+}
+
+/// DTC clear event with the re-read outcome
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DtcClearEvent {
+    pub id: i64,
+    pub session_id: i64,
+    pub ecu_id: String,
+    pub dtc_group: String,
+    pub cleared: Vec<String>,
+    pub returned: Vec<String>,
+    pub pending: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// New DTC clear event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewDtcClearEvent {
+    pub session_id: i64,
+    pub ecu_id: String,
+    pub dtc_group: String,
+    pub cleared: Vec<String>,
+    pub returned: Vec<String>,
+    pub pending: Vec<String>,
+}
+
+/// Quick test of all ECUs, grouping one session per responding ECU
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VehicleTest {
+    pub id: i64,
+    pub vehicle_id: i64,
+    pub mileage_km: Option<i32>,
+    pub ecus_ok: i32,
+    pub ecus_faulty: i32,
+    pub ecus_not_responding: i32,
+    /// Full per-ECU report as produced by the test
+    pub report: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// New quick test record
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewVehicleTest {
+    pub vehicle_id: i64,
+    pub mileage_km: Option<i32>,
+    pub ecus_ok: i32,
+    pub ecus_faulty: i32,
+    pub ecus_not_responding: i32,
+    pub report: serde_json::Value,
+}
+
+/// How a fault changed between two sessions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DtcChange {
+    /// First time the fault was ever seen
+    New,
+    /// Absent from the older session but seen in earlier ones
+    Intermittent,
+    /// Present in both sessions
+    Persistent,
+    /// Present in the older session only
+    Resolved,
+}
+
+/// One fault in a session comparison
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DtcDiffEntry {
+    pub ecu_id: String,
+    pub code: String,
+    pub description: Option<String>,
+    pub change: DtcChange,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub first_seen_mileage_km: Option<i32>,
+    pub last_seen_mileage_km: Option<i32>,
+    /// Sessions up to the newer one that reported the fault
+    pub occurrences: u32,
+}
+
+/// Fault comparison between two sessions of a vehicle
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionDtcDiff {
+    pub vehicle_id: i64,
+    pub from_session_id: i64,
+    pub to_session_id: i64,
+    pub entries: Vec<DtcDiffEntry>,
 }
 
 /// Live data snapshot
@@ -97,12 +196,126 @@ pub struct NewDtc {
 pub struct LiveDataSnapshot {
     pub id: i64,
     pub session_id: i64,
+    pub recording_id: Option<i64>,
+    /// ECU the sample was read from
+    pub ecu: Option<String>,
+    pub parameter_name: String,
+    pub value: f64,
+    pub unit: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// New live data snapshot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewLiveDataSnapshot {
+    pub session_id: i64,
+    pub recording_id: Option<i64>,
+    /// ECU the sample was read from
+    pub ecu: Option<String>,
     pub parameter_name: String,
     pub value: f64,
     pub unit: String,
     pub timestamp: DateTime<Utc>,
 }
 
+/// Live data recording, e.g. one test drive within a session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveDataRecording {
+    pub id: i64,
+    pub session_id: i64,
+    pub started_at: DateTime<Utc>,
+    /// `None` while still recording
+    pub stopped_at: Option<DateTime<Utc>>,
+    pub sample_count: i64,
+}
+
+/// Event marked in a live data recording, e.g. an alarm
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveDataMarker {
+    pub id: i64,
+    pub session_id: i64,
+    pub recording_id: Option<i64>,
+    /// "alarm_raised" or "alarm_cleared"
+    pub kind: String,
+    pub label: String,
+    pub severity: Option<String>,
+    /// Channel the marker refers to
+    pub parameter_name: Option<String>,
+    pub value: Option<f64>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// New live data marker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewLiveDataMarker {
+    pub session_id: i64,
+    pub recording_id: Option<i64>,
+    pub kind: String,
+    pub label: String,
+    pub severity: Option<String>,
+    pub parameter_name: Option<String>,
+    pub value: Option<f64>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Filter for `get_live_data`; bounds are inclusive
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LiveDataQuery {
+    pub session_id: i64,
+    pub recording_id: Option<i64>,
+    pub parameter_name: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+/// Filter and resolution for `get_live_data_buckets`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LiveDataBucketQuery {
+    pub session_id: i64,
+    pub recording_id: Option<i64>,
+    /// Channels to aggregate; all when empty
+    #[serde(default)]
+    pub parameter_names: Vec<String>,
+    /// Inclusive range; defaults to the first and last matching sample
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// Number of buckets the range is split into, e.g. the chart width in
+    /// pixels; at most `MAX_LIVE_DATA_BUCKETS`
+    pub buckets: u32,
+}
+
+/// Samples of one channel within one time bucket
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveDataBucket {
+    /// Position of the bucket in the range
+    pub index: u32,
+    pub start: DateTime<Utc>,
+    pub count: i64,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    /// Value of the latest sample in the bucket
+    pub last: f64,
+}
+
+/// Buckets of one channel; buckets without samples are left out
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveDataSeries {
+    pub parameter_name: String,
+    pub unit: String,
+    pub buckets: Vec<LiveDataBucket>,
+}
+
+/// Result of `get_live_data_buckets`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveDataBuckets {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// Length of one bucket in milliseconds
+    pub bucket_ms: f64,
+    pub series: Vec<LiveDataSeries>,
+}
+
 /// Application settings
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Setting {
@@ -182,6 +395,32 @@ impl Database {
                 FOREIGN KEY (session_id) REFERENCES diagnostic_sessions(id) ON DELETE CASCADE
             );
 
+            -- DTC clear events table
+            CREATE TABLE IF NOT EXISTS dtc_clear_events (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                session_id INTEGER NOT NULL,
+                ecu_id TEXT NOT NULL,
+                dtc_group TEXT NOT NULL,
+                cleared TEXT NOT NULL,
+                returned TEXT NOT NULL,
+                pending TEXT NOT NULL,
+                created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                FOREIGN KEY (session_id) REFERENCES diagnostic_sessions(id) ON DELETE CASCADE
+            );
+
+            -- Vehicle quick tests table
+            CREATE TABLE IF NOT EXISTS vehicle_tests (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                vehicle_id INTEGER NOT NULL,
+                mileage_km INTEGER,
+                ecus_ok INTEGER NOT NULL,
+                ecus_faulty INTEGER NOT NULL,
+                ecus_not_responding INTEGER NOT NULL,
+                report TEXT NOT NULL,
+                created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                FOREIGN KEY (vehicle_id) REFERENCES vehicles(id) ON DELETE CASCADE
+            );
+
             -- Live data snapshots table
             CREATE TABLE IF NOT EXISTS live_data_snapshots (
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -193,6 +432,30 @@ impl Database {
                 FOREIGN KEY (session_id) REFERENCES diagnostic_sessions(id) ON DELETE CASCADE
             );
 
+            -- Live data recordings table
+            CREATE TABLE IF NOT EXISTS live_data_recordings (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                session_id INTEGER NOT NULL,
+                started_at TEXT NOT NULL,
+                stopped_at TEXT,
+                FOREIGN KEY (session_id) REFERENCES diagnostic_sessions(id) ON DELETE CASCADE
+            );
+
+            -- Live data markers table (alarms raised during a recording)
+            CREATE TABLE IF NOT EXISTS live_data_markers (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                session_id INTEGER NOT NULL,
+                recording_id INTEGER,
+                kind TEXT NOT NULL,
+                label TEXT NOT NULL,
+                severity TEXT,
+                parameter_name TEXT,
+                value REAL,
+                timestamp TEXT NOT NULL,
+                FOREIGN KEY (session_id) REFERENCES diagnostic_sessions(id) ON DELETE CASCADE,
+                FOREIGN KEY (recording_id) REFERENCES live_data_recordings(id) ON DELETE CASCADE
+            );
+
             -- Settings table
             CREATE TABLE IF NOT EXISTS settings (
                 key TEXT PRIMARY KEY,
@@ -202,11 +465,37 @@ impl Database {
             -- Indexes for performance
             CREATE INDEX IF NOT EXISTS idx_sessions_vehicle ON diagnostic_sessions(vehicle_id);
             CREATE INDEX IF NOT EXISTS idx_dtcs_session ON dtcs(session_id);
+            CREATE INDEX IF NOT EXISTS idx_dtc_clear_events_session ON dtc_clear_events(session_id);
+            CREATE INDEX IF NOT EXISTS idx_vehicle_tests_vehicle ON vehicle_tests(vehicle_id);
             CREATE INDEX IF NOT EXISTS idx_live_data_session ON live_data_snapshots(session_id);
+            CREATE INDEX IF NOT EXISTS idx_live_data_recordings_session ON live_data_recordings(session_id);
+            CREATE INDEX IF NOT EXISTS idx_live_data_markers_session ON live_data_markers(session_id);
             CREATE INDEX IF NOT EXISTS idx_vehicles_vin ON vehicles(vin);
             "#,
         )?;
 
+        // Columns added after the first release
+        add_column_if_missing(&conn, "dtcs", "environment", "TEXT")?;
+        add_column_if_missing(&conn, "diagnostic_sessions", "vehicle_info", "TEXT")?;
+        add_column_if_missing(
+            &conn,
+            "diagnostic_sessions",
+            "vehicle_test_id",
+            "INTEGER REFERENCES vehicle_tests(id) ON DELETE SET NULL",
+        )?;
+        add_column_if_missing(
+            &conn,
+            "live_data_snapshots",
+            "recording_id",
+            "INTEGER REFERENCES live_data_recordings(id) ON DELETE CASCADE",
+        )?;
+        add_column_if_missing(&conn, "live_data_snapshots", "ecu", "TEXT")?;
+        conn.execute_batch(
+            "CREATE INDEX IF NOT EXISTS idx_live_data_recording ON live_data_snapshots(recording_id);
+             CREATE INDEX IF NOT EXISTS idx_live_data_channel
+                 ON live_data_snapshots(session_id, parameter_name, timestamp);",
+        )?;
+
         Ok(())
     }
 
@@ -365,11 +654,57 @@ impl Database {
         Ok(conn.last_insert_rowid())
     }
 
+    /// Store the OBD vehicle information read during a session
+    pub fn set_session_vehicle_info(
+        &self,
+        session_id: i64,
+        vehicle_info: &serde_json::Value,
+    ) -> SqlResult<()> {
+        let conn = self.conn.lock().unwrap();
+        let updated = conn.execute(
+            "UPDATE diagnostic_sessions SET vehicle_info = ?1 WHERE id = ?2",
+            params![vehicle_info.to_string(), session_id],
+        )?;
+        if updated == 0 {
+            return Err(rusqlite::Error::QueryReturnedNoRows);
+        }
+        Ok(())
+    }
+
+    /// Get a session by ID
+    pub fn get_session(&self, id: i64) -> SqlResult<Option<DiagnosticSession>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, vehicle_id, ecu_id, ecu_name, protocol, mileage_km, notes, created_at,
+                    vehicle_info, vehicle_test_id
+             FROM diagnostic_sessions WHERE id = ?1",
+        )?;
+
+        let mut rows = stmt.query(params![id])?;
+        if let Some(row) = rows.next()? {
+            Ok(Some(DiagnosticSession {
+                id: row.get(0)?,
+                vehicle_id: row.get(1)?,
+                ecu_id: row.get(2)?,
+                ecu_name: row.get(3)?,
+                protocol: row.get(4)?,
+                mileage_km: row.get(5)?,
+                notes: row.get(6)?,
+                vehicle_info: parse_json(row.get(8)?),
+                vehicle_test_id: row.get(9)?,
+                created_at: parse_datetime(row.get::<_, String>(7)?),
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
     /// Get sessions for a vehicle
     pub fn get_sessions_for_vehicle(&self, vehicle_id: i64) -> SqlResult<Vec<DiagnosticSession>> {
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn.prepare(
-            "SELECT id, vehicle_id, ecu_id, ecu_name, protocol, mileage_km, notes, created_at
+            "SELECT id, vehicle_id, ecu_id, ecu_name, protocol, mileage_km, notes, created_at,
+                    vehicle_info, vehicle_test_id
              FROM diagnostic_sessions WHERE vehicle_id = ?1 ORDER BY created_at DESC",
         )?;
 
@@ -383,6 +718,8 @@ impl Database {
                     protocol: row.get(4)?,
                     mileage_km: row.get(5)?,
                     notes: row.get(6)?,
+                    vehicle_info: parse_json(row.get(8)?),
+                    vehicle_test_id: row.get(9)?,
                     created_at: parse_datetime(row.get::<_, String>(7)?),
                 })
             })?
@@ -395,7 +732,8 @@ impl Database {
     pub fn get_recent_sessions(&self, limit: i32) -> SqlResult<Vec<DiagnosticSession>> {
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn.prepare(
-            "SELECT id, vehicle_id, ecu_id, ecu_name, protocol, mileage_km, notes, created_at
+            "SELECT id, vehicle_id, ecu_id, ecu_name, protocol, mileage_km, notes, created_at,
+                    vehicle_info, vehicle_test_id
              FROM diagnostic_sessions ORDER BY created_at DESC LIMIT ?1",
         )?;
 
@@ -409,6 +747,8 @@ impl Database {
                     protocol: row.get(4)?,
                     mileage_km: row.get(5)?,
                     notes: row.get(6)?,
+                    vehicle_info: parse_json(row.get(8)?),
+                    vehicle_test_id: row.get(9)?,
                     created_at: parse_datetime(row.get::<_, String>(7)?),
                 })
             })?
@@ -424,6 +764,130 @@ impl Database {
         Ok(rows > 0)
     }
 
+    // ========================================================================
+    // VEHICLE TEST OPERATIONS
+    // ========================================================================
+
+    /// Store a quick test with one session (and its DTCs) per ECU
+    ///
+    /// `session_id` of the DTCs is ignored; they are attached to the session
+    /// they are paired with. Returns the test ID and the new session IDs.
+    pub fn save_vehicle_test(
+        &self,
+        test: &NewVehicleTest,
+        sessions: &[(NewSession, Vec<NewDtc>)],
+    ) -> SqlResult<(i64, Vec<i64>)> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        tx.execute(
+            "INSERT INTO vehicle_tests (vehicle_id, mileage_km, ecus_ok, ecus_faulty, ecus_not_responding, report)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                test.vehicle_id,
+                test.mileage_km,
+                test.ecus_ok,
+                test.ecus_faulty,
+                test.ecus_not_responding,
+                test.report.to_string(),
+            ],
+        )?;
+        let test_id = tx.last_insert_rowid();
+
+        let mut session_ids = Vec::with_capacity(sessions.len());
+        for (session, dtcs) in sessions {
+            tx.execute(
+                "INSERT INTO diagnostic_sessions (vehicle_id, ecu_id, ecu_name, protocol, mileage_km, notes, vehicle_test_id)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    session.vehicle_id,
+                    session.ecu_id,
+                    session.ecu_name,
+                    session.protocol,
+                    session.mileage_km,
+                    session.notes,
+                    test_id,
+                ],
+            )?;
+            let session_id = tx.last_insert_rowid();
+
+            for dtc in dtcs {
+                tx.execute(
+                    "INSERT INTO dtcs (session_id, code, status, description, is_pending, is_confirmed, environment)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![
+                        session_id,
+                        dtc.code,
+                        dtc.status,
+                        dtc.description,
+                        dtc.is_pending,
+                        dtc.is_confirmed,
+                        dtc.environment.as_ref().map(|env| env.to_string()),
+                    ],
+                )?;
+            }
+            session_ids.push(session_id);
+        }
+
+        tx.commit()?;
+        Ok((test_id, session_ids))
+    }
+
+    /// Get quick tests for a vehicle, newest first
+    pub fn get_vehicle_tests(&self, vehicle_id: i64) -> SqlResult<Vec<VehicleTest>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, vehicle_id, mileage_km, ecus_ok, ecus_faulty, ecus_not_responding, report, created_at
+             FROM vehicle_tests WHERE vehicle_id = ?1 ORDER BY id DESC",
+        )?;
+
+        let tests = stmt
+            .query_map(params![vehicle_id], |row| {
+                Ok(VehicleTest {
+                    id: row.get(0)?,
+                    vehicle_id: row.get(1)?,
+                    mileage_km: row.get(2)?,
+                    ecus_ok: row.get(3)?,
+                    ecus_faulty: row.get(4)?,
+                    ecus_not_responding: row.get(5)?,
+                    report: parse_json(row.get(6)?),
+                    created_at: parse_datetime(row.get::<_, String>(7)?),
+                })
+            })?
+            .collect::<SqlResult<Vec<_>>>()?;
+
+        Ok(tests)
+    }
+
+    /// Get the sessions recorded by a quick test
+    pub fn get_sessions_for_vehicle_test(&self, test_id: i64) -> SqlResult<Vec<DiagnosticSession>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, vehicle_id, ecu_id, ecu_name, protocol, mileage_km, notes, created_at,
+                    vehicle_info, vehicle_test_id
+             FROM diagnostic_sessions WHERE vehicle_test_id = ?1 ORDER BY id",
+        )?;
+
+        let sessions = stmt
+            .query_map(params![test_id], |row| {
+                Ok(DiagnosticSession {
+                    id: row.get(0)?,
+                    vehicle_id: row.get(1)?,
+                    ecu_id: row.get(2)?,
+                    ecu_name: row.get(3)?,
+                    protocol: row.get(4)?,
+                    mileage_km: row.get(5)?,
+                    notes: row.get(6)?,
+                    vehicle_info: parse_json(row.get(8)?),
+                    vehicle_test_id: row.get(9)?,
+                    created_at: parse_datetime(row.get::<_, String>(7)?),
+                })
+            })?
+            .collect::<SqlResult<Vec<_>>>()?;
+
+        Ok(sessions)
+    }
+
     // ========================================================================
     // DTC OPERATIONS
     // ========================================================================
@@ -432,8 +896,8 @@ impl Database {
     pub fn add_dtcs(&self, dtcs: &[NewDtc]) -> SqlResult<()> {
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn.prepare(
-            "INSERT INTO dtcs (session_id, code, status, description, is_pending, is_confirmed)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            "INSERT INTO dtcs (session_id, code, status, description, is_pending, is_confirmed, environment)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
         )?;
 
         for dtc in dtcs {
@@ -444,6 +908,7 @@ impl Database {
                 dtc.description,
                 dtc.is_pending,
                 dtc.is_confirmed,
+                dtc.environment.as_ref().map(|env| env.to_string()),
             ])?;
         }
 
@@ -454,7 +919,7 @@ impl Database {
     pub fn get_dtcs_for_session(&self, session_id: i64) -> SqlResult<Vec<StoredDtc>> {
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn.prepare(
-            "SELECT id, session_id, code, status, description, is_pending, is_confirmed, created_at
+            "SELECT id, session_id, code, status, description, is_pending, is_confirmed, created_at, environment
              FROM dtcs WHERE session_id = ?1 ORDER BY code",
         )?;
 
@@ -468,6 +933,7 @@ impl Database {
                     description: row.get(4)?,
                     is_pending: row.get(5)?,
                     is_confirmed: row.get(6)?,
+                    environment: parse_json(row.get(8)?),
                     created_at: parse_datetime(row.get::<_, String>(7)?),
                 })
             })?
@@ -480,7 +946,7 @@ impl Database {
     pub fn get_dtc_history_for_vehicle(&self, vehicle_id: i64) -> SqlResult<Vec<StoredDtc>> {
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn.prepare(
-            "SELECT d.id, d.session_id, d.code, d.status, d.description, d.is_pending, d.is_confirmed, d.created_at
+            "SELECT d.id, d.session_id, d.code, d.status, d.description, d.is_pending, d.is_confirmed, d.created_at, d.environment
              FROM dtcs d
              JOIN diagnostic_sessions s ON d.session_id = s.id
              WHERE s.vehicle_id = ?1
@@ -497,6 +963,7 @@ impl Database {
                     description: row.get(4)?,
                     is_pending: row.get(5)?,
                     is_confirmed: row.get(6)?,
+                    environment: parse_json(row.get(8)?),
                     created_at: parse_datetime(row.get::<_, String>(7)?),
                 })
             })?
@@ -505,6 +972,500 @@ impl Database {
         Ok(dtcs)
     }
 
+    /// Record a DTC clear in the session history
+    pub fn add_dtc_clear_event(&self, event: &NewDtcClearEvent) -> SqlResult<i64> {
+        let conn = self.conn.lock().unwrap();
+        let codes = |codes: &[String]| serde_json::to_string(codes).unwrap_or_default();
+        conn.execute(
+            "INSERT INTO dtc_clear_events (session_id, ecu_id, dtc_group, cleared, returned, pending)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                event.session_id,
+                event.ecu_id,
+                event.dtc_group,
+                codes(&event.cleared),
+                codes(&event.returned),
+                codes(&event.pending),
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// Get DTC clear events for a session
+    pub fn get_dtc_clear_events_for_session(&self, session_id: i64) -> SqlResult<Vec<DtcClearEvent>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, session_id, ecu_id, dtc_group, cleared, returned, pending, created_at
+             FROM dtc_clear_events WHERE session_id = ?1 ORDER BY id",
+        )?;
+
+        let codes = |s: String| serde_json::from_str(&s).unwrap_or_default();
+        let events = stmt
+            .query_map(params![session_id], |row| {
+                Ok(DtcClearEvent {
+                    id: row.get(0)?,
+                    session_id: row.get(1)?,
+                    ecu_id: row.get(2)?,
+                    dtc_group: row.get(3)?,
+                    cleared: codes(row.get(4)?),
+                    returned: codes(row.get(5)?),
+                    pending: codes(row.get(6)?),
+                    created_at: parse_datetime(row.get::<_, String>(7)?),
+                })
+            })?
+            .collect::<SqlResult<Vec<_>>>()?;
+
+        Ok(events)
+    }
+
+    /// Compare the faults of two sessions of the same ECU
+    ///
+    /// The session with the lower id is taken as the older one; history
+    /// (first/last seen, occurrences) covers every session of the newer
+    /// session's vehicle up to it. The caller checks that both are of the
+    /// same ECU (`db_diff_sessions`).
+    pub fn diff_sessions(&self, session_a: i64, session_b: i64) -> SqlResult<SessionDtcDiff> {
+        let (from, to) = (session_a.min(session_b), session_a.max(session_b));
+        let conn = self.conn.lock().unwrap();
+
+        let vehicle_id: i64 = conn.query_row(
+            "SELECT vehicle_id FROM diagnostic_sessions WHERE id = ?1",
+            params![to],
+            |row| row.get(0),
+        )?;
+
+        let mut stmt = conn.prepare(
+            "SELECT s.id, s.ecu_id, s.mileage_km, s.created_at, d.code, d.description
+             FROM dtcs d
+             JOIN diagnostic_sessions s ON d.session_id = s.id
+             WHERE (s.vehicle_id = ?1 AND s.id <= ?2) OR s.id = ?3
+             ORDER BY s.id",
+        )?;
+
+        struct Sighting {
+            session_id: i64,
+            mileage_km: Option<i32>,
+            seen: DateTime<Utc>,
+        }
+
+        // (ecu, code) -> sightings in session order, latest description
+        let mut history: BTreeMap<(String, String), (Vec<Sighting>, Option<String>)> =
+            BTreeMap::new();
+        let rows = stmt.query_map(params![vehicle_id, to, from], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<i32>>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, Option<String>>(5)?,
+            ))
+        })?;
+        for row in rows {
+            let (session_id, ecu_id, mileage_km, created_at, code, description) = row?;
+            let (sightings, last_description) = history.entry((ecu_id, code)).or_default();
+            if sightings.last().map(|s| s.session_id) != Some(session_id) {
+                sightings.push(Sighting {
+                    session_id,
+                    mileage_km,
+                    seen: parse_datetime(created_at),
+                });
+            }
+            if description.is_some() {
+                *last_description = description;
+            }
+        }
+
+        let mut entries: Vec<DtcDiffEntry> = history
+            .into_iter()
+            .filter_map(|((ecu_id, code), (sightings, description))| {
+                let in_session = |id: i64| sightings.iter().any(|s| s.session_id == id);
+                let change = match (in_session(from), in_session(to)) {
+                    (true, true) => DtcChange::Persistent,
+                    (true, false) => DtcChange::Resolved,
+                    (false, true) if sightings.iter().any(|s| s.session_id < to) => {
+                        DtcChange::Intermittent
+                    }
+                    (false, true) => DtcChange::New,
+                    (false, false) => return None,
+                };
+                let first = sightings.first()?;
+                let last = sightings.last()?;
+
+                Some(DtcDiffEntry {
+                    ecu_id,
+                    code,
+                    description,
+                    change,
+                    first_seen: first.seen,
+                    last_seen: last.seen,
+                    first_seen_mileage_km: first.mileage_km,
+                    last_seen_mileage_km: last.mileage_km,
+                    occurrences: sightings.len() as u32,
+                })
+            })
+            .collect();
+        entries.sort_by(|a, b| a.change.cmp(&b.change).then_with(|| a.code.cmp(&b.code)));
+
+        Ok(SessionDtcDiff {
+            vehicle_id,
+            from_session_id: from,
+            to_session_id: to,
+            entries,
+        })
+    }
+
+    /// Compare the latest session of each ECU of a vehicle with the
+    /// previous session of that ECU, ordered by ECU
+    ///
+    /// After a quick test this covers every ECU it read. ECUs with a single
+    /// session are left out.
+    pub fn diff_latest_sessions(&self, vehicle_id: i64) -> SqlResult<Vec<SessionDtcDiff>> {
+        let pairs = {
+            let conn = self.conn.lock().unwrap();
+            let mut stmt = conn.prepare(
+                "SELECT latest.id,
+                        (SELECT prev.id FROM diagnostic_sessions prev
+                         WHERE prev.vehicle_id = latest.vehicle_id
+                           AND prev.ecu_id = latest.ecu_id
+                           AND prev.id < latest.id
+                         ORDER BY prev.id DESC LIMIT 1)
+                 FROM diagnostic_sessions latest
+                 WHERE latest.vehicle_id = ?1
+                   AND latest.id = (SELECT MAX(id) FROM diagnostic_sessions
+                                    WHERE vehicle_id = latest.vehicle_id
+                                      AND ecu_id = latest.ecu_id)
+                 ORDER BY latest.ecu_id",
+            )?;
+            let pairs = stmt
+                .query_map(params![vehicle_id], |row| {
+                    Ok((row.get::<_, i64>(0)?, row.get::<_, Option<i64>>(1)?))
+                })?
+                .collect::<SqlResult<Vec<_>>>()?;
+            pairs
+        };
+
+        pairs
+            .into_iter()
+            .filter_map(|(latest, previous)| Some((previous?, latest)))
+            .map(|(previous, latest)| self.diff_sessions(previous, latest))
+            .collect()
+    }
+
+    // ========================================================================
+    // LIVE DATA OPERATIONS
+    // ========================================================================
+
+    /// Start a live data recording in a session
+    pub fn start_live_data_recording(&self, session_id: i64) -> SqlResult<i64> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO live_data_recordings (session_id, started_at) VALUES (?1, ?2)",
+            params![session_id, format_timestamp(Utc::now())],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// Mark a recording as stopped; false if it was not running
+    pub fn stop_live_data_recording(&self, recording_id: i64) -> SqlResult<bool> {
+        let conn = self.conn.lock().unwrap();
+        let rows = conn.execute(
+            "UPDATE live_data_recordings SET stopped_at = ?2 WHERE id = ?1 AND stopped_at IS NULL",
+            params![recording_id, format_timestamp(Utc::now())],
+        )?;
+        Ok(rows > 0)
+    }
+
+    /// Get a recording by ID
+    pub fn get_live_data_recording(&self, recording_id: i64) -> SqlResult<Option<LiveDataRecording>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT r.id, r.session_id, r.started_at, r.stopped_at,
+                    (SELECT COUNT(*) FROM live_data_snapshots s WHERE s.recording_id = r.id)
+             FROM live_data_recordings r WHERE r.id = ?1",
+        )?;
+
+        let mut rows = stmt.query(params![recording_id])?;
+        if let Some(row) = rows.next()? {
+            Ok(Some(recording_from_row(row)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Get the recordings of a session, oldest first
+    pub fn get_live_data_recordings(&self, session_id: i64) -> SqlResult<Vec<LiveDataRecording>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT r.id, r.session_id, r.started_at, r.stopped_at,
+                    (SELECT COUNT(*) FROM live_data_snapshots s WHERE s.recording_id = r.id)
+             FROM live_data_recordings r WHERE r.session_id = ?1 ORDER BY r.id",
+        )?;
+
+        let recordings = stmt
+            .query_map(params![session_id], recording_from_row)?
+            .collect::<SqlResult<Vec<_>>>()?;
+
+        Ok(recordings)
+    }
+
+    /// Store a batch of samples in one transaction
+    pub fn add_live_data_snapshots(&self, snapshots: &[NewLiveDataSnapshot]) -> SqlResult<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO live_data_snapshots (session_id, recording_id, ecu, parameter_name, value, unit, timestamp)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?;
+            for snapshot in snapshots {
+                stmt.execute(params![
+                    snapshot.session_id,
+                    snapshot.recording_id,
+                    snapshot.ecu,
+                    snapshot.parameter_name,
+                    snapshot.value,
+                    snapshot.unit,
+                    format_timestamp(snapshot.timestamp),
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(snapshots.len())
+    }
+
+    /// Get the samples of a session, in time order
+    pub fn get_live_data(&self, query: &LiveDataQuery) -> SqlResult<Vec<LiveDataSnapshot>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, session_id, recording_id, ecu, parameter_name, value, unit, timestamp
+             FROM live_data_snapshots
+             WHERE session_id = ?1
+               AND (?2 IS NULL OR recording_id = ?2)
+               AND (?3 IS NULL OR parameter_name = ?3)
+               AND (?4 IS NULL OR timestamp >= ?4)
+               AND (?5 IS NULL OR timestamp <= ?5)
+             ORDER BY timestamp, id",
+        )?;
+
+        let snapshots = stmt
+            .query_map(
+                params![
+                    query.session_id,
+                    query.recording_id,
+                    query.parameter_name,
+                    query.from.map(format_timestamp),
+                    query.to.map(format_timestamp),
+                ],
+                |row| {
+                    Ok(LiveDataSnapshot {
+                        id: row.get(0)?,
+                        session_id: row.get(1)?,
+                        recording_id: row.get(2)?,
+                        ecu: row.get(3)?,
+                        parameter_name: row.get(4)?,
+                        value: row.get(5)?,
+                        unit: row.get(6)?,
+                        timestamp: parse_datetime(row.get::<_, String>(7)?),
+                    })
+                },
+            )?
+            .collect::<SqlResult<Vec<_>>>()?;
+
+        Ok(snapshots)
+    }
+
+    /// Get min/max/mean/last of each channel per time bucket
+    ///
+    /// Aggregated in SQLite, so a long recording is charted from a few
+    /// thousand rows; zooming in means querying a shorter range with the
+    /// same number of buckets. `None` when no sample matches.
+    pub fn get_live_data_buckets(
+        &self,
+        query: &LiveDataBucketQuery,
+    ) -> SqlResult<Option<LiveDataBuckets>> {
+        let conn = self.conn.lock().unwrap();
+        let names = if query.parameter_names.is_empty() {
+            None
+        } else {
+            Some(serde_json::to_string(&query.parameter_names).unwrap_or_default())
+        };
+        let filter = "session_id = ?1
+               AND (?2 IS NULL OR recording_id = ?2)
+               AND (?3 IS NULL OR parameter_name IN (SELECT value FROM json_each(?3)))
+               AND (?4 IS NULL OR timestamp >= ?4)
+               AND (?5 IS NULL OR timestamp <= ?5)";
+
+        // Open ends of the range become the first and last sample
+        let (from, to) = if let (Some(from), Some(to)) = (query.from, query.to) {
+            (from, to)
+        } else {
+            let (first, last): (Option<String>, Option<String>) = conn.query_row(
+                &format!(
+                    "SELECT MIN(timestamp), MAX(timestamp) FROM live_data_snapshots WHERE {}",
+                    filter
+                ),
+                params![
+                    query.session_id,
+                    query.recording_id,
+                    names,
+                    query.from.map(format_timestamp),
+                    query.to.map(format_timestamp),
+                ],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?;
+            match (first, last) {
+                (Some(first), Some(last)) => (
+                    query.from.unwrap_or_else(|| parse_datetime(first)),
+                    query.to.unwrap_or_else(|| parse_datetime(last)),
+                ),
+                _ => return Ok(None),
+            }
+        };
+        if to < from {
+            return Ok(None);
+        }
+
+        let buckets = query.buckets.clamp(1, MAX_LIVE_DATA_BUCKETS) as i64;
+        let from_ms = from.timestamp_millis();
+        let span_ms = (to.timestamp_millis() - from_ms).max(1);
+
+        // Bucket of a sample from its time in ms; the end of the range
+        // falls into the last bucket
+        let mut stmt = conn.prepare(&format!(
+            "WITH samples AS (
+                 SELECT id, parameter_name, unit, value, timestamp,
+                        MIN((CAST(ROUND((julianday(timestamp) - 2440587.5) * 86400000.0) AS INTEGER)
+                             - ?6) * ?7 / ?8, ?7 - 1) AS bucket
+                 FROM live_data_snapshots
+                 WHERE {}
+             ),
+             ranked AS (
+                 SELECT *, ROW_NUMBER() OVER (
+                     PARTITION BY parameter_name, bucket ORDER BY timestamp DESC, id DESC
+                 ) AS latest
+                 FROM samples
+             )
+             SELECT parameter_name, MAX(unit), bucket, COUNT(*), MIN(value), MAX(value),
+                    AVG(value), MAX(CASE WHEN latest = 1 THEN value END)
+             FROM ranked
+             GROUP BY parameter_name, bucket
+             ORDER BY parameter_name, bucket",
+            filter
+        ))?;
+
+        let mut series: Vec<LiveDataSeries> = Vec::new();
+        let mut rows = stmt.query(params![
+            query.session_id,
+            query.recording_id,
+            names,
+            format_timestamp(from),
+            format_timestamp(to),
+            from_ms,
+            buckets,
+            span_ms,
+        ])?;
+        while let Some(row) = rows.next()? {
+            let name: String = row.get(0)?;
+            let index: i64 = row.get(2)?;
+            let bucket = LiveDataBucket {
+                index: index as u32,
+                start: DateTime::from_timestamp_millis(from_ms + index * span_ms / buckets)
+                    .unwrap_or(from),
+                count: row.get(3)?,
+                min: row.get(4)?,
+                max: row.get(5)?,
+                mean: row.get(6)?,
+                last: row.get(7)?,
+            };
+
+            match series.last_mut() {
+                Some(last) if last.parameter_name == name => last.buckets.push(bucket),
+                _ => series.push(LiveDataSeries {
+                    parameter_name: name,
+                    unit: row.get(1)?,
+                    buckets: vec![bucket],
+                }),
+            }
+        }
+
+        Ok(Some(LiveDataBuckets {
+            from,
+            to,
+            bucket_ms: span_ms as f64 / buckets as f64,
+            series,
+        }))
+    }
+
+    /// Get the channels recorded in a session
+    pub fn get_live_data_parameters(&self, session_id: i64) -> SqlResult<Vec<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT parameter_name FROM live_data_snapshots
+             WHERE session_id = ?1 ORDER BY parameter_name",
+        )?;
+
+        let names = stmt
+            .query_map(params![session_id], |row| row.get(0))?
+            .collect::<SqlResult<Vec<_>>>()?;
+
+        Ok(names)
+    }
+
+    /// Store a marker
+    pub fn add_live_data_marker(&self, marker: &NewLiveDataMarker) -> SqlResult<i64> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO live_data_markers
+                 (session_id, recording_id, kind, label, severity, parameter_name, value, timestamp)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                marker.session_id,
+                marker.recording_id,
+                marker.kind,
+                marker.label,
+                marker.severity,
+                marker.parameter_name,
+                marker.value,
+                format_timestamp(marker.timestamp),
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// Get the markers of a session, or of one recording, in time order
+    pub fn get_live_data_markers(
+        &self,
+        session_id: i64,
+        recording_id: Option<i64>,
+    ) -> SqlResult<Vec<LiveDataMarker>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, session_id, recording_id, kind, label, severity, parameter_name, value, timestamp
+             FROM live_data_markers
+             WHERE session_id = ?1 AND (?2 IS NULL OR recording_id = ?2)
+             ORDER BY timestamp, id",
+        )?;
+
+        let markers = stmt
+            .query_map(params![session_id, recording_id], |row| {
+                Ok(LiveDataMarker {
+                    id: row.get(0)?,
+                    session_id: row.get(1)?,
+                    recording_id: row.get(2)?,
+                    kind: row.get(3)?,
+                    label: row.get(4)?,
+                    severity: row.get(5)?,
+                    parameter_name: row.get(6)?,
+                    value: row.get(7)?,
+                    timestamp: parse_datetime(row.get::<_, String>(8)?),
+                })
+            })?
+            .collect::<SqlResult<Vec<_>>>()?;
+
+        Ok(markers)
+    }
+
     // ========================================================================
     // SETTINGS OPERATIONS
     // ========================================================================
@@ -561,7 +1522,8 @@ impl Database {
         // Get all sessions with their DTCs
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn.prepare(
-            "SELECT id, vehicle_id, ecu_id, ecu_name, protocol, mileage_km, notes, created_at
+            "SELECT id, vehicle_id, ecu_id, ecu_name, protocol, mileage_km, notes, created_at,
+                    vehicle_info, vehicle_test_id
              FROM diagnostic_sessions ORDER BY created_at DESC",
         )?;
 
@@ -575,6 +1537,8 @@ impl Database {
                     protocol: row.get(4)?,
                     mileage_km: row.get(5)?,
                     notes: row.get(6)?,
+                    vehicle_info: parse_json(row.get(8)?),
+                    vehicle_test_id: row.get(9)?,
                     created_at: parse_datetime(row.get::<_, String>(7)?),
                 })
             })?
@@ -582,13 +1546,30 @@ impl Database {
         drop(stmt);
         drop(conn);
 
+        let vehicle_tests = vehicles
+            .iter()
+            .map(|vehicle| self.get_vehicle_tests(vehicle.id))
+            .collect::<SqlResult<Vec<_>>>()?
+            .concat();
+
         // Get DTCs for each session
         let mut sessions_with_dtcs: Vec<serde_json::Value> = Vec::new();
         for session in sessions {
             let dtcs = self.get_dtcs_for_session(session.id)?;
+            let clear_events = self.get_dtc_clear_events_for_session(session.id)?;
+            let recordings = self.get_live_data_recordings(session.id)?;
+            let live_data = self.get_live_data(&LiveDataQuery {
+                session_id: session.id,
+                ..Default::default()
+            })?;
+            let markers = self.get_live_data_markers(session.id, None)?;
             sessions_with_dtcs.push(serde_json::json!({
                 "session": session,
                 "dtcs": dtcs,
+                "clear_events": clear_events,
+                "live_data_recordings": recordings,
+                "live_data": live_data,
+                "live_data_markers": markers,
             }));
         }
 
@@ -597,6 +1578,7 @@ impl Database {
             "exported_at": Utc::now().to_rfc3339(),
             "vehicles": vehicles,
             "sessions": sessions_with_dtcs,
+            "vehicle_tests": vehicle_tests,
             "settings": settings,
         });
 
@@ -645,9 +1627,48 @@ pub struct DatabaseStats {
 fn parse_datetime(s: String) -> DateTime<Utc> {
     DateTime::parse_from_rfc3339(&s)
         .map(|dt| dt.with_timezone(&Utc))
+        // SQLite datetime('now') defaults, stored in UTC
+        .or_else(|_| NaiveDateTime::parse_from_str(&s, "%Y-%m-%d %H:%M:%S").map(|dt| dt.and_utc()))
         .unwrap_or_else(|_| Utc::now())
 }
 
+// Helper function to format sample timestamps; fixed-width UTC with
+// milliseconds, so that text comparison follows time order
+fn format_timestamp(dt: DateTime<Utc>) -> String {
+    dt.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+// Helper function to map a live_data_recordings row
+fn recording_from_row(row: &rusqlite::Row) -> SqlResult<LiveDataRecording> {
+    Ok(LiveDataRecording {
+        id: row.get(0)?,
+        session_id: row.get(1)?,
+        started_at: parse_datetime(row.get::<_, String>(2)?),
+        stopped_at: row.get::<_, Option<String>>(3)?.map(parse_datetime),
+        sample_count: row.get(4)?,
+    })
+}
+
+// Helper function to parse optional JSON columns
+fn parse_json(s: Option<String>) -> Option<serde_json::Value> {
+    s.and_then(|s| serde_json::from_str(&s).ok())
+}
+
+// Add a column to an existing table if an older schema lacks it
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, decl: &str) -> SqlResult<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<SqlResult<Vec<_>>>()?
+        .iter()
+        .any(|name| name == column);
+
+    if !exists {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl), [])?;
+    }
+    Ok(())
+}
+
 // ============================================================================
 // TESTS
 // ============================================================================
@@ -873,6 +1894,75 @@ mod tests {
         assert_eq!(sessions.len(), 3);
     }
 
+    #[test]
+    fn test_session_vehicle_info() {
+        let db = test_db();
+        let vehicle_id = create_test_vehicle(&db);
+        let session_id = db
+            .create_session(&NewSession {
+                vehicle_id,
+                ecu_id: "0x12".to_string(),
+                ecu_name: "DME/DDE".to_string(),
+                protocol: "K-Line".to_string(),
+                mileage_km: None,
+                notes: None,
+            })
+            .unwrap();
+
+        let info = serde_json::json!({ "vin": "WBANF71060B123456", "calibration_ids": ["7797576"] });
+        db.set_session_vehicle_info(session_id, &info).unwrap();
+        assert!(db.set_session_vehicle_info(session_id + 1, &info).is_err());
+
+        let sessions = db.get_sessions_for_vehicle(vehicle_id).unwrap();
+        assert_eq!(sessions[0].vehicle_info, Some(info));
+    }
+
+    #[test]
+    fn test_save_vehicle_test() {
+        let db = test_db();
+        let vehicle_id = create_test_vehicle(&db);
+
+        let session = |ecu: &str| NewSession {
+            vehicle_id,
+            ecu_id: ecu.to_string(),
+            ecu_name: ecu.to_string(),
+            protocol: "K-Line".to_string(),
+            mileage_km: Some(190000),
+            notes: None,
+        };
+        let dtc = NewDtc {
+            session_id: 0,
+            code: "2AAF".to_string(),
+            status: "0x24".to_string(),
+            description: None,
+            is_pending: true,
+            is_confirmed: false,
+            environment: None,
+        };
+        let test = NewVehicleTest {
+            vehicle_id,
+            mileage_km: Some(190000),
+            ecus_ok: 1,
+            ecus_faulty: 1,
+            ecus_not_responding: 3,
+            report: serde_json::json!({ "ecus": [] }),
+        };
+
+        let (test_id, session_ids) = db
+            .save_vehicle_test(&test, &[(session("DDE"), vec![dtc]), (session("EGS"), vec![])])
+            .unwrap();
+        assert_eq!(session_ids.len(), 2);
+
+        let tests = db.get_vehicle_tests(vehicle_id).unwrap();
+        assert_eq!(tests.len(), 1);
+        assert_eq!(tests[0].ecus_not_responding, 3);
+
+        let sessions = db.get_sessions_for_vehicle_test(test_id).unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].vehicle_test_id, Some(test_id));
+        assert_eq!(db.get_dtcs_for_session(session_ids[0]).unwrap().len(), 1);
+    }
+
     #[test]
     fn test_delete_session() {
         let db = test_db();
@@ -922,6 +2012,7 @@ mod tests {
                 description: Some("DPF pressure sensor - circuit open".to_string()),
                 is_pending: false,
                 is_confirmed: true,
+                environment: None,
             },
             NewDtc {
                 session_id,
@@ -930,6 +2021,7 @@ mod tests {
                 description: Some("DPF soot mass - limit exceeded".to_string()),
                 is_pending: true,
                 is_confirmed: false,
+                environment: None,
             },
         ];
         db.add_dtcs(&dtcs).unwrap();
@@ -940,6 +2032,74 @@ mod tests {
         assert_eq!(loaded_dtcs[1].code, "2AB0");
     }
 
+    #[test]
+    fn test_dtc_environment_round_trip() {
+        let db = test_db();
+        let vehicle_id = create_test_vehicle(&db);
+
+        let session = NewSession {
+            vehicle_id,
+            ecu_id: "DME".to_string(),
+            ecu_name: "MSV70".to_string(),
+            protocol: "KWP2000".to_string(),
+            mileage_km: None,
+            notes: None,
+        };
+        let session_id = db.create_session(&session).unwrap();
+
+        let environment = serde_json::json!({
+            "frequency_counter": 3,
+            "first": { "mileage_km": 123456, "rpm": 750 }
+        });
+        let dtc = NewDtc {
+            session_id,
+            code: "2A82".to_string(),
+            status: "0x24".to_string(),
+            description: None,
+            is_pending: false,
+            is_confirmed: true,
+            environment: Some(environment.clone()),
+        };
+        db.add_dtcs(&[dtc]).unwrap();
+
+        let loaded = db.get_dtcs_for_session(session_id).unwrap();
+        assert_eq!(loaded[0].environment, Some(environment));
+    }
+
+    #[test]
+    fn test_dtc_clear_events() {
+        let db = test_db();
+        let vehicle_id = create_test_vehicle(&db);
+
+        let session = NewSession {
+            vehicle_id,
+            ecu_id: "DDE".to_string(),
+            ecu_name: "DDE".to_string(),
+            protocol: "K-Line".to_string(),
+            mileage_km: None,
+            notes: None,
+        };
+        let session_id = db.create_session(&session).unwrap();
+
+        db.add_dtc_clear_event(&NewDtcClearEvent {
+            session_id,
+            ecu_id: "DDE".to_string(),
+            dtc_group: "powertrain".to_string(),
+            cleared: vec!["4B93".to_string()],
+            returned: vec!["4CA9".to_string()],
+            pending: vec![],
+        })
+        .unwrap();
+
+        let events = db.get_dtc_clear_events_for_session(session_id).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].cleared, vec!["4B93".to_string()]);
+        assert_eq!(events[0].returned, vec!["4CA9".to_string()]);
+
+        db.delete_session(session_id).unwrap();
+        assert!(db.get_dtc_clear_events_for_session(session_id).unwrap().is_empty());
+    }
+
     #[test]
     fn test_get_dtc_history_for_vehicle() {
         let db = test_db();
@@ -964,6 +2124,7 @@ mod tests {
                 description: None,
                 is_pending: false,
                 is_confirmed: true,
+                environment: None,
             };
             db.add_dtcs(&[dtc]).unwrap();
         }
@@ -972,6 +2133,101 @@ mod tests {
         assert_eq!(history.len(), 2);
     }
 
+    /// Session of `ecu_id` with the given fault codes
+    fn create_session_with_dtcs(
+        db: &Database,
+        vehicle_id: i64,
+        ecu_id: &str,
+        mileage_km: i32,
+        codes: &[&str],
+    ) -> i64 {
+        let session_id = db
+            .create_session(&NewSession {
+                vehicle_id,
+                ecu_id: ecu_id.to_string(),
+                ecu_name: ecu_id.to_string(),
+                protocol: "K-Line".to_string(),
+                mileage_km: Some(mileage_km),
+                notes: None,
+            })
+            .unwrap();
+        let dtcs: Vec<_> = codes
+            .iter()
+            .map(|code| NewDtc {
+                session_id,
+                code: code.to_string(),
+                status: "0x24".to_string(),
+                description: None,
+                is_pending: false,
+                is_confirmed: true,
+                environment: None,
+            })
+            .collect();
+        db.add_dtcs(&dtcs).unwrap();
+        session_id
+    }
+
+    #[test]
+    fn test_diff_sessions_classifies_faults() {
+        let db = test_db();
+        let vehicle_id = create_test_vehicle(&db);
+
+        // Four DDE sessions: codes present in each
+        let sessions = [
+            (150_000, vec!["4B93"]),
+            (152_000, vec!["2AAF", "4CA9"]),
+            (155_000, vec!["2AAF", "4CA9"]),
+            (158_000, vec!["2AAF", "4B93", "4D10"]),
+        ];
+        let ids: Vec<i64> = sessions
+            .iter()
+            .map(|(mileage, codes)| {
+                create_session_with_dtcs(&db, vehicle_id, "DDE", *mileage, codes)
+            })
+            .collect();
+
+        let diffs = db.diff_latest_sessions(vehicle_id).unwrap();
+        assert_eq!(diffs.len(), 1);
+        let diff = &diffs[0];
+        assert_eq!((diff.from_session_id, diff.to_session_id), (ids[2], ids[3]));
+
+        let change = |code: &str| diff.entries.iter().find(|e| e.code == code).unwrap();
+        assert_eq!(change("4D10").change, DtcChange::New);
+        assert_eq!(change("4B93").change, DtcChange::Intermittent);
+        assert_eq!(change("2AAF").change, DtcChange::Persistent);
+        assert_eq!(change("4CA9").change, DtcChange::Resolved);
+
+        assert_eq!(change("2AAF").occurrences, 3);
+        assert_eq!(change("4B93").first_seen_mileage_km, Some(150_000));
+        assert_eq!(change("4B93").last_seen_mileage_km, Some(158_000));
+    }
+
+    #[test]
+    fn test_diff_latest_sessions_per_ecu() {
+        let db = test_db();
+        let vehicle_id = create_test_vehicle(&db);
+
+        // Two quick tests reading DDE and EGS, then a DSC-only session
+        let dde_first = create_session_with_dtcs(&db, vehicle_id, "DDE", 150_000, &["4B93"]);
+        let egs_first = create_session_with_dtcs(&db, vehicle_id, "EGS", 150_000, &["5060"]);
+        let dde_latest = create_session_with_dtcs(&db, vehicle_id, "DDE", 152_000, &["4B93"]);
+        let egs_latest = create_session_with_dtcs(&db, vehicle_id, "EGS", 152_000, &[]);
+        create_session_with_dtcs(&db, vehicle_id, "DSC", 152_000, &["5DF0"]);
+
+        let diffs = db.diff_latest_sessions(vehicle_id).unwrap();
+        let pairs: Vec<_> = diffs
+            .iter()
+            .map(|diff| (diff.from_session_id, diff.to_session_id))
+            .collect();
+        assert_eq!(pairs, [(dde_first, dde_latest), (egs_first, egs_latest)]);
+
+        // Each diff only holds faults of its own ECU
+        assert!(diffs[0].entries.iter().all(|e| e.ecu_id == "DDE"));
+        assert_eq!(diffs[0].entries[0].change, DtcChange::Persistent);
+        assert_eq!(diffs[1].entries.len(), 1);
+        assert_eq!(diffs[1].entries[0].change, DtcChange::Resolved);
+    }
+
     // ========================================================================
     // SETTINGS TESTS
     // ========================================================================
@@ -1039,6 +2295,7 @@ mod tests {
             description: None,
             is_pending: false,
             is_confirmed: true,
+            environment: None,
         };
         db.add_dtcs(&[dtc]).unwrap();
 
@@ -1075,6 +2332,218 @@ mod tests {
         assert!(parsed["settings"].as_array().unwrap().len() > 0);
     }
 
+    // ========================================================================
+    // LIVE DATA TESTS
+    // ========================================================================
+
+    fn create_test_session(db: &Database) -> i64 {
+        let vehicle_id = create_test_vehicle(db);
+        let session = NewSession {
+            vehicle_id,
+            ecu_id: "DDE".to_string(),
+            ecu_name: "DDE".to_string(),
+            protocol: "K-Line".to_string(),
+            mileage_km: None,
+            notes: None,
+        };
+        db.create_session(&session).unwrap()
+    }
+
+    fn sample(
+        session_id: i64,
+        recording_id: i64,
+        name: &str,
+        value: f64,
+        millis: i64,
+    ) -> NewLiveDataSnapshot {
+        NewLiveDataSnapshot {
+            session_id,
+            recording_id: Some(recording_id),
+            ecu: Some("DDE".to_string()),
+            parameter_name: name.to_string(),
+            value,
+            unit: "rpm".to_string(),
+            timestamp: DateTime::from_timestamp_millis(1_700_000_000_000 + millis).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_live_data_recording_lifecycle() {
+        let db = test_db();
+        let session_id = create_test_session(&db);
+
+        let recording_id = db.start_live_data_recording(session_id).unwrap();
+        let samples: Vec<_> = (0..5)
+            .map(|i| sample(session_id, recording_id, "Engine RPM", 800.0 + i as f64, i * 100))
+            .collect();
+        assert_eq!(db.add_live_data_snapshots(&samples).unwrap(), 5);
+
+        let recording = db.get_live_data_recording(recording_id).unwrap().unwrap();
+        assert!(recording.stopped_at.is_none());
+        assert_eq!(recording.sample_count, 5);
+
+        assert!(db.stop_live_data_recording(recording_id).unwrap());
+        assert!(!db.stop_live_data_recording(recording_id).unwrap());
+
+        let recordings = db.get_live_data_recordings(session_id).unwrap();
+        assert_eq!(recordings.len(), 1);
+        assert!(recordings[0].stopped_at.is_some());
+    }
+
+    #[test]
+    fn test_get_live_data_by_channel_and_time_range() {
+        let db = test_db();
+        let session_id = create_test_session(&db);
+        let recording_id = db.start_live_data_recording(session_id).unwrap();
+
+        // Inserted out of order and with a second channel
+        db.add_live_data_snapshots(&[
+            sample(session_id, recording_id, "Engine RPM", 900.0, 2_000),
+            sample(session_id, recording_id, "Engine RPM", 800.0, 0),
+            sample(session_id, recording_id, "Coolant Temp", 85.0, 500),
+            sample(session_id, recording_id, "Engine RPM", 850.0, 1_000),
+            sample(session_id, recording_id, "Engine RPM", 950.0, 10_000),
+        ])
+        .unwrap();
+
+        let all = db
+            .get_live_data(&LiveDataQuery {
+                session_id,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(all.len(), 5);
+        assert!(all.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+
+        let base = DateTime::from_timestamp_millis(1_700_000_000_000).unwrap();
+        let rpm = db
+            .get_live_data(&LiveDataQuery {
+                session_id,
+                recording_id: Some(recording_id),
+                parameter_name: Some("Engine RPM".to_string()),
+                from: Some(base + chrono::Duration::milliseconds(1_000)),
+                to: Some(base + chrono::Duration::milliseconds(2_000)),
+            })
+            .unwrap();
+        let values: Vec<f64> = rpm.iter().map(|s| s.value).collect();
+        assert_eq!(values, vec![850.0, 900.0]);
+        assert_eq!(rpm[0].timestamp, base + chrono::Duration::milliseconds(1_000));
+
+        let names = db.get_live_data_parameters(session_id).unwrap();
+        assert_eq!(names, vec!["Coolant Temp", "Engine RPM"]);
+    }
+
+    #[test]
+    fn test_live_data_buckets() {
+        let db = test_db();
+        let session_id = create_test_session(&db);
+        let recording_id = db.start_live_data_recording(session_id).unwrap();
+
+        // 10 s of RPM at 10 Hz, coolant at 1 Hz
+        let mut samples: Vec<_> = (0..100)
+            .map(|i| sample(session_id, recording_id, "Engine RPM", i as f64, i * 100))
+            .collect();
+        samples.extend(
+            (0..10).map(|i| sample(session_id, recording_id, "Coolant Temp", 80.0, i * 1_000)),
+        );
+        db.add_live_data_snapshots(&samples).unwrap();
+
+        let base = DateTime::from_timestamp_millis(1_700_000_000_000).unwrap();
+        let all = db
+            .get_live_data_buckets(&LiveDataBucketQuery {
+                session_id,
+                buckets: 5,
+                ..Default::default()
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(all.from, base);
+        assert_eq!(all.to, base + chrono::Duration::milliseconds(9_900));
+        let names: Vec<&str> = all
+            .series
+            .iter()
+            .map(|s| s.parameter_name.as_str())
+            .collect();
+        assert_eq!(names, vec!["Coolant Temp", "Engine RPM"]);
+
+        let rpm = &all.series[1].buckets;
+        assert_eq!(rpm.len(), 5);
+        assert_eq!(rpm.iter().map(|b| b.count).sum::<i64>(), 100);
+        // First bucket covers 0..1980 ms
+        assert_eq!((rpm[0].count, rpm[0].min, rpm[0].max), (20, 0.0, 19.0));
+        assert_eq!((rpm[0].mean, rpm[0].last), (9.5, 19.0));
+        assert_eq!(rpm[0].start, base);
+        // The last sample is the end of the range and lands in the last bucket
+        assert_eq!(rpm[4].max, 99.0);
+
+        // Zoomed in: same number of buckets over 1 s, one channel
+        let zoomed = db
+            .get_live_data_buckets(&LiveDataBucketQuery {
+                session_id,
+                recording_id: Some(recording_id),
+                parameter_names: vec!["Engine RPM".to_string()],
+                from: Some(base + chrono::Duration::milliseconds(1_000)),
+                to: Some(base + chrono::Duration::milliseconds(1_999)),
+                buckets: 5,
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(zoomed.series.len(), 1);
+        let lasts: Vec<f64> = zoomed.series[0].buckets.iter().map(|b| b.last).collect();
+        assert_eq!(lasts, vec![11.0, 13.0, 15.0, 17.0, 19.0]);
+        assert!((zoomed.bucket_ms - 199.8).abs() < 1e-9);
+
+        let none = db
+            .get_live_data_buckets(&LiveDataBucketQuery {
+                session_id,
+                parameter_names: vec!["Boost".to_string()],
+                buckets: 100,
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(none.is_none());
+    }
+
+    #[test]
+    fn test_live_data_markers() {
+        let db = test_db();
+        let session_id = create_test_session(&db);
+        let first = db.start_live_data_recording(session_id).unwrap();
+        let second = db.start_live_data_recording(session_id).unwrap();
+
+        let marker = |recording_id, kind: &str, value, millis: i64| NewLiveDataMarker {
+            session_id,
+            recording_id: Some(recording_id),
+            kind: kind.to_string(),
+            label: "EGT pre-turbo high".to_string(),
+            severity: Some("critical".to_string()),
+            parameter_name: Some("Exhaust Temp Pre-Turbo".to_string()),
+            value: Some(value),
+            timestamp: DateTime::from_timestamp_millis(1_700_000_000_000 + millis).unwrap(),
+        };
+        db.add_live_data_marker(&marker(first, "alarm_cleared", 700.0, 5_000))
+            .unwrap();
+        db.add_live_data_marker(&marker(first, "alarm_raised", 760.0, 2_000))
+            .unwrap();
+        db.add_live_data_marker(&marker(second, "alarm_raised", 755.0, 9_000))
+            .unwrap();
+
+        let all = db.get_live_data_markers(session_id, None).unwrap();
+        assert_eq!(all.len(), 3);
+
+        let markers = db.get_live_data_markers(session_id, Some(first)).unwrap();
+        let kinds: Vec<&str> = markers.iter().map(|m| m.kind.as_str()).collect();
+        assert_eq!(kinds, vec!["alarm_raised", "alarm_cleared"]);
+        assert_eq!(markers[0].value, Some(760.0));
+        assert_eq!(markers[0].severity.as_deref(), Some("critical"));
+
+        db.delete_session(session_id).unwrap();
+        assert!(db
+            .get_live_data_markers(session_id, None)
+            .unwrap()
+            .is_empty());
+    }
+
     // ========================================================================
     // CASCADE DELETE TESTS
     // ========================================================================
@@ -1124,6 +2593,7 @@ mod tests {
             description: None,
             is_pending: false,
             is_confirmed: true,
+            environment: None,
         };
         db.add_dtcs(&[dtc]).unwrap();
 
@@ -1134,4 +2604,24 @@ mod tests {
         let dtcs = db.get_dtcs_for_session(session_id).unwrap();
         assert!(dtcs.is_empty());
     }
+
+    #[test]
+    fn test_delete_session_cascades_to_live_data() {
+        let db = test_db();
+        let session_id = create_test_session(&db);
+        let recording_id = db.start_live_data_recording(session_id).unwrap();
+        db.add_live_data_snapshots(&[sample(session_id, recording_id, "Engine RPM", 800.0, 0)])
+            .unwrap();
+
+        db.delete_session(session_id).unwrap();
+
+        assert!(db.get_live_data_recording(recording_id).unwrap().is_none());
+        let samples = db
+            .get_live_data(&LiveDataQuery {
+                session_id,
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(samples.is_empty());
+    }
 }
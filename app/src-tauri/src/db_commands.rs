@@ -1,8 +1,9 @@
 //! Tauri commands for database operations
 
 use crate::database::{
-    Database, DatabaseStats, DiagnosticSession, NewDtc, NewSession, NewVehicle, Setting,
-    StoredDtc, Vehicle,
+    Database, DatabaseStats, DiagnosticSession, DtcClearEvent, LiveDataBucketQuery,
+    LiveDataBuckets, LiveDataMarker, LiveDataQuery, LiveDataRecording, LiveDataSnapshot, NewDtc,
+    NewSession, NewVehicle, SessionDtcDiff, Setting, StoredDtc, Vehicle, VehicleTest,
 };
 use std::sync::Mutex;
 use tauri::State;
@@ -145,6 +146,150 @@ pub fn db_get_dtc_history(
         .map_err(|e| format!("Database error: {}", e))
 }
 
+/// Get DTC clear events for a session
+#[tauri::command]
+pub fn db_get_dtc_clear_events(
+    state: State<DbState>,
+    session_id: i64,
+) -> Result<Vec<DtcClearEvent>, String> {
+    let guard = state.0.lock().map_err(|e| format!("Lock error: {}", e))?;
+    let db = guard.as_ref().ok_or("Database not initialized")?;
+    db.get_dtc_clear_events_for_session(session_id)
+        .map_err(|e| format!("Database error: {}", e))
+}
+
+/// Get quick tests for a vehicle
+#[tauri::command]
+pub fn db_get_vehicle_tests(
+    state: State<DbState>,
+    vehicle_id: i64,
+) -> Result<Vec<VehicleTest>, String> {
+    let guard = state.0.lock().map_err(|e| format!("Lock error: {}", e))?;
+    let db = guard.as_ref().ok_or("Database not initialized")?;
+    db.get_vehicle_tests(vehicle_id)
+        .map_err(|e| format!("Database error: {}", e))
+}
+
+/// Get the per-ECU sessions of a quick test
+#[tauri::command]
+pub fn db_get_vehicle_test_sessions(
+    state: State<DbState>,
+    test_id: i64,
+) -> Result<Vec<DiagnosticSession>, String> {
+    let guard = state.0.lock().map_err(|e| format!("Lock error: {}", e))?;
+    let db = guard.as_ref().ok_or("Database not initialized")?;
+    db.get_sessions_for_vehicle_test(test_id)
+        .map_err(|e| format!("Database error: {}", e))
+}
+
+/// Compare the faults of two sessions
+#[tauri::command]
+pub fn db_diff_sessions(
+    state: State<DbState>,
+    from_session_id: i64,
+    to_session_id: i64,
+) -> Result<SessionDtcDiff, String> {
+    let guard = state.0.lock().map_err(|e| format!("Lock error: {}", e))?;
+    let db = guard.as_ref().ok_or("Database not initialized")?;
+
+    // Every fault of one ECU would show up as new or resolved against another
+    let mut ecus = Vec::new();
+    for id in [from_session_id, to_session_id] {
+        let session = db
+            .get_session(id)
+            .map_err(|e| format!("Database error: {}", e))?
+            .ok_or_else(|| format!("Session {} not found", id))?;
+        ecus.push(session.ecu_id);
+    }
+    if ecus[0] != ecus[1] {
+        return Err(format!(
+            "Session {} ({}) and session {} ({}) are of different ECUs",
+            from_session_id, ecus[0], to_session_id, ecus[1]
+        ));
+    }
+
+    db.diff_sessions(from_session_id, to_session_id)
+        .map_err(|e| format!("Database error: {}", e))
+}
+
+/// Compare the latest session of each ECU of a vehicle with its previous one
+#[tauri::command]
+pub fn db_diff_latest_sessions(
+    state: State<DbState>,
+    vehicle_id: i64,
+) -> Result<Vec<SessionDtcDiff>, String> {
+    let guard = state.0.lock().map_err(|e| format!("Lock error: {}", e))?;
+    let db = guard.as_ref().ok_or("Database not initialized")?;
+    db.diff_latest_sessions(vehicle_id)
+        .map_err(|e| format!("Database error: {}", e))
+}
+
+// ============================================================================
+// LIVE DATA COMMANDS
+// ============================================================================
+
+/// Get the live data recordings of a session
+#[tauri::command]
+pub fn db_get_live_data_recordings(
+    state: State<DbState>,
+    session_id: i64,
+) -> Result<Vec<LiveDataRecording>, String> {
+    let guard = state.0.lock().map_err(|e| format!("Lock error: {}", e))?;
+    let db = guard.as_ref().ok_or("Database not initialized")?;
+    db.get_live_data_recordings(session_id)
+        .map_err(|e| format!("Database error: {}", e))
+}
+
+/// Get recorded samples by session, recording, channel and time range
+#[tauri::command]
+pub fn db_get_live_data(
+    state: State<DbState>,
+    query: LiveDataQuery,
+) -> Result<Vec<LiveDataSnapshot>, String> {
+    let guard = state.0.lock().map_err(|e| format!("Lock error: {}", e))?;
+    let db = guard.as_ref().ok_or("Database not initialized")?;
+    db.get_live_data(&query)
+        .map_err(|e| format!("Database error: {}", e))
+}
+
+/// Get recorded samples aggregated per time bucket, for charting long
+/// recordings; `None` when nothing was recorded in the range
+#[tauri::command]
+pub fn db_get_live_data_buckets(
+    state: State<DbState>,
+    query: LiveDataBucketQuery,
+) -> Result<Option<LiveDataBuckets>, String> {
+    let guard = state.0.lock().map_err(|e| format!("Lock error: {}", e))?;
+    let db = guard.as_ref().ok_or("Database not initialized")?;
+    db.get_live_data_buckets(&query)
+        .map_err(|e| format!("Database error: {}", e))
+}
+
+/// Get the channels recorded in a session
+#[tauri::command]
+pub fn db_get_live_data_parameters(
+    state: State<DbState>,
+    session_id: i64,
+) -> Result<Vec<String>, String> {
+    let guard = state.0.lock().map_err(|e| format!("Lock error: {}", e))?;
+    let db = guard.as_ref().ok_or("Database not initialized")?;
+    db.get_live_data_parameters(session_id)
+        .map_err(|e| format!("Database error: {}", e))
+}
+
+/// Get the markers (e.g. alarms) of a session, or of one recording
+#[tauri::command]
+pub fn db_get_live_data_markers(
+    state: State<DbState>,
+    session_id: i64,
+    recording_id: Option<i64>,
+) -> Result<Vec<LiveDataMarker>, String> {
+    let guard = state.0.lock().map_err(|e| format!("Lock error: {}", e))?;
+    let db = guard.as_ref().ok_or("Database not initialized")?;
+    db.get_live_data_markers(session_id, recording_id)
+        .map_err(|e| format!("Database error: {}", e))
+}
+
 // ============================================================================
 // SETTINGS COMMANDS
 // ============================================================================
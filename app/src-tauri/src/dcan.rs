@@ -189,6 +189,26 @@ impl IsoTpFrame {
     }
 }
 
+/// Largest 11-bit CAN identifier
+const STANDARD_ID_MAX: u32 = 0x7FF;
+/// Cable length byte of frames with 11-bit and 29-bit identifiers
+const STANDARD_FRAME_LEN: u8 = 12;
+const EXTENDED_FRAME_LEN: u8 = 14;
+
+/// Split a cable frame into CAN ID and the 8 data bytes
+fn parse_cable_frame(buffer: &[u8]) -> Option<(u32, &[u8])> {
+    match buffer {
+        [EXTENDED_FRAME_LEN, rest @ ..] if rest.len() >= 12 => {
+            let id = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]);
+            Some((id, &rest[4..12]))
+        }
+        [_, hi, lo, data @ ..] if data.len() >= 8 => {
+            Some((((*hi as u32) << 8) | *lo as u32, &data[..8]))
+        }
+        _ => None,
+    }
+}
+
 /// D-CAN protocol handler
 pub struct DCanHandler {
     /// Transmit CAN ID (tester -> ECU)
@@ -236,14 +256,24 @@ impl DCanHandler {
     /// - RTS=0: K-Line mode (default)
     /// - RTS=1: D-CAN mode
     pub fn switch_to_dcan_mode(port: &mut Box<dyn serialport::SerialPort>) -> Result<(), String> {
+        Self::switch_to_dcan_mode_at(port, 500000)
+    }
+
+    /// Switch K+DCAN cable to D-CAN mode at a given bus speed
+    ///
+    /// BMW D-CAN runs at 500 kbaud; generic OBD-II cars may use 250 kbaud.
+    pub fn switch_to_dcan_mode_at(
+        port: &mut Box<dyn serialport::SerialPort>,
+        baud_rate: u32,
+    ) -> Result<(), String> {
         log::info!("Switching to D-CAN mode");
 
         // Set RTS high to enable D-CAN mode
         port.write_request_to_send(true)
             .map_err(|e| format!("Failed to set RTS: {}", e))?;
 
-        // Set baud rate to 500000 for D-CAN
-        port.set_baud_rate(500000)
+        // The cable firmware follows the serial baud rate
+        port.set_baud_rate(baud_rate)
             .map_err(|e| format!("Failed to set baud rate: {}", e))?;
 
         // Clear buffers
@@ -252,7 +282,7 @@ impl DCanHandler {
 
         thread::sleep(Duration::from_millis(100));
 
-        log::info!("D-CAN mode enabled at 500 kbaud");
+        log::info!("D-CAN mode enabled at {} kbaud", baud_rate / 1000);
         Ok(())
     }
 
@@ -343,8 +373,36 @@ impl DCanHandler {
         Self::receive_isotp_message(port, rx_id, Duration::from_millis(1000))
     }
 
+    /// Receive a message within `timeout`, e.g. unsolicited periodic data
+    pub fn receive_message_timeout(
+        port: &mut Box<dyn serialport::SerialPort>,
+        rx_id: u32,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, String> {
+        Self::receive_isotp_message(port, rx_id, timeout)
+    }
+
+    /// Send a single-frame request that gets no response
+    /// (suppressPosRspMsgIndicationBit set)
+    pub fn send_without_response(
+        port: &mut Box<dyn serialport::SerialPort>,
+        tx_id: u32,
+        data: &[u8],
+    ) -> Result<(), String> {
+        let frame = IsoTpFrame::single(data.to_vec())?;
+        Self::send_can_frame(port, tx_id, &frame.to_can_data())
+    }
+
+    /// Receive a further message without sending (e.g. after responsePending)
+    pub fn receive_message(
+        port: &mut Box<dyn serialport::SerialPort>,
+        rx_id: u32,
+    ) -> Result<Vec<u8>, String> {
+        Self::receive_isotp_message(port, rx_id, Duration::from_millis(1000))
+    }
+
     /// Send a single CAN frame via K+DCAN cable
-    fn send_can_frame(
+    pub fn send_can_frame(
         port: &mut Box<dyn serialport::SerialPort>,
         can_id: u32,
         data: &[u8; 8],
@@ -353,11 +411,17 @@ impl DCanHandler {
         // The FTDI chip with custom firmware expects raw CAN frames
         // Format varies by cable manufacturer, common format:
         // [LEN] [ID_HI] [ID_LO] [DATA x 8]
+        // 29-bit identifiers use four ID bytes: [LEN] [ID x 4] [DATA x 8]
 
-        let mut frame = Vec::with_capacity(12);
-        frame.push(12); // Total frame length
-        frame.push(((can_id >> 8) & 0xFF) as u8);
-        frame.push((can_id & 0xFF) as u8);
+        let mut frame = Vec::with_capacity(14);
+        if can_id > STANDARD_ID_MAX {
+            frame.push(EXTENDED_FRAME_LEN);
+            frame.extend_from_slice(&can_id.to_be_bytes());
+        } else {
+            frame.push(STANDARD_FRAME_LEN);
+            frame.push(((can_id >> 8) & 0xFF) as u8);
+            frame.push((can_id & 0xFF) as u8);
+        }
         frame.extend_from_slice(data);
 
         log::debug!("Sending CAN frame ID=0x{:03X}: {:02X?}", can_id, data);
@@ -374,28 +438,42 @@ impl DCanHandler {
         expected_id: u32,
         timeout: Duration,
     ) -> Result<Vec<u8>, String> {
+        let start = Instant::now();
+
+        while start.elapsed() < timeout {
+            let remaining = timeout.saturating_sub(start.elapsed());
+            match Self::receive_any_can_frame(port, remaining)? {
+                Some((id, data)) if id == expected_id => return Ok(data),
+                _ => {}
+            }
+        }
+
+        Err("Timeout waiting for CAN frame".to_string())
+    }
+
+    /// Receive the next CAN frame with any ID, `None` on timeout
+    pub fn receive_any_can_frame(
+        port: &mut Box<dyn serialport::SerialPort>,
+        timeout: Duration,
+    ) -> Result<Option<(u32, Vec<u8>)>, String> {
         let mut buffer = [0u8; 64];
         let start = Instant::now();
 
         while start.elapsed() < timeout {
             match port.read(&mut buffer) {
-                Ok(n) if n >= 11 => {
-                    // Parse frame: [LEN] [ID_HI] [ID_LO] [DATA x 8]
-                    let id = ((buffer[1] as u32) << 8) | (buffer[2] as u32);
-
-                    if id == expected_id {
-                        log::debug!("Received CAN frame ID=0x{:03X}: {:02X?}", id, &buffer[3..11]);
-                        return Ok(buffer[3..11].to_vec());
+                Ok(n) => {
+                    if let Some((id, data)) = parse_cable_frame(&buffer[..n]) {
+                        log::debug!("Received CAN frame ID=0x{:03X}: {:02X?}", id, data);
+                        return Ok(Some((id, data.to_vec())));
                     }
                 }
-                Ok(_) => {}
                 Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {}
                 Err(e) => return Err(format!("Read error: {}", e)),
             }
             thread::sleep(Duration::from_millis(1));
         }
 
-        Err("Timeout waiting for CAN frame".to_string())
+        Ok(None)
     }
 
     /// Receive a complete ISO-TP message (handles multi-frame)
@@ -508,7 +586,10 @@ pub mod can_ids {
 // High-Level D-CAN UDS Functions
 // =============================================================================
 
-use crate::bmw::Dtc;
+use crate::bmw::{Dtc, EcuInfo};
+use crate::constants::uds;
+use crate::dtc_info::{self, DtcCount, DtcExtendedData, DtcSnapshot, SnapshotIdentification};
+use bmw_diag_core::dtc_service::{self, DtcDialect};
 
 impl DCanHandler {
     /// Send UDS request and receive response via D-CAN
@@ -527,34 +608,11 @@ impl DCanHandler {
         tx_id: u32,
         rx_id: u32,
     ) -> Result<Vec<Dtc>, String> {
-        // UDS ReadDTCInformation (0x19) with sub-function 0x02 (reportDTCByStatusMask)
-        let request = vec![0x19, 0x02, 0xFF];
+        let request = DtcDialect::Uds.request();
+        let response = Self::send_message(port, tx_id, rx_id, request)?;
 
-        let response = Self::send_message(port, tx_id, rx_id, &request)?;
-
-        // Parse response
-        if response.first() != Some(&0x59) {
-            if response.first() == Some(&0x7F) {
-                let nrc = response.get(2).copied().unwrap_or(0);
-                return Err(format!("Negative response: 0x{:02X}", nrc));
-            }
-            return Err(format!("Unexpected response: {:02X?}", response));
-        }
-
-        // Response format: [0x59] [sub-function] [status_mask] [DTC1_HI] [DTC1_LO] [STATUS1] ...
-        let mut dtcs = Vec::new();
-        if response.len() >= 3 {
-            let data = &response[3..];
-            for chunk in data.chunks(3) {
-                if chunk.len() >= 3 {
-                    if let Some(dtc) = Dtc::from_bytes(chunk) {
-                        dtcs.push(dtc);
-                    }
-                }
-            }
-        }
-
-        Ok(dtcs)
+        let frame = dtc_service::parse_response(DtcDialect::Uds, &response)?;
+        Ok(frame.records.iter().map(Dtc::from_record).collect())
     }
 
     /// Clear DTCs from ECU via D-CAN
@@ -578,6 +636,68 @@ impl DCanHandler {
         }
     }
 
+    /// Read the number of DTCs matching a status mask (0x19 0x01)
+    pub fn read_dtc_count(
+        port: &mut Box<dyn serialport::SerialPort>,
+        tx_id: u32,
+        rx_id: u32,
+        status_mask: u8,
+    ) -> Result<DtcCount, String> {
+        let request = [uds::READ_DTC_INFO, uds::dtc::REPORT_NUMBER_BY_STATUS_MASK, status_mask];
+        let response = Self::send_message(port, tx_id, rx_id, &request)?;
+        dtc_info::parse_dtc_count(&response)
+    }
+
+    /// List the stored snapshot records (0x19 0x03)
+    pub fn read_snapshot_identification(
+        port: &mut Box<dyn serialport::SerialPort>,
+        tx_id: u32,
+        rx_id: u32,
+    ) -> Result<Vec<SnapshotIdentification>, String> {
+        let request = [uds::READ_DTC_INFO, uds::dtc::REPORT_SNAPSHOT_IDENTIFICATION];
+        let response = Self::send_message(port, tx_id, rx_id, &request)?;
+        dtc_info::parse_snapshot_identification(&response)
+    }
+
+    /// Read snapshot records of one DTC (0x19 0x04)
+    pub fn read_snapshot_records(
+        port: &mut Box<dyn serialport::SerialPort>,
+        tx_id: u32,
+        rx_id: u32,
+        dtc: u32,
+        record_number: u8,
+    ) -> Result<DtcSnapshot, String> {
+        let [hi, mid, lo] = dtc_info::dtc_request_bytes(dtc);
+        let request = [uds::READ_DTC_INFO, uds::dtc::REPORT_SNAPSHOT_BY_DTC, hi, mid, lo, record_number];
+        let response = Self::send_message(port, tx_id, rx_id, &request)?;
+        dtc_info::parse_snapshot_records(&response)
+    }
+
+    /// Read extended data records of one DTC (0x19 0x06)
+    pub fn read_extended_data(
+        port: &mut Box<dyn serialport::SerialPort>,
+        tx_id: u32,
+        rx_id: u32,
+        dtc: u32,
+        record_number: u8,
+    ) -> Result<DtcExtendedData, String> {
+        let [hi, mid, lo] = dtc_info::dtc_request_bytes(dtc);
+        let request = [uds::READ_DTC_INFO, uds::dtc::REPORT_EXTENDED_DATA_BY_DTC, hi, mid, lo, record_number];
+        let response = Self::send_message(port, tx_id, rx_id, &request)?;
+        dtc_info::parse_extended_data(&response, record_number)
+    }
+
+    /// Read every DTC the ECU supports, whatever its status (0x19 0x0A)
+    pub fn read_supported_dtcs(
+        port: &mut Box<dyn serialport::SerialPort>,
+        tx_id: u32,
+        rx_id: u32,
+    ) -> Result<Vec<Dtc>, String> {
+        let request = [uds::READ_DTC_INFO, uds::dtc::REPORT_SUPPORTED];
+        let response = Self::send_message(port, tx_id, rx_id, &request)?;
+        dtc_info::parse_supported_dtcs(&response)
+    }
+
     /// Read data by identifier via D-CAN
     pub fn read_data_by_id(
         port: &mut Box<dyn serialport::SerialPort>,
@@ -647,6 +767,33 @@ impl DCanHandler {
         }
     }
 
+    /// Send a functional TesterPresent and collect the response CAN IDs of
+    /// every ECU answering within `window`, in order of arrival
+    pub fn discover_ecus(
+        port: &mut Box<dyn serialport::SerialPort>,
+        window: Duration,
+    ) -> Result<Vec<u32>, String> {
+        Self::switch_to_dcan_mode(port)?;
+        Self::send_without_response(port, can_ids::FUNCTIONAL_REQ, &[0x3E, 0x00])?;
+
+        let mut responders = Vec::new();
+        let start = Instant::now();
+        while start.elapsed() < window {
+            let remaining = window.saturating_sub(start.elapsed());
+            let Some((id, data)) = Self::receive_any_can_frame(port, remaining)? else {
+                break;
+            };
+            let positive = IsoTpFrame::from_can_data(&data)
+                .is_ok_and(|frame| frame.frame_type == 0x00 && frame.data.first() == Some(&0x7E));
+            if positive && !responders.contains(&id) {
+                log::info!("ECU answering on D-CAN ID 0x{:03X}", id);
+                responders.push(id);
+            }
+        }
+
+        Ok(responders)
+    }
+
     /// Execute routine control via D-CAN
     pub fn routine_control(
         port: &mut Box<dyn serialport::SerialPort>,
@@ -688,11 +835,48 @@ impl DCanHandler {
 pub fn detect_ecu_protocol(
     port: &mut Box<dyn serialport::SerialPort>,
     ecu_name: &str,
+) -> Result<String, String> {
+    // Get K-Line address for ECU
+    let kline_addr = match ecu_name.to_uppercase().as_str() {
+        "DDE" | "DME" => Some(0x12),
+        "EGS" => Some(0x32),
+        "DSC" => Some(0x44),
+        "KOMBI" => Some(0x60),
+        "FRM" => Some(0x68),
+        "ACSM" => Some(0x6C),
+        "CAS" => Some(0x40),
+        _ => None,
+    };
+    let can = can_ids::for_ecu(ecu_name);
+    if can.is_none() && kline_addr.is_none() {
+        return Err(format!("Unknown ECU: {}", ecu_name));
+    }
+
+    probe_ecu(port, ecu_name, can, kline_addr)
+}
+
+/// Detect which protocol an ECU answers on, at the addresses of its `EcuInfo`
+pub fn detect_protocol(
+    port: &mut Box<dyn serialport::SerialPort>,
+    ecu: &EcuInfo,
+) -> Result<String, String> {
+    if ecu.can_ids().is_none() && ecu.kline_address.is_none() {
+        return Err(format!("No address for {}", ecu.id));
+    }
+    probe_ecu(port, &ecu.id, ecu.can_ids(), ecu.kline_address)
+}
+
+/// TesterPresent on D-CAN first, then a fast init on K-Line
+fn probe_ecu(
+    port: &mut Box<dyn serialport::SerialPort>,
+    ecu_name: &str,
+    can: Option<(u32, u32)>,
+    kline_addr: Option<u8>,
 ) -> Result<String, String> {
     use crate::kline::KLineHandler;
 
     // First try D-CAN if ECU has known CAN IDs
-    if let Some((tx_id, rx_id)) = can_ids::for_ecu(ecu_name) {
+    if let Some((tx_id, rx_id)) = can {
         // Switch to D-CAN mode
         DCanHandler::switch_to_dcan_mode(port)?;
 
@@ -709,21 +893,13 @@ pub fn detect_ecu_protocol(
         }
     }
 
+    let Some(kline_addr) = kline_addr else {
+        return Err(format!("ECU {} not responding on D-CAN", ecu_name));
+    };
+
     // Try K-Line
     DCanHandler::switch_to_kline_mode(port)?;
 
-    // Get K-Line address for ECU
-    let kline_addr = match ecu_name.to_uppercase().as_str() {
-        "DDE" | "DME" => 0x12,
-        "EGS" => 0x32,
-        "DSC" => 0x44,
-        "KOMBI" => 0x60,
-        "FRM" => 0x68,
-        "ACSM" => 0x6C,
-        "CAS" => 0x40,
-        _ => return Err(format!("Unknown ECU: {}", ecu_name)),
-    };
-
     // Try fast init
     let source = 0xF1;
     match KLineHandler::init_fast(port, kline_addr, source) {
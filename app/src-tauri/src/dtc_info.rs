@@ -0,0 +1,460 @@
+//! DTC detail response parsing
+//!
+//! UDS ReadDTCInformation (0x19) sub-functions beyond reportDTCByStatusMask:
+//! DTC count, snapshot identification and records, extended data records
+//! and the supported DTC list. Requests are sent by `DCanHandler`.
+//!
+//! KWP2000 ReadStatusOfDTC (0x17) with the BMW environmental conditions
+//! (Umweltbedingungen) stored with every fault.
+
+use crate::bmw::{calculate_diesel_did_value, diesel_did_length, Dtc};
+use crate::constants::{kwp, uds};
+use bmw_diag_core::dtc_service::{DtcDialect, DtcRecord};
+use serde::{Deserialize, Serialize};
+
+/// All snapshot / extended data records
+pub const ALL_RECORDS: u8 = 0xFF;
+
+/// Extended data record numbers
+pub mod extended_record {
+    pub const OCCURRENCE_COUNTER: u8 = 0x01;
+    pub const AGING_COUNTER: u8 = 0x02;
+    pub const FIRST_MILEAGE: u8 = 0x03;
+    pub const LAST_MILEAGE: u8 = 0x04;
+}
+
+/// Result of reportNumberOfDTCByStatusMask (0x01)
+#[derive(Debug, Clone, Serialize)]
+pub struct DtcCount {
+    pub availability_mask: u8,
+    /// DTC format identifier (0x00 SAE J2012, 0x01 ISO 14229-1, ...)
+    pub format: u8,
+    pub count: u16,
+}
+
+/// One entry of reportDTCSnapshotIdentification (0x03)
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotIdentification {
+    pub dtc: Dtc,
+    pub record_number: u8,
+}
+
+/// One decoded DID inside a snapshot record
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotValue {
+    pub did: u16,
+    pub name: String,
+    pub value: f64,
+    pub unit: String,
+    pub raw: Vec<u8>,
+}
+
+/// One snapshot record of reportDTCSnapshotRecordByDTCNumber (0x04)
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotRecord {
+    pub record_number: u8,
+    pub values: Vec<SnapshotValue>,
+    /// Bytes left after the first DID of unknown length
+    pub undecoded: Vec<u8>,
+}
+
+/// Result of reportDTCSnapshotRecordByDTCNumber (0x04)
+#[derive(Debug, Clone, Serialize)]
+pub struct DtcSnapshot {
+    pub dtc: Dtc,
+    pub records: Vec<SnapshotRecord>,
+}
+
+/// One extended data record of reportDTCExtDataRecordByDTCNumber (0x06)
+#[derive(Debug, Clone, Serialize)]
+pub struct ExtendedDataRecord {
+    pub record_number: u8,
+    pub name: String,
+    pub value: Option<u32>,
+    pub unit: String,
+    pub raw: Vec<u8>,
+}
+
+/// Result of reportDTCExtDataRecordByDTCNumber (0x06)
+#[derive(Debug, Clone, Serialize)]
+pub struct DtcExtendedData {
+    pub dtc: Dtc,
+    pub occurrence_counter: Option<u32>,
+    pub aging_counter: Option<u32>,
+    pub first_mileage_km: Option<u32>,
+    pub last_mileage_km: Option<u32>,
+    pub records: Vec<ExtendedDataRecord>,
+}
+
+/// Check the positive response header, returns the payload after it
+fn payload(response: &[u8], sub_function: u8) -> Result<&[u8], String> {
+    match response {
+        [sid, sub, rest @ ..]
+            if *sid == uds::READ_DTC_INFO + uds::POSITIVE_RESPONSE_OFFSET
+                && *sub == sub_function =>
+        {
+            Ok(rest)
+        }
+        [sid, _, nrc, ..] if *sid == uds::NEGATIVE_RESPONSE => {
+            Err(format!("Read DTC information failed: NRC 0x{:02X}", nrc))
+        }
+        _ => Err(format!("Unexpected response: {:02X?}", response)),
+    }
+}
+
+/// 3-byte DTC plus status byte
+fn dtc_with_status(bytes: &[u8]) -> Result<Dtc, String> {
+    Dtc::from_uds_bytes(bytes).ok_or_else(|| format!("Truncated DTC: {:02X?}", bytes))
+}
+
+/// 3-byte DTC as sent in requests
+pub fn dtc_request_bytes(dtc: u32) -> [u8; 3] {
+    [(dtc >> 16) as u8, (dtc >> 8) as u8, dtc as u8]
+}
+
+/// Parse a 0x59 0x01 response
+pub fn parse_dtc_count(response: &[u8]) -> Result<DtcCount, String> {
+    let data = payload(response, uds::dtc::REPORT_NUMBER_BY_STATUS_MASK)?;
+    if data.len() < 4 {
+        return Err(format!("DTC count response too short: {:02X?}", response));
+    }
+
+    Ok(DtcCount {
+        availability_mask: data[0],
+        format: data[1],
+        count: u16::from_be_bytes([data[2], data[3]]),
+    })
+}
+
+/// Parse a 0x59 0x03 response
+pub fn parse_snapshot_identification(response: &[u8]) -> Result<Vec<SnapshotIdentification>, String> {
+    let data = payload(response, uds::dtc::REPORT_SNAPSHOT_IDENTIFICATION)?;
+
+    // [DTC_HI] [DTC_MID] [DTC_LO] [RECORD_NUMBER] ...
+    Ok(data
+        .chunks_exact(4)
+        .filter_map(|chunk| {
+            // No status byte in this report
+            let dtc = Dtc::from_uds_bytes(&[chunk[0], chunk[1], chunk[2], 0])?;
+            Some(SnapshotIdentification {
+                dtc,
+                record_number: chunk[3],
+            })
+        })
+        .collect())
+}
+
+/// Parse a 0x59 0x04 response
+///
+/// Each record lists its DIDs; values are decoded through the diesel DID
+/// table. A DID of unknown length ends decoding of the record, the rest of
+/// the response is returned as `undecoded`.
+pub fn parse_snapshot_records(response: &[u8]) -> Result<DtcSnapshot, String> {
+    let data = payload(response, uds::dtc::REPORT_SNAPSHOT_BY_DTC)?;
+    if data.len() < 4 {
+        return Err(format!("Snapshot response too short: {:02X?}", response));
+    }
+
+    let dtc = dtc_with_status(&data[..4])?;
+    let mut records = Vec::new();
+    let mut rest = &data[4..];
+
+    // [RECORD_NUMBER] [DID_COUNT] ([DID_HI] [DID_LO] [DATA...])*
+    while let [record_number, did_count, tail @ ..] = rest {
+        let mut record = SnapshotRecord {
+            record_number: *record_number,
+            values: Vec::new(),
+            undecoded: Vec::new(),
+        };
+        rest = tail;
+
+        for _ in 0..*did_count {
+            let [hi, lo, tail @ ..] = rest else {
+                break;
+            };
+            let did = u16::from_be_bytes([*hi, *lo]);
+
+            let Some(len) = diesel_did_length(did).filter(|len| *len <= tail.len()) else {
+                record.undecoded = rest.to_vec();
+                rest = &[];
+                break;
+            };
+
+            let raw = &tail[..len];
+            if let Some((value, unit, name)) = calculate_diesel_did_value(did, raw) {
+                record.values.push(SnapshotValue {
+                    did,
+                    name,
+                    value,
+                    unit,
+                    raw: raw.to_vec(),
+                });
+            }
+            rest = &tail[len..];
+        }
+
+        records.push(record);
+    }
+
+    Ok(DtcSnapshot { dtc, records })
+}
+
+/// Name, length and unit of an extended data record
+fn extended_record_info(record_number: u8) -> Option<(&'static str, usize, &'static str)> {
+    match record_number {
+        extended_record::OCCURRENCE_COUNTER => Some(("Occurrence Counter", 1, "")),
+        extended_record::AGING_COUNTER => Some(("Aging Counter", 1, "")),
+        extended_record::FIRST_MILEAGE => Some(("First Occurrence Mileage", 3, "km")),
+        extended_record::LAST_MILEAGE => Some(("Last Occurrence Mileage", 3, "km")),
+        _ => None,
+    }
+}
+
+/// Parse a 0x59 0x06 response
+///
+/// `requested` is the record number sent in the request. For a single
+/// record the remaining bytes belong to it; when reading all records an
+/// unknown record number ends parsing and keeps the remaining bytes raw.
+pub fn parse_extended_data(response: &[u8], requested: u8) -> Result<DtcExtendedData, String> {
+    let data = payload(response, uds::dtc::REPORT_EXTENDED_DATA_BY_DTC)?;
+    if data.len() < 4 {
+        return Err(format!("Extended data response too short: {:02X?}", response));
+    }
+
+    let dtc = dtc_with_status(&data[..4])?;
+    let mut records = Vec::new();
+    let mut rest = &data[4..];
+
+    while let [record_number, tail @ ..] = rest {
+        let info = extended_record_info(*record_number);
+        let len = match info {
+            _ if requested != ALL_RECORDS => tail.len(),
+            Some((_, len, _)) if len <= tail.len() => len,
+            _ => tail.len(),
+        };
+
+        let raw = &tail[..len];
+        let (name, unit) = match info {
+            Some((name, _, unit)) => (name.to_string(), unit.to_string()),
+            None => (format!("Record 0x{:02X}", record_number), String::new()),
+        };
+        let value = (!raw.is_empty() && raw.len() <= 4)
+            .then(|| raw.iter().fold(0u32, |acc, b| (acc << 8) | *b as u32));
+
+        records.push(ExtendedDataRecord {
+            record_number: *record_number,
+            name,
+            value,
+            unit,
+            raw: raw.to_vec(),
+        });
+        rest = &tail[len..];
+    }
+
+    let value_of = |number: u8| {
+        records
+            .iter()
+            .find(|r| r.record_number == number)
+            .and_then(|r| r.value)
+    };
+
+    Ok(DtcExtendedData {
+        occurrence_counter: value_of(extended_record::OCCURRENCE_COUNTER),
+        aging_counter: value_of(extended_record::AGING_COUNTER),
+        first_mileage_km: value_of(extended_record::FIRST_MILEAGE),
+        last_mileage_km: value_of(extended_record::LAST_MILEAGE),
+        dtc,
+        records,
+    })
+}
+
+/// Parse a 0x59 0x0A response
+pub fn parse_supported_dtcs(response: &[u8]) -> Result<Vec<Dtc>, String> {
+    let data = payload(response, uds::dtc::REPORT_SUPPORTED)?;
+
+    // [AVAILABILITY_MASK] ([DTC_HI] [DTC_MID] [DTC_LO] [STATUS])*
+    Ok(data
+        .get(1..)
+        .unwrap_or_default()
+        .chunks_exact(4)
+        .filter_map(Dtc::from_uds_bytes)
+        .collect())
+}
+
+// ============================================================================
+// KWP2000 ENVIRONMENTAL CONDITIONS
+// ============================================================================
+
+/// Length of one environment block (first or last occurrence)
+const ENVIRONMENT_BLOCK_LEN: usize = 7;
+
+/// Conditions recorded at one occurrence of a fault
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EnvironmentConditions {
+    pub mileage_km: Option<u32>,
+    pub rpm: Option<u16>,
+    pub coolant_temp_c: Option<f64>,
+    pub voltage_v: Option<f64>,
+}
+
+impl EnvironmentConditions {
+    /// Decode one block: km (3 bytes), RPM (2 bytes), coolant A-40, voltage A*0.1
+    fn from_block(block: &[u8]) -> Self {
+        if block.len() < ENVIRONMENT_BLOCK_LEN {
+            return Self::default();
+        }
+
+        Self {
+            mileage_km: Some(u32::from_be_bytes([0, block[0], block[1], block[2]])),
+            rpm: Some(u16::from_be_bytes([block[3], block[4]])),
+            coolant_temp_c: Some(block[5] as f64 - 40.0),
+            voltage_v: Some(block[6] as f64 / 10.0),
+        }
+    }
+}
+
+/// Environment data stored by a KWP ECU with one fault
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DtcEnvironment {
+    pub frequency_counter: Option<u8>,
+    pub logistic_counter: Option<u8>,
+    pub first: EnvironmentConditions,
+    pub last: EnvironmentConditions,
+    /// Environment bytes as received, for layouts not decoded here
+    pub raw: Vec<u8>,
+}
+
+/// KWP ReadStatusOfDTC request for one 2-byte fault code
+pub fn kwp_status_request(code: u16) -> [u8; 3] {
+    let [hi, lo] = code.to_be_bytes();
+    [kwp::READ_STATUS_OF_DTC, hi, lo]
+}
+
+/// Parse a 0x57 response
+///
+/// Layout: `[0x57] [count] [DTC_HI] [DTC_LO] [STATUS]`, then the frequency
+/// counter, the logistic counter and the first and last occurrence blocks.
+/// Missing trailing fields are left empty.
+pub fn parse_kwp_dtc_status(response: &[u8]) -> Result<(Dtc, DtcEnvironment), String> {
+    match response.first() {
+        Some(&sid) if sid == kwp::READ_STATUS_OF_DTC + kwp::POSITIVE_RESPONSE_OFFSET => {}
+        Some(&kwp::NEGATIVE_RESPONSE) => {
+            let nrc = response.get(2).copied().unwrap_or(0);
+            return Err(format!("Read status of DTC failed: NRC 0x{:02X}", nrc));
+        }
+        _ => return Err(format!("Unexpected response: {:02X?}", response)),
+    }
+
+    let [hi, lo, status] = response
+        .get(2..5)
+        .and_then(|bytes| <[u8; 3]>::try_from(bytes).ok())
+        .ok_or_else(|| format!("Status response too short: {:02X?}", response))?;
+    let dtc = Dtc::from_record(&DtcRecord {
+        code: u16::from_be_bytes([hi, lo]) as u32,
+        code_len: 2,
+        status,
+        dialect: DtcDialect::Kwp2000,
+    });
+    let env = &response[5..];
+
+    let block = |index: usize| {
+        env.get(2 + index * ENVIRONMENT_BLOCK_LEN..)
+            .map(EnvironmentConditions::from_block)
+            .unwrap_or_default()
+    };
+
+    Ok((
+        dtc,
+        DtcEnvironment {
+            frequency_counter: env.first().copied(),
+            logistic_counter: env.get(1).copied(),
+            first: block(0),
+            last: block(1),
+            raw: env.to_vec(),
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dtc_count() {
+        let count = parse_dtc_count(&[0x59, 0x01, 0xFF, 0x01, 0x00, 0x07]).unwrap();
+        assert_eq!(count.count, 7);
+        assert_eq!(count.format, 0x01);
+
+        assert!(parse_dtc_count(&[0x7F, 0x19, 0x31]).unwrap_err().contains("0x31"));
+    }
+
+    #[test]
+    fn test_parse_snapshot_records_decodes_dids() {
+        // DTC 0x29CD00, status 0x09, record 1 with coolant temp and RPM
+        let response = [
+            0x59, 0x04, 0x29, 0xCD, 0x00, 0x09, 0x01, 0x02, 0x39, 0xD3, 0x82, 0x39, 0xE0, 0x03,
+            0x20,
+        ];
+        let snapshot = parse_snapshot_records(&response).unwrap();
+        assert_eq!(snapshot.dtc.bmw_code, "29CD00");
+        assert!(snapshot.dtc.status.confirmed);
+
+        let values = &snapshot.records[0].values;
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[0].value, 90.0);
+        assert_eq!(values[1].value, 800.0);
+    }
+
+    #[test]
+    fn test_parse_snapshot_records_unknown_did() {
+        let response = [0x59, 0x04, 0x29, 0xCD, 0x00, 0x09, 0x01, 0x01, 0xF1, 0x90, 0xAA];
+        let snapshot = parse_snapshot_records(&response).unwrap();
+        assert!(snapshot.records[0].values.is_empty());
+        assert_eq!(snapshot.records[0].undecoded, vec![0xF1, 0x90, 0xAA]);
+    }
+
+    #[test]
+    fn test_parse_extended_data_all_records() {
+        let response = [
+            0x59, 0x06, 0x29, 0xCD, 0x00, 0x09, 0x01, 0x05, 0x02, 0x28, 0x03, 0x01, 0xE2, 0x40,
+            0x04, 0x01, 0xE5, 0x10,
+        ];
+        let data = parse_extended_data(&response, ALL_RECORDS).unwrap();
+        assert_eq!(data.occurrence_counter, Some(5));
+        assert_eq!(data.aging_counter, Some(40));
+        assert_eq!(data.first_mileage_km, Some(123_456));
+        assert_eq!(data.last_mileage_km, Some(124_176));
+    }
+
+    #[test]
+    fn test_parse_supported_dtcs() {
+        let response = [0x59, 0x0A, 0xFF, 0x29, 0xCD, 0x00, 0x00, 0x2A, 0x82, 0x00, 0x08];
+        let dtcs = parse_supported_dtcs(&response).unwrap();
+        assert_eq!(dtcs.len(), 2);
+        assert!(dtcs[1].status.confirmed);
+    }
+
+    #[test]
+    fn test_parse_kwp_dtc_status_environment() {
+        let response = [
+            0x57, 0x01, 0x2A, 0x82, 0x24, // DTC 2A82, status
+            0x03, 0x28, // frequency, logistic counter
+            0x01, 0xE2, 0x40, 0x02, 0xEE, 0x82, 0x8C, // first: 123456 km, 750 rpm, 90 °C, 14.0 V
+            0x01, 0xE5, 0x10, 0x0B, 0xB8, 0x87, 0x7D, // last: 124176 km, 3000 rpm, 95 °C, 12.5 V
+        ];
+        let (dtc, env) = parse_kwp_dtc_status(&response).unwrap();
+        assert_eq!(dtc.bmw_code, "2A82");
+        assert_eq!(env.frequency_counter, Some(3));
+        assert_eq!(env.logistic_counter, Some(40));
+        assert_eq!(env.first.mileage_km, Some(123_456));
+        assert_eq!(env.first.rpm, Some(750));
+        assert_eq!(env.last.coolant_temp_c, Some(95.0));
+        assert_eq!(env.last.voltage_v, Some(12.5));
+    }
+
+    #[test]
+    fn test_parse_kwp_dtc_status_without_environment() {
+        let (_, env) = parse_kwp_dtc_status(&[0x57, 0x01, 0x2A, 0x82, 0x24]).unwrap();
+        assert_eq!(env, DtcEnvironment::default());
+    }
+}
@@ -0,0 +1,437 @@
+//! Dynamically Defined Identifiers
+//!
+//! Packs a set of DIDs into one composite identifier so the whole set is
+//! sampled with a single request: KWP2000 0x2C DynamicallyDefineLocalIdentifier
+//! read with 0x21, or UDS 0x2C DynamicallyDefineDataIdentifier read with 0x22.
+//! The composite record is split back into the source DIDs by their data
+//! lengths. ECUs that reject 0x2C are read one DID at a time.
+
+use crate::bmw::{diesel_did_length, DidValue};
+use crate::pid_commands::{did_value, request_did};
+use bmw_diag_core::dtc_service::{DtcDialect, DtcTransport};
+use bmw_diag_core::obd_service::exchange;
+
+/// KWP local identifier the composite record is defined under
+pub const KWP_DYNAMIC_LOCAL_ID: u8 = 0xF0;
+/// UDS composite DID; in the 0xF2xx range so 0x2A can schedule it too
+pub const UDS_DYNAMIC_DID: u16 = 0xF200;
+
+const DYNAMICALLY_DEFINE: u8 = 0x2C;
+const KWP_DEFINE_BY_COMMON_ID: u8 = 0x02;
+const KWP_CLEAR: u8 = 0x04;
+const UDS_DEFINE_BY_ID: u8 = 0x01;
+const UDS_CLEAR: u8 = 0x03;
+const NEGATIVE_RESPONSE: u8 = 0x7F;
+
+/// Composite record size that still fits a short-header K-Line message
+const MAX_PACKED_BYTES_KWP: usize = 60;
+/// Composite record size for UDS, kept well inside one ISO-TP message
+const MAX_PACKED_BYTES_UDS: usize = 250;
+
+/// One source DID inside a composite identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PackedDid {
+    pub did: u16,
+    /// Data bytes the DID contributes to the record
+    pub length: usize,
+}
+
+/// Layout of a composite identifier
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DynamicDefinition {
+    pub dialect: DtcDialect,
+    /// Composite identifier; a local identifier (low byte) for KWP
+    pub id: u16,
+    pub slots: Vec<PackedDid>,
+}
+
+impl DynamicDefinition {
+    /// Pack `dids` in order until the record is full
+    ///
+    /// Returns the layout and the DIDs that could not be packed, either
+    /// because their length is unknown or because there was no room left.
+    pub fn pack(dialect: DtcDialect, dids: &[u16]) -> (Self, Vec<u16>) {
+        match dialect {
+            DtcDialect::Kwp2000 => Self::pack_as(
+                dialect,
+                KWP_DYNAMIC_LOCAL_ID as u16,
+                dids,
+                MAX_PACKED_BYTES_KWP,
+            ),
+            DtcDialect::Uds => Self::pack_as(dialect, UDS_DYNAMIC_DID, dids, MAX_PACKED_BYTES_UDS),
+        }
+    }
+
+    /// Pack under identifier `id` into a record of at most `max_bytes`
+    pub fn pack_as(
+        dialect: DtcDialect,
+        id: u16,
+        dids: &[u16],
+        max_bytes: usize,
+    ) -> (Self, Vec<u16>) {
+        let mut slots = Vec::new();
+        let mut rest = Vec::new();
+        let mut used = 0;
+        for &did in dids {
+            match diesel_did_length(did) {
+                Some(length) if used + length <= max_bytes => {
+                    slots.push(PackedDid { did, length });
+                    used += length;
+                }
+                _ => rest.push(did),
+            }
+        }
+
+        (Self { dialect, id, slots }, rest)
+    }
+
+    /// Total record length in bytes
+    pub fn record_length(&self) -> usize {
+        self.slots.iter().map(|slot| slot.length).sum()
+    }
+
+    /// 0x2C requests defining the record
+    ///
+    /// KWP defines one source per request, UDS takes them all at once.
+    pub fn define_requests(&self) -> Vec<Vec<u8>> {
+        match self.dialect {
+            DtcDialect::Kwp2000 => {
+                let mut position = 1;
+                self.slots
+                    .iter()
+                    .map(|slot| {
+                        let [hi, lo] = slot.did.to_be_bytes();
+                        let request = vec![
+                            DYNAMICALLY_DEFINE,
+                            self.id as u8,
+                            KWP_DEFINE_BY_COMMON_ID,
+                            position,
+                            slot.length as u8,
+                            hi,
+                            lo,
+                            0x01, // position in the source record
+                        ];
+                        position += slot.length as u8;
+                        request
+                    })
+                    .collect()
+            }
+            DtcDialect::Uds => {
+                let [hi, lo] = self.id.to_be_bytes();
+                let mut request = vec![DYNAMICALLY_DEFINE, UDS_DEFINE_BY_ID, hi, lo];
+                for slot in &self.slots {
+                    request.extend_from_slice(&slot.did.to_be_bytes());
+                    request.push(0x01); // position in the source record
+                    request.push(slot.length as u8);
+                }
+                vec![request]
+            }
+        }
+    }
+
+    /// 0x2C request clearing the record
+    pub fn clear_request(&self) -> Vec<u8> {
+        match self.dialect {
+            DtcDialect::Kwp2000 => vec![DYNAMICALLY_DEFINE, self.id as u8, KWP_CLEAR],
+            DtcDialect::Uds => {
+                let [hi, lo] = self.id.to_be_bytes();
+                vec![DYNAMICALLY_DEFINE, UDS_CLEAR, hi, lo]
+            }
+        }
+    }
+
+    /// Request reading the record (0x21 / 0x22)
+    pub fn read_request(&self) -> Vec<u8> {
+        match self.dialect {
+            DtcDialect::Kwp2000 => vec![0x21, self.id as u8],
+            DtcDialect::Uds => {
+                let [hi, lo] = self.id.to_be_bytes();
+                vec![0x22, hi, lo]
+            }
+        }
+    }
+
+    /// Split the composite record (without response header) by source DID
+    pub fn split<'a>(&self, record: &'a [u8]) -> Result<Vec<(u16, &'a [u8])>, String> {
+        if record.len() < self.record_length() {
+            return Err(format!(
+                "Composite record too short: {} of {} bytes",
+                record.len(),
+                self.record_length()
+            ));
+        }
+
+        let mut offset = 0;
+        Ok(self
+            .slots
+            .iter()
+            .map(|slot| {
+                let data = &record[offset..offset + slot.length];
+                offset += slot.length;
+                (slot.did, data)
+            })
+            .collect())
+    }
+
+    /// Define the record on the ECU
+    ///
+    /// Returns the NRC if the ECU rejects 0x2C.
+    pub fn install(&self, transport: &mut dyn DtcTransport) -> Result<Option<u8>, String> {
+        // Drop whatever an earlier session left behind
+        let _ = exchange(transport, &self.clear_request());
+
+        for request in self.define_requests() {
+            match exchange(transport, &request)?.as_slice() {
+                [0x6C, ..] => {}
+                [NEGATIVE_RESPONSE, _, nrc, ..] => return Ok(Some(*nrc)),
+                response => return Err(format!("Unexpected response: {:02X?}", response)),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Remove the record from the ECU
+    pub fn remove(&self, transport: &mut dyn DtcTransport) -> Result<(), String> {
+        match exchange(transport, &self.clear_request())?.as_slice() {
+            [0x6C, ..] => Ok(()),
+            [NEGATIVE_RESPONSE, _, nrc, ..] => Err(format!("Negative response: NRC 0x{:02X}", nrc)),
+            response => Err(format!("Unexpected response: {:02X?}", response)),
+        }
+    }
+
+    /// Composite record from a read response
+    fn record<'a>(&self, response: &'a [u8]) -> Result<&'a [u8], String> {
+        let [hi, lo] = self.id.to_be_bytes();
+        match (self.dialect, response) {
+            (DtcDialect::Kwp2000, [0x61, id, record @ ..]) if *id == lo => Ok(record),
+            (DtcDialect::Uds, [0x62, h, l, record @ ..]) if (*h, *l) == (hi, lo) => Ok(record),
+            (_, [NEGATIVE_RESPONSE, _, nrc, ..]) => {
+                Err(format!("Negative response: NRC 0x{:02X}", nrc))
+            }
+            _ => Err(format!("Unexpected response: {:02X?}", response)),
+        }
+    }
+}
+
+/// Reads a DID set through a composite identifier where the ECU allows it
+#[derive(Debug, Clone)]
+pub struct DynamicReader {
+    packed: Option<DynamicDefinition>,
+    /// DIDs read one request each
+    single: Vec<u16>,
+}
+
+impl DynamicReader {
+    /// Define the composite identifier on the ECU
+    ///
+    /// Falls back to per-DID reads when the ECU rejects 0x2C; only transport
+    /// failures are returned as errors.
+    pub fn define(
+        transport: &mut dyn DtcTransport,
+        dialect: DtcDialect,
+        dids: &[u16],
+    ) -> Result<Self, String> {
+        let unpacked = Self {
+            packed: None,
+            single: dids.to_vec(),
+        };
+
+        let (definition, rest) = DynamicDefinition::pack(dialect, dids);
+        // A single DID gains nothing from packing
+        if definition.slots.len() < 2 {
+            return Ok(unpacked);
+        }
+
+        if let Some(nrc) = definition.install(transport)? {
+            log::info!(
+                "ECU rejected dynamic identifier (NRC 0x{:02X}), reading DIDs one by one",
+                nrc
+            );
+            return Ok(unpacked);
+        }
+
+        log::info!(
+            "Defined dynamic identifier with {} DIDs ({} bytes), {} read singly",
+            definition.slots.len(),
+            definition.record_length(),
+            rest.len()
+        );
+        Ok(Self {
+            packed: Some(definition),
+            single: rest,
+        })
+    }
+
+    /// Whether DIDs are read through the composite identifier
+    pub fn is_packed(&self) -> bool {
+        self.packed.is_some()
+    }
+
+    /// Read every DID once
+    ///
+    /// If the ECU stops serving the composite identifier, its DIDs move to
+    /// per-DID reads. DIDs that fail to read on their own are skipped.
+    pub fn read(&mut self, transport: &mut dyn DtcTransport) -> Result<Vec<DidValue>, String> {
+        let mut values = Vec::new();
+
+        if let Some(definition) = &self.packed {
+            let response = exchange(transport, &definition.read_request())?;
+            match definition.record(&response) {
+                Ok(record) => {
+                    for (did, data) in definition.split(record)? {
+                        values.push(did_value(did, data));
+                    }
+                }
+                Err(e) => {
+                    log::warn!(
+                        "Dynamic identifier read failed ({}), reading DIDs one by one",
+                        e
+                    );
+                    let mut single: Vec<u16> =
+                        definition.slots.iter().map(|slot| slot.did).collect();
+                    single.append(&mut self.single);
+                    self.single = single;
+                    self.packed = None;
+                }
+            }
+        }
+
+        for &did in &self.single {
+            if values.iter().any(|value| value.did == did) {
+                continue;
+            }
+            match request_did(transport, did) {
+                Ok(value) => values.push(value),
+                Err(e) => log::warn!("Failed to read DID 0x{:04X}: {}", did, e),
+            }
+        }
+
+        Ok(values)
+    }
+
+    /// Remove the composite identifier from the ECU
+    pub fn clear(&self, transport: &mut dyn DtcTransport) -> Result<(), String> {
+        match &self.packed {
+            Some(definition) => definition.remove(transport),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bmw::diesel_dids;
+    use std::collections::VecDeque;
+
+    /// Replays canned responses and records the requests
+    struct MockTransport {
+        responses: VecDeque<Vec<u8>>,
+        requests: Vec<Vec<u8>>,
+    }
+
+    impl MockTransport {
+        fn new(responses: &[&[u8]]) -> Self {
+            Self {
+                responses: responses.iter().map(|r| r.to_vec()).collect(),
+                requests: Vec::new(),
+            }
+        }
+    }
+
+    impl DtcTransport for MockTransport {
+        fn request(&mut self, data: &[u8]) -> Result<Vec<u8>, String> {
+            self.requests.push(data.to_vec());
+            self.receive()
+        }
+
+        fn receive(&mut self) -> Result<Vec<u8>, String> {
+            self.responses
+                .pop_front()
+                .ok_or_else(|| "No response received".to_string())
+        }
+    }
+
+    const RAIL: u16 = diesel_dids::FUEL_RAIL_PRESSURE;
+    const EGR: u16 = 0x3960;
+
+    #[test]
+    fn test_pack_skips_unknown_dids() {
+        let (definition, rest) = DynamicDefinition::pack(DtcDialect::Uds, &[RAIL, 0x1234, EGR]);
+        assert_eq!(
+            definition.slots,
+            vec![
+                PackedDid {
+                    did: RAIL,
+                    length: 2
+                },
+                PackedDid {
+                    did: EGR,
+                    length: 1
+                }
+            ]
+        );
+        assert_eq!(rest, vec![0x1234]);
+        assert_eq!(definition.record_length(), 3);
+    }
+
+    #[test]
+    fn test_define_requests() {
+        let (kwp, _) = DynamicDefinition::pack(DtcDialect::Kwp2000, &[RAIL, EGR]);
+        assert_eq!(
+            kwp.define_requests(),
+            vec![
+                vec![0x2C, 0xF0, 0x02, 0x01, 0x02, 0x39, 0x4A, 0x01],
+                vec![0x2C, 0xF0, 0x02, 0x03, 0x01, 0x39, 0x60, 0x01],
+            ]
+        );
+
+        let (uds, _) = DynamicDefinition::pack(DtcDialect::Uds, &[RAIL, EGR]);
+        assert_eq!(
+            uds.define_requests(),
+            vec![vec![
+                0x2C, 0x01, 0xF2, 0x00, 0x39, 0x4A, 0x01, 0x02, 0x39, 0x60, 0x01, 0x01
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_packed_read_splits_record() {
+        let mut transport = MockTransport::new(&[
+            &[0x6C, 0x03, 0xF2, 0x00],             // clear
+            &[0x6C, 0x01, 0xF2, 0x00],             // define
+            &[0x62, 0xF2, 0x00, 0x27, 0x10, 0xFF], // read
+        ]);
+        let mut reader =
+            DynamicReader::define(&mut transport, DtcDialect::Uds, &[RAIL, EGR]).unwrap();
+        assert!(reader.is_packed());
+
+        let values = reader.read(&mut transport).unwrap();
+        assert_eq!(transport.requests.last().unwrap(), &vec![0x22, 0xF2, 0x00]);
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[0].did, RAIL);
+        assert!((values[0].value - 1000.0).abs() < 0.01);
+        assert_eq!(values[1].raw, vec![0xFF]);
+        assert!((values[1].value - 100.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_rejected_define_falls_back_to_single_reads() {
+        let mut transport = MockTransport::new(&[
+            &[0x7F, 0x2C, 0x11], // clear
+            &[0x7F, 0x2C, 0x11], // define
+            &[0x62, 0x39, 0x4A, 0x27, 0x10],
+            &[0x62, 0x39, 0x60, 0x80],
+        ]);
+        let mut reader =
+            DynamicReader::define(&mut transport, DtcDialect::Kwp2000, &[RAIL, EGR]).unwrap();
+        assert!(!reader.is_packed());
+
+        let values = reader.read(&mut transport).unwrap();
+        assert_eq!(
+            values.iter().map(|v| v.did).collect::<Vec<_>>(),
+            vec![RAIL, EGR]
+        );
+        assert_eq!(transport.requests[2], vec![0x22, 0x39, 0x4A]);
+    }
+}
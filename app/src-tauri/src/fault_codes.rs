@@ -0,0 +1,38 @@
+//! BMW Fault Texts
+//!
+//! The fault text database of `bmw_diag_core::fault_codes` as used by the
+//! app: one process-wide database with the bundled texts, plus the JSON/CSV
+//! files from the app data dir loaded at startup.
+
+use crate::bmw::Dtc;
+use bmw_diag_core::fault_codes::{FaultCode, FaultCodeDb};
+use std::sync::{OnceLock, RwLock, RwLockReadGuard};
+
+/// Process-wide database, initialized with the bundled texts
+pub fn global() -> &'static RwLock<FaultCodeDb> {
+    static DB: OnceLock<RwLock<FaultCodeDb>> = OnceLock::new();
+    DB.get_or_init(|| RwLock::new(FaultCodeDb::bundled()))
+}
+
+fn read_global() -> RwLockReadGuard<'static, FaultCodeDb> {
+    global().read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Fill in BMW code, SAE equivalent and texts for DTCs read from `ecus`
+pub fn enrich_dtcs(dtcs: &mut [Dtc], ecus: &[&str], variant: Option<&str>) {
+    let db = read_global();
+
+    for dtc in dtcs {
+        // 2-byte KWP and 3-byte UDS codes are looked up as reported
+        let Some(code) = FaultCode::parse(&dtc.bmw_code) else {
+            continue;
+        };
+
+        if let Some(entry) = db.lookup(ecus, variant, code) {
+            dtc.sae_code = entry.sae.clone();
+            dtc.description = entry.text.preferred().map(str::to_string);
+            dtc.texts = Some(entry.text.clone());
+            dtc.fault_variant = Some(entry.variant.clone());
+        }
+    }
+}
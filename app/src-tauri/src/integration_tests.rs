@@ -77,6 +77,7 @@ mod tests {
                 description: Some("Differential pressure sensor - circuit open".to_string()),
                 is_pending: false,
                 is_confirmed: true,
+                environment: None,
             },
             NewDtc {
                 session_id,
@@ -85,6 +86,7 @@ mod tests {
                 description: Some("DPF soot mass - limit exceeded".to_string()),
                 is_pending: true,
                 is_confirmed: false,
+                environment: None,
             },
             NewDtc {
                 session_id,
@@ -93,6 +95,7 @@ mod tests {
                 description: Some("DPF regeneration - unsuccessful".to_string()),
                 is_pending: false,
                 is_confirmed: true,
+                environment: None,
             },
             NewDtc {
                 session_id,
@@ -101,6 +104,7 @@ mod tests {
                 description: Some("Exhaust gas temperature sensor 1 - signal implausible".to_string()),
                 is_pending: false,
                 is_confirmed: true,
+                environment: None,
             },
         ]
     }
@@ -114,6 +118,7 @@ mod tests {
                 description: Some("EGR valve - stuck open".to_string()),
                 is_pending: false,
                 is_confirmed: true,
+                environment: None,
             },
             NewDtc {
                 session_id,
@@ -122,6 +127,7 @@ mod tests {
                 description: Some("EGR cooler bypass valve - malfunction".to_string()),
                 is_pending: true,
                 is_confirmed: false,
+                environment: None,
             },
         ]
     }
@@ -135,6 +141,7 @@ mod tests {
                 description: Some("Injector cylinder 1 - circuit malfunction".to_string()),
                 is_pending: false,
                 is_confirmed: true,
+                environment: None,
             },
             NewDtc {
                 session_id,
@@ -143,6 +150,7 @@ mod tests {
                 description: Some("Injector cylinder 2 - circuit malfunction".to_string()),
                 is_pending: false,
                 is_confirmed: true,
+                environment: None,
             },
         ]
     }
@@ -156,6 +164,7 @@ mod tests {
                 description: Some("ABS wheel speed sensor front left - signal missing".to_string()),
                 is_pending: false,
                 is_confirmed: true,
+                environment: None,
             },
             NewDtc {
                 session_id,
@@ -164,6 +173,7 @@ mod tests {
                 description: Some("Steering angle sensor - not calibrated".to_string()),
                 is_pending: true,
                 is_confirmed: false,
+                environment: None,
             },
         ]
     }
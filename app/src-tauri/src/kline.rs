@@ -390,6 +390,15 @@ impl KLineHandler {
         let mut echo = vec![0u8; request.len()];
         let _ = port.read(&mut echo);
 
+        Self::receive_response(port)
+    }
+
+    /// Receive one response message without sending a request
+    ///
+    /// Used for responsePending and for answers split over several messages.
+    pub fn receive_response(
+        port: &mut Box<dyn serialport::SerialPort>,
+    ) -> Result<Vec<u8>, String> {
         // Read response with timeout
         let mut response = Vec::new();
         let mut buffer = [0u8; 128];
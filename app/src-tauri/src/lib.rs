@@ -1,20 +1,38 @@
+mod alarms;
 mod bmw;
 mod bmw_commands;
+mod channels;
 mod commands;
 pub mod constants;
 pub mod database;
 mod db_commands;
 mod dcan;
+mod dtc_info;
+mod dynamic_id;
+mod fault_codes;
 mod kline;
+mod live_export;
+mod live_stream;
+mod obd_can;
+mod obd_commands;
+mod periodic;
 mod pid_commands;
+mod profiles;
+mod quick_test;
+mod recording;
 mod serial;
 pub mod validators;
+mod virtual_channels;
 
 #[cfg(test)]
 mod integration_tests;
 
 use database::Database;
 use db_commands::DbState;
+use live_stream::LiveStreamState;
+use obd_commands::ObdCanState;
+use pid_commands::PidCacheState;
+use recording::RecordingState;
 use serial::SerialState;
 use std::sync::Mutex;
 use tauri::Manager;
@@ -24,6 +42,10 @@ pub fn run() {
     tauri::Builder::default()
         .manage(SerialState::new())
         .manage(DbState(Mutex::new(None)))
+        .manage(PidCacheState::default())
+        .manage(ObdCanState::default())
+        .manage(LiveStreamState::default())
+        .manage(RecordingState::default())
         .plugin(tauri_plugin_log::Builder::default().build())
         .setup(|app| {
             // Initialize database
@@ -49,6 +71,33 @@ pub fn run() {
                 }
             }
 
+            // Extra fault text files (versioned JSON/CSV) override the bundled set
+            let fault_dir = app_dir.join("fault-codes");
+            if fault_dir.is_dir() {
+                let mut db = fault_codes::global().write().unwrap_or_else(|e| e.into_inner());
+                if let Err(e) = db.load_dir(&fault_dir) {
+                    log::warn!("Failed to load fault texts: {}", e);
+                }
+            }
+
+            // Extra channel definition files override the bundled tables
+            let channel_dir = app_dir.join("channels");
+            if channel_dir.is_dir() {
+                let mut db = channels::global().write().unwrap_or_else(|e| e.into_inner());
+                if let Err(e) = db.load_dir(&channel_dir) {
+                    log::warn!("Failed to load channel definitions: {}", e);
+                }
+            }
+
+            // Extra engine profiles override the bundled ones
+            let profile_dir = app_dir.join("profiles");
+            if profile_dir.is_dir() {
+                let mut db = profiles::global().write().unwrap_or_else(|e| e.into_inner());
+                if let Err(e) = db.load_dir(&profile_dir) {
+                    log::warn!("Failed to load vehicle profiles: {}", e);
+                }
+            }
+
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
@@ -66,12 +115,19 @@ pub fn run() {
             commands::serial_clear,
             // BMW diagnostic commands
             bmw_commands::bmw_get_ecus,
+            profiles::get_vehicle_profiles,
+            profiles::get_vehicle_profile,
             bmw_commands::bmw_switch_kline,
             bmw_commands::bmw_switch_dcan,
             bmw_commands::bmw_kline_init,
             bmw_commands::bmw_kline_request,
             bmw_commands::bmw_read_dtcs_kline,
+            bmw_commands::bmw_read_dtc_environment_kline,
             bmw_commands::bmw_clear_dtcs_kline,
+            bmw_commands::bmw_emissions_readiness_kline,
+            bmw_commands::bmw_clear_emissions_dtcs_kline,
+            bmw_commands::obd_read_vehicle_info_kline,
+            bmw_commands::obd_read_vehicle_info_dcan,
             bmw_commands::bmw_read_ecu_id,
             bmw_commands::bmw_tester_present,
             // DPF (Diesel Particulate Filter) commands
@@ -106,23 +162,52 @@ pub fn run() {
             bmw_commands::bmw_egs_reset_adaptations,
             // Multi-ECU commands
             bmw_commands::bmw_read_all_dtcs,
+            quick_test::bmw_quick_test,
+            bmw_commands::bmw_clear_dtcs_verified,
+            bmw_commands::bmw_fault_db_info,
             // D-CAN specific commands
             bmw_commands::bmw_read_dtcs_dcan,
+            bmw_commands::bmw_dtc_count_dcan,
+            bmw_commands::bmw_dtc_snapshot_ids_dcan,
+            bmw_commands::bmw_dtc_snapshot_dcan,
+            bmw_commands::bmw_dtc_extended_data_dcan,
+            bmw_commands::bmw_dtc_supported_dcan,
             bmw_commands::bmw_read_dtcs_auto,
             bmw_commands::bmw_detect_protocol,
             bmw_commands::bmw_read_did_dcan,
             bmw_commands::bmw_start_session_dcan,
             bmw_commands::bmw_routine_control_dcan,
+            // Generic OBD-II over CAN
+            obd_commands::obd_can_detect,
+            obd_commands::obd_can_request,
+            obd_commands::obd_can_emissions_readiness,
+            obd_commands::obd_can_clear_emissions_dtcs,
             // PID/Live data commands
             pid_commands::get_available_pids,
+            pid_commands::get_channel_db_info,
+            pid_commands::discover_pids_kline,
+            pid_commands::read_freeze_frame_kline,
             pid_commands::read_pid_kline,
             pid_commands::read_pids_kline,
             // Diesel-specific DID commands (E60 520d M47N2/N47)
             pid_commands::get_diesel_pids,
             pid_commands::read_did_kline,
             pid_commands::read_dids_kline,
+            pid_commands::read_dids_dynamic_kline,
+            pid_commands::read_dids_dynamic_dcan,
             pid_commands::read_diesel_category_kline,
             pid_commands::get_diesel_categories,
+            // Live data streaming
+            live_stream::start_live_stream,
+            live_stream::stop_live_stream,
+            virtual_channels::get_virtual_channels,
+            virtual_channels::set_virtual_channels,
+            alarms::get_alarm_rules,
+            alarms::set_alarm_rules,
+            recording::start_live_recording,
+            recording::stop_live_recording,
+            live_export::export_live_data_csv,
+            live_export::export_live_data_mdf4,
             // Database commands - Vehicles
             db_commands::db_get_vehicles,
             db_commands::db_get_vehicle,
@@ -139,6 +224,16 @@ pub fn run() {
             db_commands::db_add_dtcs,
             db_commands::db_get_dtcs_for_session,
             db_commands::db_get_dtc_history,
+            db_commands::db_get_dtc_clear_events,
+            db_commands::db_get_vehicle_tests,
+            db_commands::db_get_vehicle_test_sessions,
+            db_commands::db_diff_sessions,
+            db_commands::db_diff_latest_sessions,
+            db_commands::db_get_live_data_recordings,
+            db_commands::db_get_live_data,
+            db_commands::db_get_live_data_buckets,
+            db_commands::db_get_live_data_parameters,
+            db_commands::db_get_live_data_markers,
             // Database commands - Settings
             db_commands::db_get_setting,
             db_commands::db_set_setting,
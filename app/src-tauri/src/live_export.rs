@@ -0,0 +1,788 @@
+//! Live Data Export
+//!
+//! Writes a recording for analysis outside the tool:
+//!
+//! - Wide CSV, one column per channel on a common time base, for Excel or
+//!   a DPF analysis spreadsheet. Channels are sampled at different times,
+//!   so cells between samples are filled by the chosen `Interpolation`.
+//! - ASAM MDF 4.10, for asammdf and other measurement tools. Each ECU is one
+//!   channel group with a time master channel; a channel not sampled at a
+//!   record's time is marked invalid there instead of being interpolated.
+
+use crate::constants::limits;
+use crate::database::{LiveDataQuery, LiveDataRecording, LiveDataSnapshot};
+use crate::db_commands::DbState;
+use chrono::{SecondsFormat, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use tauri::State;
+
+/// How CSV cells between two samples of a channel are filled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    /// Left empty unless the channel was sampled at exactly that time
+    None,
+    /// Last sampled value (sample and hold)
+    #[default]
+    Previous,
+    /// Linear between the surrounding samples
+    Linear,
+}
+
+/// Result of an export
+#[derive(Debug, Clone, Serialize)]
+pub struct LiveDataExport {
+    pub path: String,
+    pub channels: usize,
+    /// CSV lines or MDF records, without header
+    pub rows: usize,
+}
+
+/// Samples of one channel, times in ms since the recording start
+#[derive(Debug)]
+struct Channel {
+    ecu: String,
+    name: String,
+    unit: String,
+    points: Vec<(i64, f64)>,
+}
+
+/// Split samples into channels, ordered by ECU and name
+fn channels(snapshots: &[LiveDataSnapshot], start_ms: i64, default_ecu: &str) -> Vec<Channel> {
+    let mut channels: BTreeMap<(String, String), Channel> = BTreeMap::new();
+    for snapshot in snapshots {
+        let ecu = snapshot.ecu.as_deref().unwrap_or(default_ecu).to_string();
+        let channel = channels
+            .entry((ecu.clone(), snapshot.parameter_name.clone()))
+            .or_insert_with(|| Channel {
+                ecu,
+                name: snapshot.parameter_name.clone(),
+                unit: snapshot.unit.clone(),
+                points: Vec::new(),
+            });
+        channel.points.push((
+            snapshot.timestamp.timestamp_millis() - start_ms,
+            snapshot.value,
+        ));
+    }
+
+    let mut channels: Vec<Channel> = channels.into_values().collect();
+    for channel in &mut channels {
+        channel.points.sort_by_key(|&(time, _)| time);
+    }
+    channels
+}
+
+// ============================================================================
+// CSV
+// ============================================================================
+
+/// Wide CSV of `snapshots`; returns the text and the number of rows
+///
+/// Rows are at every sample time, or every `interval_ms` from the first
+/// sample when given. Times are in seconds since `start_ms`; the ECU is
+/// part of the column name when the recording covers more than one.
+pub fn to_csv(
+    snapshots: &[LiveDataSnapshot],
+    start_ms: i64,
+    default_ecu: &str,
+    interpolation: Interpolation,
+    interval_ms: Option<u64>,
+) -> Result<(String, usize), String> {
+    let channels = channels(snapshots, start_ms, default_ecu);
+    let times = time_base(&channels, interval_ms)?;
+    let multi_ecu = channels.iter().any(|ch| ch.ecu != channels[0].ecu);
+
+    let mut csv = String::from("time_s,timestamp");
+    for channel in &channels {
+        let name = if multi_ecu {
+            format!("{}.{} [{}]", channel.ecu, channel.name, channel.unit)
+        } else {
+            format!("{} [{}]", channel.name, channel.unit)
+        };
+        csv.push(',');
+        csv.push_str(&csv_field(&name));
+    }
+    csv.push('\n');
+
+    // Times only grow, so each channel is walked once
+    let mut cursors = vec![0usize; channels.len()];
+    for &time in &times {
+        let timestamp = Utc
+            .timestamp_millis_opt(start_ms + time)
+            .single()
+            .map(|dt| dt.to_rfc3339_opts(SecondsFormat::Millis, true))
+            .unwrap_or_default();
+        let _ = write!(csv, "{:.3},{}", time as f64 / 1000.0, timestamp);
+
+        for (channel, cursor) in channels.iter().zip(cursors.iter_mut()) {
+            csv.push(',');
+            if let Some(value) = value_at(&channel.points, time, interpolation, cursor) {
+                let _ = write!(csv, "{}", value);
+            }
+        }
+        csv.push('\n');
+    }
+
+    Ok((csv, times.len()))
+}
+
+/// Row times: every sample time, or a fixed grid
+fn time_base(channels: &[Channel], interval_ms: Option<u64>) -> Result<Vec<i64>, String> {
+    let times: BTreeSet<i64> = channels
+        .iter()
+        .flat_map(|ch| ch.points.iter().map(|&(time, _)| time))
+        .collect();
+
+    let (Some(interval), Some(&first), Some(&last)) = (interval_ms, times.first(), times.last())
+    else {
+        return Ok(times.into_iter().collect());
+    };
+    if interval == 0 {
+        return Err("Export interval must be greater than 0".to_string());
+    }
+
+    let rows = (last - first) as u64 / interval + 1;
+    if rows > limits::MAX_EXPORT_ROWS as u64 {
+        return Err(format!(
+            "Export would have {} rows (max {}); use a longer interval",
+            rows,
+            limits::MAX_EXPORT_ROWS
+        ));
+    }
+    Ok((0..rows as i64)
+        .map(|i| first + i * interval as i64)
+        .collect())
+}
+
+/// Value of a channel at `time`
+///
+/// `cursor` counts the points at or before the previous, earlier time.
+fn value_at(
+    points: &[(i64, f64)],
+    time: i64,
+    interpolation: Interpolation,
+    cursor: &mut usize,
+) -> Option<f64> {
+    while *cursor < points.len() && points[*cursor].0 <= time {
+        *cursor += 1;
+    }
+    let &(t0, v0) = points.get(cursor.checked_sub(1)?)?;
+
+    match interpolation {
+        _ if t0 == time => Some(v0),
+        Interpolation::None => None,
+        Interpolation::Previous => Some(v0),
+        Interpolation::Linear => {
+            let &(t1, v1) = points.get(*cursor)?;
+            Some(v0 + (v1 - v0) * (time - t0) as f64 / (t1 - t0) as f64)
+        }
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// ============================================================================
+// MDF4
+// ============================================================================
+
+/// MDF4 block types
+mod mdf_id {
+    pub const HD: &[u8; 4] = b"##HD";
+    pub const FH: &[u8; 4] = b"##FH";
+    pub const MD: &[u8; 4] = b"##MD";
+    pub const TX: &[u8; 4] = b"##TX";
+    pub const DG: &[u8; 4] = b"##DG";
+    pub const CG: &[u8; 4] = b"##CG";
+    pub const CN: &[u8; 4] = b"##CN";
+    pub const CC: &[u8; 4] = b"##CC";
+    pub const SI: &[u8; 4] = b"##SI";
+    pub const DT: &[u8; 4] = b"##DT";
+}
+
+/// Size of the file identification block, where the HD block starts
+const MDF_ID_SIZE: usize = 64;
+/// Header shared by all blocks: id, reserved, length, link count
+const MDF_BLOCK_HEADER: usize = 24;
+
+const CN_TYPE_MASTER: u8 = 2;
+const CN_SYNC_TIME: u8 = 1;
+const CN_DATA_FLOAT_LE: u8 = 4;
+const CN_FLAG_INVAL_BIT_VALID: u32 = 0x02;
+const CC_FLAG_RANGE_VALID: u16 = 0x02;
+const SI_TYPE_ECU: u8 = 1;
+const SI_BUS_OTHER: u8 = 1;
+const SI_BUS_CAN: u8 = 2;
+const SI_BUS_K_LINE: u8 = 6;
+
+/// One block; links are indices into `MdfWriter::blocks`
+struct Block {
+    id: &'static [u8; 4],
+    links: Vec<Option<usize>>,
+    data: Vec<u8>,
+}
+
+/// Collects blocks, then lays them out with absolute link addresses
+#[derive(Default)]
+struct MdfWriter {
+    blocks: Vec<Block>,
+}
+
+impl MdfWriter {
+    fn add(&mut self, id: &'static [u8; 4], links: Vec<Option<usize>>, data: Vec<u8>) -> usize {
+        self.blocks.push(Block { id, links, data });
+        self.blocks.len() - 1
+    }
+
+    fn set_link(&mut self, block: usize, link: usize, target: usize) {
+        self.blocks[block].links[link] = Some(target);
+    }
+
+    /// TX or MD block holding zero-terminated UTF-8
+    fn text_block(&mut self, id: &'static [u8; 4], text: &str) -> usize {
+        let mut data = text.as_bytes().to_vec();
+        data.push(0);
+        data.resize(data.len().next_multiple_of(8), 0);
+        self.add(id, Vec::new(), data)
+    }
+
+    fn text(&mut self, text: &str) -> usize {
+        self.text_block(mdf_id::TX, text)
+    }
+
+    fn xml(&mut self, xml: &str) -> usize {
+        self.text_block(mdf_id::MD, xml)
+    }
+
+    fn finish(self) -> Vec<u8> {
+        let block_len = |block: &Block| MDF_BLOCK_HEADER + block.links.len() * 8 + block.data.len();
+
+        let mut addresses = Vec::with_capacity(self.blocks.len());
+        let mut end = MDF_ID_SIZE;
+        for block in &self.blocks {
+            let address = end.next_multiple_of(8);
+            addresses.push(address as u64);
+            end = address + block_len(block);
+        }
+
+        let mut out = Vec::with_capacity(end);
+        out.extend_from_slice(b"MDF     4.10    ");
+        out.extend_from_slice(format!("{:<8.8}", "BMWDiag").as_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&410u16.to_le_bytes());
+        out.resize(MDF_ID_SIZE, 0);
+
+        for (block, &address) in self.blocks.iter().zip(&addresses) {
+            out.resize(address as usize, 0);
+            out.extend_from_slice(block.id);
+            out.extend_from_slice(&[0; 4]);
+            out.extend_from_slice(&(block_len(block) as u64).to_le_bytes());
+            out.extend_from_slice(&(block.links.len() as u64).to_le_bytes());
+            for link in &block.links {
+                let address = link.map_or(0, |index| addresses[index]);
+                out.extend_from_slice(&address.to_le_bytes());
+            }
+            out.extend_from_slice(&block.data);
+        }
+        out
+    }
+}
+
+/// Little-endian field writer for block data
+#[derive(Default)]
+struct Fields(Vec<u8>);
+
+impl Fields {
+    fn u8(mut self, v: u8) -> Self {
+        self.0.push(v);
+        self
+    }
+    fn u16(mut self, v: u16) -> Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+    fn u32(mut self, v: u32) -> Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+    fn u64(mut self, v: u64) -> Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+    fn f64(mut self, v: f64) -> Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+    fn zeros(mut self, n: usize) -> Self {
+        self.0.resize(self.0.len() + n, 0);
+        self
+    }
+}
+
+/// Bus type of the acquisition source, from the session protocol
+fn bus_type(protocol: &str) -> u8 {
+    let protocol = protocol.to_uppercase();
+    if protocol.contains("CAN") {
+        SI_BUS_CAN
+    } else if protocol.contains("K-LINE") || protocol.contains("KLINE") {
+        SI_BUS_K_LINE
+    } else {
+        SI_BUS_OTHER
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// MDF 4.10 file of `snapshots`; returns the bytes and the record count
+///
+/// The master time channel is in seconds since the recording start, which
+/// is also the file's start time. Values are stored as physical values;
+/// each channel carries its unit and a 1:1 conversion with the observed
+/// value range.
+pub fn to_mdf4(
+    snapshots: &[LiveDataSnapshot],
+    recording: &LiveDataRecording,
+    default_ecu: &str,
+    protocol: &str,
+) -> (Vec<u8>, usize) {
+    let start_ms = recording.started_at.timestamp_millis();
+    let channels = channels(snapshots, start_ms, default_ecu);
+    let mut w = MdfWriter::default();
+
+    let start_ns = recording.started_at.timestamp_nanos_opt().unwrap_or(0) as u64;
+    let hd_data = Fields::default()
+        .u64(start_ns)
+        .zeros(8) // tz and dst offsets, time flags, time class, flags
+        .f64(0.0)
+        .f64(0.0);
+    let hd = w.add(mdf_id::HD, vec![None; 6], hd_data.0);
+
+    let fh_comment = w.xml(&format!(
+        "<FHcomment xmlns=\"http://www.asam.net/mdf/v4\"><TX>Live data recording {}</TX>\
+         <tool_id>{}</tool_id><tool_vendor>BMW Diagnostic Tool</tool_vendor>\
+         <tool_version>{}</tool_version></FHcomment>",
+        recording.id,
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+    ));
+    let now_ns = Utc::now().timestamp_nanos_opt().unwrap_or(0) as u64;
+    let fh = w.add(
+        mdf_id::FH,
+        vec![None, Some(fh_comment)],
+        Fields::default().u64(now_ns).zeros(8).0,
+    );
+    w.set_link(hd, 1, fh);
+
+    let hd_comment = w.xml(&format!(
+        "<HDcomment xmlns=\"http://www.asam.net/mdf/v4\"><TX>Session {}, recording {}</TX></HDcomment>",
+        recording.session_id, recording.id
+    ));
+    w.set_link(hd, 5, hd_comment);
+
+    let mut groups: BTreeMap<&str, Vec<&Channel>> = BTreeMap::new();
+    for channel in &channels {
+        groups.entry(&channel.ecu).or_default().push(channel);
+    }
+
+    let mut records = 0;
+    let mut previous_dg = None;
+    for (ecu, group) in groups {
+        let dg = write_group(&mut w, ecu, &group, bus_type(protocol), &mut records);
+        match previous_dg {
+            Some(previous) => w.set_link(previous, 0, dg),
+            None => w.set_link(hd, 0, dg),
+        }
+        previous_dg = Some(dg);
+    }
+
+    (w.finish(), records)
+}
+
+/// Data group with one channel group for the channels of one ECU
+fn write_group(
+    w: &mut MdfWriter,
+    ecu: &str,
+    channels: &[&Channel],
+    bus: u8,
+    records: &mut usize,
+) -> usize {
+    // Record: time, one f64 per channel, then the invalidation bits
+    let times: BTreeSet<i64> = channels
+        .iter()
+        .flat_map(|ch| ch.points.iter().map(|&(time, _)| time))
+        .collect();
+    let data_bytes = 8 * (channels.len() + 1);
+    let inval_bytes = channels.len().div_ceil(8);
+
+    let mut data = Vec::with_capacity(times.len() * (data_bytes + inval_bytes));
+    let mut cursors = vec![0usize; channels.len()];
+    for &time in &times {
+        let mut inval = vec![0u8; inval_bytes];
+        data.extend_from_slice(&(time as f64 / 1000.0).to_le_bytes());
+        for (i, (channel, cursor)) in channels.iter().zip(cursors.iter_mut()).enumerate() {
+            let value = value_at(&channel.points, time, Interpolation::None, cursor);
+            if value.is_none() {
+                inval[i / 8] |= 1 << (i % 8);
+            }
+            data.extend_from_slice(&value.unwrap_or(0.0).to_le_bytes());
+        }
+        data.extend_from_slice(&inval);
+    }
+    *records += times.len();
+    let dt = (!data.is_empty()).then(|| w.add(mdf_id::DT, Vec::new(), data));
+
+    // Channels are linked back to front
+    let mut next_cn = None;
+    for (i, channel) in channels.iter().enumerate().rev() {
+        let name = w.text(&channel.name);
+        let unit = w.text(&channel.unit);
+
+        let (min, max) = channel
+            .points
+            .iter()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &(_, v)| {
+                (min.min(v), max.max(v))
+            });
+        let cc_data = Fields::default()
+            .u8(0) // 1:1 conversion
+            .u8(0)
+            .u16(CC_FLAG_RANGE_VALID)
+            .u16(0)
+            .u16(0)
+            .f64(min)
+            .f64(max);
+        let cc = w.add(mdf_id::CC, vec![None, Some(unit), None, None], cc_data.0);
+
+        let cn_data = channel_data(0, 0, 8 * (i as u32 + 1), CN_FLAG_INVAL_BIT_VALID, i as u32);
+        next_cn = Some(w.add(
+            mdf_id::CN,
+            vec![
+                next_cn,
+                None,
+                Some(name),
+                None,
+                Some(cc),
+                None,
+                Some(unit),
+                None,
+            ],
+            cn_data,
+        ));
+    }
+
+    let time_name = w.text("time");
+    let time_unit = w.text("s");
+    let master = w.add(
+        mdf_id::CN,
+        vec![
+            next_cn,
+            None,
+            Some(time_name),
+            None,
+            None,
+            None,
+            Some(time_unit),
+            None,
+        ],
+        channel_data(CN_TYPE_MASTER, CN_SYNC_TIME, 0, 0, 0),
+    );
+
+    let acq_name = w.text(ecu);
+    let si_comment = w.xml(&format!(
+        "<SIcomment xmlns=\"http://www.asam.net/mdf/v4\"><TX>{}</TX></SIcomment>",
+        xml_escape(ecu)
+    ));
+    let si = w.add(
+        mdf_id::SI,
+        vec![Some(acq_name), None, Some(si_comment)],
+        Fields::default().u8(SI_TYPE_ECU).u8(bus).zeros(6).0,
+    );
+
+    let cg_data = Fields::default()
+        .u64(0) // record ID
+        .u64(times.len() as u64)
+        .u16(0) // flags
+        .u16(0) // path separator
+        .zeros(4)
+        .u32(data_bytes as u32)
+        .u32(inval_bytes as u32);
+    let cg = w.add(
+        mdf_id::CG,
+        vec![None, Some(master), Some(acq_name), Some(si), None, None],
+        cg_data.0,
+    );
+
+    w.add(
+        mdf_id::DG,
+        vec![None, Some(cg), dt, None],
+        Fields::default().zeros(8).0,
+    )
+}
+
+/// CN block data for a 64-bit float at `byte_offset`
+fn channel_data(cn_type: u8, sync: u8, byte_offset: u32, flags: u32, inval_bit: u32) -> Vec<u8> {
+    Fields::default()
+        .u8(cn_type)
+        .u8(sync)
+        .u8(CN_DATA_FLOAT_LE)
+        .u8(0) // bit offset
+        .u32(byte_offset)
+        .u32(64)
+        .u32(flags)
+        .u32(inval_bit)
+        .u8(0) // precision
+        .u8(0)
+        .u16(0) // attachments
+        .zeros(6 * 8) // value, limit and extended limit ranges
+        .0
+}
+
+// ============================================================================
+// COMMANDS
+// ============================================================================
+
+/// Recording, its samples, and the ECU and protocol of its session
+fn load_recording(
+    db: &DbState,
+    recording_id: i64,
+) -> Result<(LiveDataRecording, Vec<LiveDataSnapshot>, String, String), String> {
+    let guard = db.0.lock().map_err(|e| format!("Lock error: {}", e))?;
+    let db = guard.as_ref().ok_or("Database not initialized")?;
+    let db_error = |e| format!("Database error: {}", e);
+
+    let recording = db
+        .get_live_data_recording(recording_id)
+        .map_err(db_error)?
+        .ok_or_else(|| format!("Recording {} not found", recording_id))?;
+    let session = db
+        .get_session(recording.session_id)
+        .map_err(db_error)?
+        .ok_or_else(|| format!("Session {} not found", recording.session_id))?;
+    let snapshots = db
+        .get_live_data(&LiveDataQuery {
+            session_id: recording.session_id,
+            recording_id: Some(recording_id),
+            ..Default::default()
+        })
+        .map_err(db_error)?;
+
+    Ok((recording, snapshots, session.ecu_id, session.protocol))
+}
+
+fn channel_count(snapshots: &[LiveDataSnapshot]) -> usize {
+    snapshots
+        .iter()
+        .map(|s| (s.ecu.as_deref(), s.parameter_name.as_str()))
+        .collect::<BTreeSet<_>>()
+        .len()
+}
+
+/// Export a recording as wide CSV to `path`
+///
+/// Without `interval_ms` there is one row per sample time.
+#[tauri::command]
+pub fn export_live_data_csv(
+    db: State<DbState>,
+    recording_id: i64,
+    path: String,
+    interpolation: Option<Interpolation>,
+    interval_ms: Option<u64>,
+) -> Result<LiveDataExport, String> {
+    let (recording, snapshots, ecu, _) = load_recording(&db, recording_id)?;
+    let (csv, rows) = to_csv(
+        &snapshots,
+        recording.started_at.timestamp_millis(),
+        &ecu,
+        interpolation.unwrap_or_default(),
+        interval_ms,
+    )?;
+
+    std::fs::write(&path, csv).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    log::info!(
+        "Exported recording {} to {} ({} rows)",
+        recording_id,
+        path,
+        rows
+    );
+    Ok(LiveDataExport {
+        channels: channel_count(&snapshots),
+        path,
+        rows,
+    })
+}
+
+/// Export a recording as ASAM MDF4 to `path`
+#[tauri::command]
+pub fn export_live_data_mdf4(
+    db: State<DbState>,
+    recording_id: i64,
+    path: String,
+) -> Result<LiveDataExport, String> {
+    let (recording, snapshots, ecu, protocol) = load_recording(&db, recording_id)?;
+    let (mdf, rows) = to_mdf4(&snapshots, &recording, &ecu, &protocol);
+
+    std::fs::write(&path, mdf).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    log::info!(
+        "Exported recording {} to {} ({} records)",
+        recording_id,
+        path,
+        rows
+    );
+    Ok(LiveDataExport {
+        channels: channel_count(&snapshots),
+        path,
+        rows,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    const START: i64 = 1_700_000_000_000;
+
+    fn snapshot(ecu: &str, name: &str, value: f64, millis: i64) -> LiveDataSnapshot {
+        LiveDataSnapshot {
+            id: 0,
+            session_id: 1,
+            recording_id: Some(1),
+            ecu: Some(ecu.to_string()),
+            parameter_name: name.to_string(),
+            value,
+            unit: if name == "RPM" { "rpm" } else { "°C" }.to_string(),
+            timestamp: DateTime::from_timestamp_millis(START + millis).unwrap(),
+        }
+    }
+
+    fn samples() -> Vec<LiveDataSnapshot> {
+        vec![
+            snapshot("DDE", "RPM", 800.0, 0),
+            snapshot("DDE", "Coolant", 80.0, 100),
+            snapshot("DDE", "RPM", 1000.0, 200),
+        ]
+    }
+
+    fn csv_rows(csv: &str) -> Vec<&str> {
+        csv.lines().collect()
+    }
+
+    #[test]
+    fn test_csv_interpolation() {
+        let (csv, rows) = to_csv(&samples(), START, "DDE", Interpolation::None, None).unwrap();
+        assert_eq!(rows, 3);
+        let lines = csv_rows(&csv);
+        assert_eq!(lines[0], "time_s,timestamp,Coolant [°C],RPM [rpm]");
+        assert_eq!(lines[1], "0.000,2023-11-14T22:13:20.000Z,,800");
+        assert_eq!(lines[2], "0.100,2023-11-14T22:13:20.100Z,80,");
+
+        let (csv, _) = to_csv(&samples(), START, "DDE", Interpolation::Previous, None).unwrap();
+        assert!(csv_rows(&csv)[2].ends_with(",80,800"));
+        assert!(csv_rows(&csv)[3].ends_with(",80,1000"));
+
+        let (csv, _) = to_csv(&samples(), START, "DDE", Interpolation::Linear, None).unwrap();
+        assert!(csv_rows(&csv)[2].ends_with(",80,900"));
+        // No extrapolation past the last sample of a channel
+        assert!(csv_rows(&csv)[3].ends_with(",,1000"));
+    }
+
+    #[test]
+    fn test_csv_fixed_interval_and_ecu_columns() {
+        let mut snapshots = samples();
+        snapshots.push(snapshot("EGS", "RPM", 790.0, 50));
+
+        let (csv, rows) =
+            to_csv(&snapshots, START, "DDE", Interpolation::Previous, Some(50)).unwrap();
+        assert_eq!(rows, 5);
+        let lines = csv_rows(&csv);
+        assert_eq!(
+            lines[0],
+            "time_s,timestamp,DDE.Coolant [°C],DDE.RPM [rpm],EGS.RPM [rpm]"
+        );
+        assert!(lines[2].starts_with("0.050,"));
+        assert!(lines[2].ends_with(",,800,790"));
+
+        assert!(to_csv(&snapshots, START, "DDE", Interpolation::None, Some(0)).is_err());
+    }
+
+    /// Minimal MDF4 block reader: (id, links, data)
+    fn block(mdf: &[u8], address: u64) -> (&[u8], Vec<u64>, &[u8]) {
+        let a = address as usize;
+        let len = u64::from_le_bytes(mdf[a + 8..a + 16].try_into().unwrap()) as usize;
+        let count = u64::from_le_bytes(mdf[a + 16..a + 24].try_into().unwrap()) as usize;
+        let links = (0..count)
+            .map(|i| {
+                let at = a + 24 + i * 8;
+                u64::from_le_bytes(mdf[at..at + 8].try_into().unwrap())
+            })
+            .collect();
+        (&mdf[a..a + 4], links, &mdf[a + 24 + count * 8..a + len])
+    }
+
+    fn text(mdf: &[u8], address: u64) -> String {
+        let (_, _, data) = block(mdf, address);
+        String::from_utf8_lossy(data)
+            .trim_end_matches('\0')
+            .to_string()
+    }
+
+    #[test]
+    fn test_mdf4_groups_per_ecu() {
+        let mut snapshots = samples();
+        snapshots.push(snapshot("EGS", "RPM", 790.0, 50));
+        let recording = LiveDataRecording {
+            id: 1,
+            session_id: 1,
+            started_at: DateTime::from_timestamp_millis(START).unwrap(),
+            stopped_at: None,
+            sample_count: 4,
+        };
+
+        let (mdf, records) = to_mdf4(&snapshots, &recording, "DDE", "D-CAN");
+        assert_eq!(&mdf[..16], b"MDF     4.10    ");
+        assert_eq!(records, 4);
+
+        let (id, hd_links, _) = block(&mdf, 64);
+        assert_eq!(id, b"##HD");
+        assert_eq!(block(&mdf, hd_links[1]).0, b"##FH");
+
+        // DDE group: time, Coolant, RPM over 3 records
+        let (id, dg_links, _) = block(&mdf, hd_links[0]);
+        assert_eq!(id, b"##DG");
+        let (_, cg_links, cg_data) = block(&mdf, dg_links[1]);
+        assert_eq!(text(&mdf, cg_links[2]), "DDE");
+        assert_eq!(u64::from_le_bytes(cg_data[8..16].try_into().unwrap()), 3);
+        assert_eq!(block(&mdf, cg_links[3]).2[1], SI_BUS_CAN);
+
+        let (_, master_links, master_data) = block(&mdf, cg_links[1]);
+        assert_eq!(master_data[0], CN_TYPE_MASTER);
+        let (_, coolant_links, _) = block(&mdf, master_links[0]);
+        assert_eq!(text(&mdf, coolant_links[2]), "Coolant");
+        assert_eq!(text(&mdf, coolant_links[6]), "°C");
+
+        // Record 2 (t = 0.1 s): Coolant valid, RPM invalid
+        let (_, _, dt) = block(&mdf, dg_links[2]);
+        let record = &dt[25..50];
+        assert_eq!(f64::from_le_bytes(record[0..8].try_into().unwrap()), 0.1);
+        assert_eq!(f64::from_le_bytes(record[8..16].try_into().unwrap()), 80.0);
+        assert_eq!(record[24], 0b10);
+
+        // EGS group follows
+        let (_, egs_links, _) = block(&mdf, dg_links[0]);
+        let (_, cg_links, _) = block(&mdf, egs_links[1]);
+        assert_eq!(text(&mdf, cg_links[2]), "EGS");
+    }
+}
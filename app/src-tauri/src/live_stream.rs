@@ -0,0 +1,946 @@
+//! Live Data Streaming
+//!
+//! Acquisition engine that cycles through PIDs and DIDs on its own thread
+//! and emits every sample as a `live-data:sample` event, instead of the UI
+//! polling `read_pids_kline` / `read_dids_kline` on a timer.
+//!
+//! Each channel has its own interval. Requests are spaced by P3min after
+//! the previous response rather than a fixed sleep, and the ECU session is
+//! kept open with TesterPresent while all channels are idle. The session
+//! itself is opened beforehand (`bmw_kline_init`), as for single reads.
+//!
+//! With `packed`, DID channels are read together through a dynamically
+//! defined identifier at the rate of the fastest of them. D-CAN ECUs can
+//! instead push DIDs on their own (`periodic`, UDS 0x2A); the engine then
+//! only listens and keeps the session alive.
+//!
+//! Samples are also handed to the running recording, if any (`recording`).
+//!
+//! Given a vehicle, the ECU and, when none are requested, the channels
+//! come from its engine profile (`profiles`).
+//!
+//! Virtual channels (`virtual_channels`) are computed from each batch of
+//! samples before it is emitted, then the alarm rules (`alarms`) are
+//! checked against it.
+
+use crate::alarms::{self, Alarms};
+use crate::bmw::{self, DidValue};
+use crate::bmw_commands::{DCanDtcTransport, KLineDtcTransport};
+use crate::channels;
+use crate::constants::{addresses, timing};
+use crate::db_commands::DbState;
+use crate::dcan::{can_ids, DCanHandler};
+use crate::dynamic_id::DynamicReader;
+use crate::kline::KLineHandler;
+use crate::periodic::{PeriodicRate, PeriodicSchedule};
+use crate::pid_commands::{request_did, request_pid, LiveDataValue};
+use crate::profiles::{self, Profile};
+use crate::recording::RecordingState;
+use crate::serial::SerialState;
+use crate::validators;
+use crate::virtual_channels::{self, VirtualChannels};
+use bmw_diag_core::dtc_service::{DtcDialect, DtcTransport};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+/// Event carrying one `LiveSample`
+pub const SAMPLE_EVENT: &str = "live-data:sample";
+/// Event carrying an `AlarmEvent` when an alarm is raised or cleared
+pub const ALARM_EVENT: &str = "live-data:alarm";
+/// Event carrying `StreamStopped` once the acquisition thread exits
+pub const STOPPED_EVENT: &str = "live-data:stopped";
+
+/// Failed reads in a row before the stream gives up
+const MAX_CONSECUTIVE_ERRORS: u32 = 10;
+/// Longest sleep while waiting, so a stop request is seen promptly
+const IDLE_POLL: Duration = Duration::from_millis(10);
+/// How long one wait for a periodic message holds the port
+const PERIODIC_POLL: Duration = Duration::from_millis(100);
+/// Periodic silence after which the ECU is considered gone
+const PERIODIC_SILENCE: Duration = Duration::from_secs(5);
+
+static NEXT_STREAM_ID: AtomicU64 = AtomicU64::new(1);
+
+/// How a channel is read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelKind {
+    /// OBD-II Mode 01 PID
+    Pid,
+    /// ReadDataByIdentifier (0x22)
+    Did,
+    /// Computed from other channels; `id` is the position among the
+    /// stream's virtual channels
+    Virtual,
+}
+
+/// One channel of a stream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamChannel {
+    pub kind: ChannelKind,
+    pub id: u16,
+    /// Minimum time between samples; 0 reads as fast as the bus allows.
+    /// Ignored for periodic streams, where the ECU sets the pace.
+    #[serde(default)]
+    pub interval_ms: u64,
+}
+
+/// One timestamped sample
+#[derive(Debug, Clone, Serialize)]
+pub struct LiveSample {
+    pub stream_id: u64,
+    /// Sample number within the stream
+    pub seq: u64,
+    pub kind: ChannelKind,
+    pub id: u16,
+    pub name: String,
+    pub value: f64,
+    pub unit: String,
+    pub raw: Vec<u8>,
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+/// Payload of `live-data:stopped`
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamStopped {
+    pub stream_id: u64,
+    pub samples: u64,
+    /// Why the stream ended on its own; `None` when stopped on request
+    pub error: Option<String>,
+}
+
+/// Returned by `start_live_stream`
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamInfo {
+    pub stream_id: u64,
+    /// ECU ID, e.g. "DME/DDE"; samples are recorded under it
+    pub ecu: String,
+    /// "K-Line" or "D-CAN"
+    pub protocol: String,
+    pub channels: usize,
+    /// Engine profile key when started for a vehicle
+    pub profile: Option<String>,
+    /// Keys of the virtual channels computed, in sample `id` order
+    pub virtual_channels: Vec<String>,
+    /// Keys of the alarm rules watched
+    pub alarms: Vec<String>,
+}
+
+/// Path to the ECU being streamed
+#[derive(Debug, Clone, Copy)]
+enum Link {
+    KLine { target: u8 },
+    DCan { tx_id: u32, rx_id: u32 },
+}
+
+impl Link {
+    fn protocol(self) -> &'static str {
+        match self {
+            Self::KLine { .. } => "K-Line",
+            Self::DCan { .. } => "D-CAN",
+        }
+    }
+
+    fn dialect(self) -> DtcDialect {
+        DtcDialect::for_transport(matches!(self, Self::KLine { .. }))
+    }
+
+    fn transport<'a>(
+        self,
+        port: &'a mut Box<dyn serialport::SerialPort>,
+    ) -> Box<dyn DtcTransport + 'a> {
+        match self {
+            Self::KLine { target } => Box::new(KLineDtcTransport {
+                port,
+                target,
+                source: addresses::TESTER,
+            }),
+            Self::DCan { tx_id, rx_id } => Box::new(DCanDtcTransport { port, tx_id, rx_id }),
+        }
+    }
+
+    fn tester_present(self, port: &mut Box<dyn serialport::SerialPort>) -> Result<(), String> {
+        match self {
+            Self::KLine { target } => KLineHandler::tester_present(port, target, addresses::TESTER),
+            Self::DCan { tx_id, rx_id } => DCanHandler::tester_present(port, tx_id, rx_id),
+        }
+    }
+}
+
+/// ECU ID for a K-Line address, e.g. "DME/DDE" for 0x12
+fn kline_ecu_id(target: u8) -> String {
+    let ids = bmw::ecu_ids_for_kline_address(target);
+    if ids.is_empty() {
+        format!("0x{:02X}", target)
+    } else {
+        ids.join("/")
+    }
+}
+
+/// What one schedule entry reads
+enum Slot {
+    Channel(StreamChannel),
+    /// DID channels behind one dynamically defined identifier
+    Packed(DynamicReader),
+}
+
+struct StreamHandle {
+    id: u64,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl StreamHandle {
+    fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if self.thread.join().is_err() {
+            log::error!("Live stream {} thread panicked", self.id);
+        }
+    }
+}
+
+/// The running stream, if any
+#[derive(Default)]
+pub struct LiveStreamState(Mutex<Option<StreamHandle>>);
+
+impl LiveStreamState {
+    /// Stop the running stream and wait for it to wind down; returns
+    /// whether one was running
+    pub fn stop(&self) -> Result<bool, String> {
+        let handle = self
+            .0
+            .lock()
+            .map_err(|e| format!("Lock error: {}", e))?
+            .take();
+
+        match handle {
+            Some(handle) => {
+                handle.stop();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+/// Start streaming `channels` from one ECU
+///
+/// The ECU is either a K-Line `target_address` or a D-CAN `ecu_name`.
+/// Replaces a stream that is already running.
+///
+/// With `packed`, DID channels share one dynamically defined identifier;
+/// ECUs that reject it are polled per DID as usual. With `periodic` (D-CAN,
+/// DID channels only) the ECU transmits the DIDs itself at that rate; if it
+/// refuses, the stream falls back to polling.
+///
+/// With a `vehicle_id` the engine ECU of the vehicle's profile is used when
+/// no ECU is given, and an empty `channels` list streams the profile's
+/// Mode 01 and DID channels.
+///
+/// The saved virtual channels whose inputs are streamed are computed too,
+/// or only those listed in `virtual_channels`. The saved alarm rules on
+/// the streamed channels are watched.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub fn start_live_stream(
+    app: AppHandle,
+    stream: State<LiveStreamState>,
+    db: State<DbState>,
+    target_address: Option<u8>,
+    ecu_name: Option<String>,
+    channels: Vec<StreamChannel>,
+    packed: Option<bool>,
+    periodic: Option<PeriodicRate>,
+    vehicle_id: Option<i64>,
+    virtual_channels: Option<Vec<String>>,
+) -> Result<StreamInfo, String> {
+    let profile = profiles::for_optional_vehicle(&db, vehicle_id)?;
+    let target_address = match &profile {
+        Some(profile) if ecu_name.is_none() => target_address.or_else(|| profile.engine_address()),
+        _ => target_address,
+    };
+    let channels = match &profile {
+        Some(profile) if channels.is_empty() => profile_channels(profile),
+        _ => channels,
+    };
+
+    let (link, ecu) = match (ecu_name, target_address) {
+        (Some(ecu_name), _) => {
+            let (tx_id, rx_id) = can_ids::for_ecu(&ecu_name)
+                .ok_or_else(|| format!("Unknown ECU for D-CAN: {}", ecu_name))?;
+            (Link::DCan { tx_id, rx_id }, ecu_name.to_uppercase())
+        }
+        (None, Some(target)) => (Link::KLine { target }, kline_ecu_id(target)),
+        (None, None) => return Err("Either target_address or ecu_name is required".to_string()),
+    };
+    let packed = packed.unwrap_or(false);
+
+    let ids = |kind| {
+        channels
+            .iter()
+            .filter(|ch| ch.kind == kind)
+            .map(|ch| ch.id)
+            .collect::<Vec<_>>()
+    };
+    let (pids, dids) = (ids(ChannelKind::Pid), ids(ChannelKind::Did));
+    if channels.iter().any(|ch| ch.kind == ChannelKind::Virtual) {
+        return Err("Virtual channels are selected with virtual_channels".to_string());
+    }
+    if pids.is_empty() && dids.is_empty() {
+        return Err("No channels to stream".to_string());
+    }
+    if !pids.is_empty() {
+        validators::validate_pids(&pids).map_err(|e| e.to_string())?;
+    }
+    if (packed || periodic.is_some()) && !dids.is_empty() {
+        validators::validate_dynamic_dids(&dids).map_err(|e| e.to_string())?;
+    } else if !dids.is_empty() {
+        validators::validate_dids(&dids).map_err(|e| e.to_string())?;
+    }
+    if periodic.is_some() {
+        if !matches!(link, Link::DCan { .. }) {
+            return Err("Periodic streaming needs a D-CAN ECU".to_string());
+        }
+        if !pids.is_empty() {
+            return Err("Periodic streaming supports DID channels only".to_string());
+        }
+    }
+
+    let virtuals = setup_virtual_channels(&db, &channels, virtual_channels)?;
+    let alarms = setup_alarms(&db, &channels, &virtuals)?;
+
+    stream.stop()?;
+    let mut current = stream.0.lock().map_err(|e| format!("Lock error: {}", e))?;
+
+    let id = NEXT_STREAM_ID.fetch_add(1, Ordering::Relaxed);
+    let stop = Arc::new(AtomicBool::new(false));
+    let info = StreamInfo {
+        stream_id: id,
+        ecu: ecu.clone(),
+        protocol: link.protocol().to_string(),
+        channels: channels.len(),
+        profile: profile.map(|profile| profile.def.key),
+        virtual_channels: virtuals.keys(),
+        alarms: alarms.keys(),
+    };
+
+    let thread = {
+        let stop = stop.clone();
+        let mode = StreamMode { packed, periodic };
+        thread::Builder::new()
+            .name(format!("live-stream-{}", id))
+            .spawn(move || run_stream(app, id, link, ecu, channels, virtuals, alarms, mode, stop))
+            .map_err(|e| format!("Failed to start stream: {}", e))?
+    };
+
+    log::info!(
+        "Live stream {} started: {} channel(s) via {:?}",
+        id,
+        info.channels,
+        link
+    );
+    *current = Some(StreamHandle { id, stop, thread });
+    Ok(info)
+}
+
+/// Default live channels of a profile
+///
+/// Only Mode 01 and DID sets are used; the stream cannot read local
+/// identifiers. Keys missing from the channel tables are skipped.
+fn profile_channels(profile: &Profile) -> Vec<StreamChannel> {
+    let db = channels::read_global();
+    let mut list = Vec::new();
+    for (ecu, service, keys) in profile.live_sets() {
+        let kind = match service {
+            channels::SERVICE_MODE_01 => ChannelKind::Pid,
+            channels::SERVICE_DATA_ID => ChannelKind::Did,
+            _ => continue,
+        };
+        for key in keys {
+            match db.channels(ecu, service).find(|channel| &channel.def.key == key) {
+                Some(channel) => list.push(StreamChannel {
+                    kind,
+                    id: channel.id,
+                    interval_ms: 0,
+                }),
+                None => log::warn!(
+                    "Profile {}: no {} channel {} for service 0x{:02X}",
+                    profile.def.key,
+                    ecu,
+                    key,
+                    service
+                ),
+            }
+        }
+    }
+    list
+}
+
+/// Virtual channels for a stream, from the saved definitions
+///
+/// Without `selected`, definitions whose inputs the stream does not read
+/// are left out; a selected one that cannot be computed is an error.
+fn setup_virtual_channels(
+    db: &DbState,
+    channels: &[StreamChannel],
+    selected: Option<Vec<String>>,
+) -> Result<VirtualChannels, String> {
+    let mut defs = virtual_channels::load(db)?;
+    if let Some(selected) = &selected {
+        if let Some(key) = selected.iter().find(|key| !defs.iter().any(|def| &def.key == *key)) {
+            return Err(format!("Unknown virtual channel: {}", key));
+        }
+        defs.retain(|def| selected.contains(&def.key));
+    }
+
+    let streamed: Vec<(ChannelKind, u16)> = channels.iter().map(|ch| (ch.kind, ch.id)).collect();
+    let (virtuals, skipped) = VirtualChannels::new(defs, &streamed);
+    if !skipped.is_empty() {
+        if selected.is_some() {
+            return Err(format!(
+                "Virtual channels need channels that are not streamed: {}",
+                skipped.join(", ")
+            ));
+        }
+        log::info!("Virtual channels not computed for this stream: {}", skipped.join(", "));
+    }
+    Ok(virtuals)
+}
+
+/// Alarm rules on the channels of a stream, from the saved rules
+fn setup_alarms(
+    db: &DbState,
+    channels: &[StreamChannel],
+    virtuals: &VirtualChannels,
+) -> Result<Alarms, String> {
+    let rules = alarms::load(db)?;
+    let streamed: Vec<(ChannelKind, u16)> = channels.iter().map(|ch| (ch.kind, ch.id)).collect();
+    let (alarms, skipped) = Alarms::new(rules, &streamed, &virtuals.keys());
+    if !skipped.is_empty() {
+        log::info!("Alarm rules not watched for this stream: {}", skipped.join(", "));
+    }
+    Ok(alarms)
+}
+
+/// Stop the running stream; returns whether one was running
+#[tauri::command]
+pub fn stop_live_stream(stream: State<LiveStreamState>) -> Result<bool, String> {
+    stream.stop()
+}
+
+#[derive(Debug, Clone, Copy)]
+struct StreamMode {
+    packed: bool,
+    periodic: Option<PeriodicRate>,
+}
+
+/// State shared by the acquisition loops of one stream
+struct Stream<'a> {
+    app: &'a AppHandle,
+    serial: &'a SerialState,
+    id: u64,
+    link: Link,
+    ecu: &'a str,
+    stop: &'a AtomicBool,
+    seq: u64,
+    recording: &'a RecordingState,
+    db: &'a DbState,
+    virtuals: VirtualChannels,
+    alarms: Alarms,
+}
+
+impl Stream<'_> {
+    fn stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    fn with_transport<T>(
+        &self,
+        f: impl FnOnce(&mut dyn DtcTransport) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let link = self.link;
+        self.serial
+            .with_port(|port| f(link.transport(port).as_mut()))
+    }
+
+    fn emit(&mut self, mut samples: Vec<LiveSample>) {
+        let computed = self.virtuals.update(&samples);
+        samples.extend(computed);
+        for sample in &mut samples {
+            self.seq += 1;
+            sample.stream_id = self.id;
+            sample.seq = self.seq;
+            if let Err(e) = self.app.emit(SAMPLE_EVENT, &*sample) {
+                log::warn!("Failed to emit live sample: {}", e);
+            }
+        }
+        self.recording.record(self.db, self.ecu, &samples);
+
+        for mut event in self.alarms.update(&samples) {
+            event.stream_id = self.id;
+            log::info!(
+                "Live stream {}: alarm {} {:?} at {} {}",
+                self.id,
+                event.rule,
+                event.state,
+                event.value,
+                event.unit
+            );
+            if let Err(e) = self.app.emit(ALARM_EVENT, &event) {
+                log::warn!("Failed to emit alarm: {}", e);
+            }
+            self.recording.mark(self.db, &event);
+        }
+    }
+}
+
+/// Acquisition thread
+#[allow(clippy::too_many_arguments)]
+fn run_stream(
+    app: AppHandle,
+    stream_id: u64,
+    link: Link,
+    ecu: String,
+    channels: Vec<StreamChannel>,
+    virtuals: VirtualChannels,
+    alarms: Alarms,
+    mode: StreamMode,
+    stop: Arc<AtomicBool>,
+) {
+    let serial = app.state::<SerialState>();
+    let recording = app.state::<RecordingState>();
+    let db = app.state::<DbState>();
+    let mut stream = Stream {
+        app: &app,
+        serial: &serial,
+        id: stream_id,
+        link,
+        ecu: &ecu,
+        stop: &stop,
+        seq: 0,
+        recording: &recording,
+        db: &db,
+        virtuals,
+        alarms,
+    };
+
+    let result = acquire(&mut stream, channels, mode);
+    recording.flush(&db);
+
+    log::info!(
+        "Live stream {} stopped after {} samples",
+        stream_id,
+        stream.seq
+    );
+    let stopped = StreamStopped {
+        stream_id,
+        samples: stream.seq,
+        error: result.err(),
+    };
+    if let Err(e) = app.emit(STOPPED_EVENT, stopped) {
+        log::warn!("Failed to emit stream stop: {}", e);
+    }
+}
+
+fn acquire(
+    stream: &mut Stream,
+    channels: Vec<StreamChannel>,
+    mode: StreamMode,
+) -> Result<(), String> {
+    if let Link::DCan { .. } = stream.link {
+        stream.serial.with_port(DCanHandler::switch_to_dcan_mode)?;
+    }
+
+    if let Some(rate) = mode.periodic {
+        let dids: Vec<u16> = channels.iter().map(|ch| ch.id).collect();
+        match start_periodic(stream, rate, &dids) {
+            Ok(schedule) => return listen_periodic(stream, &schedule),
+            Err(e) => log::warn!(
+                "Live stream {}: periodic transmission unavailable ({}), polling instead",
+                stream.id,
+                e
+            ),
+        }
+    }
+
+    poll(stream, channels, mode.packed)
+}
+
+/// Request/response acquisition
+fn poll(stream: &mut Stream, channels: Vec<StreamChannel>, packed: bool) -> Result<(), String> {
+    let (mut slots, intervals) = if packed {
+        pack_channels(stream, channels)
+    } else {
+        let intervals = channels.iter().map(interval).collect();
+        (channels.into_iter().map(Slot::Channel).collect(), intervals)
+    };
+    let mut schedule = Schedule::new(intervals, Instant::now());
+
+    let mut errors = 0u32;
+    let mut result = Ok(());
+    // End of the last exchange; the next request waits P3min after it
+    let mut last_exchange: Option<Instant> = None;
+
+    while !stream.stopped() {
+        let now = Instant::now();
+        if let Some(last) = last_exchange {
+            let ready = last + timing::P3_MIN;
+            if now < ready {
+                thread::sleep((ready - now).min(IDLE_POLL));
+                continue;
+            }
+        }
+
+        let index = match schedule.next(now) {
+            Ok(index) => index,
+            Err(due) => {
+                let idle = last_exchange.map_or(Duration::ZERO, |last| now - last);
+                if idle >= Duration::from_millis(timing::TESTER_PRESENT_INTERVAL_MS) {
+                    let link = stream.link;
+                    if let Err(e) = stream.serial.with_port(|port| link.tester_present(port)) {
+                        log::warn!("Live stream {} TesterPresent failed: {}", stream.id, e);
+                    }
+                    last_exchange = Some(Instant::now());
+                } else {
+                    thread::sleep((due - now).min(IDLE_POLL));
+                }
+                continue;
+            }
+        };
+
+        let slot = &mut slots[index];
+        let read = stream.with_transport(|transport| read_slot(transport, slot));
+        last_exchange = Some(Instant::now());
+        schedule.mark_read(index, now);
+
+        match read {
+            Ok(samples) => {
+                errors = 0;
+                stream.emit(samples);
+            }
+            Err(e) => {
+                let what = match slot {
+                    Slot::Channel(ch) => format!("{:?} 0x{:04X}", ch.kind, ch.id),
+                    Slot::Packed(_) => "dynamic identifier".to_string(),
+                };
+                log::warn!("Live stream {} failed to read {}: {}", stream.id, what, e);
+
+                if !stream.serial.is_connected() {
+                    result = Err("Connection lost".to_string());
+                    break;
+                }
+                errors += 1;
+                if errors >= MAX_CONSECUTIVE_ERRORS {
+                    result = Err(format!("{} reads failed in a row, last: {}", errors, e));
+                    break;
+                }
+            }
+        }
+    }
+
+    for slot in &slots {
+        if let Slot::Packed(reader) = slot {
+            if let Err(e) = stream.with_transport(|transport| reader.clear(transport)) {
+                log::warn!(
+                    "Live stream {} failed to clear dynamic identifier: {}",
+                    stream.id,
+                    e
+                );
+            }
+        }
+    }
+
+    result
+}
+
+fn interval(channel: &StreamChannel) -> Duration {
+    Duration::from_millis(channel.interval_ms)
+}
+
+/// Put DID channels behind one dynamic identifier, PIDs stay on their own
+///
+/// Returns the slots with their intervals. If the ECU rejects the
+/// identifier every channel keeps its own slot.
+fn pack_channels(stream: &Stream, channels: Vec<StreamChannel>) -> (Vec<Slot>, Vec<Duration>) {
+    let dids: Vec<u16> = channels
+        .iter()
+        .filter(|ch| ch.kind == ChannelKind::Did)
+        .map(|ch| ch.id)
+        .collect();
+
+    let dialect = stream.link.dialect();
+    let reader =
+        stream.with_transport(|transport| DynamicReader::define(transport, dialect, &dids));
+
+    match reader {
+        Ok(reader) if reader.is_packed() => {
+            let (did_channels, others): (Vec<_>, Vec<_>) = channels
+                .into_iter()
+                .partition(|ch| ch.kind == ChannelKind::Did);
+            // Packed DIDs are read at the rate of the fastest one
+            let packed_interval = did_channels.iter().map(interval).min().unwrap_or_default();
+
+            let mut intervals: Vec<Duration> = others.iter().map(interval).collect();
+            let mut slots: Vec<Slot> = others.into_iter().map(Slot::Channel).collect();
+            slots.push(Slot::Packed(reader));
+            intervals.push(packed_interval);
+            (slots, intervals)
+        }
+        result => {
+            if let Err(e) = result {
+                log::warn!(
+                    "Dynamic identifier setup failed, polling DIDs singly: {}",
+                    e
+                );
+            }
+            let intervals = channels.iter().map(interval).collect();
+            (channels.into_iter().map(Slot::Channel).collect(), intervals)
+        }
+    }
+}
+
+fn read_slot(transport: &mut dyn DtcTransport, slot: &mut Slot) -> Result<Vec<LiveSample>, String> {
+    match slot {
+        Slot::Channel(channel) => match channel.kind {
+            ChannelKind::Pid => request_pid(transport, channel.id).map(pid_sample),
+            ChannelKind::Did => request_did(transport, channel.id).map(did_sample),
+            ChannelKind::Virtual => Err("Virtual channels are not read from the ECU".to_string()),
+        }
+        .map(|sample| vec![sample]),
+        Slot::Packed(reader) => {
+            let values = reader.read(transport)?;
+            Ok(values.into_iter().map(did_sample).collect())
+        }
+    }
+}
+
+/// Schedule the DIDs for periodic transmission
+fn start_periodic(
+    stream: &Stream,
+    rate: PeriodicRate,
+    dids: &[u16],
+) -> Result<PeriodicSchedule, String> {
+    let (schedule, left_out) = PeriodicSchedule::plan(rate, dids);
+    if schedule.is_empty() {
+        return Err("No DID with known length to schedule".to_string());
+    }
+    if !left_out.is_empty() {
+        log::warn!(
+            "Live stream {}: DIDs not scheduled for periodic transmission: {:04X?}",
+            stream.id,
+            left_out
+        );
+    }
+
+    stream.with_transport(|transport| schedule.start(transport))?;
+    log::info!(
+        "Live stream {}: periodic transmission started ({:?})",
+        stream.id,
+        rate
+    );
+    Ok(schedule)
+}
+
+/// Receive periodic messages until stopped, then end transmission
+fn listen_periodic(stream: &mut Stream, schedule: &PeriodicSchedule) -> Result<(), String> {
+    let Link::DCan { tx_id, rx_id } = stream.link else {
+        return Err("Periodic streaming needs a D-CAN ECU".to_string());
+    };
+
+    let mut last_message = Instant::now();
+    let mut last_tester_present = Instant::now();
+
+    let result = loop {
+        if stream.stopped() {
+            break Ok(());
+        }
+
+        // S3 keep-alive without a response that would mix with the data
+        if last_tester_present.elapsed()
+            >= Duration::from_millis(timing::TESTER_PRESENT_INTERVAL_MS)
+        {
+            let sent = stream
+                .serial
+                .with_port(|port| DCanHandler::send_without_response(port, tx_id, &[0x3E, 0x80]));
+            if let Err(e) = sent {
+                log::warn!("Live stream {} TesterPresent failed: {}", stream.id, e);
+            }
+            last_tester_present = Instant::now();
+        }
+
+        let received = stream
+            .serial
+            .with_port(|port| DCanHandler::receive_message_timeout(port, rx_id, PERIODIC_POLL));
+        match received {
+            Ok(message) => match schedule.decode(&message) {
+                Some(values) => {
+                    last_message = Instant::now();
+                    stream.emit(values.into_iter().map(did_sample).collect());
+                }
+                None => log::debug!("Ignoring non-periodic message: {:02X?}", message),
+            },
+            Err(_) if !stream.serial.is_connected() => break Err("Connection lost".to_string()),
+            Err(_) if last_message.elapsed() >= PERIODIC_SILENCE => {
+                break Err(format!(
+                    "No periodic data for {} s",
+                    PERIODIC_SILENCE.as_secs()
+                ));
+            }
+            Err(_) => {}
+        }
+    };
+
+    // Without a port the ECU stops on its own once the session times out
+    if stream.serial.is_connected() {
+        match stream.with_transport(|transport| schedule.stop(transport)) {
+            Ok(()) => log::info!("Live stream {}: periodic transmission stopped", stream.id),
+            Err(e) => log::warn!(
+                "Live stream {} failed to stop periodic transmission: {}",
+                stream.id,
+                e
+            ),
+        }
+    }
+
+    result
+}
+
+fn pid_sample(value: LiveDataValue) -> LiveSample {
+    LiveSample {
+        stream_id: 0,
+        seq: 0,
+        kind: ChannelKind::Pid,
+        id: value.pid,
+        name: value.name,
+        value: value.value,
+        unit: value.unit,
+        raw: value.raw,
+        timestamp: value.timestamp,
+    }
+}
+
+fn did_sample(value: DidValue) -> LiveSample {
+    LiveSample {
+        stream_id: 0,
+        seq: 0,
+        kind: ChannelKind::Did,
+        id: value.did,
+        name: value.name,
+        value: value.value,
+        unit: value.unit,
+        raw: value.raw,
+        timestamp: value.timestamp,
+    }
+}
+
+/// Per-channel due times
+///
+/// The channel that has been due the longest is read first; among equally
+/// due channels the least recently read wins, so channels without an
+/// interval share the bus round-robin.
+struct Schedule {
+    intervals: Vec<Duration>,
+    due: Vec<Instant>,
+    /// Read order stamp of each channel, 0 if never read
+    last_read: Vec<u64>,
+    reads: u64,
+}
+
+impl Schedule {
+    fn new(intervals: Vec<Duration>, now: Instant) -> Self {
+        let count = intervals.len();
+        Self {
+            intervals,
+            due: vec![now; count],
+            last_read: vec![0; count],
+            reads: 0,
+        }
+    }
+
+    /// Channel to read at `now`, or when the next one falls due
+    fn next(&self, now: Instant) -> Result<usize, Instant> {
+        let index = (0..self.due.len())
+            .min_by_key(|&i| (self.due[i], self.last_read[i]))
+            .expect("stream has channels");
+
+        if self.due[index] <= now {
+            Ok(index)
+        } else {
+            Err(self.due[index])
+        }
+    }
+
+    /// Reschedule a channel read at `now`
+    ///
+    /// Keeps the channel's rate steady; a channel that fell behind restarts
+    /// from now instead of bursting to catch up.
+    fn mark_read(&mut self, index: usize, now: Instant) {
+        let interval = self.intervals[index];
+        let next = self.due[index] + interval;
+        self.due[index] = if next > now { next } else { now + interval };
+        self.reads += 1;
+        self.last_read[index] = self.reads;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn test_schedule_round_robin_without_interval() {
+        let start = Instant::now();
+        let mut schedule = Schedule::new(vec![ms(0), ms(0), ms(0)], start);
+
+        let mut order = Vec::new();
+        for step in 0..6 {
+            let now = start + ms(step);
+            let index = schedule.next(now).unwrap();
+            schedule.mark_read(index, now);
+            order.push(index);
+        }
+        assert_eq!(order, vec![0, 1, 2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn test_schedule_honors_channel_rates() {
+        let start = Instant::now();
+        let mut schedule = Schedule::new(vec![ms(100), ms(0)], start);
+
+        // Slow channel first, then the fast one until the slow one is due
+        assert_eq!(schedule.next(start), Ok(0));
+        schedule.mark_read(0, start);
+        for step in 1..5 {
+            let now = start + ms(step * 20);
+            assert_eq!(schedule.next(now), Ok(1));
+            schedule.mark_read(1, now);
+        }
+        // Both due: the fast channel has waited longer, then the slow one
+        let now = start + ms(100);
+        assert_eq!(schedule.next(now), Ok(1));
+        schedule.mark_read(1, now);
+        assert_eq!(schedule.next(now), Ok(0));
+    }
+
+    #[test]
+    fn test_schedule_waits_for_next_due() {
+        let start = Instant::now();
+        let mut schedule = Schedule::new(vec![ms(200)], start);
+
+        schedule.mark_read(0, start);
+        assert_eq!(schedule.next(start + ms(50)), Err(start + ms(200)));
+
+        // Read late: next due is from now, not a catch-up burst
+        schedule.mark_read(0, start + ms(500));
+        assert_eq!(schedule.next(start + ms(500)), Err(start + ms(700)));
+    }
+}
@@ -0,0 +1,333 @@
+//! Generic OBD-II over ISO 15765-4 CAN
+//!
+//! Functional requests go to every emission related ECU at once (0x7DF,
+//! or 0x18DB33F1 with 29-bit identifiers) and the replies of all ECUs are
+//! collected. Bus speed and identifier length are detected by probing in
+//! the ISO 15765-4 order, so the K+DCAN cable also works on non-BMW cars.
+
+use crate::dcan::{DCanHandler, IsoTpFrame};
+use bmw_diag_core::dtc_service::DtcTransport;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+/// Time an ECU has to answer (P2CAN max is 50 ms, leave margin)
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(100);
+/// Extended time after responsePending (P2*CAN)
+const PENDING_TIMEOUT: Duration = Duration::from_millis(5000);
+/// Upper bound on one collection, even with a busy bus
+const MAX_COLLECT_TIME: Duration = Duration::from_secs(10);
+
+/// Supported-PIDs request used to probe the bus
+const PROBE_REQUEST: [u8; 2] = [0x01, 0x00];
+
+/// CAN identifier length
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CanAddressing {
+    /// 11-bit: functional 0x7DF, ECUs 0x7E0-0x7E7, replies 0x7E8-0x7EF
+    Standard,
+    /// 29-bit: functional 0x18DB33F1, ECU xx at 0x18DAxxF1, replies 0x18DAF1xx
+    Extended,
+}
+
+impl CanAddressing {
+    /// Functional (broadcast) request ID
+    pub fn functional_id(self) -> u32 {
+        match self {
+            Self::Standard => 0x7DF,
+            Self::Extended => 0x18DB_33F1,
+        }
+    }
+
+    /// Whether an ID carries an ECU reply
+    pub fn is_response(self, id: u32) -> bool {
+        match self {
+            Self::Standard => (0x7E8..=0x7EF).contains(&id),
+            Self::Extended => id & 0xFFFF_FF00 == 0x18DA_F100,
+        }
+    }
+
+    /// Physical request ID of the ECU replying on `response_id`
+    pub fn request_id(self, response_id: u32) -> u32 {
+        match self {
+            Self::Standard => response_id - 8,
+            Self::Extended => 0x18DA_00F1 | ((response_id & 0xFF) << 8),
+        }
+    }
+}
+
+/// Bus settings found by `detect`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObdCanConfig {
+    pub baud_rate: u32,
+    pub addressing: CanAddressing,
+}
+
+/// ISO 15765-4 initialisation order
+const PROBE_ORDER: [ObdCanConfig; 4] = [
+    ObdCanConfig { baud_rate: 500_000, addressing: CanAddressing::Standard },
+    ObdCanConfig { baud_rate: 500_000, addressing: CanAddressing::Extended },
+    ObdCanConfig { baud_rate: 250_000, addressing: CanAddressing::Standard },
+    ObdCanConfig { baud_rate: 250_000, addressing: CanAddressing::Extended },
+];
+
+/// One ECU's reply to a request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObdCanResponse {
+    /// CAN ID the ECU replied on
+    pub ecu_id: u32,
+    pub data: Vec<u8>,
+}
+
+/// Reassembles the ISO-TP replies of several ECUs
+#[derive(Debug, Default)]
+pub struct ResponseCollector {
+    /// ECU -> (total length, next sequence number, data so far)
+    partial: HashMap<u32, (usize, u8, Vec<u8>)>,
+    /// ECUs that sent responsePending and owe a final reply
+    pending: HashSet<u32>,
+    complete: Vec<ObdCanResponse>,
+}
+
+impl ResponseCollector {
+    /// Add a received frame; returns true for a first frame, which needs
+    /// flow control before the ECU sends the rest
+    pub fn push(&mut self, ecu_id: u32, can_data: &[u8]) -> Result<bool, String> {
+        let frame = IsoTpFrame::from_can_data(can_data)?;
+        match frame.frame_type {
+            0x00 => {
+                self.finish_message(ecu_id, frame.data);
+                Ok(false)
+            }
+            0x10 => {
+                let total = frame.total_length.unwrap_or(0) as usize;
+                self.partial.insert(ecu_id, (total, 1, frame.data));
+                Ok(true)
+            }
+            0x20 => {
+                let Some((total, next, mut data)) = self.partial.remove(&ecu_id) else {
+                    return Ok(false);
+                };
+                let sequence = frame.sequence.unwrap_or(0);
+                if sequence != next {
+                    return Err(format!(
+                        "Sequence error from 0x{:X}: expected {}, got {}",
+                        ecu_id, next, sequence
+                    ));
+                }
+                data.extend_from_slice(&frame.data);
+                if data.len() >= total {
+                    data.truncate(total);
+                    self.finish_message(ecu_id, data);
+                } else {
+                    self.partial.insert(ecu_id, (total, (next + 1) & 0x0F, data));
+                }
+                Ok(false)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn finish_message(&mut self, ecu_id: u32, data: Vec<u8>) {
+        if matches!(data.as_slice(), [0x7F, _, 0x78, ..]) {
+            self.pending.insert(ecu_id);
+            return;
+        }
+        self.pending.remove(&ecu_id);
+        self.complete.push(ObdCanResponse { ecu_id, data });
+    }
+
+    /// Whether an ECU is still expected to send something
+    pub fn awaiting(&self) -> bool {
+        !self.partial.is_empty() || !self.pending.is_empty()
+    }
+
+    /// Complete replies, ordered by ECU ID
+    pub fn finish(mut self) -> Vec<ObdCanResponse> {
+        self.complete.sort_by_key(|response| response.ecu_id);
+        self.complete
+    }
+}
+
+/// Generic OBD-II access over ISO 15765-4
+pub struct ObdCanHandler;
+
+impl ObdCanHandler {
+    /// Find the bus speed and identifier length the vehicle answers on
+    ///
+    /// Leaves the cable in D-CAN mode at the detected speed.
+    pub fn detect(port: &mut Box<dyn serialport::SerialPort>) -> Result<ObdCanConfig, String> {
+        for config in PROBE_ORDER {
+            DCanHandler::switch_to_dcan_mode_at(port, config.baud_rate)?;
+
+            match Self::functional_request(port, config.addressing, &PROBE_REQUEST) {
+                Ok(responses) if responses.iter().any(|r| r.data.first() == Some(&0x41)) => {
+                    log::info!(
+                        "OBD-II on CAN at {} kbaud, {:?} IDs ({} ECUs)",
+                        config.baud_rate / 1000,
+                        config.addressing,
+                        responses.len()
+                    );
+                    return Ok(config);
+                }
+                Ok(_) => {}
+                Err(e) => log::debug!("No OBD-II reply with {:?}: {}", config, e),
+            }
+        }
+
+        Err("No OBD-II ECU answered on CAN (500/250 kbaud, 11/29-bit)".to_string())
+    }
+
+    /// Send a request to all ECUs and collect every reply
+    pub fn functional_request(
+        port: &mut Box<dyn serialport::SerialPort>,
+        addressing: CanAddressing,
+        data: &[u8],
+    ) -> Result<Vec<ObdCanResponse>, String> {
+        Self::send_single_frame(port, addressing.functional_id(), data)?;
+        Self::collect(port, addressing, None)
+    }
+
+    /// Send a request to one ECU (by reply ID) and collect its reply
+    pub fn physical_request(
+        port: &mut Box<dyn serialport::SerialPort>,
+        addressing: CanAddressing,
+        ecu_id: u32,
+        data: &[u8],
+    ) -> Result<Vec<u8>, String> {
+        Self::send_single_frame(port, addressing.request_id(ecu_id), data)?;
+        Self::receive_from(port, addressing, ecu_id)
+    }
+
+    /// Wait for a further reply from one ECU
+    pub fn receive_from(
+        port: &mut Box<dyn serialport::SerialPort>,
+        addressing: CanAddressing,
+        ecu_id: u32,
+    ) -> Result<Vec<u8>, String> {
+        Self::collect(port, addressing, Some(ecu_id))?
+            .into_iter()
+            .next()
+            .map(|response| response.data)
+            .ok_or_else(|| format!("No response from ECU 0x{:X}", ecu_id))
+    }
+
+    /// OBD-II requests are at most 7 bytes, so a single frame suffices
+    fn send_single_frame(
+        port: &mut Box<dyn serialport::SerialPort>,
+        can_id: u32,
+        data: &[u8],
+    ) -> Result<(), String> {
+        let frame = IsoTpFrame::single(data.to_vec())?;
+        DCanHandler::send_can_frame(port, can_id, &frame.to_can_data())
+    }
+
+    /// Gather replies until the bus stays quiet for the response timeout
+    fn collect(
+        port: &mut Box<dyn serialport::SerialPort>,
+        addressing: CanAddressing,
+        only: Option<u32>,
+    ) -> Result<Vec<ObdCanResponse>, String> {
+        let start = Instant::now();
+        let mut collector = ResponseCollector::default();
+
+        while start.elapsed() < MAX_COLLECT_TIME {
+            let timeout = if collector.awaiting() {
+                PENDING_TIMEOUT
+            } else {
+                RESPONSE_TIMEOUT
+            };
+            let Some((id, data)) = DCanHandler::receive_any_can_frame(port, timeout)? else {
+                break;
+            };
+            if !addressing.is_response(id) || only.is_some_and(|ecu| ecu != id) {
+                continue;
+            }
+
+            if collector.push(id, &data)? {
+                let flow_control = IsoTpFrame::flow_control(0, 0, 0);
+                DCanHandler::send_can_frame(port, addressing.request_id(id), &flow_control.to_can_data())?;
+            }
+            // A physical request is done once its ECU has replied
+            if only.is_some() && !collector.awaiting() && !collector.complete.is_empty() {
+                break;
+            }
+        }
+
+        Ok(collector.finish())
+    }
+}
+
+/// One ECU on the OBD-II CAN bus, for the shared OBD services
+pub struct ObdCanTransport<'a> {
+    pub port: &'a mut Box<dyn serialport::SerialPort>,
+    pub addressing: CanAddressing,
+    pub ecu_id: u32,
+}
+
+impl DtcTransport for ObdCanTransport<'_> {
+    fn request(&mut self, data: &[u8]) -> Result<Vec<u8>, String> {
+        ObdCanHandler::physical_request(self.port, self.addressing, self.ecu_id, data)
+    }
+
+    fn receive(&mut self) -> Result<Vec<u8>, String> {
+        ObdCanHandler::receive_from(self.port, self.addressing, self.ecu_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_addressing_ids() {
+        let standard = CanAddressing::Standard;
+        assert!(standard.is_response(0x7E9));
+        assert!(!standard.is_response(0x7DF));
+        assert_eq!(standard.request_id(0x7E9), 0x7E1);
+
+        let extended = CanAddressing::Extended;
+        assert!(extended.is_response(0x18DA_F110));
+        assert!(!extended.is_response(0x18DB_33F1));
+        assert_eq!(extended.request_id(0x18DA_F110), 0x18DA_10F1);
+    }
+
+    #[test]
+    fn test_collector_aggregates_ecus() {
+        let mut collector = ResponseCollector::default();
+
+        // Engine ECU: VIN in three frames
+        assert!(collector
+            .push(0x7E8, &[0x10, 0x14, 0x49, 0x02, 0x01, b'W', b'B', b'A'])
+            .unwrap());
+        // Transmission ECU answers in between with a single frame
+        assert!(!collector
+            .push(0x7E9, &[0x03, 0x7F, 0x09, 0x78, 0, 0, 0, 0])
+            .unwrap());
+        collector
+            .push(0x7E8, &[0x21, b'N', b'F', b'7', b'1', b'0', b'6', b'0'])
+            .unwrap();
+        assert!(collector.awaiting());
+        collector
+            .push(0x7E8, &[0x22, b'B', b'1', b'2', b'3', b'4', b'5', b'6'])
+            .unwrap();
+        // Still waiting for the pending ECU
+        assert!(collector.awaiting());
+        collector.push(0x7E9, &[0x02, 0x49, 0x02, 0, 0, 0, 0, 0]).unwrap();
+        assert!(!collector.awaiting());
+
+        let responses = collector.finish();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0].ecu_id, 0x7E8);
+        assert_eq!(&responses[0].data[3..], b"WBANF71060B123456");
+        assert_eq!(responses[1].data, vec![0x49, 0x02]);
+    }
+
+    #[test]
+    fn test_collector_rejects_sequence_gap() {
+        let mut collector = ResponseCollector::default();
+        collector.push(0x7E8, &[0x10, 0x0A, 1, 2, 3, 4, 5, 6]).unwrap();
+        assert!(collector.push(0x7E8, &[0x22, 7, 8, 9, 10, 0, 0, 0]).is_err());
+    }
+}
@@ -0,0 +1,129 @@
+//! Generic OBD-II Commands over ISO 15765-4 CAN
+//!
+//! Emissions diagnostics for any OBD-II vehicle, not just BMW, using the
+//! D-CAN side of the K+DCAN cable.
+
+use crate::dcan::DCanHandler;
+use crate::obd_can::{ObdCanConfig, ObdCanHandler, ObdCanResponse, ObdCanTransport};
+use crate::serial::SerialState;
+use bmw_diag_core::obd_service::{self, EmissionsReport};
+use serde::Serialize;
+use std::sync::Mutex;
+use tauri::State;
+
+/// Bus settings detected for the connected vehicle
+#[derive(Default)]
+pub struct ObdCanState(pub Mutex<Option<ObdCanConfig>>);
+
+/// Emissions readiness of one ECU
+#[derive(Debug, Clone, Serialize)]
+pub struct EcuEmissionsReport {
+    /// CAN ID the ECU replies on
+    pub ecu_id: u32,
+    pub report: Option<EmissionsReport>,
+    pub error: Option<String>,
+}
+
+/// Run `f` on the port in D-CAN mode, detecting the bus settings first
+/// if none are known
+fn with_obd_can<T>(
+    state: &State<SerialState>,
+    can: &State<ObdCanState>,
+    f: impl FnOnce(&mut Box<dyn serialport::SerialPort>, ObdCanConfig) -> Result<T, String>,
+) -> Result<T, String> {
+    let known = *can.0.lock().map_err(|e| format!("Lock error: {}", e))?;
+
+    state.with_port(|port| {
+        let config = match known {
+            Some(config) => {
+                DCanHandler::switch_to_dcan_mode_at(port, config.baud_rate)?;
+                config
+            }
+            None => {
+                let config = ObdCanHandler::detect(port)?;
+                *can.0.lock().map_err(|e| format!("Lock error: {}", e))? = Some(config);
+                config
+            }
+        };
+        f(port, config)
+    })
+}
+
+/// Detect OBD-II CAN bus speed (500k/250k) and identifier length (11/29-bit)
+#[tauri::command]
+pub fn obd_can_detect(
+    state: State<SerialState>,
+    can: State<ObdCanState>,
+) -> Result<ObdCanConfig, String> {
+    let config = state.with_port(ObdCanHandler::detect)?;
+    *can.0.lock().map_err(|e| format!("Lock error: {}", e))? = Some(config);
+    Ok(config)
+}
+
+/// Send a functional OBD-II request and return every ECU's reply
+#[tauri::command]
+pub fn obd_can_request(
+    state: State<SerialState>,
+    can: State<ObdCanState>,
+    data: Vec<u8>,
+) -> Result<Vec<ObdCanResponse>, String> {
+    with_obd_can(&state, &can, |port, config| {
+        ObdCanHandler::functional_request(port, config.addressing, &data)
+    })
+}
+
+/// Emissions readiness report of every OBD-II ECU on the CAN bus
+#[tauri::command]
+pub fn obd_can_emissions_readiness(
+    state: State<SerialState>,
+    can: State<ObdCanState>,
+) -> Result<Vec<EcuEmissionsReport>, String> {
+    with_obd_can(&state, &can, |port, config| {
+        let ecus: Vec<u32> = ObdCanHandler::functional_request(port, config.addressing, &[0x01, 0x00])?
+            .into_iter()
+            .filter(|response| response.data.first() == Some(&0x41))
+            .map(|response| response.ecu_id)
+            .collect();
+
+        Ok(ecus
+            .into_iter()
+            .map(|ecu_id| {
+                let mut transport = ObdCanTransport {
+                    port: &mut *port,
+                    addressing: config.addressing,
+                    ecu_id,
+                };
+                match obd_service::read_emissions_report(&mut transport) {
+                    Ok(report) => EcuEmissionsReport {
+                        ecu_id,
+                        report: Some(report),
+                        error: None,
+                    },
+                    Err(e) => EcuEmissionsReport {
+                        ecu_id,
+                        report: None,
+                        error: Some(e),
+                    },
+                }
+            })
+            .collect())
+    })
+}
+
+/// Clear emission DTCs on all OBD-II ECUs (Mode 04, functional)
+///
+/// Returns the IDs of the ECUs that confirmed the clear.
+#[tauri::command]
+pub fn obd_can_clear_emissions_dtcs(
+    state: State<SerialState>,
+    can: State<ObdCanState>,
+) -> Result<Vec<u32>, String> {
+    with_obd_can(&state, &can, |port, config| {
+        let responses = ObdCanHandler::functional_request(port, config.addressing, &[0x04])?;
+        Ok(responses
+            .into_iter()
+            .filter(|response| response.data.first() == Some(&0x44))
+            .map(|response| response.ecu_id)
+            .collect())
+    })
+}
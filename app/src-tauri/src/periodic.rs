@@ -0,0 +1,222 @@
+//! Periodic Data (UDS 0x2A ReadDataByPeriodicIdentifier)
+//!
+//! Lets a D-CAN ECU push DID records on its own at slow, medium or fast
+//! rate, with no request per sample. The DIDs are packed into 0xF2xx
+//! dynamically defined identifiers small enough that every periodic
+//! message fits one CAN frame, and each unsolicited 0x6A message is split
+//! back into `DidValue`s.
+
+use crate::bmw::DidValue;
+use crate::dynamic_id::{DynamicDefinition, UDS_DYNAMIC_DID};
+use crate::pid_commands::did_value;
+use bmw_diag_core::dtc_service::{DtcDialect, DtcTransport};
+use bmw_diag_core::obd_service::exchange;
+use serde::{Deserialize, Serialize};
+
+const READ_PERIODIC: u8 = 0x2A;
+const PERIODIC_RESPONSE: u8 = 0x6A;
+const STOP_SENDING: u8 = 0x04;
+const NEGATIVE_RESPONSE: u8 = 0x7F;
+
+/// Record bytes per message: a single frame less PCI, SID and identifier
+const RECORD_BYTES: usize = 5;
+/// Periodic identifiers one schedule may use
+const MAX_RECORDS: usize = 16;
+/// Periodic messages tolerated ahead of the stop confirmation
+const MAX_STALE_MESSAGES: usize = 20;
+
+/// Transmission rate; the ECU defines the actual periods
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeriodicRate {
+    Slow,
+    Medium,
+    Fast,
+}
+
+impl PeriodicRate {
+    fn transmission_mode(self) -> u8 {
+        match self {
+            Self::Slow => 0x01,
+            Self::Medium => 0x02,
+            Self::Fast => 0x03,
+        }
+    }
+}
+
+/// DIDs scheduled for periodic transmission
+#[derive(Debug, Clone)]
+pub struct PeriodicSchedule {
+    pub rate: PeriodicRate,
+    records: Vec<DynamicDefinition>,
+}
+
+impl PeriodicSchedule {
+    /// Group `dids` into single-frame records
+    ///
+    /// Returns the schedule and the DIDs left out because their length is
+    /// unknown or all periodic identifiers are taken.
+    pub fn plan(rate: PeriodicRate, dids: &[u16]) -> (Self, Vec<u16>) {
+        let mut records = Vec::new();
+        let mut pending = dids.to_vec();
+
+        while !pending.is_empty() && records.len() < MAX_RECORDS {
+            let id = UDS_DYNAMIC_DID + records.len() as u16;
+            let (record, rest) =
+                DynamicDefinition::pack_as(DtcDialect::Uds, id, &pending, RECORD_BYTES);
+            if record.slots.is_empty() {
+                break;
+            }
+            records.push(record);
+            pending = rest;
+        }
+
+        (Self { rate, records }, pending)
+    }
+
+    /// Whether any DID was scheduled
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Periodic identifiers (low byte of the 0xF2xx DIDs)
+    fn identifiers(&self) -> impl Iterator<Item = u8> + '_ {
+        self.records.iter().map(|record| record.id as u8)
+    }
+
+    pub fn start_request(&self) -> Vec<u8> {
+        let mut request = vec![READ_PERIODIC, self.rate.transmission_mode()];
+        request.extend(self.identifiers());
+        request
+    }
+
+    pub fn stop_request(&self) -> Vec<u8> {
+        let mut request = vec![READ_PERIODIC, STOP_SENDING];
+        request.extend(self.identifiers());
+        request
+    }
+
+    /// Define the records and start transmission
+    ///
+    /// Records already defined are removed again if the ECU refuses.
+    pub fn start(&self, transport: &mut dyn DtcTransport) -> Result<(), String> {
+        let result = self.define_and_start(transport);
+        if result.is_err() {
+            self.remove_records(transport);
+        }
+        result
+    }
+
+    fn define_and_start(&self, transport: &mut dyn DtcTransport) -> Result<(), String> {
+        for record in &self.records {
+            if let Some(nrc) = record.install(transport)? {
+                return Err(format!(
+                    "Dynamic identifier 0x{:04X} rejected: NRC 0x{:02X}",
+                    record.id, nrc
+                ));
+            }
+        }
+
+        match exchange(transport, &self.start_request())?.as_slice() {
+            [PERIODIC_RESPONSE] => Ok(()),
+            [NEGATIVE_RESPONSE, _, nrc, ..] => Err(format!("Negative response: NRC 0x{:02X}", nrc)),
+            response => Err(format!("Unexpected response: {:02X?}", response)),
+        }
+    }
+
+    /// Stop transmission and remove the records
+    pub fn stop(&self, transport: &mut dyn DtcTransport) -> Result<(), String> {
+        let mut response = transport.request(&self.stop_request())?;
+        // Messages already queued arrive ahead of the confirmation
+        for _ in 0..MAX_STALE_MESSAGES {
+            if !matches!(response.as_slice(), [PERIODIC_RESPONSE, _, ..]) {
+                break;
+            }
+            response = transport.receive()?;
+        }
+
+        let result = match response.as_slice() {
+            [PERIODIC_RESPONSE] => Ok(()),
+            [NEGATIVE_RESPONSE, _, nrc, ..] => Err(format!("Negative response: NRC 0x{:02X}", nrc)),
+            response => Err(format!("Unexpected response: {:02X?}", response)),
+        };
+        self.remove_records(transport);
+        result
+    }
+
+    fn remove_records(&self, transport: &mut dyn DtcTransport) {
+        for record in &self.records {
+            if let Err(e) = record.remove(transport) {
+                log::warn!(
+                    "Failed to clear dynamic identifier 0x{:04X}: {}",
+                    record.id,
+                    e
+                );
+            }
+        }
+    }
+
+    /// DID values carried by one periodic message, `None` if it is not one
+    pub fn decode(&self, message: &[u8]) -> Option<Vec<DidValue>> {
+        let [PERIODIC_RESPONSE, id, record @ ..] = message else {
+            return None;
+        };
+        let definition = self.records.iter().find(|r| r.id as u8 == *id)?;
+        let values = definition
+            .split(record)
+            .ok()?
+            .into_iter()
+            .map(|(did, data)| did_value(did, data))
+            .collect();
+        Some(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bmw::diesel_dids;
+
+    const RAIL: u16 = diesel_dids::FUEL_RAIL_PRESSURE;
+    const BOOST: u16 = 0x3970;
+    const EGR: u16 = 0x3960;
+
+    #[test]
+    fn test_plan_fits_records_in_single_frames() {
+        let (schedule, rest) =
+            PeriodicSchedule::plan(PeriodicRate::Fast, &[RAIL, BOOST, EGR, 0x1234]);
+
+        // 2 + 2 + 1 bytes fill the first record exactly
+        assert_eq!(schedule.records.len(), 1);
+        assert_eq!(schedule.records[0].record_length(), 5);
+        assert_eq!(rest, vec![0x1234]);
+        assert_eq!(schedule.start_request(), vec![0x2A, 0x03, 0x00]);
+        assert_eq!(schedule.stop_request(), vec![0x2A, 0x04, 0x00]);
+    }
+
+    #[test]
+    fn test_plan_spreads_over_identifiers() {
+        let (schedule, rest) =
+            PeriodicSchedule::plan(PeriodicRate::Slow, &[RAIL, BOOST, 0x3971, EGR]);
+
+        assert!(rest.is_empty());
+        let ids: Vec<u16> = schedule.records.iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec![0xF200, 0xF201]);
+        assert_eq!(schedule.start_request(), vec![0x2A, 0x01, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn test_decode_periodic_message() {
+        let (schedule, _) = PeriodicSchedule::plan(PeriodicRate::Medium, &[RAIL, EGR]);
+
+        let values = schedule.decode(&[0x6A, 0x00, 0x27, 0x10, 0xFF]).unwrap();
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[0].did, RAIL);
+        assert!((values[0].value - 1000.0).abs() < 0.01);
+
+        // Positive response, unknown identifier, short record
+        assert!(schedule.decode(&[0x6A]).is_none());
+        assert!(schedule.decode(&[0x6A, 0x05, 0x27, 0x10, 0xFF]).is_none());
+        assert!(schedule.decode(&[0x6A, 0x00, 0x27]).is_none());
+    }
+}
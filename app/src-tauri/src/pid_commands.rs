@@ -3,13 +3,29 @@
 //! Reads OBD-II PIDs and BMW-specific live data from ECUs.
 //! Includes diesel-specific DIDs for E60 520d (M47N2/N47).
 
-use crate::bmw::{get_diesel_pid_definitions, calculate_diesel_did_value, DieselPidDefinition, DidValue};
+use crate::bmw::{self, get_diesel_pid_definitions, calculate_diesel_did_value, DieselPidDefinition, DidValue};
+use crate::bmw_commands::{DCanDtcTransport, KLineDtcTransport};
+use crate::channels;
+use crate::constants::addresses;
+use crate::db_commands::DbState;
+use crate::dcan::{can_ids, DCanHandler};
+use crate::dynamic_id::DynamicReader;
 use crate::kline::KLineHandler;
+use crate::profiles;
 use crate::serial::SerialState;
+use crate::validators;
+use bmw_diag_core::dtc_service::{DtcDialect, DtcTransport};
+use bmw_diag_core::obd_service;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
 use tauri::State;
 use std::time::Duration;
 
+/// Mode 01 PIDs each ECU reported as supported, by K-Line address
+#[derive(Default)]
+pub struct PidCacheState(pub Mutex<HashMap<u8, Vec<u8>>>);
+
 /// PID definition
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PidDefinition {
@@ -34,100 +50,158 @@ pub struct LiveDataValue {
 }
 
 /// Available PIDs that can be read
+///
+/// With `target_address`, only the PIDs that ECU reported in
+/// `discover_pids_kline` are listed; ECUs not yet queried get the full list.
 #[tauri::command]
-pub fn get_available_pids() -> Vec<PidDefinition> {
-    vec![
-        PidDefinition {
-            id: 0x05,
-            name: "Engine Coolant Temperature".to_string(),
-            short_name: "Coolant".to_string(),
-            unit: "°C".to_string(),
-            min: -40.0,
-            max: 215.0,
-            format: "temperature".to_string(),
-        },
-        PidDefinition {
-            id: 0x0C,
-            name: "Engine RPM".to_string(),
-            short_name: "RPM".to_string(),
-            unit: "rpm".to_string(),
-            min: 0.0,
-            max: 8000.0,
-            format: "rpm".to_string(),
-        },
-        PidDefinition {
-            id: 0x0D,
-            name: "Vehicle Speed".to_string(),
-            short_name: "Speed".to_string(),
-            unit: "km/h".to_string(),
-            min: 0.0,
-            max: 255.0,
-            format: "speed".to_string(),
-        },
-        PidDefinition {
-            id: 0x0F,
-            name: "Intake Air Temperature".to_string(),
-            short_name: "Intake".to_string(),
-            unit: "°C".to_string(),
-            min: -40.0,
-            max: 215.0,
-            format: "temperature".to_string(),
-        },
-        PidDefinition {
-            id: 0x10,
-            name: "MAF Air Flow Rate".to_string(),
-            short_name: "MAF".to_string(),
-            unit: "g/s".to_string(),
-            min: 0.0,
-            max: 655.35,
-            format: "flow".to_string(),
-        },
-        PidDefinition {
-            id: 0x11,
-            name: "Throttle Position".to_string(),
-            short_name: "Throttle".to_string(),
-            unit: "%".to_string(),
-            min: 0.0,
-            max: 100.0,
-            format: "percent".to_string(),
-        },
-        PidDefinition {
-            id: 0x2F,
-            name: "Fuel Tank Level".to_string(),
-            short_name: "Fuel".to_string(),
-            unit: "%".to_string(),
-            min: 0.0,
-            max: 100.0,
-            format: "percent".to_string(),
-        },
-        PidDefinition {
-            id: 0x42,
-            name: "Control Module Voltage".to_string(),
-            short_name: "Voltage".to_string(),
-            unit: "V".to_string(),
-            min: 0.0,
-            max: 65.535,
-            format: "voltage".to_string(),
-        },
-        PidDefinition {
-            id: 0x46,
-            name: "Ambient Air Temperature".to_string(),
-            short_name: "Ambient".to_string(),
-            unit: "°C".to_string(),
-            min: -40.0,
-            max: 215.0,
-            format: "temperature".to_string(),
-        },
-        PidDefinition {
-            id: 0x5C,
-            name: "Engine Oil Temperature".to_string(),
-            short_name: "Oil Temp".to_string(),
-            unit: "°C".to_string(),
-            min: -40.0,
-            max: 210.0,
-            format: "temperature".to_string(),
-        },
-    ]
+pub fn get_available_pids(
+    cache: State<PidCacheState>,
+    target_address: Option<u8>,
+) -> Result<Vec<PidDefinition>, String> {
+    let cache = cache.0.lock().map_err(|e| format!("Lock error: {}", e))?;
+    let supported = target_address.and_then(|target| cache.get(&target));
+
+    Ok(pid_definitions()
+        .into_iter()
+        .filter(|def| supported.map_or(true, |pids| pids.iter().any(|pid| *pid as u16 == def.id)))
+        .collect())
+}
+
+/// Query the supported-PID bitmaps (0x00/0x20/...) of an ECU and cache them
+///
+/// Returns the PID definitions the ECU supports.
+#[tauri::command]
+pub fn discover_pids_kline(
+    state: State<SerialState>,
+    cache: State<PidCacheState>,
+    target_address: u8,
+) -> Result<Vec<PidDefinition>, String> {
+    let source = addresses::TESTER;
+
+    let supported = state.with_port(|port| {
+        let mut transport = KLineDtcTransport {
+            port,
+            target: target_address,
+            source,
+        };
+        obd_service::read_supported_pids(&mut transport)
+            .map_err(|e| format!("PID discovery failed: {}", e))
+    })?;
+    log::info!(
+        "ECU 0x{:02X} supports {} Mode 01 PIDs",
+        target_address,
+        supported.len()
+    );
+
+    cache
+        .0
+        .lock()
+        .map_err(|e| format!("Lock error: {}", e))?
+        .insert(target_address, supported);
+
+    get_available_pids(cache, Some(target_address))
+}
+
+/// Decoded Mode 02 freeze frame
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FreezeFrameResult {
+    pub frame: u8,
+    /// DTC that stored the frame, `None` if no frame is stored
+    pub dtc: Option<String>,
+    pub values: Vec<LiveDataValue>,
+}
+
+/// Read a Mode 02 freeze frame via K-Line
+///
+/// Values use the same scaling as live data. If the ECU has no Mode 02
+/// bitmap, the cached (or full) live data PID list is tried instead.
+#[tauri::command]
+pub fn read_freeze_frame_kline(
+    state: State<SerialState>,
+    cache: State<PidCacheState>,
+    target_address: u8,
+    frame: Option<u8>,
+) -> Result<FreezeFrameResult, String> {
+    let source = addresses::TESTER;
+    let frame = frame.unwrap_or(0);
+    let fallback: Vec<u8> = get_available_pids(cache, Some(target_address))?
+        .iter()
+        .filter_map(|def| u8::try_from(def.id).ok())
+        .collect();
+
+    let freeze_frame = state.with_port(|port| {
+        let mut transport = KLineDtcTransport {
+            port,
+            target: target_address,
+            source,
+        };
+        obd_service::read_freeze_frame(&mut transport, frame, &fallback)
+            .map_err(|e| format!("Freeze frame read failed: {}", e))
+    })?;
+
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+
+    let values = freeze_frame
+        .values
+        .into_iter()
+        .filter_map(|value| {
+            let pid = value.pid as u16;
+            let (value_f, unit, name) = calculate_pid_value(pid, &value.data).ok()?;
+            Some(LiveDataValue {
+                pid,
+                name,
+                value: value_f,
+                unit,
+                raw: value.data,
+                timestamp,
+            })
+        })
+        .collect();
+
+    Ok(FreezeFrameResult {
+        frame: freeze_frame.frame,
+        dtc: freeze_frame.dtc,
+        values,
+    })
+}
+
+/// Live data PIDs known to the tester, from the OBD-II channel table
+fn pid_definitions() -> Vec<PidDefinition> {
+    let db = channels::read_global();
+    db.channels(channels::OBD, channels::SERVICE_MODE_01)
+        .map(|channel| {
+            let def = &channel.def;
+            PidDefinition {
+                id: channel.id,
+                name: def.description.en.clone().unwrap_or_else(|| channel.label()),
+                short_name: def.short_name.clone(),
+                unit: def.unit.clone(),
+                min: def.min,
+                max: def.max,
+                format: def.category.clone(),
+            }
+        })
+        .collect()
+}
+
+/// Channel definition database info
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelDbInfo {
+    pub versions: Vec<String>,
+    pub channels: usize,
+}
+
+/// Get loaded channel definition file versions
+#[tauri::command]
+pub fn get_channel_db_info() -> ChannelDbInfo {
+    let db = channels::read_global();
+    ChannelDbInfo {
+        versions: db.versions().to_vec(),
+        channels: db.len(),
+    }
 }
 
 /// Read a single PID value via K-Line
@@ -137,17 +211,18 @@ pub fn read_pid_kline(
     target_address: u8,
     pid: u16,
 ) -> Result<LiveDataValue, String> {
-    let source = 0xF1;
-
-    let mut manager = state
-        .0
-        .lock()
-        .map_err(|e| format!("Lock error: {}", e))?;
-
-    let port = manager
-        .get_port_mut()
-        .ok_or_else(|| "Not connected".to_string())?;
+    state.with_port(|port| {
+        let mut transport = KLineDtcTransport {
+            port,
+            target: target_address,
+            source: addresses::TESTER,
+        };
+        request_pid(&mut transport, pid)
+    })
+}
 
+/// Read one Mode 01 PID from an open session
+pub(crate) fn request_pid(transport: &mut dyn DtcTransport, pid: u16) -> Result<LiveDataValue, String> {
     // OBD-II Mode 01 - Show current data
     // Request format: [0x01] [PID]
     let request = if pid <= 0xFF {
@@ -157,7 +232,7 @@ pub fn read_pid_kline(
         vec![0x01, (pid >> 8) as u8, (pid & 0xFF) as u8]
     };
 
-    let response = KLineHandler::send_request(port, target_address, source, &request)?;
+    let response = obd_service::exchange(transport, &request)?;
 
     // Parse response
     // Response format: [0x41] [PID] [DATA...]
@@ -254,93 +329,14 @@ pub fn read_pids_kline(
 }
 
 /// Calculate PID value from raw bytes
+///
+/// Unknown PIDs give the first byte as a raw value.
 fn calculate_pid_value(pid: u16, data: &[u8]) -> Result<(f64, String, String), String> {
+    if let Some(decoded) = channels::decode(channels::OBD, channels::SERVICE_MODE_01, pid, data) {
+        return Ok(decoded);
+    }
     let a = data.first().copied().unwrap_or(0) as f64;
-    let b = data.get(1).copied().unwrap_or(0) as f64;
-
-    let (value, unit, name) = match pid {
-        // Engine coolant temperature
-        0x05 => (a - 40.0, "°C".to_string(), "Coolant Temp".to_string()),
-
-        // Engine RPM
-        0x0C => (
-            (256.0 * a + b) / 4.0,
-            "rpm".to_string(),
-            "Engine RPM".to_string(),
-        ),
-
-        // Vehicle speed
-        0x0D => (a, "km/h".to_string(), "Vehicle Speed".to_string()),
-
-        // Intake air temperature
-        0x0F => (a - 40.0, "°C".to_string(), "Intake Air Temp".to_string()),
-
-        // MAF air flow rate
-        0x10 => (
-            (256.0 * a + b) / 100.0,
-            "g/s".to_string(),
-            "MAF Rate".to_string(),
-        ),
-
-        // Throttle position
-        0x11 => (
-            a * 100.0 / 255.0,
-            "%".to_string(),
-            "Throttle Position".to_string(),
-        ),
-
-        // Fuel tank level input
-        0x2F => (
-            a * 100.0 / 255.0,
-            "%".to_string(),
-            "Fuel Level".to_string(),
-        ),
-
-        // Control module voltage
-        0x42 => (
-            (256.0 * a + b) / 1000.0,
-            "V".to_string(),
-            "Battery Voltage".to_string(),
-        ),
-
-        // Ambient air temperature
-        0x46 => (a - 40.0, "°C".to_string(), "Ambient Temp".to_string()),
-
-        // Engine oil temperature
-        0x5C => (a - 40.0, "°C".to_string(), "Oil Temp".to_string()),
-
-        // Absolute load value
-        0x43 => (
-            (256.0 * a + b) * 100.0 / 255.0,
-            "%".to_string(),
-            "Absolute Load".to_string(),
-        ),
-
-        // Timing advance
-        0x0E => (a / 2.0 - 64.0, "°".to_string(), "Timing Advance".to_string()),
-
-        // Short term fuel trim Bank 1
-        0x06 => (
-            (a - 128.0) * 100.0 / 128.0,
-            "%".to_string(),
-            "STFT Bank 1".to_string(),
-        ),
-
-        // Long term fuel trim Bank 1
-        0x07 => (
-            (a - 128.0) * 100.0 / 128.0,
-            "%".to_string(),
-            "LTFT Bank 1".to_string(),
-        ),
-
-        // Intake manifold pressure
-        0x0B => (a, "kPa".to_string(), "Intake Pressure".to_string()),
-
-        // Unknown PID - return raw value
-        _ => (a, "raw".to_string(), format!("PID 0x{:02X}", pid)),
-    };
-
-    Ok((value, unit, name))
+    Ok((a, "raw".to_string(), format!("PID 0x{:02X}", pid)))
 }
 
 // =============================================================================
@@ -348,9 +344,20 @@ fn calculate_pid_value(pid: u16, data: &[u8]) -> Result<(f64, String, String), S
 // =============================================================================
 
 /// Get available diesel-specific PIDs/DIDs
+///
+/// With a `vehicle_id` the list is empty unless the vehicle's profile
+/// reads the DDE table (petrol engines).
 #[tauri::command]
-pub fn get_diesel_pids() -> Vec<DieselPidDefinition> {
-    get_diesel_pid_definitions()
+pub fn get_diesel_pids(
+    db: State<DbState>,
+    vehicle_id: Option<i64>,
+) -> Result<Vec<DieselPidDefinition>, String> {
+    match profiles::for_optional_vehicle(&db, vehicle_id)? {
+        Some(profile) if !profile.has_channels(channels::DDE, channels::SERVICE_DATA_ID) => {
+            Ok(Vec::new())
+        }
+        _ => Ok(get_diesel_pid_definitions()),
+    }
 }
 
 /// Read a single DID (Data Identifier) via K-Line using UDS service 0x22
@@ -360,22 +367,23 @@ pub fn read_did_kline(
     target_address: u8,
     did: u16,
 ) -> Result<DidValue, String> {
-    let source = 0xF1;
-
-    let mut manager = state
-        .0
-        .lock()
-        .map_err(|e| format!("Lock error: {}", e))?;
-
-    let port = manager
-        .get_port_mut()
-        .ok_or_else(|| "Not connected".to_string())?;
+    state.with_port(|port| {
+        let mut transport = KLineDtcTransport {
+            port,
+            target: target_address,
+            source: addresses::TESTER,
+        };
+        request_did(&mut transport, did)
+    })
+}
 
+/// Read one DID from an open session
+pub(crate) fn request_did(transport: &mut dyn DtcTransport, did: u16) -> Result<DidValue, String> {
     // UDS Service 0x22 - ReadDataByIdentifier
     // Request format: [0x22] [DID_HIGH] [DID_LOW]
     let request = vec![0x22, (did >> 8) as u8, (did & 0xFF) as u8];
 
-    let response = KLineHandler::send_request(port, target_address, source, &request)?;
+    let response = obd_service::exchange(transport, &request)?;
 
     // Parse response
     // Positive response format: [0x62] [DID_HIGH] [DID_LOW] [DATA...]
@@ -383,34 +391,11 @@ pub fn read_did_kline(
         if response.first() == Some(&0x7F) {
             let service = response.get(1).copied().unwrap_or(0);
             let nrc = response.get(2).copied().unwrap_or(0);
-            let nrc_desc = match nrc {
-                0x10 => "General reject",
-                0x11 => "Service not supported",
-                0x12 => "Sub-function not supported",
-                0x13 => "Incorrect message length",
-                0x14 => "Response too long",
-                0x21 => "Busy - repeat request",
-                0x22 => "Conditions not correct",
-                0x24 => "Request sequence error",
-                0x25 => "No response from subnet",
-                0x26 => "Failure prevents execution",
-                0x31 => "Request out of range",
-                0x33 => "Security access denied",
-                0x35 => "Invalid key",
-                0x36 => "Exceeded number of attempts",
-                0x37 => "Required time delay not expired",
-                0x70 => "Upload/download not accepted",
-                0x71 => "Transfer data suspended",
-                0x72 => "General programming failure",
-                0x73 => "Wrong block sequence counter",
-                0x78 => "Request correctly received - response pending",
-                0x7E => "Sub-function not supported in active session",
-                0x7F => "Service not supported in active session",
-                _ => "Unknown error",
-            };
             return Err(format!(
                 "Negative response for service 0x{:02X}: 0x{:02X} ({})",
-                service, nrc, nrc_desc
+                service,
+                nrc,
+                bmw::nrc::description(nrc)
             ));
         }
         return Err(format!("Unexpected response: {:02X?}", response));
@@ -430,7 +415,11 @@ pub fn read_did_kline(
     // Extract data bytes (skip service ID and DID)
     let data = &response[3..];
 
-    // Calculate value using diesel-specific formulas
+    Ok(did_value(did, data))
+}
+
+/// Scale DID data with the diesel formulas, raw for unknown DIDs
+pub(crate) fn did_value(did: u16, data: &[u8]) -> DidValue {
     let (value, unit, name) = calculate_diesel_did_value(did, data)
         .unwrap_or_else(|| {
             // Fallback for unknown DIDs
@@ -449,14 +438,14 @@ pub fn read_did_kline(
         .map(|d| d.as_millis() as u64)
         .unwrap_or(0);
 
-    Ok(DidValue {
+    DidValue {
         did,
         name,
         value,
         unit,
         raw: data.to_vec(),
         timestamp,
-    })
+    }
 }
 
 /// Read multiple DIDs in sequence via K-Line
@@ -489,33 +478,7 @@ pub fn read_dids_kline(
                     // Verify DID
                     let resp_did = ((response[1] as u16) << 8) | (response[2] as u16);
                     if resp_did == did {
-                        let data = &response[3..];
-
-                        let (value, unit, name) = calculate_diesel_did_value(did, data)
-                            .unwrap_or_else(|| {
-                                let raw_value = if data.len() >= 2 {
-                                    ((data[0] as f64) * 256.0) + (data[1] as f64)
-                                } else if !data.is_empty() {
-                                    data[0] as f64
-                                } else {
-                                    0.0
-                                };
-                                (raw_value, "raw".to_string(), format!("DID 0x{:04X}", did))
-                            });
-
-                        let timestamp = std::time::SystemTime::now()
-                            .duration_since(std::time::UNIX_EPOCH)
-                            .map(|d| d.as_millis() as u64)
-                            .unwrap_or(0);
-
-                        results.push(DidValue {
-                            did,
-                            name,
-                            value,
-                            unit,
-                            raw: data.to_vec(),
-                            timestamp,
-                        });
+                        results.push(did_value(did, &response[3..]));
                     }
                 } else if response.first() == Some(&0x7F) {
                     // Log negative response but continue with other DIDs
@@ -535,6 +498,66 @@ pub fn read_dids_kline(
     Ok(results)
 }
 
+/// DIDs read through a dynamically defined identifier
+#[derive(Debug, Clone, Serialize)]
+pub struct DynamicDidRead {
+    /// False when the ECU rejected 0x2C and DIDs were read one by one
+    pub packed: bool,
+    pub values: Vec<DidValue>,
+}
+
+/// Read many DIDs with one request via K-Line (KWP 0x2C + 0x21)
+#[tauri::command]
+pub fn read_dids_dynamic_kline(
+    state: State<SerialState>,
+    target_address: u8,
+    dids: Vec<u16>,
+) -> Result<DynamicDidRead, String> {
+    validators::validate_dynamic_dids(&dids).map_err(|e| e.to_string())?;
+
+    state.with_port(|port| {
+        let mut transport = KLineDtcTransport {
+            port,
+            target: target_address,
+            source: addresses::TESTER,
+        };
+        read_dynamic(&mut transport, DtcDialect::Kwp2000, &dids)
+    })
+}
+
+/// Read many DIDs with one request via D-CAN (UDS 0x2C + 0x22)
+#[tauri::command]
+pub fn read_dids_dynamic_dcan(
+    state: State<SerialState>,
+    ecu_name: String,
+    dids: Vec<u16>,
+) -> Result<DynamicDidRead, String> {
+    validators::validate_dynamic_dids(&dids).map_err(|e| e.to_string())?;
+    let (tx_id, rx_id) = can_ids::for_ecu(&ecu_name)
+        .ok_or_else(|| format!("Unknown ECU for D-CAN: {}", ecu_name))?;
+
+    state.with_port(|port| {
+        DCanHandler::switch_to_dcan_mode(port)?;
+        let mut transport = DCanDtcTransport { port, tx_id, rx_id };
+        read_dynamic(&mut transport, DtcDialect::Uds, &dids)
+    })
+}
+
+/// Define, read once and clear a dynamic identifier
+fn read_dynamic(
+    transport: &mut dyn DtcTransport,
+    dialect: DtcDialect,
+    dids: &[u16],
+) -> Result<DynamicDidRead, String> {
+    let mut reader = DynamicReader::define(transport, dialect, dids)?;
+    let packed = reader.is_packed();
+    let values = reader.read(transport)?;
+    if let Err(e) = reader.clear(transport) {
+        log::warn!("Failed to clear dynamic identifier: {}", e);
+    }
+    Ok(DynamicDidRead { packed, values })
+}
+
 /// Read all diesel DIDs by category
 #[tauri::command]
 pub fn read_diesel_category_kline(
@@ -576,33 +599,7 @@ pub fn read_diesel_category_kline(
                 if response.first() == Some(&0x62) && response.len() >= 3 {
                     let resp_did = ((response[1] as u16) << 8) | (response[2] as u16);
                     if resp_did == did {
-                        let data = &response[3..];
-
-                        let (value, unit, name) = calculate_diesel_did_value(did, data)
-                            .unwrap_or_else(|| {
-                                let raw_value = if data.len() >= 2 {
-                                    ((data[0] as f64) * 256.0) + (data[1] as f64)
-                                } else if !data.is_empty() {
-                                    data[0] as f64
-                                } else {
-                                    0.0
-                                };
-                                (raw_value, "raw".to_string(), format!("DID 0x{:04X}", did))
-                            });
-
-                        let timestamp = std::time::SystemTime::now()
-                            .duration_since(std::time::UNIX_EPOCH)
-                            .map(|d| d.as_millis() as u64)
-                            .unwrap_or(0);
-
-                        results.push(DidValue {
-                            did,
-                            name,
-                            value,
-                            unit,
-                            raw: data.to_vec(),
-                            timestamp,
-                        });
+                        results.push(did_value(did, &response[3..]));
                     }
                 }
             }
@@ -0,0 +1,640 @@
+//! Engine and Vehicle Profiles
+//!
+//! A profile says which channel tables, DPF routine IDs and ECUs apply to
+//! an engine (M47N2, M57N2, N47, N57, M54, N52, N53, N54). It is chosen
+//! from the engine code and chassis of the selected vehicle, so the live
+//! data, DPF and ECU scan commands talk to the car that is connected
+//! instead of assuming an E60 520d.
+//!
+//! The bundled profiles live in `data/profiles/` at the repository root;
+//! additional JSON files are loaded from the app data dir and replace
+//! bundled profiles with the same key.
+
+use crate::bmw;
+use crate::database::Vehicle;
+use crate::db_commands::DbState;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::{OnceLock, RwLock, RwLockReadGuard};
+use tauri::State;
+
+/// Bundled profile file
+const BUNDLED: (&str, &str) = (
+    "profiles.json",
+    include_str!("../../../data/profiles/profiles.json"),
+);
+
+/// Engine fuel type
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Fuel {
+    Diesel,
+    Petrol,
+}
+
+/// Channel table used by a profile
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelSet {
+    /// ECU name as in the channel files (`OBD`, `DDE`, `DME`, ...)
+    pub ecu: String,
+    /// Request service, 2 hex digits
+    pub service: String,
+    /// Channel keys the live stream reads when no channels are requested
+    #[serde(default)]
+    pub live: Vec<String>,
+}
+
+/// DPF routines, each a list of RoutineControl IDs in hex tried in order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DpfRoutineDef {
+    pub reset_ash: Vec<String>,
+    pub reset_learned: Vec<String>,
+    pub new_dpf: Vec<String>,
+    pub start_regen: Vec<String>,
+    pub stop_regen: Vec<String>,
+}
+
+/// One profile as written in a profile file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileDef {
+    /// Stable name, e.g. `M47N2`
+    pub key: String,
+    pub name: String,
+    /// Engine code prefixes, e.g. `M57` for `M57D30`
+    pub engine_codes: Vec<String>,
+    /// Chassis codes the profile applies to; empty for any
+    #[serde(default)]
+    pub chassis: Vec<String>,
+    pub fuel: Fuel,
+    /// ECU id of the engine ECU (`DDE` or `DME`)
+    pub engine_ecu: String,
+    #[serde(default)]
+    pub channels: Vec<ChannelSet>,
+    /// Omitted for engines without a particulate filter
+    #[serde(default)]
+    pub dpf: Option<DpfRoutineDef>,
+    /// ECU ids fitted to the vehicle, as in `bmw::e60_ecus()`
+    pub ecus: Vec<String>,
+}
+
+/// JSON file layout
+#[derive(Debug, Deserialize)]
+struct ProfileFile {
+    version: String,
+    /// Key of the profile used when nothing matches
+    #[serde(default)]
+    default: Option<String>,
+    profiles: Vec<ProfileDef>,
+}
+
+/// DPF routine run by the DPF commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DpfRoutine {
+    ResetAsh,
+    ResetLearned,
+    NewDpf,
+    StartRegen,
+    StopRegen,
+}
+
+/// Validated profile with its identifiers parsed
+#[derive(Debug, Clone, Serialize)]
+pub struct Profile {
+    #[serde(flatten)]
+    pub def: ProfileDef,
+    /// (ECU, service) of each entry of `def.channels`
+    #[serde(skip)]
+    channel_sets: Vec<(String, u8)>,
+    #[serde(skip)]
+    dpf: Option<[Vec<u16>; 5]>,
+}
+
+fn parse_ids(key: &str, what: &str, ids: &[String]) -> Result<Vec<u16>, String> {
+    if ids.is_empty() {
+        return Err(format!("{}: no routine for {}", key, what));
+    }
+    ids.iter()
+        .map(|id| {
+            u16::from_str_radix(id.trim(), 16)
+                .map_err(|_| format!("{}: invalid routine ID '{}'", key, id))
+        })
+        .collect()
+}
+
+impl Profile {
+    fn new(def: ProfileDef) -> Result<Self, String> {
+        if def.engine_codes.is_empty() {
+            return Err(format!("{}: no engine codes", def.key));
+        }
+
+        let known: Vec<String> = bmw::e60_ecus().into_iter().map(|ecu| ecu.id).collect();
+        if let Some(ecu) = def
+            .ecus
+            .iter()
+            .find(|ecu| !known.contains(&ecu.to_ascii_uppercase()))
+        {
+            return Err(format!("{}: unknown ECU '{}'", def.key, ecu));
+        }
+        if !def
+            .ecus
+            .iter()
+            .any(|ecu| ecu.eq_ignore_ascii_case(&def.engine_ecu))
+        {
+            return Err(format!(
+                "{}: engine ECU '{}' is not in the ECU list",
+                def.key, def.engine_ecu
+            ));
+        }
+
+        let channel_sets = def
+            .channels
+            .iter()
+            .map(|set| {
+                u8::from_str_radix(set.service.trim(), 16)
+                    .map(|service| (set.ecu.to_ascii_uppercase(), service))
+                    .map_err(|_| format!("{}: invalid service '{}'", def.key, set.service))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let dpf = match &def.dpf {
+            Some(dpf) => Some([
+                parse_ids(&def.key, "reset_ash", &dpf.reset_ash)?,
+                parse_ids(&def.key, "reset_learned", &dpf.reset_learned)?,
+                parse_ids(&def.key, "new_dpf", &dpf.new_dpf)?,
+                parse_ids(&def.key, "start_regen", &dpf.start_regen)?,
+                parse_ids(&def.key, "stop_regen", &dpf.stop_regen)?,
+            ]),
+            None => None,
+        };
+
+        Ok(Self {
+            def,
+            channel_sets,
+            dpf,
+        })
+    }
+
+    /// Default live channels as (ECU, service, channel keys)
+    pub fn live_sets(&self) -> impl Iterator<Item = (&str, u8, &[String])> {
+        self.channel_sets
+            .iter()
+            .zip(&self.def.channels)
+            .filter(|(_, set)| !set.live.is_empty())
+            .map(|((ecu, service), set)| (ecu.as_str(), *service, set.live.as_slice()))
+    }
+
+    /// Whether the profile reads the table of `ecu` and `service`
+    pub fn has_channels(&self, ecu: &str, service: u8) -> bool {
+        self.channel_sets.iter().any(|(set_ecu, set_service)| {
+            *set_service == service && set_ecu.eq_ignore_ascii_case(ecu)
+        })
+    }
+
+    /// Routine IDs to try for a DPF routine, `None` without a DPF
+    pub fn dpf_routine(&self, routine: DpfRoutine) -> Option<&[u16]> {
+        self.dpf
+            .as_ref()
+            .map(|ids| ids[routine as usize].as_slice())
+    }
+
+    /// Whether an ECU id is fitted with this engine
+    pub fn has_ecu(&self, ecu_id: &str) -> bool {
+        self.def
+            .ecus
+            .iter()
+            .any(|ecu| ecu.eq_ignore_ascii_case(ecu_id))
+    }
+
+    /// K-Line address of the engine ECU
+    pub fn engine_address(&self) -> Option<u8> {
+        bmw::e60_ecus()
+            .into_iter()
+            .find(|ecu| ecu.id.eq_ignore_ascii_case(&self.def.engine_ecu))
+            .and_then(|ecu| ecu.kline_address)
+    }
+
+    /// Length of the longest engine code prefix matching `code`
+    fn engine_match(&self, code: &str) -> Option<usize> {
+        self.def
+            .engine_codes
+            .iter()
+            .map(|prefix| normalize(prefix))
+            .filter(|prefix| !prefix.is_empty() && code.starts_with(prefix.as_str()))
+            .map(|prefix| prefix.len())
+            .max()
+    }
+
+    fn fits_chassis(&self, chassis: Option<&str>) -> bool {
+        match chassis {
+            Some(chassis) if !self.def.chassis.is_empty() => self
+                .def
+                .chassis
+                .iter()
+                .any(|c| c.eq_ignore_ascii_case(chassis)),
+            _ => true,
+        }
+    }
+}
+
+/// Upper case without spaces, dashes and dots
+fn normalize(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// Chassis code written in a model name, e.g. `E60` in "520d E60 LCI"
+pub fn chassis_from_model(model: &str) -> Option<String> {
+    model
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .map(str::to_ascii_uppercase)
+        .find(|word| {
+            let bytes = word.as_bytes();
+            bytes.len() == 3
+                && matches!(bytes[0], b'E' | b'F' | b'G')
+                && bytes[1..].iter().all(u8::is_ascii_digit)
+        })
+}
+
+/// Profile chosen for a vehicle
+#[derive(Debug, Clone, Serialize)]
+pub struct ProfileSelection {
+    pub profile: Profile,
+    /// False when the default profile was used for lack of a match
+    pub matched: bool,
+    pub engine_code: Option<String>,
+    pub chassis: Option<String>,
+}
+
+/// Profiles by key, in file order
+#[derive(Debug, Default)]
+pub struct ProfileDb {
+    profiles: Vec<Profile>,
+    default: Option<String>,
+    /// "<source> <version>" of every loaded file
+    versions: Vec<String>,
+}
+
+impl ProfileDb {
+    /// Database with the bundled profiles
+    pub fn bundled() -> Self {
+        let mut db = Self::default();
+        let (source, json) = BUNDLED;
+        if let Err(e) = db.load_json(source, json) {
+            log::error!("Bundled profile file is invalid: {}", e);
+        }
+        db
+    }
+
+    /// Load a JSON profile file, returns the number of profiles
+    ///
+    /// The whole file is rejected if any profile is invalid.
+    pub fn load_json(&mut self, source: &str, json: &str) -> Result<usize, String> {
+        let file: ProfileFile =
+            serde_json::from_str(json).map_err(|e| format!("{}: {}", source, e))?;
+        let profiles = file
+            .profiles
+            .into_iter()
+            .map(Profile::new)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("{}: {}", source, e))?;
+
+        if let Some(default) = &file.default {
+            let known = profiles
+                .iter()
+                .chain(&self.profiles)
+                .any(|p| &p.def.key == default);
+            if !known {
+                return Err(format!("{}: unknown default profile '{}'", source, default));
+            }
+        }
+
+        let count = profiles.len();
+        for profile in profiles {
+            match self
+                .profiles
+                .iter_mut()
+                .find(|p| p.def.key == profile.def.key)
+            {
+                Some(existing) => *existing = profile,
+                None => self.profiles.push(profile),
+            }
+        }
+        if file.default.is_some() {
+            self.default = file.default;
+        }
+        self.versions.push(format!("{} {}", source, file.version));
+        Ok(count)
+    }
+
+    /// Load every `.json` file in a directory (sorted by name)
+    pub fn load_dir(&mut self, dir: &Path) -> Result<usize, String> {
+        let mut paths: Vec<_> = std::fs::read_dir(dir)
+            .map_err(|e| format!("{}: {}", dir.display(), e))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("json"))
+            .collect();
+        paths.sort();
+
+        let mut total = 0;
+        for path in paths {
+            let source = path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let loaded = std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|s| self.load_json(&source, &s));
+
+            match loaded {
+                Ok(count) => {
+                    log::info!("Loaded {} profiles from {}", count, path.display());
+                    total += count;
+                }
+                Err(e) => log::warn!("Skipping profile file {}: {}", path.display(), e),
+            }
+        }
+        Ok(total)
+    }
+
+    /// Profile by key
+    pub fn get(&self, key: &str) -> Option<&Profile> {
+        self.profiles
+            .iter()
+            .find(|p| p.def.key.eq_ignore_ascii_case(key))
+    }
+
+    /// All profiles, in file order
+    pub fn profiles(&self) -> &[Profile] {
+        &self.profiles
+    }
+
+    /// Loaded file versions
+    pub fn versions(&self) -> &[String] {
+        &self.versions
+    }
+
+    /// Profile used when nothing matches
+    fn fallback(&self) -> Option<&Profile> {
+        self.default
+            .as_deref()
+            .and_then(|key| self.get(key))
+            .or_else(|| self.profiles.first())
+    }
+
+    /// Best profile for an engine code and chassis
+    ///
+    /// The longest engine code prefix wins; profiles for other chassis are
+    /// skipped when the chassis is known. Falls back to the default profile
+    /// with `matched` false.
+    pub fn select(
+        &self,
+        engine_code: Option<&str>,
+        chassis: Option<&str>,
+    ) -> Result<ProfileSelection, String> {
+        let code = engine_code.map(normalize).filter(|code| !code.is_empty());
+        let best = code.as_deref().and_then(|code| {
+            self.profiles
+                .iter()
+                .filter(|p| p.fits_chassis(chassis))
+                .filter_map(|p| p.engine_match(code).map(|len| (len, p)))
+                // max_by_key keeps the last maximum; reverse so file order breaks ties
+                .rev()
+                .max_by_key(|(len, _)| *len)
+                .map(|(_, p)| p)
+        });
+
+        let (profile, matched) = match best {
+            Some(profile) => (profile, true),
+            None => (self.fallback().ok_or("No vehicle profiles loaded")?, false),
+        };
+        Ok(ProfileSelection {
+            profile: profile.clone(),
+            matched,
+            engine_code: engine_code.map(str::to_string),
+            chassis: chassis.map(str::to_string),
+        })
+    }
+
+    /// Profile for a stored vehicle
+    pub fn for_vehicle(&self, vehicle: &Vehicle) -> Result<ProfileSelection, String> {
+        let chassis = chassis_from_model(&vehicle.model);
+        self.select(vehicle.engine_code.as_deref(), chassis.as_deref())
+    }
+}
+
+/// Process-wide database, initialized with the bundled profiles
+pub fn global() -> &'static RwLock<ProfileDb> {
+    static DB: OnceLock<RwLock<ProfileDb>> = OnceLock::new();
+    DB.get_or_init(|| RwLock::new(ProfileDb::bundled()))
+}
+
+/// Read access to the process-wide database
+pub fn read_global() -> RwLockReadGuard<'static, ProfileDb> {
+    global().read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Profile for an optional vehicle; the default profile without one
+pub fn for_vehicle(db: &DbState, vehicle_id: Option<i64>) -> Result<ProfileSelection, String> {
+    let vehicle = match vehicle_id {
+        Some(id) => {
+            let guard = db.0.lock().map_err(|e| format!("Lock error: {}", e))?;
+            let database = guard.as_ref().ok_or("Database not initialized")?;
+            let vehicle = database
+                .get_vehicle(id)
+                .map_err(|e| format!("Database error: {}", e))?;
+            Some(vehicle.ok_or_else(|| format!("Vehicle {} not found", id))?)
+        }
+        None => None,
+    };
+
+    let profiles = read_global();
+    let selection = match &vehicle {
+        Some(vehicle) => profiles.for_vehicle(vehicle)?,
+        None => profiles.select(None, None)?,
+    };
+    if vehicle.is_some() && !selection.matched {
+        log::warn!(
+            "No profile for engine {:?} / chassis {:?}, using {}",
+            selection.engine_code,
+            selection.chassis,
+            selection.profile.def.key
+        );
+    }
+    Ok(selection)
+}
+
+/// Profile of a vehicle only when one is given
+pub fn for_optional_vehicle(
+    db: &DbState,
+    vehicle_id: Option<i64>,
+) -> Result<Option<Profile>, String> {
+    match vehicle_id {
+        Some(_) => for_vehicle(db, vehicle_id).map(|selection| Some(selection.profile)),
+        None => Ok(None),
+    }
+}
+
+/// Loaded profiles and file versions
+#[derive(Debug, Clone, Serialize)]
+pub struct ProfileDbInfo {
+    pub versions: Vec<String>,
+    pub profiles: Vec<Profile>,
+}
+
+/// List the known engine profiles
+#[tauri::command]
+pub fn get_vehicle_profiles() -> ProfileDbInfo {
+    let db = read_global();
+    ProfileDbInfo {
+        versions: db.versions().to_vec(),
+        profiles: db.profiles().to_vec(),
+    }
+}
+
+/// Profile the commands use for a vehicle (the default without one)
+#[tauri::command]
+pub fn get_vehicle_profile(
+    db: State<DbState>,
+    vehicle_id: Option<i64>,
+) -> Result<ProfileSelection, String> {
+    for_vehicle(&db, vehicle_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bmw::dpf_routines;
+
+    fn profile(key: &str, codes: &str, chassis: &str) -> String {
+        format!(
+            r#"{{"key": "{}", "name": "T", "engine_codes": [{}], "chassis": [{}],
+                "fuel": "diesel", "engine_ecu": "DDE", "ecus": ["DDE"]}}"#,
+            key, codes, chassis
+        )
+    }
+
+    #[test]
+    fn test_bundled_profiles_load() {
+        let db = ProfileDb::bundled();
+        assert_eq!(db.versions().len(), 1);
+        assert!(db.profiles().len() >= 8);
+
+        // The 520d profile keeps the routine IDs the DPF commands always used
+        let m47 = db.get("M47N2").unwrap();
+        assert_eq!(
+            m47.dpf_routine(DpfRoutine::ResetAsh),
+            Some(
+                &[
+                    dpf_routines::RESET_ASH_LOADING,
+                    dpf_routines::alt::RESET_ASH
+                ][..]
+            )
+        );
+        assert_eq!(
+            m47.dpf_routine(DpfRoutine::StopRegen),
+            Some(&[dpf_routines::STOP_FORCED_REGEN][..])
+        );
+        assert!(m47.has_channels("dde", 0x22));
+        let (ecu, service, keys) = m47.live_sets().next().unwrap();
+        assert_eq!((ecu, service), ("DDE", 0x22));
+        assert!(keys.iter().any(|key| key == "FUEL_RAIL_PRESSURE"));
+        assert_eq!(m47.engine_address(), Some(0x12));
+
+        let n54 = db.get("N54").unwrap();
+        assert_eq!(n54.def.fuel, Fuel::Petrol);
+        assert_eq!(n54.dpf_routine(DpfRoutine::StartRegen), None);
+        assert!(n54.has_ecu("DME") && !n54.has_ecu("DDE"));
+    }
+
+    #[test]
+    fn test_select_by_engine_code_and_chassis() {
+        let db = ProfileDb::bundled();
+        let key = |code: Option<&str>, chassis: Option<&str>| {
+            let selection = db.select(code, chassis).unwrap();
+            (selection.profile.def.key, selection.matched)
+        };
+
+        assert_eq!(
+            key(Some("M57D30"), Some("E60")),
+            ("M57N2".to_string(), true)
+        );
+        assert_eq!(key(Some("n47-d20"), None), ("N47".to_string(), true));
+        assert_eq!(key(Some("N54B30"), Some("E90")), ("N54".to_string(), true));
+        // Known engine in a chassis the profile does not list
+        assert_eq!(
+            key(Some("N54B30"), Some("F10")),
+            ("M47N2".to_string(), false)
+        );
+        assert_eq!(key(Some("S85"), None), ("M47N2".to_string(), false));
+        assert_eq!(key(None, None), ("M47N2".to_string(), false));
+    }
+
+    #[test]
+    fn test_longest_prefix_and_overrides() {
+        let mut db = ProfileDb::default();
+        db.load_json(
+            "a.json",
+            &format!(
+                r#"{{"version": "1", "default": "GEN", "profiles": [{}, {}]}}"#,
+                profile("GEN", r#""M57""#, ""),
+                profile("TU2", r#""M57N2""#, r#""E60""#)
+            ),
+        )
+        .unwrap();
+
+        let select = |code| db.select(Some(code), Some("E60")).unwrap().profile.def.key;
+        assert_eq!(select("M57N2D30"), "TU2");
+        assert_eq!(select("M57D25"), "GEN");
+
+        db.load_json(
+            "b.json",
+            &format!(
+                r#"{{"version": "2", "profiles": [{}]}}"#,
+                profile("TU2", r#""M57""#, "")
+            ),
+        )
+        .unwrap();
+        assert_eq!(db.profiles().len(), 2);
+        assert_eq!(db.get("TU2").unwrap().def.engine_codes, vec!["M57"]);
+    }
+
+    #[test]
+    fn test_invalid_profiles_are_rejected() {
+        let mut db = ProfileDb::default();
+        let file = |p: String| format!(r#"{{"version": "1", "profiles": [{}]}}"#, p);
+
+        assert!(db.load_json("a", &file(profile("A", "", ""))).is_err());
+        assert!(db
+            .load_json(
+                "b",
+                &file(profile("B", r#""X""#, "").replace(r#"["DDE"]"#, r#"["XYZ"]"#))
+            )
+            .is_err());
+        assert!(db
+            .load_json(
+                "c",
+                &file(profile("C", r#""X""#, "").replace(r#"["DDE"]"#, r#"["EGS"]"#))
+            )
+            .is_err());
+        assert!(db
+            .load_json(
+                "d",
+                r#"{"version": "1", "default": "NONE", "profiles": []}"#
+            )
+            .is_err());
+        assert!(db.profiles().is_empty());
+        assert!(db.versions().is_empty());
+    }
+
+    #[test]
+    fn test_chassis_from_model() {
+        assert_eq!(chassis_from_model("520d E60"), Some("E60".to_string()));
+        assert_eq!(
+            chassis_from_model("335i (e92) coupe"),
+            Some("E92".to_string())
+        );
+        assert_eq!(chassis_from_model("520d"), None);
+    }
+}
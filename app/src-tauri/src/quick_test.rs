@@ -0,0 +1,476 @@
+//! Vehicle Quick Test
+//!
+//! Visits every known ECU, plus any other ECU answering a functional
+//! TesterPresent on D-CAN, on the protocol it answers on, reads its
+//! identification, fault memory and fault count, and reports the vehicle
+//! as a whole. Progress is streamed to the UI as `quick-test:progress`
+//! events; the result can be stored as one vehicle test grouping a session
+//! per responding ECU.
+
+use crate::bmw::{self, ecu_groups, group_id, Dtc, EcuInfo, Protocol};
+use crate::bmw_commands::{DCanDtcTransport, KLineDtcTransport};
+use crate::constants::{addresses, kwp, uds};
+use crate::database::{NewDtc, NewSession, NewVehicleTest};
+use crate::db_commands::DbState;
+use crate::dcan::{can_ids, detect_protocol, DCanHandler};
+use crate::fault_codes;
+use crate::kline::KLineHandler;
+use crate::profiles;
+use crate::serial::SerialState;
+use bmw_diag_core::dtc_service::{self, DtcDialect};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, State};
+
+/// Event carrying `QuickTestProgress`
+pub const PROGRESS_EVENT: &str = "quick-test:progress";
+
+/// KWP ReadECUIdentification option: BMW identification table
+const KWP_IDENT_OPTION: u8 = 0x80;
+/// UDS vehicleManufacturerSparePartNumber
+const UDS_SPARE_PART_DID: u16 = 0xF187;
+/// How long to collect answers to the functional TesterPresent
+const DISCOVERY_WINDOW: Duration = Duration::from_millis(500);
+/// ID prefix of ECUs found on D-CAN that are not in `bmw::e60_ecus()`
+const DISCOVERED_PREFIX: &str = "CAN_";
+
+/// Outcome for one ECU
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EcuTestStatus {
+    Ok,
+    Faulty,
+    NotResponding,
+}
+
+/// Quick test result of one ECU
+#[derive(Debug, Clone, Serialize)]
+pub struct EcuTestResult {
+    /// ECU ID; ECUs sharing an address are tested once, e.g. "DME/DDE"
+    pub ecu_id: String,
+    pub ecu_name: String,
+    pub protocol: Option<String>,
+    pub status: EcuTestStatus,
+    pub ident: Option<String>,
+    pub fault_count: usize,
+    pub dtcs: Vec<Dtc>,
+    pub message: String,
+    /// Session the result was stored in
+    pub session_id: Option<i64>,
+}
+
+/// Whole-vehicle quick test report
+#[derive(Debug, Clone, Serialize)]
+pub struct QuickTestReport {
+    pub ecus: Vec<EcuTestResult>,
+    pub ok: usize,
+    pub faulty: usize,
+    pub not_responding: usize,
+    pub duration_ms: u64,
+    /// Stored vehicle test, if a vehicle was given
+    pub vehicle_test_id: Option<i64>,
+}
+
+/// Progress event, sent before and after each ECU
+#[derive(Debug, Clone, Serialize)]
+pub struct QuickTestProgress {
+    /// 1-based position of the ECU
+    pub index: usize,
+    pub total: usize,
+    pub ecu_id: String,
+    /// Set once the ECU is done
+    pub result: Option<EcuTestResult>,
+}
+
+impl QuickTestReport {
+    /// Report over `ecus`, counting them by status
+    fn new(ecus: Vec<EcuTestResult>, duration_ms: u64) -> Self {
+        let count = |status| ecus.iter().filter(|ecu| ecu.status == status).count();
+        let (ok, faulty, not_responding) = (
+            count(EcuTestStatus::Ok),
+            count(EcuTestStatus::Faulty),
+            count(EcuTestStatus::NotResponding),
+        );
+        Self {
+            ecus,
+            ok,
+            faulty,
+            not_responding,
+            duration_ms,
+            vehicle_test_id: None,
+        }
+    }
+}
+
+/// Run a quick test over all known and discovered ECUs (or `ecu_names`)
+///
+/// With a `vehicle_id` the report is stored as a vehicle test, with one
+/// session per responding ECU, and without `ecu_names` only the ECUs of
+/// the vehicle's profile are tested, plus those found on D-CAN. Runs off
+/// the main thread so progress events reach the UI while the test holds
+/// the port.
+#[tauri::command(async)]
+pub fn bmw_quick_test(
+    app: AppHandle,
+    state: State<SerialState>,
+    db: State<DbState>,
+    vehicle_id: Option<i64>,
+    mileage_km: Option<i32>,
+    ecu_names: Option<Vec<String>>,
+) -> Result<QuickTestReport, String> {
+    let discover = ecu_names.is_none();
+    let ecu_names = match (ecu_names, profiles::for_optional_vehicle(&db, vehicle_id)?) {
+        (None, Some(profile)) => Some(profile.def.ecus),
+        (names, _) => names,
+    };
+    let mut groups = ecu_groups(bmw::e60_ecus(), ecu_names.as_deref());
+
+    let start = Instant::now();
+
+    // One lock for the whole run, so nothing interleaves with the test
+    let ecus = state.with_port(|port| {
+        if discover {
+            match DCanHandler::discover_ecus(port, DISCOVERY_WINDOW) {
+                Ok(responders) => {
+                    let found = discovered_ecus(&responders, &groups);
+                    groups.extend(ecu_groups(found, None));
+                }
+                Err(e) => log::warn!("D-CAN discovery failed: {}", e),
+            }
+        }
+        if groups.is_empty() {
+            return Err("No matching ECU".to_string());
+        }
+
+        let total = groups.len();
+        let mut results = Vec::with_capacity(total);
+        for (index, group) in groups.iter().enumerate() {
+            let ecu_id = group_id(group);
+            emit_progress(&app, index + 1, total, &ecu_id, None);
+
+            let result = test_ecu(port, group);
+            emit_progress(&app, index + 1, total, &ecu_id, Some(&result));
+            results.push(result);
+
+            // Delay between ECUs
+            std::thread::sleep(Duration::from_millis(200));
+        }
+        Ok(results)
+    })?;
+
+    let mut report = QuickTestReport::new(ecus, start.elapsed().as_millis() as u64);
+
+    if let Some(vehicle_id) = vehicle_id {
+        match save_report(&db, vehicle_id, mileage_km, &report) {
+            Ok((test_id, sessions)) => {
+                report.vehicle_test_id = Some(test_id);
+                for ecu in &mut report.ecus {
+                    ecu.session_id = sessions
+                        .iter()
+                        .find(|(ecu_id, _)| *ecu_id == ecu.ecu_id)
+                        .map(|(_, session_id)| *session_id);
+                }
+            }
+            Err(e) => log::warn!("Failed to store quick test: {}", e),
+        }
+    }
+
+    Ok(report)
+}
+
+/// ECUs among the D-CAN `responders` (response IDs) that `groups` misses
+///
+/// A response ID of a known ECU adds that ECU, e.g. one left out of the
+/// vehicle profile; any other ID becomes an ECU named after it.
+fn discovered_ecus(responders: &[u32], groups: &[Vec<EcuInfo>]) -> Vec<EcuInfo> {
+    let answers_on = |ecu: &EcuInfo, rx_id: u32| ecu.can_rx_id == Some(rx_id);
+    let known = bmw::e60_ecus();
+    let mut found = Vec::new();
+
+    for &rx_id in responders {
+        if groups.iter().flatten().any(|ecu| answers_on(ecu, rx_id)) {
+            continue;
+        }
+        let matching: Vec<EcuInfo> = known
+            .iter()
+            .filter(|ecu| answers_on(ecu, rx_id))
+            .cloned()
+            .collect();
+        if matching.is_empty() {
+            found.push(EcuInfo {
+                id: format!("{}{:03X}", DISCOVERED_PREFIX, rx_id),
+                name: format!("Unknown ECU (D-CAN 0x{:03X})", rx_id),
+                description: "Answered the functional TesterPresent".to_string(),
+                kline_address: None,
+                can_tx_id: Some(rx_id.saturating_sub(can_ids::RESPONSE_OFFSET)),
+                can_rx_id: Some(rx_id),
+                protocol: Protocol::DCan,
+            });
+        } else {
+            found.extend(matching);
+        }
+    }
+
+    found
+}
+
+fn emit_progress(
+    app: &AppHandle,
+    index: usize,
+    total: usize,
+    ecu_id: &str,
+    result: Option<&EcuTestResult>,
+) {
+    let progress = QuickTestProgress {
+        index,
+        total,
+        ecu_id: ecu_id.to_string(),
+        result: result.cloned(),
+    };
+    if let Err(e) = app.emit(PROGRESS_EVENT, progress) {
+        log::warn!("Failed to emit quick test progress: {}", e);
+    }
+}
+
+/// Identification, fault memory and fault count of one ECU
+fn test_ecu(port: &mut Box<dyn serialport::SerialPort>, group: &[EcuInfo]) -> EcuTestResult {
+    let ecu = &group[0];
+    let mut result = EcuTestResult {
+        ecu_id: group_id(group),
+        ecu_name: ecu.name.clone(),
+        protocol: None,
+        status: EcuTestStatus::NotResponding,
+        ident: None,
+        fault_count: 0,
+        dtcs: Vec::new(),
+        message: String::new(),
+        session_id: None,
+    };
+
+    let protocol = match detect_protocol(port, ecu) {
+        Ok(protocol) => protocol,
+        Err(e) => {
+            result.message = e;
+            return result;
+        }
+    };
+
+    let read = match (protocol.as_str(), ecu.can_ids(), ecu.kline_address) {
+        ("D-CAN", Some((tx_id, rx_id)), _) => {
+            result.ident = DCanHandler::read_data_by_id(port, tx_id, rx_id, UDS_SPARE_PART_DID)
+                .ok()
+                .map(|data| ident_text(&data));
+            let count = DCanHandler::read_dtc_count(port, tx_id, rx_id, uds::dtc::STATUS_MASK_ALL)
+                .ok()
+                .map(|count| count.count as usize);
+            let mut transport = DCanDtcTransport { port, tx_id, rx_id };
+            dtc_service::read_dtcs(&mut transport, DtcDialect::for_transport(false))
+                .map(|readout| (readout, count))
+        }
+        ("K-Line", _, Some(target)) => {
+            let source = addresses::TESTER;
+            result.ident = KLineHandler::send_request(
+                port,
+                target,
+                source,
+                &[kwp::READ_ECU_ID, KWP_IDENT_OPTION],
+            )
+            .ok()
+            .and_then(|response| match response.as_slice() {
+                [0x5A, KWP_IDENT_OPTION, data @ ..] => Some(ident_text(data)),
+                _ => None,
+            });
+            let mut transport = KLineDtcTransport { port, target, source };
+            dtc_service::read_dtcs(&mut transport, DtcDialect::for_transport(true))
+                .map(|readout| (readout, None))
+        }
+        _ => Err(format!("No {} address for {}", protocol, ecu.id)),
+    };
+    result.protocol = Some(protocol);
+
+    match read {
+        Ok((readout, count)) => {
+            result.dtcs = readout.records.iter().map(Dtc::from_record).collect();
+            let ids: Vec<&str> = group.iter().map(|ecu| ecu.id.as_str()).collect();
+            fault_codes::enrich_dtcs(&mut result.dtcs, &ids, None);
+
+            result.fault_count = count.unwrap_or(0).max(result.dtcs.len());
+            result.status = if result.fault_count == 0 {
+                EcuTestStatus::Ok
+            } else {
+                EcuTestStatus::Faulty
+            };
+            result.message = format!("{} fault(s)", result.fault_count);
+        }
+        Err(e) => result.message = format!("Read DTCs failed: {}", e),
+    }
+
+    result
+}
+
+/// Identification as text if printable, hex otherwise
+fn ident_text(data: &[u8]) -> String {
+    let trimmed: Vec<u8> = data
+        .iter()
+        .copied()
+        .filter(|b| *b != 0x00 && *b != 0xFF)
+        .collect();
+    if !trimmed.is_empty() && trimmed.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+        String::from_utf8_lossy(&trimmed).trim().to_string()
+    } else {
+        data.iter().map(|b| format!("{:02X}", b)).collect()
+    }
+}
+
+/// Store the report with one session per responding ECU
+///
+/// Returns the vehicle test ID and the (ECU ID, session ID) of each
+/// stored session.
+fn save_report(
+    db: &DbState,
+    vehicle_id: i64,
+    mileage_km: Option<i32>,
+    report: &QuickTestReport,
+) -> Result<(i64, Vec<(String, i64)>), String> {
+    let sessions: Vec<(NewSession, Vec<NewDtc>)> = report
+        .ecus
+        .iter()
+        .filter(|ecu| ecu.status != EcuTestStatus::NotResponding)
+        .map(|ecu| {
+            let session = NewSession {
+                vehicle_id,
+                ecu_id: ecu.ecu_id.clone(),
+                ecu_name: ecu.ecu_name.clone(),
+                protocol: ecu.protocol.clone().unwrap_or_default(),
+                mileage_km,
+                notes: ecu.ident.as_ref().map(|ident| format!("Quick test, ident {}", ident)),
+            };
+            let dtcs = ecu
+                .dtcs
+                .iter()
+                .map(|dtc| NewDtc {
+                    session_id: 0,
+                    code: dtc.code.clone(),
+                    status: format!("0x{:02X}", dtc.status.raw),
+                    description: dtc.description.clone(),
+                    is_pending: dtc.status.pending,
+                    is_confirmed: dtc.status.confirmed,
+                    environment: None,
+                })
+                .collect();
+            (session, dtcs)
+        })
+        .collect();
+
+    let summary = serde_json::to_value(report).map_err(|e| e.to_string())?;
+    let test = NewVehicleTest {
+        vehicle_id,
+        mileage_km,
+        ecus_ok: report.ok as i32,
+        ecus_faulty: report.faulty as i32,
+        ecus_not_responding: report.not_responding as i32,
+        report: summary,
+    };
+
+    let guard = db.0.lock().map_err(|e| e.to_string())?;
+    let db = guard.as_ref().ok_or("Database not initialized")?;
+    let (test_id, session_ids) = db
+        .save_vehicle_test(&test, &sessions)
+        .map_err(|e| e.to_string())?;
+
+    let ecu_ids = sessions.into_iter().map(|(session, _)| session.ecu_id);
+    Ok((test_id, ecu_ids.zip(session_ids).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(groups: &[Vec<EcuInfo>]) -> Vec<String> {
+        groups.iter().map(|group| group_id(group)).collect()
+    }
+
+    fn result(ecu_id: &str, status: EcuTestStatus) -> EcuTestResult {
+        EcuTestResult {
+            ecu_id: ecu_id.to_string(),
+            ecu_name: ecu_id.to_string(),
+            protocol: None,
+            status,
+            ident: None,
+            fault_count: 0,
+            dtcs: Vec::new(),
+            message: String::new(),
+            session_id: None,
+        }
+    }
+
+    #[test]
+    fn test_ecu_groups_share_engine_address() {
+        let groups = ecu_groups(bmw::e60_ecus(), None);
+        assert_eq!(groups.len(), bmw::e60_ecus().len() - 1);
+        assert_eq!(group_id(&groups[0]), "DME/DDE");
+        assert!(groups[1..].iter().all(|group| group.len() == 1));
+
+        let names = ["kombi".to_string(), "DDE".to_string()];
+        let groups = ecu_groups(bmw::e60_ecus(), Some(&names));
+        assert_eq!(ids(&groups), ["DDE", "KOMBI"]);
+    }
+
+    #[test]
+    fn test_discovered_ecus() {
+        let names = ["DDE".to_string()];
+        let groups = ecu_groups(bmw::e60_ecus(), Some(&names));
+
+        // Already tested, known but not in the profile, unknown
+        let found = discovered_ecus(&[0x612, 0x660, 0x6A8], &groups);
+        let found_ids: Vec<&str> = found.iter().map(|ecu| ecu.id.as_str()).collect();
+        assert_eq!(found_ids, ["KOMBI", "CAN_6A8"]);
+
+        let unknown = &found[1];
+        assert_eq!(unknown.protocol, Protocol::DCan);
+        assert_eq!(unknown.can_ids(), Some((0x6A0, 0x6A8)));
+        assert_eq!(found[0].can_ids(), Some((0x6F1, 0x660)));
+    }
+
+    #[test]
+    fn test_ecus_are_probed_at_their_own_addresses() {
+        let names = ["IHKA".to_string(), "PDC".to_string(), "ACSM".to_string()];
+        let groups = ecu_groups(bmw::e60_ecus(), Some(&names));
+        assert_eq!(ids(&groups), ["ACSM", "IHKA", "PDC"]);
+
+        let addresses: Vec<_> = groups
+            .iter()
+            .map(|group| (group[0].can_ids(), group[0].kline_address))
+            .collect();
+        assert_eq!(
+            addresses,
+            [
+                (Some((0x6B8, 0x6B8)), Some(0x4A)),
+                (None, Some(0x5B)),
+                (Some((0x6F1, 0x672)), None),
+            ]
+        );
+
+        // PDC answering the functional TesterPresent is not listed twice
+        assert!(discovered_ecus(&[0x672], &groups).is_empty());
+        let found = discovered_ecus(&[0x672], &[]);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, "PDC");
+    }
+
+    #[test]
+    fn test_report_counts_statuses() {
+        let report = QuickTestReport::new(
+            vec![
+                result("DME/DDE", EcuTestStatus::Faulty),
+                result("EGS", EcuTestStatus::Ok),
+                result("DSC", EcuTestStatus::Ok),
+                result("IHKA", EcuTestStatus::NotResponding),
+            ],
+            1200,
+        );
+        assert_eq!((report.ok, report.faulty, report.not_responding), (2, 1, 1));
+        assert_eq!(report.ecus.len(), 4);
+        assert_eq!(report.vehicle_test_id, None);
+    }
+}
@@ -0,0 +1,330 @@
+//! Live Data Recording
+//!
+//! Saves the samples of the live stream into `live_data_snapshots`, tied to
+//! a diagnostic session, so a test drive can be looked at later. Samples
+//! are buffered and written in batches, one transaction per flush, to keep
+//! the database out of the acquisition loop's timing.
+//!
+//! Alarms raised and cleared meanwhile are saved as markers (`alarms`).
+//!
+//! A recording is independent of the stream: it may be started before or
+//! after `start_live_stream` and takes the samples of any stream running
+//! meanwhile.
+
+use crate::alarms::AlarmEvent;
+use crate::database::{LiveDataRecording, NewLiveDataMarker, NewLiveDataSnapshot};
+use crate::db_commands::DbState;
+use crate::live_stream::LiveSample;
+use chrono::{DateTime, Utc};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::State;
+
+/// Buffered samples that trigger a flush
+const FLUSH_SAMPLES: usize = 500;
+/// Longest time samples stay buffered
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The running recording, if any
+#[derive(Default)]
+pub struct RecordingState(Mutex<Option<Recorder>>);
+
+struct Recorder {
+    id: i64,
+    session_id: i64,
+    buffer: Vec<NewLiveDataSnapshot>,
+    last_flush: Instant,
+}
+
+impl Recorder {
+    fn flush(&mut self, db: &DbState) -> Result<usize, String> {
+        self.last_flush = Instant::now();
+        if self.buffer.is_empty() {
+            return Ok(0);
+        }
+
+        // Samples are dropped on failure rather than piling up
+        let batch = std::mem::take(&mut self.buffer);
+        let guard = db.0.lock().map_err(|e| format!("Lock error: {}", e))?;
+        let db = guard.as_ref().ok_or("Database not initialized")?;
+        db.add_live_data_snapshots(&batch)
+            .map_err(|e| format!("Database error: {}", e))
+    }
+}
+
+impl RecordingState {
+    /// Buffer samples for the running recording, flushing when due
+    pub fn record(&self, db: &DbState, ecu: &str, samples: &[LiveSample]) {
+        let Ok(mut current) = self.0.lock() else {
+            return;
+        };
+        let Some(recorder) = current.as_mut() else {
+            return;
+        };
+
+        recorder
+            .buffer
+            .extend(samples.iter().map(|sample| {
+                NewLiveDataSnapshot {
+                    session_id: recorder.session_id,
+                    recording_id: Some(recorder.id),
+                    ecu: Some(ecu.to_string()),
+                    parameter_name: sample.name.clone(),
+                    value: sample.value,
+                    unit: sample.unit.clone(),
+                    timestamp: DateTime::from_timestamp_millis(sample.timestamp as i64)
+                        .unwrap_or_else(Utc::now),
+                }
+            }));
+
+        if recorder.buffer.len() >= FLUSH_SAMPLES || recorder.last_flush.elapsed() >= FLUSH_INTERVAL
+        {
+            if let Err(e) = recorder.flush(db) {
+                log::warn!("Recording {}: failed to save samples: {}", recorder.id, e);
+            }
+        }
+    }
+
+    /// Save an alarm as a marker of the running recording
+    ///
+    /// Markers are rare and written right away, not buffered.
+    pub fn mark(&self, db: &DbState, event: &AlarmEvent) {
+        let Ok(current) = self.0.lock() else {
+            return;
+        };
+        let Some(recorder) = current.as_ref() else {
+            return;
+        };
+
+        let marker = NewLiveDataMarker {
+            session_id: recorder.session_id,
+            recording_id: Some(recorder.id),
+            kind: event.marker_kind().to_string(),
+            label: event.name.clone(),
+            severity: Some(event.severity.as_str().to_string()),
+            parameter_name: Some(event.channel.clone()),
+            value: Some(event.value),
+            timestamp: DateTime::from_timestamp_millis(event.timestamp as i64)
+                .unwrap_or_else(Utc::now),
+        };
+        let saved = db
+            .0
+            .lock()
+            .map_err(|e| format!("Lock error: {}", e))
+            .and_then(|guard| {
+                let db = guard.as_ref().ok_or("Database not initialized")?;
+                db.add_live_data_marker(&marker)
+                    .map_err(|e| format!("Database error: {}", e))
+            });
+        if let Err(e) = saved {
+            log::warn!("Recording {}: failed to save marker: {}", recorder.id, e);
+        }
+    }
+
+    /// Write out buffered samples, e.g. when a stream ends
+    pub fn flush(&self, db: &DbState) {
+        let Ok(mut current) = self.0.lock() else {
+            return;
+        };
+        if let Some(recorder) = current.as_mut() {
+            if let Err(e) = recorder.flush(db) {
+                log::warn!("Recording {}: failed to save samples: {}", recorder.id, e);
+            }
+        }
+    }
+
+    /// Start a recording in `session_id`, replacing a running one;
+    /// returns its ID
+    fn start(&self, db: &DbState, session_id: i64) -> Result<i64, String> {
+        self.stop(db)?;
+
+        let id = {
+            let guard = db.0.lock().map_err(|e| format!("Lock error: {}", e))?;
+            let database = guard.as_ref().ok_or("Database not initialized")?;
+            database
+                .start_live_data_recording(session_id)
+                .map_err(|e| format!("Database error: {}", e))?
+        };
+
+        *self.0.lock().map_err(|e| format!("Lock error: {}", e))? = Some(Recorder {
+            id,
+            session_id,
+            buffer: Vec::new(),
+            last_flush: Instant::now(),
+        });
+
+        log::info!("Recording {} started in session {}", id, session_id);
+        Ok(id)
+    }
+
+    /// Finish the running recording; returns its ID
+    fn stop(&self, db: &DbState) -> Result<Option<i64>, String> {
+        let recorder = self
+            .0
+            .lock()
+            .map_err(|e| format!("Lock error: {}", e))?
+            .take();
+        let Some(mut recorder) = recorder else {
+            return Ok(None);
+        };
+
+        let flushed = recorder.flush(db);
+        let guard = db.0.lock().map_err(|e| format!("Lock error: {}", e))?;
+        let database = guard.as_ref().ok_or("Database not initialized")?;
+        database
+            .stop_live_data_recording(recorder.id)
+            .map_err(|e| format!("Database error: {}", e))?;
+        flushed?;
+
+        log::info!("Recording {} stopped", recorder.id);
+        Ok(Some(recorder.id))
+    }
+}
+
+fn get_recording(db: &DbState, recording_id: i64) -> Result<Option<LiveDataRecording>, String> {
+    let guard = db.0.lock().map_err(|e| format!("Lock error: {}", e))?;
+    let db = guard.as_ref().ok_or("Database not initialized")?;
+    db.get_live_data_recording(recording_id)
+        .map_err(|e| format!("Database error: {}", e))
+}
+
+/// Start recording live samples into a session
+///
+/// Replaces a recording that is already running.
+#[tauri::command]
+pub fn start_live_recording(
+    recording: State<RecordingState>,
+    db: State<DbState>,
+    session_id: i64,
+) -> Result<LiveDataRecording, String> {
+    let id = recording.start(&db, session_id)?;
+    get_recording(&db, id)?.ok_or_else(|| format!("Recording {} not found", id))
+}
+
+/// Stop the running recording; returns it once all samples are saved
+#[tauri::command]
+pub fn stop_live_recording(
+    recording: State<RecordingState>,
+    db: State<DbState>,
+) -> Result<Option<LiveDataRecording>, String> {
+    match recording.stop(&db)? {
+        Some(id) => get_recording(&db, id),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{Database, NewSession, NewVehicle};
+    use crate::live_stream::ChannelKind;
+
+    fn test_db() -> (DbState, i64) {
+        let db = Database::in_memory().unwrap();
+        let vehicle_id = db
+            .create_vehicle(&NewVehicle {
+                vin: None,
+                make: "BMW".to_string(),
+                model: "520d E60".to_string(),
+                year: 2008,
+                engine_code: Some("M47TU2D20".to_string()),
+                mileage_km: None,
+                notes: None,
+            })
+            .unwrap();
+        let session_id = db
+            .create_session(&NewSession {
+                vehicle_id,
+                ecu_id: "DDE".to_string(),
+                ecu_name: "DDE".to_string(),
+                protocol: "K-Line".to_string(),
+                mileage_km: None,
+                notes: None,
+            })
+            .unwrap();
+        (DbState(Mutex::new(Some(db))), session_id)
+    }
+
+    fn samples(count: usize) -> Vec<LiveSample> {
+        (0..count)
+            .map(|i| LiveSample {
+                stream_id: 1,
+                seq: i as u64,
+                kind: ChannelKind::Pid,
+                id: 0x0C,
+                name: "Engine RPM".to_string(),
+                value: 800.0 + i as f64,
+                unit: "rpm".to_string(),
+                raw: vec![0x0C, 0x80],
+                timestamp: 1_700_000_000_000 + i as u64 * 100,
+            })
+            .collect()
+    }
+
+    /// Samples saved so far in the recording
+    fn saved(db: &DbState, recording_id: i64) -> i64 {
+        get_recording(db, recording_id).unwrap().unwrap().sample_count
+    }
+
+    #[test]
+    fn test_samples_are_batched() {
+        let (db, session_id) = test_db();
+        let recording = RecordingState::default();
+        let id = recording.start(&db, session_id).unwrap();
+
+        recording.record(&db, "DDE", &samples(FLUSH_SAMPLES - 1));
+        assert_eq!(saved(&db, id), 0);
+
+        recording.record(&db, "DDE", &samples(1));
+        assert_eq!(saved(&db, id), FLUSH_SAMPLES as i64);
+
+        // A partial batch goes out once it is old enough
+        recording.record(&db, "DDE", &samples(3));
+        assert_eq!(saved(&db, id), FLUSH_SAMPLES as i64);
+        if let Some(recorder) = recording.0.lock().unwrap().as_mut() {
+            recorder.last_flush -= FLUSH_INTERVAL;
+        }
+        recording.record(&db, "DDE", &samples(1));
+        assert_eq!(saved(&db, id), FLUSH_SAMPLES as i64 + 4);
+    }
+
+    #[test]
+    fn test_flush_is_one_transaction() {
+        let (db, session_id) = test_db();
+        let recording = RecordingState::default();
+        let id = recording.start(&db, session_id).unwrap();
+
+        // SQLite stores NaN as NULL, which the last row's NOT NULL value
+        // rejects; nothing of the batch may be left behind
+        let mut batch = samples(3);
+        batch[2].value = f64::NAN;
+        recording.record(&db, "DDE", &batch);
+        recording.flush(&db);
+        assert_eq!(saved(&db, id), 0);
+
+        // The failed batch is dropped, later ones are saved
+        recording.record(&db, "DDE", &samples(2));
+        recording.flush(&db);
+        assert_eq!(saved(&db, id), 2);
+    }
+
+    #[test]
+    fn test_stop_flushes_buffer() {
+        let (db, session_id) = test_db();
+        let recording = RecordingState::default();
+        let id = recording.start(&db, session_id).unwrap();
+
+        recording.record(&db, "DDE", &samples(5));
+        assert_eq!(saved(&db, id), 0);
+
+        assert_eq!(recording.stop(&db).unwrap(), Some(id));
+        let stopped = get_recording(&db, id).unwrap().unwrap();
+        assert_eq!(stopped.sample_count, 5);
+        assert!(stopped.stopped_at.is_some());
+
+        // Nothing is recorded once stopped
+        recording.record(&db, "DDE", &samples(5));
+        assert_eq!(recording.stop(&db).unwrap(), None);
+        assert_eq!(saved(&db, id), 5);
+    }
+}
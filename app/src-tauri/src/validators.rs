@@ -163,18 +163,23 @@ pub fn validate_pids(pids: &[u16]) -> ValidationResult<()> {
 
 /// Validates a list of DIDs
 pub fn validate_dids(dids: &[u16]) -> ValidationResult<()> {
+    validate_did_list(dids, limits::MAX_DIDS_PER_REQUEST)
+}
+
+/// Validates a list of DIDs to pack into a dynamically defined identifier
+pub fn validate_dynamic_dids(dids: &[u16]) -> ValidationResult<()> {
+    validate_did_list(dids, limits::MAX_DIDS_PER_DYNAMIC_ID)
+}
+
+fn validate_did_list(dids: &[u16], max: usize) -> ValidationResult<()> {
     if dids.is_empty() {
         return Err(ValidationError::new("dids", "DID list cannot be empty"));
     }
 
-    if dids.len() > limits::MAX_DIDS_PER_REQUEST {
+    if dids.len() > max {
         return Err(ValidationError::new(
             "dids",
-            format!(
-                "Too many DIDs: {} (max: {})",
-                dids.len(),
-                limits::MAX_DIDS_PER_REQUEST
-            ),
+            format!("Too many DIDs: {} (max: {})", dids.len(), max),
         ));
     }
 
@@ -0,0 +1,669 @@
+//! Virtual Live Data Channels
+//!
+//! Channels computed from the samples of a running stream instead of read
+//! from the ECU: a formula over other channels, e.g. the rail pressure
+//! deviation `FUEL_RAIL_PRESSURE - FUEL_RAIL_PRESSURE_DESIRED`, optionally
+//! followed by a moving average, delta, min/max hold or time integral.
+//!
+//! They are evaluated in the acquisition thread each time one of their
+//! inputs gets a sample, and come out as ordinary `LiveSample`s, so they
+//! are emitted, recorded and exported like real channels. The definitions
+//! are kept as JSON in the settings table.
+
+use crate::channels;
+use crate::db_commands::DbState;
+use crate::live_stream::{ChannelKind, LiveSample};
+use bmw_diag_core::formula::Formula;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use tauri::State;
+
+/// Settings key holding the definitions
+pub const SETTINGS_KEY: &str = "virtual_channels";
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+/// Processing of the formula result over time
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Transform {
+    /// Mean of the values of the last `window_ms`
+    MovingAverage { window_ms: u64 },
+    /// Change since the previous value
+    Delta,
+    /// Lowest value of the last `window_ms`, or since the stream started
+    MinHold {
+        #[serde(default)]
+        window_ms: Option<u64>,
+    },
+    /// Highest value of the last `window_ms`, or since the stream started
+    MaxHold {
+        #[serde(default)]
+        window_ms: Option<u64>,
+    },
+    /// Running time integral in value x seconds, times `scale`
+    /// (1/3600 turns L/h into litres)
+    Integral {
+        #[serde(default = "default_scale")]
+        scale: f64,
+    },
+}
+
+/// User-defined virtual channel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VirtualChannelDef {
+    /// Name later virtual channels use to refer to this one
+    pub key: String,
+    /// Sample name, as shown and recorded
+    pub name: String,
+    #[serde(default)]
+    pub unit: String,
+    /// Formula over channel keys (`ENGINE_RPM`), `PID_0C` / `DID_394A`, and
+    /// keys of virtual channels defined before this one
+    pub expression: String,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+}
+
+fn is_identifier(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Whether `key` names a streamed channel: a key of the channel tables
+/// `input_names` uses, or `PID_xx` / `DID_xxxx`
+fn is_channel_name(key: &str) -> bool {
+    let generic = |prefix: &str, digits: usize| {
+        key.strip_prefix(prefix)
+            .is_some_and(|hex| hex.len() == digits && hex.chars().all(|c| c.is_ascii_hexdigit()))
+    };
+    if generic("PID_", 2) || generic("DID_", 4) {
+        return true;
+    }
+
+    let db = channels::read_global();
+    let mut tables = db
+        .channels(channels::OBD, channels::SERVICE_MODE_01)
+        .chain(db.channels(channels::DDE, channels::SERVICE_DATA_ID));
+    tables.any(|channel| channel.def.key == key)
+}
+
+/// Check a list of definitions before it is saved
+///
+/// Keys must be unique identifiers that do not shadow a real channel,
+/// expressions must parse and may only refer to virtual channels defined
+/// earlier in the list.
+pub fn validate(defs: &[VirtualChannelDef]) -> Result<(), String> {
+    for (index, def) in defs.iter().enumerate() {
+        if !is_identifier(&def.key) {
+            return Err(format!("Invalid virtual channel key '{}'", def.key));
+        }
+        if is_channel_name(&def.key) {
+            return Err(format!(
+                "Virtual channel key '{}' is already the name of a channel",
+                def.key
+            ));
+        }
+        if defs[..index].iter().any(|other| other.key == def.key) {
+            return Err(format!("Duplicate virtual channel key '{}'", def.key));
+        }
+
+        let formula = Formula::parse(&def.expression).map_err(|e| format!("{}: {}", def.key, e))?;
+        if formula.variables().is_empty() {
+            return Err(format!("{}: the expression uses no channel", def.key));
+        }
+        if let Some(var) = formula
+            .variables()
+            .into_iter()
+            .find(|var| defs[index..].iter().any(|later| later.key == *var))
+        {
+            return Err(format!(
+                "{}: '{}' is not defined before this channel",
+                def.key, var
+            ));
+        }
+
+        match def.transform {
+            Some(Transform::MovingAverage { window_ms: 0 })
+            | Some(Transform::MinHold { window_ms: Some(0) })
+            | Some(Transform::MaxHold { window_ms: Some(0) }) => {
+                return Err(format!("{}: window must be longer than 0 ms", def.key));
+            }
+            Some(Transform::Integral { scale }) if !scale.is_finite() => {
+                return Err(format!("{}: invalid integral scale", def.key));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Names a streamed channel can be referred to by: its key in the channel
+/// tables, then `PID_xx` / `DID_xxxx`
+fn input_names(kind: ChannelKind, id: u16) -> Vec<String> {
+    let db = channels::read_global();
+    let (channel, generic) = match kind {
+        ChannelKind::Pid => (
+            db.get(channels::OBD, channels::SERVICE_MODE_01, id),
+            format!("PID_{:02X}", id),
+        ),
+        ChannelKind::Did => (
+            db.get(channels::DDE, channels::SERVICE_DATA_ID, id),
+            format!("DID_{:04X}", id),
+        ),
+        ChannelKind::Virtual => (None, String::new()),
+    };
+    channel
+        .map(|channel| channel.def.key.clone())
+        .into_iter()
+        .chain(Some(generic).filter(|name| !name.is_empty()))
+        .collect()
+}
+
+/// Channel each name refers to among the `streamed` channels; where a PID
+/// and a DID share a key, the key names the DID
+pub fn channel_names(streamed: &[(ChannelKind, u16)]) -> HashMap<String, (ChannelKind, u16)> {
+    let mut ordered: Vec<_> = streamed.to_vec();
+    ordered.sort_by_key(|(kind, _)| *kind != ChannelKind::Did);
+
+    let mut names = HashMap::new();
+    for (kind, id) in ordered {
+        for name in input_names(kind, id) {
+            names.entry(name).or_insert((kind, id));
+        }
+    }
+    names
+}
+
+/// Time-dependent state of a transform
+#[derive(Debug)]
+enum TransformState {
+    None,
+    Average(u64, VecDeque<(u64, f64)>),
+    Delta(Option<f64>),
+    /// Whether it holds the maximum, window, values in the window
+    WindowHold(bool, u64, VecDeque<(u64, f64)>),
+    Hold(bool, Option<f64>),
+    Integral {
+        scale: f64,
+        last: Option<(u64, f64)>,
+        total: f64,
+    },
+}
+
+/// Drop values older than `window` before `now`
+fn trim(values: &mut VecDeque<(u64, f64)>, now: u64, window: u64) {
+    while values
+        .front()
+        .is_some_and(|(t, _)| now.saturating_sub(*t) >= window)
+    {
+        values.pop_front();
+    }
+}
+
+impl TransformState {
+    fn new(transform: Option<Transform>) -> Self {
+        match transform {
+            None => Self::None,
+            Some(Transform::MovingAverage { window_ms }) => {
+                Self::Average(window_ms, VecDeque::new())
+            }
+            Some(Transform::Delta) => Self::Delta(None),
+            Some(Transform::MinHold { window_ms: Some(w) }) => {
+                Self::WindowHold(false, w, VecDeque::new())
+            }
+            Some(Transform::MaxHold { window_ms: Some(w) }) => {
+                Self::WindowHold(true, w, VecDeque::new())
+            }
+            Some(Transform::MinHold { window_ms: None }) => Self::Hold(false, None),
+            Some(Transform::MaxHold { window_ms: None }) => Self::Hold(true, None),
+            Some(Transform::Integral { scale }) => Self::Integral {
+                scale,
+                last: None,
+                total: 0.0,
+            },
+        }
+    }
+
+    /// Output for a new value at `timestamp` (ms); `None` while there is
+    /// nothing to output yet (first value of a delta)
+    fn apply(&mut self, timestamp: u64, value: f64) -> Option<f64> {
+        match self {
+            Self::None => Some(value),
+            Self::Average(window, values) => {
+                values.push_back((timestamp, value));
+                trim(values, timestamp, *window);
+                Some(values.iter().map(|(_, v)| v).sum::<f64>() / values.len() as f64)
+            }
+            Self::Delta(previous) => previous.replace(value).map(|previous| value - previous),
+            Self::WindowHold(max, window, values) => {
+                values.push_back((timestamp, value));
+                trim(values, timestamp, *window);
+                let held = values.iter().map(|(_, v)| *v);
+                if *max {
+                    held.reduce(f64::max)
+                } else {
+                    held.reduce(f64::min)
+                }
+            }
+            Self::Hold(max, held) => {
+                let next = match *held {
+                    Some(held) if *max => held.max(value),
+                    Some(held) => held.min(value),
+                    None => value,
+                };
+                *held = Some(next);
+                Some(next)
+            }
+            Self::Integral { scale, last, total } => {
+                if let Some((t0, v0)) = *last {
+                    let seconds = timestamp.saturating_sub(t0) as f64 / 1000.0;
+                    *total += (value + v0) / 2.0 * seconds * *scale;
+                }
+                *last = Some((timestamp, value));
+                Some(*total)
+            }
+        }
+    }
+}
+
+/// A virtual channel set up for one stream
+#[derive(Debug)]
+struct Computed {
+    id: u16,
+    def: VirtualChannelDef,
+    formula: Formula,
+    /// Formula variable and the slot of its value
+    inputs: Vec<(String, usize)>,
+    /// Slot of this channel's own value
+    slot: usize,
+    state: TransformState,
+}
+
+/// Virtual channels of a running stream
+///
+/// Keeps the latest value of every input and computes the channels whose
+/// inputs changed, in definition order, so a channel may build on the
+/// ones before it.
+#[derive(Debug, Default)]
+pub struct VirtualChannels {
+    values: Vec<Option<f64>>,
+    slots: HashMap<(ChannelKind, u16), usize>,
+    channels: Vec<Computed>,
+}
+
+impl VirtualChannels {
+    /// Set up the definitions computable from the `streamed` channels
+    ///
+    /// Returns the keys of the definitions left out because the stream
+    /// does not read one of their inputs.
+    pub fn new(
+        defs: Vec<VirtualChannelDef>,
+        streamed: &[(ChannelKind, u16)],
+    ) -> (Self, Vec<String>) {
+        let mut this = Self::default();
+        let mut names: HashMap<String, usize> = HashMap::new();
+
+        for (name, channel) in channel_names(streamed) {
+            let next = this.values.len();
+            let slot = *this.slots.entry(channel).or_insert(next);
+            if slot == next {
+                this.values.push(None);
+            }
+            names.insert(name, slot);
+        }
+
+        let mut skipped = Vec::new();
+        for def in defs {
+            // Saved before keys were checked; it would shadow the real channel
+            if is_channel_name(&def.key) {
+                log::warn!("Virtual channel {} shadows a channel", def.key);
+                skipped.push(def.key);
+                continue;
+            }
+            let formula = match Formula::parse(&def.expression) {
+                Ok(formula) => formula,
+                Err(e) => {
+                    log::warn!("Virtual channel {}: {}", def.key, e);
+                    skipped.push(def.key);
+                    continue;
+                }
+            };
+            let inputs: Option<Vec<(String, usize)>> = formula
+                .variables()
+                .into_iter()
+                .map(|var| names.get(var).map(|&slot| (var.to_string(), slot)))
+                .collect();
+            let Some(inputs) = inputs.filter(|inputs| !inputs.is_empty()) else {
+                skipped.push(def.key);
+                continue;
+            };
+
+            let slot = this.values.len();
+            this.values.push(None);
+            names.insert(def.key.clone(), slot);
+            this.channels.push(Computed {
+                id: this.channels.len() as u16,
+                state: TransformState::new(def.transform),
+                def,
+                formula,
+                inputs,
+                slot,
+            });
+        }
+
+        (this, skipped)
+    }
+
+    /// Keys of the channels computed
+    pub fn keys(&self) -> Vec<String> {
+        self.channels.iter().map(|ch| ch.def.key.clone()).collect()
+    }
+
+    /// Take in a batch of samples, return the virtual samples it produces
+    pub fn update(&mut self, samples: &[LiveSample]) -> Vec<LiveSample> {
+        let Self {
+            values,
+            slots,
+            channels,
+        } = self;
+        if channels.is_empty() {
+            return Vec::new();
+        }
+
+        let mut changed = vec![false; values.len()];
+        let mut timestamp = 0;
+        for sample in samples {
+            if let Some(&slot) = slots.get(&(sample.kind, sample.id)) {
+                values[slot] = Some(sample.value);
+                changed[slot] = true;
+                timestamp = timestamp.max(sample.timestamp);
+            }
+        }
+
+        let mut computed = Vec::new();
+        for channel in channels.iter_mut() {
+            if !channel.inputs.iter().any(|&(_, slot)| changed[slot]) {
+                continue;
+            }
+            // Wait until every input has been read once
+            if channel
+                .inputs
+                .iter()
+                .any(|&(_, slot)| values[slot].is_none())
+            {
+                continue;
+            }
+
+            let vars = |name: &str| {
+                channel
+                    .inputs
+                    .iter()
+                    .find(|(var, _)| var == name)
+                    .and_then(|&(_, slot)| values[slot])
+            };
+            let value = match channel.formula.eval(&vars) {
+                Ok(value) => value,
+                Err(e) => {
+                    log::debug!("Virtual channel {}: {}", channel.def.key, e);
+                    continue;
+                }
+            };
+            let Some(value) = channel.state.apply(timestamp, value) else {
+                continue;
+            };
+
+            values[channel.slot] = Some(value);
+            changed[channel.slot] = true;
+            computed.push(LiveSample {
+                stream_id: 0,
+                seq: 0,
+                kind: ChannelKind::Virtual,
+                id: channel.id,
+                name: channel.def.name.clone(),
+                value,
+                unit: channel.def.unit.clone(),
+                raw: Vec::new(),
+                timestamp,
+            });
+        }
+        computed
+    }
+}
+
+/// Saved definitions, empty if none were saved
+pub fn load(db: &DbState) -> Result<Vec<VirtualChannelDef>, String> {
+    let guard = db.0.lock().map_err(|e| format!("Lock error: {}", e))?;
+    let database = guard.as_ref().ok_or("Database not initialized")?;
+    match database
+        .get_setting(SETTINGS_KEY)
+        .map_err(|e| format!("Database error: {}", e))?
+    {
+        Some(json) => serde_json::from_str(&json)
+            .map_err(|e| format!("Invalid virtual channel settings: {}", e)),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Get the saved virtual channel definitions
+#[tauri::command]
+pub fn get_virtual_channels(db: State<DbState>) -> Result<Vec<VirtualChannelDef>, String> {
+    load(&db)
+}
+
+/// Replace the saved virtual channel definitions
+///
+/// Takes effect with the next `start_live_stream`.
+#[tauri::command]
+pub fn set_virtual_channels(
+    db: State<DbState>,
+    channels: Vec<VirtualChannelDef>,
+) -> Result<(), String> {
+    validate(&channels)?;
+    let json = serde_json::to_string(&channels).map_err(|e| e.to_string())?;
+
+    let guard = db.0.lock().map_err(|e| format!("Lock error: {}", e))?;
+    let database = guard.as_ref().ok_or("Database not initialized")?;
+    database
+        .set_setting(SETTINGS_KEY, &json)
+        .map_err(|e| format!("Database error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RAIL: u16 = 0x394A;
+    const RAIL_DESIRED: u16 = 0x394B;
+
+    fn def(key: &str, expression: &str, transform: Option<Transform>) -> VirtualChannelDef {
+        VirtualChannelDef {
+            key: key.to_string(),
+            name: key.to_string(),
+            unit: String::new(),
+            expression: expression.to_string(),
+            transform,
+        }
+    }
+
+    fn sample(kind: ChannelKind, id: u16, value: f64, timestamp: u64) -> LiveSample {
+        LiveSample {
+            stream_id: 0,
+            seq: 0,
+            kind,
+            id,
+            name: String::new(),
+            value,
+            unit: String::new(),
+            raw: Vec::new(),
+            timestamp,
+        }
+    }
+
+    fn did(id: u16, value: f64, timestamp: u64) -> LiveSample {
+        sample(ChannelKind::Did, id, value, timestamp)
+    }
+
+    fn values(out: &[LiveSample]) -> Vec<f64> {
+        out.iter().map(|s| s.value).collect()
+    }
+
+    #[test]
+    fn test_expression_over_channel_keys() {
+        let (mut virt, skipped) = VirtualChannels::new(
+            vec![
+                def(
+                    "RAIL_DEV",
+                    "FUEL_RAIL_PRESSURE - FUEL_RAIL_PRESSURE_DESIRED",
+                    None,
+                ),
+                def("RAIL_DEV_ABS", "abs(RAIL_DEV)", None),
+                def(
+                    "BOOST_DEV",
+                    "BOOST_PRESSURE_ACTUAL - BOOST_PRESSURE_DESIRED",
+                    None,
+                ),
+            ],
+            &[(ChannelKind::Did, RAIL), (ChannelKind::Did, RAIL_DESIRED)],
+        );
+        assert_eq!(skipped, vec!["BOOST_DEV"]);
+        assert_eq!(virt.keys(), vec!["RAIL_DEV", "RAIL_DEV_ABS"]);
+
+        // Nothing until both inputs have a value
+        assert!(virt.update(&[did(RAIL, 900.0, 1000)]).is_empty());
+
+        let out = virt.update(&[did(RAIL_DESIRED, 1000.0, 1050)]);
+        assert_eq!(values(&out), vec![-100.0, 100.0]);
+        assert_eq!(out[0].kind, ChannelKind::Virtual);
+        assert_eq!((out[0].id, out[1].id), (0, 1));
+        assert_eq!(out[0].timestamp, 1050);
+
+        // Unrelated samples do not trigger an evaluation
+        assert!(virt.update(&[did(0x39E0, 800.0, 1100)]).is_empty());
+    }
+
+    #[test]
+    fn test_generic_names_and_shared_keys() {
+        let (mut virt, skipped) = VirtualChannels::new(
+            vec![
+                def("RPM_DIFF", "ENGINE_RPM - PID_0C", None),
+                def("RPM_DID", "DID_39E0", None),
+            ],
+            &[(ChannelKind::Pid, 0x0C), (ChannelKind::Did, 0x39E0)],
+        );
+        assert!(skipped.is_empty());
+
+        // ENGINE_RPM is both a PID and a DID key; it names the DID
+        let out = virt.update(&[
+            sample(ChannelKind::Pid, 0x0C, 790.0, 10),
+            did(0x39E0, 800.0, 10),
+        ]);
+        assert_eq!(values(&out), vec![10.0, 800.0]);
+    }
+
+    #[test]
+    fn test_channel_names_are_not_shadowed() {
+        let (mut virt, skipped) = VirtualChannels::new(
+            vec![def("ENGINE_RPM", "DID_39E0 * 2", None), def("RPM", "ENGINE_RPM", None)],
+            &[(ChannelKind::Did, 0x39E0)],
+        );
+        assert_eq!(skipped, vec!["ENGINE_RPM"]);
+
+        let out = virt.update(&[did(0x39E0, 800.0, 10)]);
+        assert_eq!(values(&out), vec![800.0]);
+    }
+
+    #[test]
+    fn test_transforms() {
+        let (mut virt, _) = VirtualChannels::new(
+            vec![
+                def(
+                    "AVG",
+                    "DID_394A",
+                    Some(Transform::MovingAverage { window_ms: 1000 }),
+                ),
+                def("DELTA", "DID_394A", Some(Transform::Delta)),
+                def(
+                    "MAX",
+                    "DID_394A",
+                    Some(Transform::MaxHold { window_ms: None }),
+                ),
+                def(
+                    "MIN",
+                    "DID_394A",
+                    Some(Transform::MinHold {
+                        window_ms: Some(1000),
+                    }),
+                ),
+                def(
+                    "SUM",
+                    "DID_394A",
+                    Some(Transform::Integral {
+                        scale: 1.0 / 3600.0,
+                    }),
+                ),
+            ],
+            &[(ChannelKind::Did, RAIL)],
+        );
+
+        let mut step = |value, timestamp| {
+            let out = virt.update(&[did(RAIL, value, timestamp)]);
+            out.into_iter()
+                .map(|s| (s.name, s.value))
+                .collect::<HashMap<_, _>>()
+        };
+
+        let first = step(36.0, 0);
+        assert_eq!(first["AVG"], 36.0);
+        assert!(!first.contains_key("DELTA"));
+        assert_eq!(first["SUM"], 0.0);
+
+        let second = step(72.0, 500);
+        assert_eq!(second["AVG"], 54.0);
+        assert_eq!(second["DELTA"], 36.0);
+        assert_eq!(second["MAX"], 72.0);
+        assert_eq!(second["MIN"], 36.0);
+        // (36 + 72) / 2 * 0.5 s / 3600
+        assert!((second["SUM"] - 0.0075).abs() < 1e-12);
+
+        // The first value has left the 1 s windows
+        let third = step(18.0, 1200);
+        assert_eq!(third["AVG"], 45.0);
+        assert_eq!(third["DELTA"], -54.0);
+        assert_eq!(third["MAX"], 72.0);
+        assert_eq!(third["MIN"], 18.0);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&[
+            def("A", "DID_394A * 2", None),
+            def("B", "A + 1", Some(Transform::Delta)),
+        ])
+        .is_ok());
+
+        assert!(validate(&[def("1A", "DID_394A", None)]).is_err());
+        // Keys of real channels
+        assert!(validate(&[def("ENGINE_RPM", "DID_394A", None)]).is_err());
+        assert!(validate(&[def("PID_0C", "DID_394A", None)]).is_err());
+        assert!(validate(&[def("DID_39e0", "DID_394A", None)]).is_err());
+        assert!(validate(&[def("PID_TOTAL", "DID_394A", None)]).is_ok());
+        assert!(validate(&[def("A", "DID_394A +", None)]).is_err());
+        assert!(validate(&[def("A", "DID_394A", None), def("A", "PID_0C", None)]).is_err());
+        assert!(validate(&[def("A", "42", None)]).is_err());
+        // Forward and self references
+        assert!(validate(&[def("A", "B", None), def("B", "PID_0C", None)]).is_err());
+        assert!(validate(&[def("A", "A + 1", None)]).is_err());
+        assert!(validate(&[def(
+            "A",
+            "DID_394A",
+            Some(Transform::MovingAverage { window_ms: 0 })
+        )])
+        .is_err());
+    }
+}
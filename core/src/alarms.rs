@@ -0,0 +1,265 @@
+//! Live Data Alarm Rules
+//!
+//! Threshold rules on live data, e.g. exhaust gas temperature before the
+//! turbo above 750 °C for 2 s, or battery voltage below 11.8 V. A rule must
+//! see the threshold exceeded for its minimum duration before it is raised,
+//! and clears once the value is back past the threshold by its hysteresis.
+//!
+//! Only the rule format and the raise/clear logic live here; each front end
+//! matches rules to its channels and reports the changes its own way.
+
+use serde::{Deserialize, Serialize};
+
+/// Side of the threshold that raises the alarm
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    Above,
+    Below,
+}
+
+impl Condition {
+    fn exceeded(self, value: f64, threshold: f64) -> bool {
+        match self {
+            Self::Above => value > threshold,
+            Self::Below => value < threshold,
+        }
+    }
+
+    /// Back past the threshold by at least `hysteresis`
+    fn released(self, value: f64, threshold: f64, hysteresis: f64) -> bool {
+        match self {
+            Self::Above => value <= threshold - hysteresis,
+            Self::Below => value >= threshold + hysteresis,
+        }
+    }
+
+    /// The value further past the threshold
+    fn worst(self, a: f64, b: f64) -> f64 {
+        match self {
+            Self::Above => a.max(b),
+            Self::Below => a.min(b),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Info,
+    #[default]
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Critical => "critical",
+        }
+    }
+}
+
+/// User-defined alarm rule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlarmRule {
+    pub key: String,
+    /// Shown with the alarm
+    pub name: String,
+    /// Name of the watched channel; what names exist depends on the front
+    /// end (channel keys, `PID_05`, `DID_3F1C`, virtual channels, ...)
+    pub channel: String,
+    pub condition: Condition,
+    pub threshold: f64,
+    /// How far back past the threshold the value must go to clear
+    #[serde(default)]
+    pub hysteresis: f64,
+    /// How long the threshold must be exceeded before the alarm is raised
+    #[serde(default)]
+    pub min_duration_ms: u64,
+    #[serde(default)]
+    pub severity: Severity,
+}
+
+/// Check a list of rules before it is used
+pub fn validate(rules: &[AlarmRule]) -> Result<(), String> {
+    for (index, rule) in rules.iter().enumerate() {
+        if rule.key.trim().is_empty() {
+            return Err("Alarm rule key is empty".to_string());
+        }
+        if rules[..index].iter().any(|other| other.key == rule.key) {
+            return Err(format!("Duplicate alarm rule key '{}'", rule.key));
+        }
+        if rule.channel.trim().is_empty() {
+            return Err(format!("{}: no channel", rule.key));
+        }
+        if !rule.threshold.is_finite() {
+            return Err(format!("{}: invalid threshold", rule.key));
+        }
+        if !rule.hysteresis.is_finite() || rule.hysteresis < 0.0 {
+            return Err(format!("{}: hysteresis must be 0 or more", rule.key));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlarmState {
+    Raised,
+    Cleared,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Clear,
+    /// Threshold exceeded, minimum duration not reached yet
+    Pending,
+    Raised,
+}
+
+/// One rule and its state
+#[derive(Debug)]
+pub struct Watch {
+    rule: AlarmRule,
+    phase: Phase,
+    since: u64,
+    peak: f64,
+}
+
+impl Watch {
+    pub fn new(rule: AlarmRule) -> Self {
+        Self {
+            rule,
+            phase: Phase::Clear,
+            since: 0,
+            peak: 0.0,
+        }
+    }
+
+    pub fn rule(&self) -> &AlarmRule {
+        &self.rule
+    }
+
+    /// Value furthest past the threshold since it was exceeded
+    pub fn peak(&self) -> f64 {
+        self.peak
+    }
+
+    /// When the threshold was first exceeded (ms)
+    pub fn since(&self) -> u64 {
+        self.since
+    }
+
+    /// Take in a value at `timestamp` (ms); returns the new state when the
+    /// alarm is raised or cleared
+    ///
+    /// A pending alarm is dropped as soon as the value is back within the
+    /// threshold; hysteresis only applies once it is raised.
+    pub fn step(&mut self, timestamp: u64, value: f64) -> Option<AlarmState> {
+        let AlarmRule {
+            condition,
+            threshold,
+            hysteresis,
+            min_duration_ms,
+            ..
+        } = self.rule;
+
+        if self.phase == Phase::Raised {
+            self.peak = condition.worst(self.peak, value);
+            if condition.released(value, threshold, hysteresis) {
+                self.phase = Phase::Clear;
+                return Some(AlarmState::Cleared);
+            }
+            return None;
+        }
+
+        if !condition.exceeded(value, threshold) {
+            self.phase = Phase::Clear;
+            return None;
+        }
+        if self.phase == Phase::Clear {
+            self.phase = Phase::Pending;
+            self.since = timestamp;
+            self.peak = value;
+        } else {
+            self.peak = condition.worst(self.peak, value);
+        }
+
+        if timestamp.saturating_sub(self.since) >= min_duration_ms {
+            self.phase = Phase::Raised;
+            return Some(AlarmState::Raised);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(key: &str, condition: Condition, threshold: f64) -> AlarmRule {
+        AlarmRule {
+            key: key.to_string(),
+            name: key.to_string(),
+            channel: "DID_3F1C".to_string(),
+            condition,
+            threshold,
+            hysteresis: 0.0,
+            min_duration_ms: 0,
+            severity: Severity::Warning,
+        }
+    }
+
+    #[test]
+    fn test_min_duration_and_hysteresis() {
+        let mut egt = rule("EGT_HIGH", Condition::Above, 750.0);
+        egt.hysteresis = 20.0;
+        egt.min_duration_ms = 2000;
+        let mut watch = Watch::new(egt);
+
+        // A short excursion is not enough
+        assert_eq!(watch.step(0, 760.0), None);
+        assert_eq!(watch.step(1500, 740.0), None);
+
+        assert_eq!(watch.step(2000, 755.0), None);
+        assert_eq!(watch.step(3000, 780.0), None);
+        assert_eq!(watch.step(4000, 770.0), Some(AlarmState::Raised));
+        assert_eq!((watch.since(), watch.peak()), (2000, 780.0));
+
+        // Within the hysteresis band it stays raised
+        assert_eq!(watch.step(4500, 740.0), None);
+        assert_eq!(watch.step(5000, 790.0), None);
+        assert_eq!(watch.step(6000, 730.0), Some(AlarmState::Cleared));
+        assert_eq!(watch.peak(), 790.0);
+    }
+
+    #[test]
+    fn test_below_threshold() {
+        let mut watch = Watch::new(rule("BATTERY_LOW", Condition::Below, 11.8));
+        assert_eq!(watch.step(0, 12.4), None);
+        assert_eq!(watch.step(100, 11.5), Some(AlarmState::Raised));
+        assert_eq!(watch.step(200, 11.2), None);
+        assert_eq!(watch.peak(), 11.2);
+        assert_eq!(watch.step(300, 11.8), Some(AlarmState::Cleared));
+    }
+
+    #[test]
+    fn test_validate() {
+        let ok = rule("COOLANT_HIGH", Condition::Above, 110.0);
+        assert!(validate(std::slice::from_ref(&ok)).is_ok());
+        assert!(validate(&[ok.clone(), ok.clone()]).is_err());
+
+        let mut bad = ok.clone();
+        bad.hysteresis = -1.0;
+        assert!(validate(&[bad]).is_err());
+        let mut bad = ok.clone();
+        bad.threshold = f64::NAN;
+        assert!(validate(&[bad]).is_err());
+        let mut bad = ok;
+        bad.channel = String::new();
+        assert!(validate(&[bad]).is_err());
+    }
+}
@@ -0,0 +1,481 @@
+//! Live Data Channel Definitions
+//!
+//! Describes how to turn the raw bytes of an OBD-II PID, a KWP local
+//! identifier or a DID into an engineering value: byte layout, formula or
+//! linear scaling, unit, range and localized names. The definitions are
+//! data, so adding a channel does not need a rebuild.
+//!
+//! The bundled tables live in `data/channels/` at the repository root;
+//! each front end loads additional JSON files from its own directory, and
+//! those replace bundled channels with the same (ECU, service, identifier).
+
+use crate::formula::Formula;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Bundled channel tables, loaded in this order
+const BUNDLED: [(&str, &str); 4] = [
+    ("obd2.json", include_str!("../../data/channels/obd2.json")),
+    (
+        "dde_diesel.json",
+        include_str!("../../data/channels/dde_diesel.json"),
+    ),
+    (
+        "dme_kwp.json",
+        include_str!("../../data/channels/dme_kwp.json"),
+    ),
+    (
+        "egs_kwp.json",
+        include_str!("../../data/channels/egs_kwp.json"),
+    ),
+];
+
+/// ECU name used for generic OBD-II PIDs
+pub const OBD: &str = "OBD";
+/// ECU name of the diesel engine ECU
+pub const DDE: &str = "DDE";
+/// ECU name of the petrol engine ECU
+pub const DME: &str = "DME";
+/// ECU name of the automatic gearbox
+pub const EGS: &str = "EGS";
+
+/// OBD-II Mode 01 (show current data)
+pub const SERVICE_MODE_01: u8 = 0x01;
+/// KWP2000 ReadDataByLocalIdentifier
+pub const SERVICE_LOCAL_ID: u8 = 0x21;
+/// ReadDataByIdentifier (KWP2000 and UDS)
+pub const SERVICE_DATA_ID: u8 = 0x22;
+
+/// Data bytes a formula can address as `A`, `B`, ...
+const BYTE_VARS: &str = "ABCDEFGH";
+
+/// Channel text in the supported languages
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ChannelText {
+    #[serde(default)]
+    pub de: Option<String>,
+    #[serde(default)]
+    pub en: Option<String>,
+    #[serde(default)]
+    pub es: Option<String>,
+}
+
+impl ChannelText {
+    /// Preferred text for the UI (Spanish, then English, then German)
+    pub fn preferred(&self) -> Option<&str> {
+        self.es
+            .as_deref()
+            .or(self.en.as_deref())
+            .or(self.de.as_deref())
+    }
+}
+
+/// Byte order of multi-byte raw values
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Endian {
+    #[default]
+    Big,
+    Little,
+}
+
+fn default_length() -> usize {
+    1
+}
+
+/// One channel as written in a definition file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelDef {
+    /// Stable name, e.g. `FUEL_RAIL_PRESSURE`
+    pub key: String,
+    /// ECU id as in `bmw::e60_ecus()`, or `OBD` for generic PIDs
+    pub ecu: String,
+    /// Request service, 2 hex digits (`01`, `21`, `22`)
+    pub service: String,
+    /// PID, local identifier or DID in hex
+    pub id: String,
+    /// First data byte of the value, after the echoed identifier
+    #[serde(default)]
+    pub offset: usize,
+    /// Data bytes of the value (1..=8)
+    #[serde(default = "default_length")]
+    pub length: usize,
+    /// Raw value is two's complement
+    #[serde(default)]
+    pub signed: bool,
+    #[serde(default)]
+    pub endian: Endian,
+    /// Formula over `X` (raw value), `A`..`H` (data bytes) and `N`
+    /// (bytes received); without one the value is `X * scale + bias`
+    #[serde(default)]
+    pub formula: Option<String>,
+    #[serde(default)]
+    pub scale: Option<f64>,
+    #[serde(default)]
+    pub bias: Option<f64>,
+    #[serde(default)]
+    pub unit: String,
+    pub min: f64,
+    pub max: f64,
+    #[serde(default)]
+    pub category: String,
+    #[serde(default)]
+    pub name: ChannelText,
+    #[serde(default)]
+    pub short_name: String,
+    #[serde(default)]
+    pub description: ChannelText,
+    #[serde(default)]
+    pub warning_low: Option<f64>,
+    #[serde(default)]
+    pub warning_high: Option<f64>,
+    #[serde(default)]
+    pub critical_low: Option<f64>,
+    #[serde(default)]
+    pub critical_high: Option<f64>,
+}
+
+/// JSON file layout
+#[derive(Debug, Deserialize)]
+struct ChannelFile {
+    version: String,
+    channels: Vec<ChannelDef>,
+}
+
+/// Validated channel with its formula parsed
+#[derive(Debug, Clone)]
+pub struct Channel {
+    pub def: ChannelDef,
+    pub service: u8,
+    pub id: u16,
+    formula: Option<Formula>,
+}
+
+impl Channel {
+    fn new(def: ChannelDef) -> Result<Self, String> {
+        let service = u8::from_str_radix(def.service.trim(), 16)
+            .map_err(|_| format!("{}: invalid service '{}'", def.key, def.service))?;
+        let id = u16::from_str_radix(def.id.trim(), 16)
+            .map_err(|_| format!("{}: invalid identifier '{}'", def.key, def.id))?;
+        if !(1..=8).contains(&def.length) {
+            return Err(format!(
+                "{}: length must be 1..=8, got {}",
+                def.key, def.length
+            ));
+        }
+
+        let formula = match &def.formula {
+            Some(source) => {
+                if def.scale.is_some() || def.bias.is_some() {
+                    return Err(format!("{}: use either formula or scale/bias", def.key));
+                }
+                let formula = Formula::parse(source).map_err(|e| format!("{}: {}", def.key, e))?;
+                if let Some(var) = formula.variables().into_iter().find(|v| !is_data_var(v)) {
+                    return Err(format!("{}: unknown variable '{}'", def.key, var));
+                }
+                Some(formula)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            def,
+            service,
+            id,
+            formula,
+        })
+    }
+
+    /// Data bytes needed for the value, including the offset
+    pub fn data_length(&self) -> usize {
+        self.def.offset + self.def.length
+    }
+
+    /// Raw integer from the value bytes; missing trailing bytes read as 0
+    fn raw(&self, bytes: &[u8]) -> f64 {
+        let mut value: u64 = 0;
+        for i in 0..self.def.length {
+            let index = match self.def.endian {
+                Endian::Big => i,
+                Endian::Little => self.def.length - 1 - i,
+            };
+            value = (value << 8) | bytes.get(index).copied().unwrap_or(0) as u64;
+        }
+
+        let bits = self.def.length * 8;
+        if self.def.signed && bits < 64 && value & (1 << (bits - 1)) != 0 {
+            (value as i64 - (1i64 << bits)) as f64
+        } else if self.def.signed {
+            value as i64 as f64
+        } else {
+            value as f64
+        }
+    }
+
+    /// Scale the data bytes that follow the identifier
+    ///
+    /// `None` when there is no byte at the offset or the formula fails
+    /// (e.g. division by zero).
+    pub fn decode(&self, data: &[u8]) -> Option<f64> {
+        let bytes = data.get(self.def.offset..).filter(|b| !b.is_empty())?;
+        let raw = self.raw(bytes);
+
+        let Some(formula) = &self.formula else {
+            return Some(raw * self.def.scale.unwrap_or(1.0) + self.def.bias.unwrap_or(0.0));
+        };
+
+        let vars = |name: &str| match name {
+            "X" => Some(raw),
+            "N" => Some(bytes.len() as f64),
+            _ => BYTE_VARS
+                .find(name)
+                .filter(|_| name.len() == 1)
+                .map(|i| bytes.get(i).copied().unwrap_or(0) as f64),
+        };
+        match formula.eval(&vars) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                log::warn!("{} 0x{:X}: {}", self.def.key, self.id, e);
+                None
+            }
+        }
+    }
+
+    /// Formula as shown to the user, the linear form for scale/bias channels
+    pub fn formula_text(&self) -> String {
+        match &self.formula {
+            Some(formula) => formula.source().to_string(),
+            None => format!(
+                "X * {} + {}",
+                self.def.scale.unwrap_or(1.0),
+                self.def.bias.unwrap_or(0.0)
+            ),
+        }
+    }
+
+    /// English name for value labels, falling back to the key
+    pub fn label(&self) -> String {
+        self.def
+            .name
+            .en
+            .clone()
+            .or_else(|| self.def.name.preferred().map(str::to_string))
+            .unwrap_or_else(|| self.def.key.clone())
+    }
+}
+
+fn is_data_var(name: &str) -> bool {
+    name == "X" || name == "N" || (name.len() == 1 && BYTE_VARS.contains(name))
+}
+
+/// Channel definitions indexed by (ECU, service, identifier)
+#[derive(Debug, Default)]
+pub struct ChannelDb {
+    /// Channels in file order, so listings keep the order of the tables
+    channels: Vec<Channel>,
+    index: HashMap<(String, u8, u16), usize>,
+    /// "<source> <version>" of every loaded file
+    versions: Vec<String>,
+}
+
+impl ChannelDb {
+    /// Database with the bundled tables
+    pub fn bundled() -> Self {
+        let mut db = Self::default();
+        for (source, json) in BUNDLED {
+            if let Err(e) = db.load_json(source, json) {
+                log::error!("Bundled channel table is invalid: {}", e);
+            }
+        }
+        db
+    }
+
+    /// Load a JSON channel file, returns the number of channels
+    ///
+    /// The whole file is rejected if any channel is invalid.
+    pub fn load_json(&mut self, source: &str, json: &str) -> Result<usize, String> {
+        let file: ChannelFile =
+            serde_json::from_str(json).map_err(|e| format!("{}: {}", source, e))?;
+        let channels = file
+            .channels
+            .into_iter()
+            .map(Channel::new)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("{}: {}", source, e))?;
+
+        let count = channels.len();
+        for channel in channels {
+            self.insert(channel);
+        }
+        self.versions.push(format!("{} {}", source, file.version));
+        Ok(count)
+    }
+
+    /// Load every `.json` file in a directory (sorted by name)
+    pub fn load_dir(&mut self, dir: &Path) -> Result<usize, String> {
+        let mut paths: Vec<_> = std::fs::read_dir(dir)
+            .map_err(|e| format!("{}: {}", dir.display(), e))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("json"))
+            .collect();
+        paths.sort();
+
+        let mut total = 0;
+        for path in paths {
+            let source = path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let loaded = std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|s| self.load_json(&source, &s));
+
+            match loaded {
+                Ok(count) => {
+                    log::info!("Loaded {} channels from {}", count, path.display());
+                    total += count;
+                }
+                Err(e) => log::warn!("Skipping channel file {}: {}", path.display(), e),
+            }
+        }
+        Ok(total)
+    }
+
+    /// Add a channel, replacing one with the same ECU, service and identifier
+    fn insert(&mut self, channel: Channel) {
+        let key = (
+            channel.def.ecu.to_ascii_uppercase(),
+            channel.service,
+            channel.id,
+        );
+        match self.index.get(&key) {
+            Some(&pos) => self.channels[pos] = channel,
+            None => {
+                self.index.insert(key, self.channels.len());
+                self.channels.push(channel);
+            }
+        }
+    }
+
+    /// Channel for an identifier read from `ecu` with `service`
+    pub fn get(&self, ecu: &str, service: u8, id: u16) -> Option<&Channel> {
+        self.index
+            .get(&(ecu.to_ascii_uppercase(), service, id))
+            .map(|&pos| &self.channels[pos])
+    }
+
+    /// All channels of one ECU and service, in file order
+    pub fn channels<'a>(&'a self, ecu: &'a str, service: u8) -> impl Iterator<Item = &'a Channel> {
+        self.channels
+            .iter()
+            .filter(move |c| c.service == service && c.def.ecu.eq_ignore_ascii_case(ecu))
+    }
+
+    /// Loaded file versions
+    pub fn versions(&self) -> &[String] {
+        &self.versions
+    }
+
+    /// Number of distinct channels
+    pub fn len(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(extra: &str) -> String {
+        format!(
+            r#"{{"version": "1", "channels": [
+                {{"key": "T", "ecu": "DDE", "service": "22", "id": "1234", "min": 0, "max": 1 {}}}
+            ]}}"#,
+            extra
+        )
+    }
+
+    #[test]
+    fn test_bundled_tables_load() {
+        let db = ChannelDb::bundled();
+        assert_eq!(db.versions().len(), BUNDLED.len());
+
+        let rail = db.get(DDE, SERVICE_DATA_ID, 0x394A).unwrap();
+        assert_eq!(rail.data_length(), 2);
+        assert!((rail.decode(&[0x27, 0x10]).unwrap() - 1000.0).abs() < 0.01);
+        assert_eq!(rail.label(), "Rail Pressure");
+
+        let rpm = db.get("obd", SERVICE_MODE_01, 0x0C).unwrap();
+        assert_eq!(rpm.decode(&[0x0C, 0x80]), Some(800.0));
+
+        // Local identifiers overlap between ECUs
+        let dme = db.get(DME, SERVICE_LOCAL_ID, 0x20).unwrap();
+        let egs = db.get(EGS, SERVICE_LOCAL_ID, 0x20).unwrap();
+        assert_ne!(dme.def.key, egs.def.key);
+        assert_eq!(dme.decode(&[20]), Some(800.0));
+        assert_eq!(dme.decode(&[0x03, 0x20]), Some(800.0));
+        let torque = db.get(EGS, SERVICE_LOCAL_ID, 0x40).unwrap();
+        assert_eq!(torque.decode(&[0x02, 0x58]), Some(100.0));
+        assert_eq!(torque.decode(&[25]), Some(100.0));
+    }
+
+    #[test]
+    fn test_scale_signed_little_endian() {
+        let mut db = ChannelDb::default();
+        db.load_json(
+            "t.json",
+            &channel(r#", "offset": 1, "length": 2, "signed": true, "endian": "little", "scale": 0.5, "bias": 10"#),
+        )
+        .unwrap();
+
+        let ch = db.get(DDE, SERVICE_DATA_ID, 0x1234).unwrap();
+        // 0xFFFE little endian = -2
+        assert_eq!(ch.decode(&[0x99, 0xFE, 0xFF]), Some(9.0));
+        assert_eq!(ch.data_length(), 3);
+        assert_eq!(ch.decode(&[0x99]), None);
+        assert_eq!(ch.formula_text(), "X * 0.5 + 10");
+    }
+
+    #[test]
+    fn test_later_files_override_channels() {
+        let mut db = ChannelDb::bundled();
+        let before = db.len();
+        db.load_json(
+            "override.json",
+            r#"{"version": "2", "channels": [
+                {"key": "FUEL_RAIL_PRESSURE", "ecu": "DDE", "service": "22", "id": "394A",
+                 "length": 2, "formula": "X", "unit": "hPa", "min": 0, "max": 1}
+            ]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(db.len(), before);
+        let rail = db.get(DDE, SERVICE_DATA_ID, 0x394A).unwrap();
+        assert_eq!(rail.decode(&[0x01, 0x00]), Some(256.0));
+        assert_eq!(rail.def.unit, "hPa");
+        // Order of the listing is kept
+        assert_eq!(db.channels(DDE, SERVICE_DATA_ID).next().unwrap().id, 0x394A);
+    }
+
+    #[test]
+    fn test_invalid_channels_are_rejected() {
+        let mut db = ChannelDb::default();
+        assert!(db
+            .load_json("a", &channel(r#", "formula": "A + Y""#))
+            .is_err());
+        assert!(db
+            .load_json("b", &channel(r#", "formula": "A +""#))
+            .is_err());
+        assert!(db
+            .load_json("c", &channel(r#", "formula": "A", "scale": 2"#))
+            .is_err());
+        assert!(db.load_json("d", &channel(r#", "length": 9"#)).is_err());
+        assert!(db.is_empty());
+        assert!(db.versions().is_empty());
+    }
+}
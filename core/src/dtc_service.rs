@@ -0,0 +1,499 @@
+//! DTC read service
+//!
+//! Picks KWP2000 ReadDTCByStatus (0x18) or UDS ReadDTCInformation (0x19)
+//! from the ECU dialect, parses both response layouts and follows
+//! multi-part responses.
+
+use crate::fault_codes::FaultCode;
+use serde::{Deserialize, Serialize};
+
+/// KWP2000 ReadDTCByStatus: all DTCs with status, all groups
+const KWP_READ_DTCS: [u8; 4] = [0x18, 0x02, 0xFF, 0x00];
+/// UDS ReadDTCInformation: reportDTCByStatusMask, any status
+const UDS_READ_DTCS: [u8; 3] = [0x19, 0x02, 0xFF];
+
+/// Positive response to ClearDiagnosticInformation (0x14)
+const CLEAR_POSITIVE: u8 = 0x54;
+
+/// UDS statusOfDTC testFailed bit
+const UDS_TEST_FAILED: u8 = 0x01;
+
+const NEGATIVE_RESPONSE: u8 = 0x7F;
+const NRC_SERVICE_NOT_SUPPORTED: u8 = 0x11;
+const NRC_SUB_FUNCTION_NOT_SUPPORTED: u8 = 0x12;
+const NRC_RESPONSE_PENDING: u8 = 0x78;
+
+/// Upper bound on responsePending and follow-up frames for one read
+const MAX_EXTRA_FRAMES: usize = 16;
+
+/// Diagnostic dialect spoken by an ECU
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum DtcDialect {
+    /// KWP2000 (ISO 14230), E60 K-Line ECUs
+    Kwp2000,
+    /// UDS (ISO 14229), D-CAN ECUs
+    Uds,
+}
+
+impl DtcDialect {
+    /// Dialect tried first for a transport
+    pub fn for_transport(kline: bool) -> Self {
+        if kline {
+            Self::Kwp2000
+        } else {
+            Self::Uds
+        }
+    }
+
+    /// Request bytes, service ID first
+    pub fn request(self) -> &'static [u8] {
+        match self {
+            Self::Kwp2000 => &KWP_READ_DTCS,
+            Self::Uds => &UDS_READ_DTCS,
+        }
+    }
+
+    fn other(self) -> Self {
+        match self {
+            Self::Kwp2000 => Self::Uds,
+            Self::Uds => Self::Kwp2000,
+        }
+    }
+}
+
+/// One fault as reported by the ECU
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DtcRecord {
+    /// 2-byte BMW/SAE code or 3-byte UDS code
+    pub code: u32,
+    /// Number of code bytes (2 or 3)
+    pub code_len: usize,
+    /// Status byte as sent by the ECU
+    pub status: u8,
+    pub dialect: DtcDialect,
+}
+
+impl DtcRecord {
+    /// Code bytes followed by the raw status byte
+    pub fn raw_bytes(&self) -> Vec<u8> {
+        let code = self.code.to_be_bytes();
+        let mut bytes = code[4 - self.code_len..].to_vec();
+        bytes.push(self.status);
+        bytes
+    }
+
+    /// The first two code bytes (SAE P/C/B/U part of a UDS code)
+    pub fn short_code(&self) -> u16 {
+        (self.code >> (8 * (self.code_len - 2))) as u16
+    }
+
+    /// The code with its length, as used for fault text lookups
+    pub fn fault_code(&self) -> FaultCode {
+        FaultCode {
+            code: self.code,
+            len: self.code_len,
+        }
+    }
+
+    /// Status in the UDS bit layout (ISO 14229 statusOfDTC)
+    pub fn uds_status(&self) -> u8 {
+        match self.dialect {
+            DtcDialect::Uds => self.status,
+            DtcDialect::Kwp2000 => kwp_status_to_uds(self.status),
+        }
+    }
+}
+
+/// Map an ISO 14230 statusOfDTC byte to the UDS bit layout
+///
+/// KWP bits 6-5 hold the storage state (01 stored, 10 pending,
+/// 11 present) and bit 7 the warning lamp.
+pub fn kwp_status_to_uds(status: u8) -> u8 {
+    let mut uds = 0;
+    match (status >> 5) & 0x03 {
+        0b11 => uds |= 0x01 | 0x02 | 0x08 | 0x20, // failed now, confirmed
+        0b10 => uds |= 0x04 | 0x20,               // pending
+        0b01 => uds |= 0x08 | 0x20,               // stored, not present
+        _ => {}
+    }
+    if status & 0x80 != 0 {
+        uds |= 0x80;
+    }
+    uds
+}
+
+/// DTCs from one response frame
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DtcFrame {
+    /// Total count announced by the ECU (KWP only)
+    pub announced: Option<usize>,
+    pub records: Vec<DtcRecord>,
+}
+
+/// Parse one positive response frame (service ID first)
+pub fn parse_response(dialect: DtcDialect, response: &[u8]) -> Result<DtcFrame, String> {
+    match (dialect, response) {
+        (DtcDialect::Kwp2000, [0x58, count, data @ ..]) => {
+            let count = *count as usize;
+            Ok(DtcFrame {
+                announced: Some(count),
+                records: parse_records(data, kwp_record_len(data.len(), count), dialect),
+            })
+        }
+        (DtcDialect::Uds, [0x59, 0x02, _availability, data @ ..]) => Ok(DtcFrame {
+            announced: None,
+            records: parse_records(data, 4, dialect),
+        }),
+        (_, [NEGATIVE_RESPONSE, _, nrc, ..]) => Err(format!(
+            "Negative response: NRC 0x{:02X}",
+            nrc
+        )),
+        _ => Err(format!("Unexpected response: {:02X?}", response)),
+    }
+}
+
+/// Record length for a KWP payload: 3 (2-byte code) or 4 (3-byte code)
+fn kwp_record_len(payload_len: usize, count: usize) -> usize {
+    match (payload_len % 3, payload_len % 4) {
+        (1 | 2, 0) => 4,
+        (0, 0) if count > 0 && payload_len == count * 4 => 4,
+        _ => 3,
+    }
+}
+
+fn parse_records(data: &[u8], record_len: usize, dialect: DtcDialect) -> Vec<DtcRecord> {
+    data.chunks_exact(record_len)
+        .filter_map(|chunk| {
+            let (code, status) = chunk.split_at(record_len - 1);
+            let code = code.iter().fold(0u32, |acc, b| (acc << 8) | *b as u32);
+            // All-zero codes pad the list on some ECUs
+            (code != 0).then_some(DtcRecord {
+                code,
+                code_len: record_len - 1,
+                status: status[0],
+                dialect,
+            })
+        })
+        .collect()
+}
+
+/// Request/response access to one ECU
+pub trait DtcTransport {
+    /// Send a request (service ID first) and return the response frame
+    fn request(&mut self, data: &[u8]) -> Result<Vec<u8>, String>;
+    /// Receive a further response frame without sending
+    fn receive(&mut self) -> Result<Vec<u8>, String>;
+}
+
+/// Result of a DTC read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DtcReadout {
+    pub dialect: DtcDialect,
+    pub records: Vec<DtcRecord>,
+}
+
+/// Read all DTCs, falling back to the other dialect if the ECU rejects
+/// the service
+pub fn read_dtcs(transport: &mut dyn DtcTransport, dialect: DtcDialect) -> Result<DtcReadout, String> {
+    match read_with(transport, dialect) {
+        Err(e) if is_unsupported(&e) => read_with(transport, dialect.other()),
+        result => result,
+    }
+}
+
+fn is_unsupported(error: &str) -> bool {
+    [NRC_SERVICE_NOT_SUPPORTED, NRC_SUB_FUNCTION_NOT_SUPPORTED]
+        .iter()
+        .any(|nrc| error.ends_with(&format!("NRC 0x{:02X}", nrc)))
+}
+
+fn read_with(transport: &mut dyn DtcTransport, dialect: DtcDialect) -> Result<DtcReadout, String> {
+    let mut response = transport.request(dialect.request())?;
+    let mut extra_frames = 0;
+
+    // responsePending: the ECU sends the real answer later
+    while is_pending(&response) && extra_frames < MAX_EXTRA_FRAMES {
+        response = transport.receive()?;
+        extra_frames += 1;
+    }
+
+    let first = parse_response(dialect, &response)?;
+    let mut records = first.records;
+
+    // Long KWP lists arrive in several frames
+    if let Some(announced) = first.announced {
+        while records.len() < announced && extra_frames < MAX_EXTRA_FRAMES {
+            let Ok(frame) = transport
+                .receive()
+                .and_then(|next| parse_response(dialect, &next))
+            else {
+                break;
+            };
+            if frame.records.is_empty() {
+                break;
+            }
+            records.extend(frame.records);
+            extra_frames += 1;
+        }
+    }
+
+    Ok(DtcReadout { dialect, records })
+}
+
+/// Whether a frame is a responsePending (NRC 0x78) placeholder
+pub fn is_pending(response: &[u8]) -> bool {
+    matches!(response, [NEGATIVE_RESPONSE, _, NRC_RESPONSE_PENDING, ..])
+}
+
+// ============================================================================
+// CLEARING
+// ============================================================================
+
+/// DTC group for ClearDiagnosticInformation (0x14)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DtcGroup {
+    All,
+    Powertrain,
+    Chassis,
+    Body,
+    Network,
+    /// A single fault, as returned by the read (2 or 3 bytes)
+    Code(u32),
+}
+
+impl DtcGroup {
+    /// SAE category (top two code bits) of a letter group
+    fn category(self) -> Option<u8> {
+        match self {
+            Self::Powertrain => Some(0),
+            Self::Chassis => Some(1),
+            Self::Body => Some(2),
+            Self::Network => Some(3),
+            Self::All | Self::Code(_) => None,
+        }
+    }
+
+    /// Request bytes, service ID first
+    ///
+    /// groupOfDTC is 2 bytes for KWP2000 (0xFF00 = all) and 3 bytes for
+    /// UDS (0xFFFFFF = all). Letter groups select the first code of their
+    /// SAE category, e.g. 0x4000 / 0x400000 for chassis.
+    pub fn clear_request(self, dialect: DtcDialect) -> Vec<u8> {
+        let group = match (self, dialect) {
+            (Self::All, DtcDialect::Kwp2000) => 0xFF00,
+            (Self::All, DtcDialect::Uds) => 0xFF_FFFF,
+            (Self::Code(code), _) => code,
+            (_, DtcDialect::Kwp2000) => (self.category().unwrap_or(0) as u32) << 14,
+            (_, DtcDialect::Uds) => (self.category().unwrap_or(0) as u32) << 22,
+        };
+
+        let bytes = group.to_be_bytes();
+        let group_len = match dialect {
+            DtcDialect::Kwp2000 => 2,
+            DtcDialect::Uds => 3,
+        };
+        let mut request = vec![0x14];
+        request.extend_from_slice(&bytes[4 - group_len..]);
+        request
+    }
+
+    /// Whether a fault belongs to the group
+    pub fn contains(self, record: &DtcRecord) -> bool {
+        match self {
+            Self::All => true,
+            Self::Code(code) => record.code == code,
+            _ => self.category() == Some((record.short_code() >> 14) as u8),
+        }
+    }
+}
+
+/// Outcome of a verified clear
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ClearSummary {
+    pub dialect: DtcDialect,
+    pub group: DtcGroup,
+    /// Present before, gone after the clear
+    pub cleared: Vec<DtcRecord>,
+    /// Reported as failing again right after the clear
+    pub returned: Vec<DtcRecord>,
+    /// Still stored after the clear, but not failing
+    pub pending: Vec<DtcRecord>,
+}
+
+/// Clear a DTC group, falling back to the other dialect if the ECU rejects
+/// the service
+pub fn clear_dtcs(
+    transport: &mut dyn DtcTransport,
+    dialect: DtcDialect,
+    group: DtcGroup,
+) -> Result<DtcDialect, String> {
+    match clear_with(transport, dialect, group) {
+        Err(e) if is_unsupported(&e) => clear_with(transport, dialect.other(), group),
+        result => result,
+    }
+}
+
+fn clear_with(
+    transport: &mut dyn DtcTransport,
+    dialect: DtcDialect,
+    group: DtcGroup,
+) -> Result<DtcDialect, String> {
+    let mut response = transport.request(&group.clear_request(dialect))?;
+    let mut extra_frames = 0;
+
+    while is_pending(&response) && extra_frames < MAX_EXTRA_FRAMES {
+        response = transport.receive()?;
+        extra_frames += 1;
+    }
+
+    match response.as_slice() {
+        [CLEAR_POSITIVE, ..] => Ok(dialect),
+        [NEGATIVE_RESPONSE, _, nrc, ..] => Err(format!("Negative response: NRC 0x{:02X}", nrc)),
+        _ => Err(format!("Unexpected response: {:02X?}", response)),
+    }
+}
+
+/// Read, clear a group and read again to see which faults came back
+pub fn clear_and_verify(
+    transport: &mut dyn DtcTransport,
+    dialect: DtcDialect,
+    group: DtcGroup,
+) -> Result<ClearSummary, String> {
+    let before = read_dtcs(transport, dialect)?;
+    let dialect = clear_dtcs(transport, before.dialect, group)?;
+    let after = read_dtcs(transport, dialect)?;
+
+    Ok(classify_clear(dialect, group, &before.records, &after.records))
+}
+
+/// Sort the faults of `group` into cleared, returned and pending
+pub fn classify_clear(
+    dialect: DtcDialect,
+    group: DtcGroup,
+    before: &[DtcRecord],
+    after: &[DtcRecord],
+) -> ClearSummary {
+    let after: Vec<_> = after.iter().filter(|r| group.contains(r)).cloned().collect();
+    let (returned, pending) = after
+        .iter()
+        .cloned()
+        .partition(|r| r.uds_status() & UDS_TEST_FAILED != 0);
+
+    ClearSummary {
+        dialect,
+        group,
+        cleared: before
+            .iter()
+            .filter(|r| group.contains(r) && !after.iter().any(|a| a.code == r.code))
+            .cloned()
+            .collect(),
+        returned,
+        pending,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// Replays canned frames; `request` pops the next one too
+    struct Replay(VecDeque<Vec<u8>>, Vec<Vec<u8>>);
+
+    impl DtcTransport for Replay {
+        fn request(&mut self, data: &[u8]) -> Result<Vec<u8>, String> {
+            self.1.push(data.to_vec());
+            self.receive()
+        }
+
+        fn receive(&mut self) -> Result<Vec<u8>, String> {
+            self.0.pop_front().ok_or_else(|| "No response received".to_string())
+        }
+    }
+
+    fn replay(frames: &[&[u8]]) -> Replay {
+        Replay(frames.iter().map(|f| f.to_vec()).collect(), Vec::new())
+    }
+
+    #[test]
+    fn test_kwp_two_byte_layout_and_status() {
+        let frame = parse_response(
+            DtcDialect::Kwp2000,
+            &[0x58, 0x02, 0x2A, 0x82, 0x60, 0x29, 0xCD, 0xA0],
+        )
+        .unwrap();
+        assert_eq!(frame.records.len(), 2);
+        assert_eq!(frame.records[0].code, 0x2A82);
+        assert_eq!(frame.records[0].uds_status(), 0x2B); // present
+        assert_eq!(frame.records[1].uds_status(), 0xA8); // stored, lamp on
+        assert_eq!(frame.records[1].raw_bytes(), vec![0x29, 0xCD, 0xA0]);
+    }
+
+    #[test]
+    fn test_kwp_three_byte_layout() {
+        let frame =
+            parse_response(DtcDialect::Kwp2000, &[0x58, 0x01, 0x2A, 0x82, 0x11, 0x60]).unwrap();
+        assert_eq!(frame.records[0].code, 0x2A8211);
+        assert_eq!(frame.records[0].short_code(), 0x2A82);
+    }
+
+    #[test]
+    fn test_uds_layout() {
+        let frame = parse_response(
+            DtcDialect::Uds,
+            &[0x59, 0x02, 0xFF, 0x29, 0xCD, 0x00, 0x09, 0x00, 0x00, 0x00, 0x00],
+        )
+        .unwrap();
+        assert_eq!(frame.records.len(), 1);
+        assert_eq!(frame.records[0].code_len, 3);
+        assert_eq!(frame.records[0].uds_status(), 0x09);
+    }
+
+    #[test]
+    fn test_multi_part_and_pending() {
+        let mut transport = replay(&[
+            &[0x7F, 0x18, 0x78],
+            &[0x58, 0x03, 0x2A, 0x82, 0x60, 0x29, 0xCD, 0x20],
+            &[0x58, 0x03, 0x29, 0xCE, 0x20],
+        ]);
+        let readout = read_dtcs(&mut transport, DtcDialect::Kwp2000).unwrap();
+        assert_eq!(readout.records.len(), 3);
+        assert_eq!(readout.records[2].code, 0x29CE);
+    }
+
+    #[test]
+    fn test_falls_back_to_other_dialect() {
+        let mut transport = replay(&[
+            &[0x7F, 0x18, 0x11],
+            &[0x59, 0x02, 0xFF, 0x29, 0xCD, 0x00, 0x09],
+        ]);
+        let readout = read_dtcs(&mut transport, DtcDialect::Kwp2000).unwrap();
+        assert_eq!(readout.dialect, DtcDialect::Uds);
+        assert_eq!(transport.1[1], UDS_READ_DTCS.to_vec());
+    }
+
+    #[test]
+    fn test_clear_requests() {
+        assert_eq!(DtcGroup::All.clear_request(DtcDialect::Kwp2000), vec![0x14, 0xFF, 0x00]);
+        assert_eq!(DtcGroup::Chassis.clear_request(DtcDialect::Kwp2000), vec![0x14, 0x40, 0x00]);
+        assert_eq!(DtcGroup::Network.clear_request(DtcDialect::Uds), vec![0x14, 0xC0, 0x00, 0x00]);
+        assert_eq!(DtcGroup::Code(0x2A82).clear_request(DtcDialect::Kwp2000), vec![0x14, 0x2A, 0x82]);
+    }
+
+    #[test]
+    fn test_clear_and_verify_classifies_faults() {
+        let mut transport = replay(&[
+            &[0x58, 0x03, 0x2A, 0x82, 0x60, 0x29, 0xCD, 0x20, 0x5A, 0x10, 0x60],
+            &[0x54, 0x00, 0x00],
+            &[0x58, 0x01, 0x2A, 0x82, 0x60],
+        ]);
+        let summary =
+            clear_and_verify(&mut transport, DtcDialect::Kwp2000, DtcGroup::Powertrain).unwrap();
+
+        assert_eq!(transport.1[1], vec![0x14, 0x00, 0x00]);
+        assert_eq!(summary.cleared.len(), 1); // 29CD; 5A10 is chassis
+        assert_eq!(summary.returned[0].code, 0x2A82);
+        assert!(summary.pending.is_empty());
+    }
+}
@@ -0,0 +1,435 @@
+//! BMW Fault Text Database
+//!
+//! BMW KWP ECUs report BMW hex fault codes (e.g. `2A82`) whose meaning
+//! depends on the ECU variant; UDS ECUs report 3-byte codes (`480A12`).
+//! This module maps (ECU variant, code) to German/English/Spanish texts and
+//! the SAE equivalent where one exists.
+//!
+//! The bundled set lives in `data/fault-codes/` at the repository root;
+//! each front end loads additional versioned JSON/CSV files from its own
+//! directory.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+/// Bundled fault texts
+const BUNDLED_JSON: &str = include_str!("../../data/fault-codes/bmw_e60.json");
+
+/// Variant wildcard: text applies to every variant of the ECU
+pub const ANY_VARIANT: &str = "*";
+
+/// Fault code as reported by the ECU: 2 bytes on KWP2000, 3 on UDS
+///
+/// The length is part of the key, so a UDS code never picks up the text of
+/// the KWP code that shares its first two bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FaultCode {
+    pub code: u32,
+    /// Number of code bytes (2 or 3)
+    pub len: usize,
+}
+
+impl FaultCode {
+    pub fn kwp(code: u16) -> Self {
+        Self {
+            code: code as u32,
+            len: 2,
+        }
+    }
+
+    pub fn uds(code: u32) -> Self {
+        Self {
+            code: code & 0xFF_FFFF,
+            len: 3,
+        }
+    }
+
+    /// Parse 4 (KWP) or 6 (UDS) hex digits
+    pub fn parse(code: &str) -> Option<Self> {
+        let code = code.trim();
+        if !code.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let value = u32::from_str_radix(code, 16).ok()?;
+        match code.len() {
+            4 => Some(Self::kwp(value as u16)),
+            6 => Some(Self::uds(value)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FaultCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:0width$X}", self.code, width = self.len * 2)
+    }
+}
+
+/// Fault text in the supported languages
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct FaultText {
+    pub de: Option<String>,
+    pub en: Option<String>,
+    pub es: Option<String>,
+}
+
+impl FaultText {
+    /// Preferred text for the UI (Spanish, then English, then German)
+    pub fn preferred(&self) -> Option<&str> {
+        self.es
+            .as_deref()
+            .or(self.en.as_deref())
+            .or(self.de.as_deref())
+    }
+}
+
+/// One database entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FaultEntry {
+    /// ECU id as in `bmw::e60_ecus()` (DME, DDE, EGS...)
+    pub ecu: String,
+    /// ECU software variant (MSV70, DDE6.0...) or `*`
+    pub variant: String,
+    /// BMW fault code, 4 hex digits (KWP2000) or 6 (UDS)
+    pub code: String,
+    /// SAE J2012 equivalent, if the fault has one
+    #[serde(default)]
+    pub sae: Option<String>,
+    #[serde(default)]
+    pub text: FaultText,
+}
+
+/// JSON file layout
+#[derive(Debug, Deserialize)]
+struct FaultFile {
+    version: String,
+    entries: Vec<FaultEntry>,
+}
+
+/// Fault texts indexed by code
+#[derive(Debug, Default)]
+pub struct FaultCodeDb {
+    entries: HashMap<FaultCode, Vec<FaultEntry>>,
+    /// "<source> <version>" of every loaded file
+    versions: Vec<String>,
+}
+
+impl FaultCodeDb {
+    /// Database with the bundled texts
+    pub fn bundled() -> Self {
+        let mut db = Self::default();
+        if let Err(e) = db.load_json("bundled", BUNDLED_JSON) {
+            log::error!("Bundled fault code database is invalid: {}", e);
+        }
+        db
+    }
+
+    /// Load a JSON fault file, returns the number of entries
+    pub fn load_json(&mut self, source: &str, json: &str) -> Result<usize, String> {
+        let file: FaultFile =
+            serde_json::from_str(json).map_err(|e| format!("{}: {}", source, e))?;
+        let count = file.entries.len();
+
+        for entry in file.entries {
+            self.insert(entry).map_err(|e| format!("{}: {}", source, e))?;
+        }
+        self.versions.push(format!("{} {}", source, file.version));
+        Ok(count)
+    }
+
+    /// Load a CSV fault file, returns the number of entries
+    ///
+    /// Line 1: `# version: <version>`, line 2: header
+    /// `ecu,variant,code,sae,de,en,es`, then one entry per line.
+    pub fn load_csv(&mut self, source: &str, csv: &str) -> Result<usize, String> {
+        let mut lines = csv.lines();
+        let version = lines
+            .next()
+            .and_then(|l| l.trim().strip_prefix("# version:"))
+            .map(|v| v.trim().to_string())
+            .ok_or_else(|| format!("{}: missing '# version:' line", source))?;
+        lines.next(); // header
+
+        let mut count = 0;
+        for (i, line) in lines.enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields = split_csv_line(line);
+            if fields.len() != 7 {
+                return Err(format!(
+                    "{} line {}: expected 7 fields, got {}",
+                    source,
+                    i + 3,
+                    fields.len()
+                ));
+            }
+
+            let opt = |s: &String| (!s.is_empty()).then(|| s.clone());
+            let entry = FaultEntry {
+                ecu: fields[0].clone(),
+                variant: fields[1].clone(),
+                code: fields[2].clone(),
+                sae: opt(&fields[3]),
+                text: FaultText {
+                    de: opt(&fields[4]),
+                    en: opt(&fields[5]),
+                    es: opt(&fields[6]),
+                },
+            };
+            self.insert(entry)
+                .map_err(|e| format!("{} line {}: {}", source, i + 3, e))?;
+            count += 1;
+        }
+
+        self.versions.push(format!("{} {}", source, version));
+        Ok(count)
+    }
+
+    /// Load every `.json` and `.csv` file in a directory (sorted by name)
+    pub fn load_dir(&mut self, dir: &Path) -> Result<usize, String> {
+        let mut paths: Vec<_> = std::fs::read_dir(dir)
+            .map_err(|e| format!("{}: {}", dir.display(), e))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .collect();
+        paths.sort();
+
+        let mut total = 0;
+        for path in paths {
+            let source = path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let loaded = match path.extension().and_then(|e| e.to_str()) {
+                Some("json") => std::fs::read_to_string(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|s| self.load_json(&source, &s)),
+                Some("csv") => std::fs::read_to_string(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|s| self.load_csv(&source, &s)),
+                _ => continue,
+            };
+
+            match loaded {
+                Ok(count) => {
+                    log::info!("Loaded {} fault texts from {}", count, path.display());
+                    total += count;
+                }
+                Err(e) => log::warn!("Skipping fault file {}: {}", path.display(), e),
+            }
+        }
+        Ok(total)
+    }
+
+    /// Add an entry, replacing one with the same ECU, variant and code
+    ///
+    /// ECU and variant are compared ignoring case, as in `lookup`.
+    fn insert(&mut self, entry: FaultEntry) -> Result<(), String> {
+        let code = FaultCode::parse(&entry.code)
+            .ok_or_else(|| format!("invalid fault code '{}'", entry.code))?;
+        let list = self.entries.entry(code).or_default();
+        list.retain(|e| {
+            !(e.ecu.eq_ignore_ascii_case(&entry.ecu)
+                && e.variant.eq_ignore_ascii_case(&entry.variant))
+        });
+        list.push(entry);
+        Ok(())
+    }
+
+    /// Find the text for `code` reported by one of `ecus`
+    ///
+    /// Only entries of the same code length match. An exact variant match
+    /// wins, then a `*` entry. Without a known variant the first entry of
+    /// any variant of the ECU is used.
+    pub fn lookup(
+        &self,
+        ecus: &[&str],
+        variant: Option<&str>,
+        code: FaultCode,
+    ) -> Option<&FaultEntry> {
+        let candidates: Vec<_> = self
+            .entries
+            .get(&code)?
+            .iter()
+            .filter(|e| ecus.iter().any(|ecu| e.ecu.eq_ignore_ascii_case(ecu)))
+            .collect();
+
+        if let Some(variant) = variant {
+            if let Some(entry) = candidates
+                .iter()
+                .find(|e| e.variant.eq_ignore_ascii_case(variant))
+            {
+                return Some(entry);
+            }
+        }
+        if let Some(entry) = candidates.iter().find(|e| e.variant == ANY_VARIANT) {
+            return Some(entry);
+        }
+        if variant.is_none() {
+            return candidates.first().copied();
+        }
+        None
+    }
+
+    /// Loaded file versions
+    pub fn versions(&self) -> &[String] {
+        &self.versions
+    }
+
+    /// Number of distinct (ECU, variant, code) entries
+    pub fn len(&self) -> usize {
+        self.entries.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Split one CSV line, honouring double quotes
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field.trim_end_matches('\r').to_string());
+    fields.iter().map(|f| f.trim().to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundled_database_loads() {
+        let db = FaultCodeDb::bundled();
+        assert!(!db.is_empty());
+        assert_eq!(db.versions().len(), 1);
+
+        let entry = db
+            .lookup(&["DME"], Some("MSV70"), FaultCode::kwp(0x29CD))
+            .unwrap();
+        assert_eq!(entry.sae.as_deref(), Some("P0301"));
+        assert!(entry.text.de.is_some() && entry.text.en.is_some() && entry.text.es.is_some());
+    }
+
+    #[test]
+    fn test_lookup_prefers_exact_variant() {
+        let mut db = FaultCodeDb::default();
+        db.load_csv(
+            "test.csv",
+            "# version: 1\n\
+             ecu,variant,code,sae,de,en,es\n\
+             DME,*,2A82,,Generisch,Generic,Generico\n\
+             DME,MSD80,2A82,,,\"Intake VANOS, MSD80\",\n",
+        )
+        .unwrap();
+
+        let code = FaultCode::kwp(0x2A82);
+        let exact = db.lookup(&["DME"], Some("MSD80"), code).unwrap();
+        assert_eq!(exact.text.en.as_deref(), Some("Intake VANOS, MSD80"));
+        assert_eq!(exact.text.preferred(), Some("Intake VANOS, MSD80"));
+
+        let fallback = db.lookup(&["DME"], Some("MSV70"), code).unwrap();
+        assert_eq!(fallback.variant, ANY_VARIANT);
+
+        assert!(db.lookup(&["EGS"], None, code).is_none());
+        assert_eq!(db.versions(), &["test.csv 1".to_string()]);
+    }
+
+    #[test]
+    fn test_later_files_override_entries() {
+        let mut db = FaultCodeDb::bundled();
+        let before = db.len();
+        db.load_json(
+            "override.json",
+            r#"{"version": "2", "entries": [
+                {"ecu": "DME", "variant": "MSV70", "code": "29CD", "text": {"en": "Updated"}}
+            ]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(db.len(), before);
+        let entry = db
+            .lookup(&["DME"], Some("MSV70"), FaultCode::kwp(0x29CD))
+            .unwrap();
+        assert_eq!(entry.text.en.as_deref(), Some("Updated"));
+        assert_eq!(entry.sae, None);
+    }
+
+    #[test]
+    fn test_override_ignores_case() {
+        let mut db = FaultCodeDb::bundled();
+        let before = db.len();
+        db.load_csv(
+            "override.csv",
+            "# version: 2\n\
+             ecu,variant,code,sae,de,en,es\n\
+             dme,msv70,29CD,,,Updated,\n",
+        )
+        .unwrap();
+
+        assert_eq!(db.len(), before);
+        let entry = db
+            .lookup(&["DME"], Some("MSV70"), FaultCode::kwp(0x29CD))
+            .unwrap();
+        assert_eq!(entry.text.en.as_deref(), Some("Updated"));
+    }
+
+    #[test]
+    fn test_uds_codes_do_not_match_kwp_entries() {
+        let mut db = FaultCodeDb::default();
+        db.load_json(
+            "test.json",
+            r#"{"version": "1", "entries": [
+                {"ecu": "DDE", "variant": "*", "code": "480A", "text": {"en": "KWP fault"}},
+                {"ecu": "DDE", "variant": "*", "code": "480A12", "text": {"en": "UDS fault"}}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(db.len(), 2);
+
+        let uds = db.lookup(&["DDE"], None, FaultCode::uds(0x480A12)).unwrap();
+        assert_eq!(uds.text.en.as_deref(), Some("UDS fault"));
+        let kwp = db.lookup(&["DDE"], None, FaultCode::kwp(0x480A)).unwrap();
+        assert_eq!(kwp.text.en.as_deref(), Some("KWP fault"));
+        // Same first two bytes, other failure type: no text rather than the KWP one
+        assert!(db.lookup(&["DDE"], None, FaultCode::uds(0x480A00)).is_none());
+
+        assert_eq!(FaultCode::parse("480a12"), Some(FaultCode::uds(0x480A12)));
+        assert_eq!(FaultCode::uds(0x00_0A12).to_string(), "000A12");
+        assert_eq!(FaultCode::parse("+480"), None);
+    }
+
+    #[test]
+    fn test_invalid_code_is_rejected() {
+        let mut db = FaultCodeDb::default();
+        let result = db.load_json(
+            "bad.json",
+            r#"{"version": "1", "entries": [{"ecu": "DME", "variant": "*", "code": "P0301"}]}"#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_split_csv_line_quotes() {
+        assert_eq!(
+            split_csv_line(r#"a,"b, c","say ""hi""",,"#),
+            vec!["a", "b, c", "say \"hi\"", "", ""]
+        );
+    }
+}
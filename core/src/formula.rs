@@ -0,0 +1,559 @@
+//! Channel Formula Evaluator
+//!
+//! Small arithmetic language for scaling raw ECU data in channel definition
+//! files and for virtual channels computed from live data. Formulas are
+//! parsed once into an expression tree and evaluated per sample; there are
+//! no loops, assignments or user-defined functions, so a formula from a
+//! data file cannot do anything but compute a number.
+//!
+//! Grammar, loosest binding first:
+//!
+//! ```text
+//! a || b    a && b    a < b  a <= b  a > b  a >= b  a == b  a != b
+//! a + b  a - b    a * b  a / b  a % b    -a  !a    a ^ b
+//! 12  0.5  0x1F  NAME  func(args)  (expr)
+//! ```
+//!
+//! Comparisons and logic yield 1.0 or 0.0; any non-zero value is true.
+//! Functions: `abs`, `min`, `max`, `clamp`, `if`, `round`, `floor`, `ceil`, `sqrt`.
+
+use std::fmt;
+
+/// Deepest nesting accepted, keeps hostile files from overflowing the stack
+const MAX_DEPTH: usize = 64;
+
+/// Parsed formula
+#[derive(Debug, Clone, PartialEq)]
+pub struct Formula {
+    source: String,
+    expr: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(f64),
+    Var(String),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Call(Func, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Func {
+    Abs,
+    Min,
+    Max,
+    Clamp,
+    If,
+    Round,
+    Floor,
+    Ceil,
+    Sqrt,
+}
+
+impl Func {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "abs" => Func::Abs,
+            "min" => Func::Min,
+            "max" => Func::Max,
+            "clamp" => Func::Clamp,
+            "if" => Func::If,
+            "round" => Func::Round,
+            "floor" => Func::Floor,
+            "ceil" => Func::Ceil,
+            "sqrt" => Func::Sqrt,
+            _ => return None,
+        })
+    }
+
+    /// Accepted argument counts (min, max)
+    fn arity(self) -> (usize, usize) {
+        match self {
+            Func::Min | Func::Max => (2, usize::MAX),
+            Func::Clamp | Func::If => (3, 3),
+            _ => (1, 1),
+        }
+    }
+}
+
+impl Formula {
+    /// Parse a formula
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+            depth: 0,
+        };
+        let expr = parser.expr()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("unexpected '{}' in '{}'", token, source));
+        }
+        Ok(Self {
+            source: source.to_string(),
+            expr,
+        })
+    }
+
+    /// Formula text as written
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Variable names the formula refers to, in order of first use
+    pub fn variables(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        collect_vars(&self.expr, &mut names);
+        names
+    }
+
+    /// Evaluate with `vars` resolving variable names
+    ///
+    /// Fails on unknown variables and on results that are not finite
+    /// (division by zero, `sqrt` of a negative number).
+    pub fn eval(&self, vars: &dyn Fn(&str) -> Option<f64>) -> Result<f64, String> {
+        let value = eval(&self.expr, vars)?;
+        if value.is_finite() {
+            Ok(value)
+        } else {
+            Err(format!("'{}' is not finite", self.source))
+        }
+    }
+}
+
+impl fmt::Display for Formula {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+fn collect_vars<'a>(expr: &'a Expr, names: &mut Vec<&'a str>) {
+    match expr {
+        Expr::Number(_) => {}
+        Expr::Var(name) => {
+            if !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+        Expr::Neg(inner) | Expr::Not(inner) => collect_vars(inner, names),
+        Expr::Binary(_, lhs, rhs) => {
+            collect_vars(lhs, names);
+            collect_vars(rhs, names);
+        }
+        Expr::Call(_, args) => args.iter().for_each(|arg| collect_vars(arg, names)),
+    }
+}
+
+fn flag(value: bool) -> f64 {
+    if value {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+fn eval(expr: &Expr, vars: &dyn Fn(&str) -> Option<f64>) -> Result<f64, String> {
+    Ok(match expr {
+        Expr::Number(n) => *n,
+        Expr::Var(name) => vars(name).ok_or_else(|| format!("unknown variable '{}'", name))?,
+        Expr::Neg(inner) => -eval(inner, vars)?,
+        Expr::Not(inner) => flag(eval(inner, vars)? == 0.0),
+        Expr::Binary(op, lhs, rhs) => {
+            let a = eval(lhs, vars)?;
+            // Short-circuit so `if`-like guards never touch the other side
+            match op {
+                BinOp::And if a == 0.0 => return Ok(0.0),
+                BinOp::Or if a != 0.0 => return Ok(1.0),
+                _ => {}
+            }
+            let b = eval(rhs, vars)?;
+            match op {
+                BinOp::Add => a + b,
+                BinOp::Sub => a - b,
+                BinOp::Mul => a * b,
+                BinOp::Div => a / b,
+                BinOp::Rem => a % b,
+                BinOp::Pow => a.powf(b),
+                BinOp::Lt => flag(a < b),
+                BinOp::Le => flag(a <= b),
+                BinOp::Gt => flag(a > b),
+                BinOp::Ge => flag(a >= b),
+                BinOp::Eq => flag(a == b),
+                BinOp::Ne => flag(a != b),
+                BinOp::And | BinOp::Or => flag(b != 0.0),
+            }
+        }
+        Expr::Call(Func::If, args) => {
+            if eval(&args[0], vars)? != 0.0 {
+                eval(&args[1], vars)?
+            } else {
+                eval(&args[2], vars)?
+            }
+        }
+        Expr::Call(func, args) => {
+            let values = args
+                .iter()
+                .map(|arg| eval(arg, vars))
+                .collect::<Result<Vec<_>, _>>()?;
+            match func {
+                Func::Abs => values[0].abs(),
+                Func::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+                Func::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+                Func::Clamp => values[0].max(values[1]).min(values[2]),
+                Func::Round => values[0].round(),
+                Func::Floor => values[0].floor(),
+                Func::Ceil => values[0].ceil(),
+                Func::Sqrt => values[0].sqrt(),
+                Func::If => unreachable!(),
+            }
+        }
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+    Comma,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Ident(name) => f.write_str(name),
+            Token::Op(op) => f.write_str(op),
+            Token::LParen => f.write_str("("),
+            Token::RParen => f.write_str(")"),
+            Token::Comma => f.write_str(","),
+        }
+    }
+}
+
+/// Operators, two-character ones first so `<=` is not read as `<`
+const OPERATORS: [&str; 16] = [
+    "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "^", "<", ">", "!", "=",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_digit() || c == '.' {
+            let (token, len) = lex_number(rest)?;
+            tokens.push(token);
+            rest = &rest[len..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..len].to_string()));
+            rest = &rest[len..];
+        } else if c == '(' || c == ')' || c == ',' {
+            tokens.push(match c {
+                '(' => Token::LParen,
+                ')' => Token::RParen,
+                _ => Token::Comma,
+            });
+            rest = &rest[1..];
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            if *op == "=" {
+                return Err(format!("'=' is not an operator, use '==' in '{}'", source));
+            }
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            return Err(format!("unexpected character '{}' in '{}'", c, source));
+        }
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+fn lex_number(text: &str) -> Result<(Token, usize), String> {
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        let len = hex
+            .find(|c: char| !c.is_ascii_hexdigit())
+            .unwrap_or(hex.len());
+        let value = u64::from_str_radix(&hex[..len], 16)
+            .map_err(|_| format!("invalid hex number '{}'", &text[..len + 2]))?;
+        return Ok((Token::Number(value as f64), len + 2));
+    }
+
+    let len = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let value = text[..len]
+        .parse::<f64>()
+        .map_err(|_| format!("invalid number '{}'", &text[..len]))?;
+    Ok((Token::Number(value), len))
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn eat_op(&mut self, ops: &[&'static str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Op(op)) if ops.contains(op) => {
+                let op = *op;
+                self.pos += 1;
+                Some(op)
+            }
+            _ => None,
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err("formula is nested too deeply".to_string());
+        }
+        let expr = self.or();
+        self.depth -= 1;
+        expr
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.and()?;
+        while self.eat_op(&["||"]).is_some() {
+            lhs = Expr::Binary(BinOp::Or, Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.comparison()?;
+        while self.eat_op(&["&&"]).is_some() {
+            lhs = Expr::Binary(BinOp::And, Box::new(lhs), Box::new(self.comparison()?));
+        }
+        Ok(lhs)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let lhs = self.additive()?;
+        let op = match self.eat_op(&["<", "<=", ">", ">=", "==", "!="]) {
+            Some("<") => BinOp::Lt,
+            Some("<=") => BinOp::Le,
+            Some(">") => BinOp::Gt,
+            Some(">=") => BinOp::Ge,
+            Some("==") => BinOp::Eq,
+            Some(_) => BinOp::Ne,
+            None => return Ok(lhs),
+        };
+        Ok(Expr::Binary(op, Box::new(lhs), Box::new(self.additive()?)))
+    }
+
+    fn additive(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.term()?;
+        while let Some(op) = self.eat_op(&["+", "-"]) {
+            let op = if op == "+" { BinOp::Add } else { BinOp::Sub };
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.term()?));
+        }
+        Ok(lhs)
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        while let Some(op) = self.eat_op(&["*", "/", "%"]) {
+            let op = match op {
+                "*" => BinOp::Mul,
+                "/" => BinOp::Div,
+                _ => BinOp::Rem,
+            };
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.eat_op(&["-", "!"]) {
+            Some(op) => {
+                self.depth += 1;
+                if self.depth > MAX_DEPTH {
+                    return Err("formula is nested too deeply".to_string());
+                }
+                let inner = Box::new(self.unary()?);
+                self.depth -= 1;
+                Ok(if op == "-" {
+                    Expr::Neg(inner)
+                } else {
+                    Expr::Not(inner)
+                })
+            }
+            None => self.power(),
+        }
+    }
+
+    fn power(&mut self) -> Result<Expr, String> {
+        let base = self.primary()?;
+        if self.eat_op(&["^"]).is_some() {
+            // Right-associative: 2^3^2 == 2^(3^2)
+            return Ok(Expr::Binary(
+                BinOp::Pow,
+                Box::new(base),
+                Box::new(self.unary()?),
+            ));
+        }
+        Ok(base)
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next().cloned() {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Ident(name)) => {
+                if self.peek() != Some(&Token::LParen) {
+                    return Ok(Expr::Var(name));
+                }
+                self.pos += 1;
+                let func =
+                    Func::from_name(&name).ok_or_else(|| format!("unknown function '{}'", name))?;
+                let args = self.args()?;
+                let (min, max) = func.arity();
+                if args.len() < min || args.len() > max {
+                    return Err(format!(
+                        "'{}' takes {} argument(s), got {}",
+                        name,
+                        if min == max {
+                            min.to_string()
+                        } else {
+                            format!("at least {}", min)
+                        },
+                        args.len()
+                    ));
+                }
+                Ok(Expr::Call(func, args))
+            }
+            Some(Token::LParen) => {
+                let inner = self.expr()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(inner),
+                    _ => Err("missing ')'".to_string()),
+                }
+            }
+            Some(token) => Err(format!("unexpected '{}'", token)),
+            None => Err("unexpected end of formula".to_string()),
+        }
+    }
+
+    /// Arguments after an opening parenthesis, through the closing one
+    fn args(&mut self) -> Result<Vec<Expr>, String> {
+        let mut args = Vec::new();
+        if self.peek() == Some(&Token::RParen) {
+            self.pos += 1;
+            return Ok(args);
+        }
+        loop {
+            args.push(self.expr()?);
+            match self.next() {
+                Some(Token::Comma) => continue,
+                Some(Token::RParen) => return Ok(args),
+                _ => return Err("missing ')' after arguments".to_string()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval_with(source: &str, vars: &[(&str, f64)]) -> Result<f64, String> {
+        let lookup = |name: &str| vars.iter().find(|(n, _)| *n == name).map(|(_, v)| *v);
+        Formula::parse(source)?.eval(&lookup)
+    }
+
+    #[test]
+    fn test_precedence_and_associativity() {
+        assert_eq!(eval_with("1 + 2 * 3", &[]), Ok(7.0));
+        assert_eq!(eval_with("(1 + 2) * 3", &[]), Ok(9.0));
+        assert_eq!(eval_with("10 - 4 - 3", &[]), Ok(3.0));
+        assert_eq!(eval_with("2 ^ 3 ^ 2", &[]), Ok(512.0));
+        assert_eq!(eval_with("-2 ^ 2", &[]), Ok(-4.0));
+        assert_eq!(eval_with("0x10 + 1", &[]), Ok(17.0));
+    }
+
+    #[test]
+    fn test_byte_formulas() {
+        let vars = [("A", 0x27 as f64), ("B", 0x10 as f64)];
+        assert_eq!(eval_with("(A*256+B) * 0.1", &vars), Ok(1000.0));
+        assert_eq!(eval_with("A - 40", &vars), Ok(-1.0));
+        assert!((eval_with("A * 100 / 255", &vars).unwrap() - 15.29).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_functions_and_logic() {
+        assert_eq!(
+            eval_with("if(N >= 2, A*256+B, A*40)", &[("N", 1.0), ("A", 20.0)]),
+            Ok(800.0)
+        );
+        assert_eq!(eval_with("max(1, 5, 3) + min(2, -1)", &[]), Ok(4.0));
+        assert_eq!(eval_with("clamp(150, 0, 100)", &[]), Ok(100.0));
+        assert_eq!(eval_with("abs(-3) == 3 && !(1 > 2)", &[]), Ok(1.0));
+        // The untaken branch is not evaluated
+        assert_eq!(eval_with("if(1, 2, missing)", &[]), Ok(2.0));
+        assert_eq!(eval_with("0 && missing", &[]), Ok(0.0));
+    }
+
+    #[test]
+    fn test_variables_listed_once() {
+        let formula = Formula::parse("(A*256+B) - A + X").unwrap();
+        assert_eq!(formula.variables(), vec!["A", "B", "X"]);
+        assert_eq!(formula.to_string(), "(A*256+B) - A + X");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(Formula::parse("A +").is_err());
+        assert!(Formula::parse("(A").is_err());
+        assert!(Formula::parse("A = 1").is_err());
+        assert!(Formula::parse("exec(1)").is_err());
+        assert!(Formula::parse("clamp(1, 2)").is_err());
+        assert!(Formula::parse("A $ B").is_err());
+        assert!(Formula::parse(&"(".repeat(100)).is_err());
+        assert!(Formula::parse(&"-".repeat(100)).is_err());
+
+        assert!(eval_with("1 / 0", &[]).is_err());
+        assert!(eval_with("A", &[])
+            .unwrap_err()
+            .contains("unknown variable"));
+    }
+}
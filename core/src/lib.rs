@@ -0,0 +1,15 @@
+//! BMW Diagnostic Core
+//!
+//! Protocol services and data definitions used by both front ends, the
+//! Tauri app (`app/src-tauri`) and the FTDI daemon (`daemon-ftdi`), so they
+//! talk to the ECUs, scale values and report results the same way. Nothing
+//! here does I/O: the front ends pass in their transport.
+
+pub mod alarms;
+pub mod channels;
+pub mod dtc_service;
+pub mod fault_codes;
+pub mod formula;
+pub mod nrc;
+pub mod obd_service;
+pub mod routine;
@@ -0,0 +1,27 @@
+//! Negative response codes
+//!
+//! Text for the NRC of a negative response (0x7F), as the app and daemon
+//! show it in their results.
+
+/// Description of a negative response code
+pub fn description(code: u8) -> &'static str {
+    match code {
+        0x10 => "General reject",
+        0x11 => "Service not supported",
+        0x12 => "Sub-function not supported",
+        0x13 => "Incorrect message length or invalid format",
+        0x21 => "Busy - repeat request",
+        0x22 => "Conditions not correct",
+        0x24 => "Request sequence error",
+        0x31 => "Request out of range",
+        0x33 => "Security access denied",
+        0x35 => "Invalid key",
+        0x36 => "Exceeded number of attempts",
+        0x37 => "Required time delay not expired",
+        0x70 => "Upload/download not accepted",
+        0x71 => "Transfer data suspended",
+        0x72 => "General programming failure",
+        0x7F => "Service not supported in active session",
+        _ => "Unknown error",
+    }
+}
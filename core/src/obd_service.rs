@@ -0,0 +1,758 @@
+//! OBD-II emissions services
+//!
+//! Mode 01 PID 01 (MIL and readiness monitors), Mode 03/07/0A stored,
+//! pending and permanent DTCs and Mode 04 clear, as needed for an
+//! inspection pre-check, plus supported-PID discovery, Mode 02 freeze
+//! frames and Mode 09 vehicle information.
+
+use crate::dtc_service::{is_pending, DtcTransport};
+use serde::Serialize;
+
+/// Mode 01 PID 01: monitor status since DTCs cleared
+const MONITOR_STATUS_REQUEST: [u8; 2] = [0x01, 0x01];
+
+/// Upper bound on responsePending frames for one request
+const MAX_PENDING_FRAMES: usize = 16;
+
+const NEGATIVE_RESPONSE: u8 = 0x7F;
+
+/// Last "PIDs supported" bitmap (0x80 covers 0x81-0xA0)
+const LAST_BITMAP_PID: u8 = 0x80;
+
+/// Mode 02 PID holding the DTC that stored the freeze frame
+const FREEZE_FRAME_DTC_PID: u8 = 0x02;
+
+/// Continuous monitors (byte B bits 0-2, incomplete flags in bits 4-6)
+const CONTINUOUS_MONITORS: [&str; 3] = ["Misfire", "Fuel system", "Components"];
+
+/// Non-continuous monitors of spark ignition engines (bytes C/D bits 0-7)
+const SPARK_MONITORS: [Option<&str>; 8] = [
+    Some("Catalyst"),
+    Some("Heated catalyst"),
+    Some("EVAP system"),
+    Some("Secondary air system"),
+    Some("A/C refrigerant"),
+    Some("Oxygen sensor"),
+    Some("Oxygen sensor heater"),
+    Some("EGR system"),
+];
+
+/// Non-continuous monitors of compression ignition engines (bytes C/D)
+const DIESEL_MONITORS: [Option<&str>; 8] = [
+    Some("NMHC catalyst"),
+    Some("NOx/SCR aftertreatment"),
+    None,
+    Some("Boost pressure"),
+    None,
+    Some("Exhaust gas sensor"),
+    Some("DPF"),
+    Some("EGR/VVT system"),
+];
+
+/// OBD DTC services
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ObdDtcKind {
+    /// Mode 03, confirmed emission related DTCs
+    Stored,
+    /// Mode 07, detected during the current or last drive cycle
+    Pending,
+    /// Mode 0A, cannot be cleared by Mode 04
+    Permanent,
+}
+
+impl ObdDtcKind {
+    pub fn service(self) -> u8 {
+        match self {
+            Self::Stored => 0x03,
+            Self::Pending => 0x07,
+            Self::Permanent => 0x0A,
+        }
+    }
+}
+
+/// One readiness monitor
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReadinessMonitor {
+    pub name: &'static str,
+    /// Whether the vehicle has this monitor
+    pub available: bool,
+    /// Whether the monitor has run to completion since DTCs were cleared
+    pub complete: bool,
+}
+
+/// Decoded Mode 01 PID 01
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MonitorStatus {
+    pub mil_on: bool,
+    /// Number of emission related DTCs
+    pub dtc_count: u8,
+    pub compression_ignition: bool,
+    pub monitors: Vec<ReadinessMonitor>,
+}
+
+impl MonitorStatus {
+    /// Decode the four data bytes A-D (SAE J1979)
+    pub fn decode(data: &[u8]) -> Result<Self, String> {
+        let [a, b, c, d] = data[..] else {
+            return Err(format!("PID 01 needs 4 data bytes, got {}", data.len()));
+        };
+        let compression_ignition = b & 0x08 != 0;
+
+        let mut monitors: Vec<ReadinessMonitor> = CONTINUOUS_MONITORS
+            .iter()
+            .enumerate()
+            .map(|(bit, name)| ReadinessMonitor {
+                name,
+                available: b & (1 << bit) != 0,
+                complete: b & (0x10 << bit) == 0,
+            })
+            .collect();
+
+        let table = if compression_ignition {
+            &DIESEL_MONITORS
+        } else {
+            &SPARK_MONITORS
+        };
+        monitors.extend(table.iter().enumerate().filter_map(|(bit, name)| {
+            Some(ReadinessMonitor {
+                name: (*name)?,
+                available: c & (1 << bit) != 0,
+                complete: d & (1 << bit) == 0,
+            })
+        }));
+
+        Ok(Self {
+            mil_on: a & 0x80 != 0,
+            dtc_count: a & 0x7F,
+            compression_ignition,
+            monitors,
+        })
+    }
+
+    /// Supported monitors that have not completed
+    pub fn incomplete(&self) -> Vec<&'static str> {
+        self.monitors
+            .iter()
+            .filter(|m| m.available && !m.complete)
+            .map(|m| m.name)
+            .collect()
+    }
+}
+
+/// Emissions readiness report
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EmissionsReport {
+    pub status: MonitorStatus,
+    /// MIL off and every supported monitor complete
+    pub ready: bool,
+    pub incomplete: Vec<&'static str>,
+    pub stored: Vec<String>,
+    pub pending: Vec<String>,
+    /// `None` if the ECU predates Mode 0A
+    pub permanent: Option<Vec<String>>,
+}
+
+/// Format a 2-byte OBD DTC as P/C/B/U plus four hex digits
+pub fn format_obd_dtc(code: u16) -> String {
+    let letter = ['P', 'C', 'B', 'U'][(code >> 14) as usize];
+    format!("{}{:04X}", letter, code & 0x3FFF)
+}
+
+/// Parse a Mode 03/07/0A positive response (service ID first)
+///
+/// ISO 15765 responses carry a DTC count before the code pairs, K-Line
+/// responses do not and pad their three pairs with zeros.
+pub fn parse_obd_dtcs(kind: ObdDtcKind, response: &[u8]) -> Result<Vec<String>, String> {
+    match response {
+        [sid, data @ ..] if *sid == kind.service() + 0x40 => {
+            let pairs = if data.len() % 2 == 1 { &data[1..] } else { data };
+            Ok(pairs
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .filter(|code| *code != 0)
+                .map(format_obd_dtc)
+                .collect())
+        }
+        [NEGATIVE_RESPONSE, _, nrc, ..] => Err(format!("Negative response: NRC 0x{:02X}", nrc)),
+        _ => Err(format!("Unexpected response: {:02X?}", response)),
+    }
+}
+
+/// Send a request and wait out responsePending frames
+pub fn exchange(transport: &mut dyn DtcTransport, request: &[u8]) -> Result<Vec<u8>, String> {
+    let mut response = transport.request(request)?;
+    for _ in 0..MAX_PENDING_FRAMES {
+        if !is_pending(&response) {
+            break;
+        }
+        response = transport.receive()?;
+    }
+    Ok(response)
+}
+
+/// Read MIL and readiness monitors (Mode 01 PID 01)
+pub fn read_monitor_status(transport: &mut dyn DtcTransport) -> Result<MonitorStatus, String> {
+    match exchange(transport, &MONITOR_STATUS_REQUEST)?.as_slice() {
+        [0x41, 0x01, data @ ..] => MonitorStatus::decode(data),
+        [NEGATIVE_RESPONSE, _, nrc, ..] => Err(format!("Negative response: NRC 0x{:02X}", nrc)),
+        response => Err(format!("Unexpected response: {:02X?}", response)),
+    }
+}
+
+/// Read stored, pending or permanent emission DTCs
+pub fn read_obd_dtcs(
+    transport: &mut dyn DtcTransport,
+    kind: ObdDtcKind,
+) -> Result<Vec<String>, String> {
+    let response = exchange(transport, &[kind.service()])?;
+    parse_obd_dtcs(kind, &response)
+}
+
+/// Build the full readiness report
+pub fn read_emissions_report(transport: &mut dyn DtcTransport) -> Result<EmissionsReport, String> {
+    let status = read_monitor_status(transport)?;
+    let stored = read_obd_dtcs(transport, ObdDtcKind::Stored)?;
+    let pending = read_obd_dtcs(transport, ObdDtcKind::Pending)?;
+    // Mode 0A is only mandatory from MY2010
+    let permanent = read_obd_dtcs(transport, ObdDtcKind::Permanent).ok();
+
+    let incomplete = status.incomplete();
+    Ok(EmissionsReport {
+        ready: !status.mil_on && incomplete.is_empty(),
+        incomplete,
+        status,
+        stored,
+        pending,
+        permanent,
+    })
+}
+
+/// Clear emission DTCs and reset the readiness monitors (Mode 04)
+pub fn clear_emissions_dtcs(transport: &mut dyn DtcTransport) -> Result<(), String> {
+    match exchange(transport, &[0x04])?.as_slice() {
+        [0x44, ..] => Ok(()),
+        [NEGATIVE_RESPONSE, _, nrc, ..] => Err(format!("Negative response: NRC 0x{:02X}", nrc)),
+        response => Err(format!("Unexpected response: {:02X?}", response)),
+    }
+}
+
+// ============================================================================
+// SUPPORTED PIDS AND FREEZE FRAMES
+// ============================================================================
+
+/// PIDs flagged in a "PIDs supported" bitmap (bit 31 of A is `base + 1`)
+pub fn parse_pid_bitmap(base: u8, bitmap: &[u8]) -> Vec<u8> {
+    bitmap
+        .iter()
+        .take(4)
+        .enumerate()
+        .flat_map(|(byte, bits)| {
+            (0..8u8)
+                .filter(move |bit| bits & (0x80 >> bit) != 0)
+                .map(move |bit| base + byte as u8 * 8 + bit + 1)
+        })
+        .collect()
+}
+
+/// Data PIDs only, without the bitmap PIDs 0x20/0x40/...
+fn without_bitmaps(pids: Vec<u8>) -> Vec<u8> {
+    pids.into_iter().filter(|pid| pid % 0x20 != 0).collect()
+}
+
+/// Walk the bitmaps of Mode 01, or of Mode 02 for one freeze frame
+///
+/// Each bitmap flags whether the next one exists, so the walk stops at the
+/// first range the ECU does not announce.
+fn read_pid_bitmaps(
+    transport: &mut dyn DtcTransport,
+    frame: Option<u8>,
+) -> Result<Vec<u8>, String> {
+    let service = if frame.is_some() { 0x02 } else { 0x01 };
+    let mut supported = Vec::new();
+    let mut base = 0x00;
+
+    loop {
+        let mut request = vec![service, base];
+        request.extend(frame);
+        let response = exchange(transport, &request)?;
+
+        let header = 2 + frame.is_some() as usize;
+        match response.as_slice() {
+            [sid, pid, ..] if *sid == service + 0x40 && *pid == base && response.len() >= header + 4 => {
+                supported.extend(parse_pid_bitmap(base, &response[header..header + 4]));
+            }
+            [NEGATIVE_RESPONSE, _, nrc, ..] if base == 0 => {
+                return Err(format!("Negative response: NRC 0x{:02X}", nrc))
+            }
+            _ if base == 0 => return Err(format!("Unexpected response: {:02X?}", response)),
+            // A later range failing just ends the walk
+            _ => break,
+        }
+
+        if base == LAST_BITMAP_PID || !supported.contains(&(base + 0x20)) {
+            break;
+        }
+        base += 0x20;
+    }
+
+    Ok(supported)
+}
+
+/// Mode 01 PIDs the ECU supports
+pub fn read_supported_pids(transport: &mut dyn DtcTransport) -> Result<Vec<u8>, String> {
+    read_pid_bitmaps(transport, None).map(without_bitmaps)
+}
+
+/// One freeze frame value, raw data bytes A, B, ...
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FreezeFrameValue {
+    pub pid: u8,
+    pub data: Vec<u8>,
+}
+
+/// Mode 02 freeze frame
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FreezeFrame {
+    pub frame: u8,
+    /// DTC that stored the frame, `None` if no frame is stored
+    pub dtc: Option<String>,
+    pub values: Vec<FreezeFrameValue>,
+}
+
+/// Read one Mode 02 PID of a freeze frame
+fn read_freeze_frame_pid(
+    transport: &mut dyn DtcTransport,
+    frame: u8,
+    pid: u8,
+) -> Result<Vec<u8>, String> {
+    match exchange(transport, &[0x02, pid, frame])?.as_slice() {
+        [0x42, echo, echo_frame, data @ ..] if *echo == pid && *echo_frame == frame => {
+            Ok(data.to_vec())
+        }
+        [NEGATIVE_RESPONSE, _, nrc, ..] => Err(format!("Negative response: NRC 0x{:02X}", nrc)),
+        response => Err(format!("Unexpected response: {:02X?}", response)),
+    }
+}
+
+/// Read a freeze frame and the DTC that stored it
+///
+/// Reads the PIDs the ECU announces for the frame, or `fallback_pids`
+/// if it has no Mode 02 bitmap. PIDs the ECU rejects are left out.
+pub fn read_freeze_frame(
+    transport: &mut dyn DtcTransport,
+    frame: u8,
+    fallback_pids: &[u8],
+) -> Result<FreezeFrame, String> {
+    let dtc = read_freeze_frame_pid(transport, frame, FREEZE_FRAME_DTC_PID)?;
+    let dtc = match dtc[..] {
+        [0x00, 0x00] | [] => None,
+        [hi, lo, ..] => Some(format_obd_dtc(u16::from_be_bytes([hi, lo]))),
+        [_] => return Err("Truncated freeze frame DTC".to_string()),
+    };
+    if dtc.is_none() {
+        return Ok(FreezeFrame {
+            frame,
+            dtc,
+            values: Vec::new(),
+        });
+    }
+
+    let pids = match read_pid_bitmaps(transport, Some(frame)) {
+        Ok(pids) => without_bitmaps(pids),
+        Err(_) => fallback_pids.to_vec(),
+    };
+
+    let values = pids
+        .into_iter()
+        .filter(|pid| *pid != FREEZE_FRAME_DTC_PID)
+        .filter_map(|pid| {
+            read_freeze_frame_pid(transport, frame, pid)
+                .ok()
+                .map(|data| FreezeFrameValue { pid, data })
+        })
+        .collect();
+
+    Ok(FreezeFrame { frame, dtc, values })
+}
+
+// ============================================================================
+// MODE 09 VEHICLE INFORMATION
+// ============================================================================
+
+/// Mode 09 information types
+pub mod info_type {
+    pub const VIN: u8 = 0x02;
+    pub const CALIBRATION_ID: u8 = 0x04;
+    pub const CVN: u8 = 0x06;
+    pub const IPT_SPARK: u8 = 0x08;
+    pub const ECU_NAME: u8 = 0x0A;
+    pub const IPT_COMPRESSION: u8 = 0x0B;
+}
+
+/// Monitor counter pairs of in-use performance tracking, after OBDCOND and
+/// IGNCNTR (SAE J1979 InfoType 08/0B)
+const IPT_SPARK_MONITORS: [&str; 8] = [
+    "Catalyst bank 1",
+    "Catalyst bank 2",
+    "Oxygen sensor bank 1",
+    "Oxygen sensor bank 2",
+    "EGR/VVT",
+    "Secondary air",
+    "EVAP",
+    "Secondary oxygen sensor bank 1",
+];
+const IPT_COMPRESSION_MONITORS: [&str; 7] = [
+    "NMHC catalyst",
+    "NOx catalyst",
+    "NOx adsorber",
+    "PM filter",
+    "Exhaust gas sensor",
+    "Boost pressure",
+    "Fuel system",
+];
+
+/// Completion ratio of one monitor in normal driving
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IptMonitor {
+    pub name: &'static str,
+    /// Times the monitor completed
+    pub completions: u16,
+    /// Drive cycles that met its conditions
+    pub conditions: u16,
+    /// `completions / conditions`, `None` before the first qualifying cycle
+    pub ratio: Option<f64>,
+}
+
+/// In-use performance tracking counters
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InUsePerformance {
+    /// Drive cycles meeting the general OBD conditions
+    pub obd_conditions: u16,
+    pub ignition_cycles: u16,
+    pub monitors: Vec<IptMonitor>,
+}
+
+impl InUsePerformance {
+    /// Decode the big-endian counter list of InfoType 08 or 0B
+    pub fn decode(info_type: u8, data: &[u8]) -> Option<Self> {
+        let names: &[&'static str] = match info_type {
+            info_type::IPT_SPARK => &IPT_SPARK_MONITORS,
+            info_type::IPT_COMPRESSION => &IPT_COMPRESSION_MONITORS,
+            _ => return None,
+        };
+        let counters: Vec<u16> = data
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+        let [obd_conditions, ignition_cycles, pairs @ ..] = counters.as_slice() else {
+            return None;
+        };
+
+        let monitors = names
+            .iter()
+            .zip(pairs.chunks_exact(2))
+            .map(|(name, pair)| IptMonitor {
+                name,
+                completions: pair[0],
+                conditions: pair[1],
+                ratio: (pair[1] > 0).then(|| pair[0] as f64 / pair[1] as f64),
+            })
+            .collect();
+
+        Some(Self {
+            obd_conditions: *obd_conditions,
+            ignition_cycles: *ignition_cycles,
+            monitors,
+        })
+    }
+}
+
+/// Mode 09 vehicle information; fields the ECU does not support stay empty
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ObdVehicleInfo {
+    pub vin: Option<String>,
+    /// Calibration IDs, one per software module
+    pub calibration_ids: Vec<String>,
+    /// Calibration verification numbers, hex, in CALID order
+    pub cvns: Vec<String>,
+    pub ecu_name: Option<String>,
+    pub in_use_performance: Option<InUsePerformance>,
+}
+
+/// K-Line message count InfoType for an InfoType, if it has one
+///
+/// ISO 9141/14230 split each item into 4-byte messages and announce how
+/// many follow; ISO 15765 sends the item in one response.
+fn message_count_type(info_type: u8) -> Option<u8> {
+    match info_type {
+        info_type::VIN
+        | info_type::CALIBRATION_ID
+        | info_type::CVN
+        | info_type::IPT_SPARK
+        | info_type::ECU_NAME => Some(info_type - 1),
+        _ => None,
+    }
+}
+
+/// Read one InfoType and join its messages into the data bytes
+///
+/// Every message is `[0x49, type, n, data..]`, where `n` is the message
+/// number (K-Line) or the number of data items (CAN).
+pub fn read_info_type(transport: &mut dyn DtcTransport, info_type: u8) -> Result<Vec<u8>, String> {
+    let messages = message_count_type(info_type)
+        .and_then(|count_type| match exchange(transport, &[0x09, count_type]) {
+            Ok(response) => match response.as_slice() {
+                [0x49, echo, count, ..] if *echo == count_type => Some(*count as usize),
+                _ => None,
+            },
+            Err(_) => None,
+        })
+        .unwrap_or(1);
+
+    let mut data = Vec::new();
+    for n in 0..messages.clamp(1, MAX_PENDING_FRAMES) {
+        let response = if n == 0 {
+            exchange(transport, &[0x09, info_type])?
+        } else {
+            transport.receive()?
+        };
+        match response.as_slice() {
+            [0x49, echo, _, payload @ ..] if *echo == info_type => data.extend_from_slice(payload),
+            [NEGATIVE_RESPONSE, _, nrc, ..] => {
+                return Err(format!("Negative response: NRC 0x{:02X}", nrc))
+            }
+            _ => return Err(format!("Unexpected response: {:02X?}", response)),
+        }
+    }
+    Ok(data)
+}
+
+/// ASCII text with NUL padding and fill bytes removed
+fn ascii_text(data: &[u8]) -> String {
+    data.iter()
+        .filter(|b| b.is_ascii_graphic() || **b == b' ')
+        .map(|b| *b as char)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Decode a VIN (K-Line pads the first message with three zero bytes)
+pub fn decode_vin(data: &[u8]) -> Option<String> {
+    let vin = ascii_text(data);
+    (!vin.is_empty()).then_some(vin)
+}
+
+/// Split calibration IDs, 16 bytes each
+pub fn decode_calibration_ids(data: &[u8]) -> Vec<String> {
+    data.chunks(16)
+        .map(ascii_text)
+        .filter(|id| !id.is_empty())
+        .collect()
+}
+
+/// Split CVNs, 4 bytes each, as hex
+pub fn decode_cvns(data: &[u8]) -> Vec<String> {
+    data.chunks_exact(4)
+        .map(|cvn| cvn.iter().map(|b| format!("{:02X}", b)).collect())
+        .collect()
+}
+
+/// Read VIN, calibration IDs, CVNs, ECU name and in-use performance
+///
+/// Unsupported InfoTypes are skipped; fails only if none can be read.
+pub fn read_vehicle_info(transport: &mut dyn DtcTransport) -> Result<ObdVehicleInfo, String> {
+    let vin = read_info_type(transport, info_type::VIN);
+    let calibration_ids = read_info_type(transport, info_type::CALIBRATION_ID);
+    let cvns = read_info_type(transport, info_type::CVN);
+    let ecu_name = read_info_type(transport, info_type::ECU_NAME);
+
+    // Spark ignition ECUs answer 08, compression ignition ones 0B
+    let in_use_performance = [info_type::IPT_SPARK, info_type::IPT_COMPRESSION]
+        .into_iter()
+        .find_map(|ipt| {
+            read_info_type(transport, ipt)
+                .ok()
+                .and_then(|data| InUsePerformance::decode(ipt, &data))
+        });
+
+    if [&vin, &calibration_ids, &cvns, &ecu_name]
+        .iter()
+        .all(|result| result.is_err())
+        && in_use_performance.is_none()
+    {
+        return Err(vin.err().unwrap_or_default());
+    }
+
+    Ok(ObdVehicleInfo {
+        vin: vin.ok().and_then(|data| decode_vin(&data)),
+        calibration_ids: calibration_ids
+            .map(|data| decode_calibration_ids(&data))
+            .unwrap_or_default(),
+        cvns: cvns.map(|data| decode_cvns(&data)).unwrap_or_default(),
+        ecu_name: ecu_name.ok().map(|data| ascii_text(&data)).filter(|n| !n.is_empty()),
+        in_use_performance,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    struct Replay(VecDeque<Vec<u8>>);
+
+    impl DtcTransport for Replay {
+        fn request(&mut self, _data: &[u8]) -> Result<Vec<u8>, String> {
+            self.receive()
+        }
+
+        fn receive(&mut self) -> Result<Vec<u8>, String> {
+            self.0.pop_front().ok_or_else(|| "No response received".to_string())
+        }
+    }
+
+    #[test]
+    fn test_decode_diesel_monitor_status() {
+        // MIL on, 2 DTCs; diesel; all continuous available, components
+        // incomplete; NMHC, EGR and DPF available, DPF incomplete
+        let status = MonitorStatus::decode(&[0x82, 0x4F, 0xC1, 0x40]).unwrap();
+        assert!(status.mil_on);
+        assert_eq!(status.dtc_count, 2);
+        assert!(status.compression_ignition);
+        assert_eq!(status.incomplete(), vec!["Components", "DPF"]);
+        assert!(status.monitors.iter().all(|m| m.name != "EVAP system"));
+    }
+
+    #[test]
+    fn test_decode_spark_monitor_status() {
+        let status = MonitorStatus::decode(&[0x00, 0x07, 0x65, 0x04]).unwrap();
+        assert!(!status.compression_ignition);
+        assert_eq!(status.incomplete(), vec!["EVAP system"]);
+        assert!(MonitorStatus::decode(&[0x00, 0x07]).is_err());
+    }
+
+    #[test]
+    fn test_parse_obd_dtcs_both_layouts() {
+        // K-Line: three pairs, zero padded
+        let kline = [0x43, 0x01, 0x33, 0x00, 0x00, 0x00, 0x00];
+        assert_eq!(parse_obd_dtcs(ObdDtcKind::Stored, &kline).unwrap(), vec!["P0133"]);
+
+        // CAN: count byte first
+        let can = [0x47, 0x02, 0x04, 0x01, 0xC1, 0x00];
+        assert_eq!(
+            parse_obd_dtcs(ObdDtcKind::Pending, &can).unwrap(),
+            vec!["P0401", "U0100"]
+        );
+
+        assert!(parse_obd_dtcs(ObdDtcKind::Permanent, &[0x7F, 0x0A, 0x11]).is_err());
+    }
+
+    #[test]
+    fn test_emissions_report() {
+        let mut transport = Replay(
+            [
+                vec![0x41, 0x01, 0x00, 0x0F, 0xC1, 0x00],
+                vec![0x7F, 0x03, 0x78],
+                vec![0x43, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+                vec![0x47, 0x02, 0x44, 0x00, 0x00, 0x00, 0x00],
+                vec![0x7F, 0x0A, 0x11],
+            ]
+            .into(),
+        );
+
+        let report = read_emissions_report(&mut transport).unwrap();
+        assert!(report.ready);
+        assert!(report.stored.is_empty());
+        assert_eq!(report.pending, vec!["P0244"]);
+        assert_eq!(report.permanent, None);
+    }
+
+    #[test]
+    fn test_parse_pid_bitmap() {
+        // 0xBE1FA813: 01 03-07 0C-10 11 13 15 1C 1F 20
+        let pids = parse_pid_bitmap(0x00, &[0xBE, 0x1F, 0xA8, 0x13]);
+        assert_eq!(
+            pids,
+            vec![0x01, 0x03, 0x04, 0x05, 0x06, 0x07, 0x0C, 0x0D, 0x0E, 0x0F, 0x10, 0x11, 0x13, 0x15, 0x1C, 0x1F, 0x20]
+        );
+        assert_eq!(parse_pid_bitmap(0x40, &[0x80, 0x00, 0x00, 0x01]), vec![0x41, 0x60]);
+    }
+
+    #[test]
+    fn test_read_supported_pids_follows_bitmaps() {
+        let mut transport = Replay(
+            [
+                vec![0x41, 0x00, 0x18, 0x00, 0x00, 0x01],
+                vec![0x41, 0x20, 0x00, 0x00, 0x20, 0x00],
+            ]
+            .into(),
+        );
+        // 0x20 announces the next range, 0x40 (bit 0 of the second) does not
+        assert_eq!(read_supported_pids(&mut transport).unwrap(), vec![0x04, 0x05, 0x33]);
+    }
+
+    #[test]
+    fn test_read_freeze_frame() {
+        let mut transport = Replay(
+            [
+                vec![0x42, 0x02, 0x00, 0x04, 0x01],
+                vec![0x7F, 0x02, 0x12],
+                vec![0x42, 0x05, 0x00, 0x7B],
+                vec![0x7F, 0x02, 0x31],
+            ]
+            .into(),
+        );
+        let frame = read_freeze_frame(&mut transport, 0, &[0x05, 0x0C]).unwrap();
+        assert_eq!(frame.dtc.as_deref(), Some("P0401"));
+        assert_eq!(
+            frame.values,
+            vec![FreezeFrameValue { pid: 0x05, data: vec![0x7B] }]
+        );
+
+        // No frame stored
+        let mut transport = Replay([vec![0x42, 0x02, 0x00, 0x00, 0x00]].into());
+        let frame = read_freeze_frame(&mut transport, 0, &[0x05]).unwrap();
+        assert_eq!(frame.dtc, None);
+        assert!(frame.values.is_empty());
+    }
+
+    #[test]
+    fn test_read_info_type_kline_messages() {
+        // VIN in five K-Line messages, first one zero padded
+        let vin = b"\0\0\0WBANF71060B123456";
+        let mut frames: VecDeque<Vec<u8>> = [vec![0x49, 0x01, 0x05]].into();
+        for (n, chunk) in vin.chunks(4).enumerate() {
+            let mut frame = vec![0x49, 0x02, n as u8 + 1];
+            frame.extend_from_slice(chunk);
+            frames.push_back(frame);
+        }
+
+        let data = read_info_type(&mut Replay(frames), info_type::VIN).unwrap();
+        assert_eq!(decode_vin(&data).as_deref(), Some("WBANF71060B123456"));
+    }
+
+    #[test]
+    fn test_read_info_type_can_single_response() {
+        let mut frame = vec![0x49, 0x04, 0x02];
+        frame.extend_from_slice(b"7797576\0\0\0\0\0\0\0\0\0");
+        frame.extend_from_slice(b"DDE7.1 0A14\0\0\0\0\0");
+        let mut transport = Replay([vec![0x7F, 0x09, 0x12], frame].into());
+
+        let data = read_info_type(&mut transport, info_type::CALIBRATION_ID).unwrap();
+        assert_eq!(decode_calibration_ids(&data), vec!["7797576", "DDE7.1 0A14"]);
+        assert_eq!(decode_cvns(&[0x1A, 0x2B, 0x3C, 0x4D]), vec!["1A2B3C4D"]);
+    }
+
+    #[test]
+    fn test_decode_in_use_performance() {
+        let data = [0x00, 0x64, 0x01, 0x2C, 0x00, 0x32, 0x00, 0x64, 0x00, 0x00, 0x00, 0x00];
+        let ipt = InUsePerformance::decode(info_type::IPT_COMPRESSION, &data).unwrap();
+        assert_eq!(ipt.obd_conditions, 100);
+        assert_eq!(ipt.ignition_cycles, 300);
+        assert_eq!(ipt.monitors[0].ratio, Some(0.5));
+        assert_eq!(ipt.monitors[1].ratio, None);
+        assert!(InUsePerformance::decode(info_type::VIN, &data).is_none());
+    }
+}
@@ -0,0 +1,87 @@
+//! RoutineControl (0x31) results
+//!
+//! Status text and data of a routine's answer, so the app and daemon report
+//! routines (DPF regeneration, lamp test, adaptation resets) the same way.
+
+use crate::nrc;
+
+const POSITIVE_RESPONSE: u8 = 0x71;
+const NEGATIVE_RESPONSE: u8 = 0x7F;
+
+/// Outcome of one RoutineControl request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutineStatus {
+    pub success: bool,
+    /// "OK", the NRC text or why the answer was not understood
+    pub status: String,
+    /// Routine status record after the routine ID
+    pub data: Vec<u8>,
+}
+
+impl RoutineStatus {
+    /// Interpret the answer to a RoutineControl request, service ID first
+    pub fn from_response(response: &[u8]) -> Self {
+        match response {
+            [POSITIVE_RESPONSE, ..] => Self {
+                success: true,
+                status: "OK".to_string(),
+                data: response.get(3..).unwrap_or_default().to_vec(),
+            },
+            [NEGATIVE_RESPONSE, ..] => {
+                let nrc = response.get(2).copied().unwrap_or(0);
+                Self::rejected(format!("{} (0x{:02X})", nrc::description(nrc), nrc))
+            }
+            _ => Self::rejected(format!("Unexpected: {:02X?}", response)),
+        }
+    }
+
+    /// The request itself failed, e.g. no answer from the ECU
+    pub fn failed(error: &str) -> Self {
+        Self::rejected(format!("Failed: {}", error))
+    }
+
+    fn rejected(status: String) -> Self {
+        Self {
+            success: false,
+            status,
+            data: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_positive_response() {
+        let result = RoutineStatus::from_response(&[0x71, 0xA0, 0x94, 0x02, 0x5A]);
+        assert!(result.success);
+        assert_eq!(result.status, "OK");
+        assert_eq!(result.data, vec![0x02, 0x5A]);
+    }
+
+    #[test]
+    fn test_negative_response() {
+        // Conditions not correct, e.g. engine not running for a regen
+        let result = RoutineStatus::from_response(&[0x7F, 0x31, 0x22]);
+        assert!(!result.success);
+        assert_eq!(result.status, "Conditions not correct (0x22)");
+        assert!(result.data.is_empty());
+    }
+
+    #[test]
+    fn test_unexpected_response() {
+        let result = RoutineStatus::from_response(&[0x62, 0xF0, 0x01]);
+        assert!(!result.success);
+        assert_eq!(result.status, "Unexpected: [62, F0, 01]");
+    }
+
+    #[test]
+    fn test_failed_request() {
+        let result = RoutineStatus::failed("No response received");
+        assert!(!result.success);
+        assert_eq!(result.status, "Failed: No response received");
+        assert!(result.data.is_empty());
+    }
+}
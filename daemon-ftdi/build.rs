@@ -5,8 +5,13 @@
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
 
+    // Built without D2XX: the serial port backend needs no extra library
+    if std::env::var_os("CARGO_FEATURE_D2XX").is_none() {
+        return;
+    }
+
     // Check if libftd2xx is installed on Linux
-    let lib_paths = vec![
+    let lib_paths = [
         "/usr/local/lib/libftd2xx.so",
         "/usr/lib/libftd2xx.so",
         "/usr/lib/x86_64-linux-gnu/libftd2xx.so",
@@ -22,6 +27,8 @@ fn main() {
         println!("cargo:warning=  1. Download from: https://ftdichip.com/drivers/d2xx-drivers/");
         println!("cargo:warning=  2. Extract and copy libftd2xx.so to /usr/local/lib/");
         println!("cargo:warning=  3. Run: sudo ldconfig");
+        println!("cargo:warning=");
+        println!("cargo:warning=Or build without it: --no-default-features");
         println!("cargo:warning==============================================");
     }
 }
@@ -0,0 +1,180 @@
+//! Live Data Alarms
+//!
+//! The alarm rules of the Tauri app (`bmw_diag_core::alarms`), checked
+//! against the values clients poll (`read_pid(s)`, `read_bmw_pid(s)`,
+//! `read_engine_data`). Raising and clearing are pushed to every client as
+//! `alarm` events. A rule's channel is a channel key
+//! (`COOLANT_TEMPERATURE`), `PID_05`, or `LID_xx` for a DME local
+//! identifier.
+//!
+//! The daemon keeps no settings: clients send the rules with
+//! `set_alarm_rules`, and they last until the daemon exits.
+
+use crate::channels::{self, CHANNEL_DB};
+use anyhow::{anyhow, Result};
+use bmw_diag_core::alarms::{self, AlarmRule, AlarmState, Severity, Watch};
+use serde::Serialize;
+
+/// Data of an `alarm` event
+#[derive(Debug, Clone, Serialize)]
+pub struct AlarmEvent {
+    pub rule: String,
+    pub name: String,
+    pub severity: Severity,
+    pub state: AlarmState,
+    pub channel: String,
+    pub value: f64,
+    pub unit: String,
+    pub threshold: f64,
+    /// Value furthest past the threshold since it was exceeded
+    pub peak: f64,
+    /// When the threshold was first exceeded, ms since the Unix epoch
+    pub since: u64,
+    pub timestamp: u64,
+}
+
+/// Rules and their state
+#[derive(Debug, Default)]
+pub struct AlarmWatch {
+    watches: Vec<Watch>,
+}
+
+impl AlarmWatch {
+    /// Replace the rules; alarms raised before start over
+    pub fn set_rules(&mut self, rules: Vec<AlarmRule>) -> Result<()> {
+        alarms::validate(&rules).map_err(|e| anyhow!(e))?;
+        self.watches = rules.into_iter().map(Watch::new).collect();
+        Ok(())
+    }
+
+    pub fn rules(&self) -> Vec<AlarmRule> {
+        self.watches.iter().map(|w| w.rule().clone()).collect()
+    }
+
+    /// Check a value known by `names`; returns the alarms raised or cleared
+    pub fn update(
+        &mut self,
+        names: &[String],
+        value: f64,
+        unit: &str,
+        timestamp: u64,
+    ) -> Vec<AlarmEvent> {
+        let mut events = Vec::new();
+        for watch in &mut self.watches {
+            if !names.contains(&watch.rule().channel) {
+                continue;
+            }
+            if let Some(state) = watch.step(timestamp, value) {
+                let rule = watch.rule();
+                events.push(AlarmEvent {
+                    rule: rule.key.clone(),
+                    name: rule.name.clone(),
+                    severity: rule.severity,
+                    state,
+                    channel: rule.channel.clone(),
+                    value,
+                    unit: unit.to_string(),
+                    threshold: rule.threshold,
+                    peak: watch.peak(),
+                    since: watch.since(),
+                    timestamp,
+                });
+            }
+        }
+        events
+    }
+}
+
+/// Names of a value read with `service`: its channel key, then the
+/// generic `PID_xx` / `LID_xx`
+fn names(ecu: &str, service: u8, id: u8, generic: String) -> Vec<String> {
+    let db = CHANNEL_DB.read().unwrap_or_else(|e| e.into_inner());
+    db.get(ecu, service, id as u16)
+        .map(|channel| channel.def.key.clone())
+        .into_iter()
+        .chain(Some(generic))
+        .collect()
+}
+
+/// Names of an OBD-II Mode 01 PID
+pub fn pid_names(pid: u8) -> Vec<String> {
+    names(
+        channels::OBD,
+        channels::SERVICE_MODE_01,
+        pid,
+        format!("PID_{:02X}", pid),
+    )
+}
+
+/// Names of a DME local identifier
+pub fn local_id_names(id: u8) -> Vec<String> {
+    names(
+        channels::DME,
+        channels::SERVICE_LOCAL_ID,
+        id,
+        format!("LID_{:02X}", id),
+    )
+}
+
+/// Milliseconds since the Unix epoch
+pub fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bmw_diag_core::alarms::Condition;
+
+    fn coolant_rule() -> AlarmRule {
+        AlarmRule {
+            key: "COOLANT_HIGH".to_string(),
+            name: "Coolant temperature high".to_string(),
+            channel: "PID_05".to_string(),
+            condition: Condition::Above,
+            threshold: 110.0,
+            hysteresis: 5.0,
+            min_duration_ms: 1000,
+            severity: Severity::Critical,
+        }
+    }
+
+    #[test]
+    fn test_rule_raises_and_clears() {
+        let mut watch = AlarmWatch::default();
+        watch.set_rules(vec![coolant_rule()]).unwrap();
+        let names = pid_names(0x05);
+        assert!(names.contains(&"PID_05".to_string()));
+
+        assert!(watch.update(&names, 112.0, "°C", 0).is_empty());
+        // Other channels are not checked against the rule
+        assert!(watch
+            .update(&pid_names(0x0C), 3000.0, "rpm", 500)
+            .is_empty());
+
+        let raised = watch.update(&names, 115.0, "°C", 1000);
+        assert_eq!(raised.len(), 1);
+        assert_eq!(raised[0].state, AlarmState::Raised);
+        assert_eq!((raised[0].since, raised[0].peak), (0, 115.0));
+
+        assert!(watch.update(&names, 107.0, "°C", 2000).is_empty());
+        let cleared = watch.update(&names, 105.0, "°C", 3000);
+        assert_eq!(cleared[0].state, AlarmState::Cleared);
+    }
+
+    #[test]
+    fn test_invalid_rules_are_rejected() {
+        let mut watch = AlarmWatch::default();
+        assert!(watch
+            .set_rules(vec![coolant_rule(), coolant_rule()])
+            .is_err());
+
+        let mut rule = coolant_rule();
+        rule.hysteresis = -1.0;
+        assert!(watch.set_rules(vec![rule]).is_err());
+        assert!(watch.rules().is_empty());
+    }
+}
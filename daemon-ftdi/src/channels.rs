@@ -0,0 +1,61 @@
+//! Live Data Channel Definitions
+//!
+//! Scaling for OBD-II PIDs and BMW local identifiers with the channel
+//! tables of `bmw_diag_core::channels`, the same files the Tauri app reads:
+//! the bundled tables plus JSON files from `$BMW_DIAG_CHANNELS` (default
+//! `/usr/share/bmw-diag/channels`).
+
+use bmw_diag_core::channels::ChannelDb;
+use std::path::Path;
+use std::sync::{LazyLock, RwLock};
+use tracing::warn;
+
+pub use bmw_diag_core::channels::{DME, EGS, OBD, SERVICE_LOCAL_ID, SERVICE_MODE_01};
+
+/// Default directory for extra channel files
+pub const DEFAULT_DIR: &str = "/usr/share/bmw-diag/channels";
+
+/// Global database, bundled tables plus the configured directory
+pub static CHANNEL_DB: LazyLock<RwLock<ChannelDb>> = LazyLock::new(|| {
+    let mut db = ChannelDb::bundled();
+    let dir = std::env::var("BMW_DIAG_CHANNELS").unwrap_or_else(|_| DEFAULT_DIR.to_string());
+    if Path::new(&dir).is_dir() {
+        if let Err(e) = db.load_dir(Path::new(&dir)) {
+            warn!("Failed to load channel definitions from {}: {}", dir, e);
+        }
+    }
+    RwLock::new(db)
+});
+
+/// Value and unit of an identifier with the global database
+///
+/// Unknown identifiers give the first byte with unit `raw`; data too short
+/// for a known channel gives 0.
+pub fn scale(ecu: &str, service: u8, id: u8, data: &[u8]) -> (f64, String) {
+    let db = CHANNEL_DB.read().unwrap_or_else(|e| e.into_inner());
+    match db.get(ecu, service, id as u16) {
+        Some(channel) => (
+            channel.decode(data).unwrap_or(0.0),
+            channel.def.unit.clone(),
+        ),
+        None => (data.first().copied().unwrap_or(0) as f64, "raw".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scale_known_and_unknown_identifiers() {
+        assert_eq!(
+            scale(OBD, SERVICE_MODE_01, 0x0C, &[0x0C, 0x80]),
+            (800.0, "rpm".to_string())
+        );
+        assert_eq!(scale(DME, SERVICE_LOCAL_ID, 0x20, &[]).0, 0.0);
+        assert_eq!(
+            scale(DME, SERVICE_LOCAL_ID, 0xEE, &[0x42]),
+            (66.0, "raw".to_string())
+        );
+    }
+}
@@ -0,0 +1,39 @@
+//! BMW Fault Text Database
+//!
+//! Fault texts of `bmw_diag_core::fault_codes`, the same files the Tauri
+//! app reads: the bundled set plus JSON/CSV files from `$BMW_DIAG_FAULT_DB`
+//! (default `/usr/share/bmw-diag/fault-codes`).
+
+use bmw_diag_core::fault_codes::{FaultCode, FaultCodeDb};
+use std::path::Path;
+use std::sync::{LazyLock, RwLock};
+use tracing::warn;
+
+/// Default directory for extra fault files
+pub const DEFAULT_DIR: &str = "/usr/share/bmw-diag/fault-codes";
+
+/// Global database, bundled texts plus the configured directory
+pub static FAULT_DB: LazyLock<RwLock<FaultCodeDb>> = LazyLock::new(|| {
+    let mut db = FaultCodeDb::bundled();
+    let dir = std::env::var("BMW_DIAG_FAULT_DB").unwrap_or_else(|_| DEFAULT_DIR.to_string());
+    if Path::new(&dir).is_dir() {
+        if let Err(e) = db.load_dir(Path::new(&dir)) {
+            warn!("Failed to load fault texts from {}: {}", dir, e);
+        }
+    }
+    RwLock::new(db)
+});
+
+/// Fault text fields for one DTC, merged into the `read_dtcs` response
+pub fn describe(ecus: &[&str], variant: Option<&str>, code: FaultCode) -> serde_json::Value {
+    let db = FAULT_DB.read().unwrap_or_else(|e| e.into_inner());
+    let entry = db.lookup(ecus, variant, code);
+
+    serde_json::json!({
+        "bmw_code": code.to_string(),
+        "sae_code": entry.and_then(|e| e.sae.clone()),
+        "description": entry.and_then(|e| e.text.preferred()),
+        "texts": entry.map(|e| &e.text),
+        "fault_variant": entry.map(|e| &e.variant)
+    })
+}
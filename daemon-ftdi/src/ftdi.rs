@@ -3,7 +3,8 @@
 //! Provides low-level access to FTDI chips for precise timing control.
 //! Uses D2XX drivers instead of VCP for microsecond-level timing.
 
-use anyhow::{anyhow, Result};
+use crate::transport::{delay_ms, Backend, TimingPrecision, Transport};
+use anyhow::Result;
 use libftd2xx::{Ftdi, FtdiCommon, list_devices as ftdi_list, BitMode};
 use std::time::{Duration, Instant};
 use std::thread;
@@ -22,6 +23,8 @@ pub struct FtdiConnection {
     device: Ftdi,
     baud_rate: u32,
     connected: bool,
+    last_break_us: Option<u64>,
+    last_5baud_us: Option<u64>,
 }
 
 /// List all available FTDI devices
@@ -62,6 +65,8 @@ impl FtdiConnection {
             device,
             baud_rate: 10400,
             connected: true,
+            last_break_us: None,
+            last_5baud_us: None,
         })
     }
 
@@ -79,6 +84,8 @@ impl FtdiConnection {
             device,
             baud_rate: 10400,
             connected: true,
+            last_break_us: None,
+            last_5baud_us: None,
         })
     }
 
@@ -90,28 +97,6 @@ impl FtdiConnection {
         Ok(())
     }
 
-    /// Configure for K-Line communication (10400 baud, 8N1)
-    pub fn configure_kline(&mut self) -> Result<()> {
-        info!("Configuring for K-Line (10400 baud, 8N1)");
-
-        self.set_baud_rate(10400)?;
-
-        // 8 data bits, 1 stop bit, no parity
-        self.device.set_data_characteristics(
-            libftd2xx::BitsPerWord::Bits8,
-            libftd2xx::StopBits::Bits1,
-            libftd2xx::Parity::No,
-        )?;
-
-        // No flow control - use the specific method
-        self.device.set_flow_control_none()?;
-
-        // Set latency timer to minimum (1ms) for fastest response
-        self.device.set_latency_timer(Duration::from_millis(1))?;
-
-        Ok(())
-    }
-
     /// Configure for D-CAN communication (500 kbaud)
     ///
     /// **WARNING: D-CAN is NOT fully implemented!**
@@ -144,15 +129,49 @@ impl FtdiConnection {
         Ok(())
     }
 
+    /// Check if connected
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Get current baud rate
+    pub fn baud_rate(&self) -> u32 {
+        self.baud_rate
+    }
+}
+
+impl Transport for FtdiConnection {
+    /// Configure for K-Line communication (10400 baud, 8N1)
+    fn configure_kline(&mut self) -> Result<()> {
+        info!("Configuring for K-Line (10400 baud, 8N1)");
+
+        self.set_baud_rate(10400)?;
+
+        // 8 data bits, 1 stop bit, no parity
+        self.device.set_data_characteristics(
+            libftd2xx::BitsPerWord::Bits8,
+            libftd2xx::StopBits::Bits1,
+            libftd2xx::Parity::No,
+        )?;
+
+        // No flow control - use the specific method
+        self.device.set_flow_control_none()?;
+
+        // Set latency timer to minimum (1ms) for fastest response
+        self.device.set_latency_timer(Duration::from_millis(1))?;
+
+        Ok(())
+    }
+
     /// Write bytes with precise timing
-    pub fn write(&mut self, data: &[u8]) -> Result<usize> {
+    fn write(&mut self, data: &[u8]) -> Result<usize> {
         debug!("TX: {:02X?}", data);
         let written = self.device.write(data)?;
         Ok(written)
     }
 
     /// Read bytes with timeout
-    pub fn read(&mut self, buffer: &mut [u8], timeout_ms: u64) -> Result<usize> {
+    fn read(&mut self, buffer: &mut [u8], timeout_ms: u64) -> Result<usize> {
         let start = Instant::now();
         let timeout = Duration::from_millis(timeout_ms);
         let mut total_read = 0;
@@ -177,67 +196,15 @@ impl FtdiConnection {
         Ok(total_read)
     }
 
-    /// Read exact number of bytes with timeout
-    pub fn read_exact(&mut self, length: usize, timeout_ms: u64) -> Result<Vec<u8>> {
-        let mut buffer = vec![0u8; length];
-        let read = self.read(&mut buffer, timeout_ms)?;
-
-        if read < length {
-            return Err(anyhow!(
-                "Timeout: expected {} bytes, got {}",
-                length,
-                read
-            ));
-        }
-
-        Ok(buffer)
-    }
-
     /// Purge RX and TX buffers
-    pub fn purge(&mut self) -> Result<()> {
+    fn purge(&mut self) -> Result<()> {
         self.device.purge_all()?;
         Ok(())
     }
 
-    /// High-precision delay for microseconds
-    /// Uses thread::sleep for bulk of delay, spin-wait only for final precision
-    pub fn delay_us(us: u64) {
-        let start = Instant::now();
-        let target = Duration::from_micros(us);
-
-        // For delays > 2ms, use sleep for most of it to save CPU
-        if us > 2000 {
-            let sleep_time = Duration::from_micros(us.saturating_sub(1000));
-            std::thread::sleep(sleep_time);
-        }
-
-        // Spin-wait for final precision (last ~1ms or less)
-        while start.elapsed() < target {
-            std::hint::spin_loop();
-        }
-    }
-
-    /// High-precision delay for milliseconds
-    /// Uses thread::sleep for bulk of delay, spin-wait only for final precision
-    pub fn delay_ms(ms: u64) {
-        let start = Instant::now();
-        let target = Duration::from_millis(ms);
-
-        // For delays > 2ms, sleep for most of it
-        if ms > 2 {
-            let sleep_time = Duration::from_millis(ms.saturating_sub(1));
-            std::thread::sleep(sleep_time);
-        }
-
-        // Spin-wait for final millisecond precision
-        while start.elapsed() < target {
-            std::hint::spin_loop();
-        }
-    }
-
     /// Send byte at 5 baud (for K-Line slow init per ISO 9141-2)
     /// Format: START + 7 data bits + ODD PARITY + STOP = 10 bits at 200ms each
-    pub fn send_5baud(&mut self, byte: u8) -> Result<()> {
+    fn send_5baud(&mut self, byte: u8) -> Result<()> {
         info!("Sending 0x{:02X} at 5 baud (ISO 9141-2 format)...", byte);
 
         // At 5 baud, each bit takes 200ms (1/5 = 0.2s = 200ms)
@@ -256,25 +223,27 @@ impl FtdiConnection {
         // Set to asynchronous bit-bang mode
         // Mask 0x01 = only TXD pin is output
         self.device.set_bit_mode(0x01, BitMode::AsyncBitbang)?;
+        let start = Instant::now();
 
         // Start bit (LOW)
         self.device.write(&[0x00])?;
-        Self::delay_ms(200);
+        delay_ms(200);
 
         // 7 Data bits (LSB first) - bits 0-6 only
         for i in 0..7 {
             let bit = (data_bits >> i) & 0x01;
             self.device.write(&[bit])?;
-            Self::delay_ms(200);
+            delay_ms(200);
         }
 
         // Parity bit (odd parity)
         self.device.write(&[parity_bit])?;
-        Self::delay_ms(200);
+        delay_ms(200);
 
         // Stop bit (HIGH)
         self.device.write(&[0x01])?;
-        Self::delay_ms(200);
+        delay_ms(200);
+        self.last_5baud_us = Some(start.elapsed().as_micros() as u64);
 
         // Return to normal UART mode (reset bit mode)
         self.device.set_bit_mode(0x00, BitMode::Reset)?;
@@ -287,16 +256,37 @@ impl FtdiConnection {
     }
 
     /// Break signal for fast init
-    pub fn send_break(&mut self, duration_ms: u64) -> Result<()> {
+    fn send_break(&mut self, duration_ms: u64) -> Result<()> {
         debug!("Sending break signal for {}ms", duration_ms);
+        let start = Instant::now();
         self.device.set_break_on()?;
-        Self::delay_ms(duration_ms);
+        delay_ms(duration_ms);
         self.device.set_break_off()?;
+        self.last_break_us = Some(start.elapsed().as_micros() as u64);
         Ok(())
     }
 
+    /// Probe the device to detect a USB hot-unplug
+    ///
+    /// D2XX keeps the handle valid after the cable is pulled, but every
+    /// call on it fails with FT_IO_ERROR / FT_DEVICE_NOT_FOUND. A queue
+    /// status query is the cheapest call that touches the hardware.
+    fn is_alive(&mut self) -> bool {
+        if !self.connected {
+            return false;
+        }
+        match self.device.queue_status() {
+            Ok(_) => true,
+            Err(e) => {
+                warn!("FTDI device not responding: {}", e);
+                self.connected = false;
+                false
+            }
+        }
+    }
+
     /// Close the connection
-    pub fn close(&mut self) -> Result<()> {
+    fn close(&mut self) -> Result<()> {
         if self.connected {
             info!("Closing FTDI connection");
             self.device.close()?;
@@ -305,14 +295,19 @@ impl FtdiConnection {
         Ok(())
     }
 
-    /// Check if connected
-    pub fn is_connected(&self) -> bool {
-        self.connected
-    }
-
-    /// Get current baud rate
-    pub fn baud_rate(&self) -> u32 {
-        self.baud_rate
+    fn timing_precision(&self) -> TimingPrecision {
+        TimingPrecision {
+            backend: Backend::D2xx,
+            // Full-speed USB: line changes go out on the next 1ms frame
+            transition_resolution_us: 1000,
+            rx_latency_ms: Some(1),
+            last_break_us: self.last_break_us,
+            last_5baud_us: self.last_5baud_us,
+            notes: vec![
+                "5-baud init is bit-banged on TXD in async bitbang mode".to_string(),
+                "Latency timer set to 1ms".to_string(),
+            ],
+        }
     }
 }
 
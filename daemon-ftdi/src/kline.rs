@@ -3,15 +3,20 @@
 //! Implements ISO 9141-2 and ISO 14230 (KWP2000) initialization
 //! with microsecond-level timing precision.
 
-use crate::ftdi::FtdiConnection;
 use crate::kwp2000::{KwpMessage, KwpResponse};
+use crate::metrics::{RequestResult, METRICS};
+use crate::transport::{delay_ms, Transport};
 use anyhow::{anyhow, Result};
+use bmw_diag_core::dtc_service::{
+    self, ClearSummary, DtcDialect, DtcGroup, DtcReadout, DtcTransport,
+};
+use bmw_diag_core::obd_service::{self, EmissionsReport, FreezeFrame, ObdVehicleInfo};
 use std::time::Instant;
 use tracing::{debug, info, warn};
 
 /// K-Line protocol handler
 pub struct KLine {
-    ftdi: FtdiConnection,
+    transport: Box<dyn Transport>,
     ecu_address: u8,
     tester_address: u8,
     initialized: bool,
@@ -35,6 +40,7 @@ pub enum EcuAddress {
     DSC = 0x44,    // Stability Control - K-Line pin 8
     KOMBI = 0x60,  // Instrument Cluster - K-Line pin 8
     IHKA = 0x5B,   // Climate Control - K-Line pin 8
+    FRM = 0x68,    // Footwell Module (lights) - via gateway, see note above
 }
 
 impl From<EcuAddress> for u8 {
@@ -43,6 +49,21 @@ impl From<EcuAddress> for u8 {
     }
 }
 
+/// ECU ids (as used by the fault text database) at a K-Line address
+///
+/// DME and DDE share 0x12, so petrol and diesel texts are both candidates.
+pub fn ecu_ids(address: u8) -> &'static [&'static str] {
+    match address {
+        0x12 => &["DME", "DDE"],
+        0x18 => &["EGS"],
+        0x44 => &["DSC"],
+        0x5B => &["IHKA"],
+        0x60 => &["KOMBI"],
+        0x68 => &["FRM"],
+        _ => &[],
+    }
+}
+
 /// Initialization result
 #[derive(Debug)]
 pub struct InitResult {
@@ -54,9 +75,9 @@ pub struct InitResult {
 
 impl KLine {
     /// Create new K-Line handler
-    pub fn new(ftdi: FtdiConnection) -> Self {
+    pub fn new(transport: Box<dyn Transport>) -> Self {
         Self {
-            ftdi,
+            transport,
             ecu_address: 0x12, // Default to DME
             tester_address: 0xF1,
             initialized: false,
@@ -81,17 +102,24 @@ impl KLine {
     /// 4. Send inverted KB2
     /// 5. Receive inverted address (0xCC = ~0x33)
     pub fn init_5baud(&mut self, address: u8) -> Result<InitResult> {
+        let start = Instant::now();
+        let result = self.slow_init_sequence(address);
+        METRICS.record_init("5baud", matches!(result, Ok(ref r) if r.success), start.elapsed());
+        result
+    }
+
+    fn slow_init_sequence(&mut self, address: u8) -> Result<InitResult> {
         info!("Starting 5-baud initialization for ECU 0x{:02X}", address);
         self.ecu_address = address;
 
         // Ensure K-Line configuration
-        self.ftdi.configure_kline()?;
-        self.ftdi.purge()?;
+        self.transport.configure_kline()?;
+        self.transport.purge()?;
 
         // Step 1: Send functional address 0x33 at 5 baud
         // ISO 14230-2 slow init ALWAYS uses 0x33, not the physical ECU address
         const INIT_ADDRESS: u8 = 0x33;
-        self.ftdi.send_5baud(INIT_ADDRESS)?;
+        self.transport.send_5baud(INIT_ADDRESS)?;
 
         // Step 2: Wait for sync byte (0x55) - ECU sends this at 10400 baud
         // Wait up to 400ms for response (extended for slower ECUs)
@@ -100,7 +128,7 @@ impl KLine {
 
         while start.elapsed().as_millis() < 400 {
             let mut buf = [0u8; 1];
-            if self.ftdi.read(&mut buf, 50)? > 0 {
+            if self.transport.read(&mut buf, 50)? > 0 {
                 if buf[0] == 0x55 {
                     sync_received = true;
                     debug!("Received sync byte 0x55");
@@ -121,39 +149,40 @@ impl KLine {
 
         // Step 3: Receive key bytes (KB1, KB2)
         // W1 timing: 5-20ms between bytes
-        FtdiConnection::delay_ms(5);
+        delay_ms(5);
 
-        let kb1 = self.ftdi.read_exact(1, 50)?[0];
+        let kb1 = self.transport.read_exact(1, 50)?[0];
         debug!("Received KB1: 0x{:02X}", kb1);
 
-        FtdiConnection::delay_ms(5);
+        delay_ms(5);
 
-        let kb2 = self.ftdi.read_exact(1, 50)?[0];
+        let kb2 = self.transport.read_exact(1, 50)?[0];
         debug!("Received KB2: 0x{:02X}", kb2);
 
         self.key_bytes = Some([kb1, kb2]);
 
         // Step 4: Send inverted KB2
         // W4 timing: 25-50ms after receiving KB2
-        FtdiConnection::delay_ms(25);
+        delay_ms(25);
 
         let inverted_kb2 = !kb2;
         debug!("Sending inverted KB2: 0x{:02X}", inverted_kb2);
-        self.ftdi.write(&[inverted_kb2])?;
+        self.transport.write(&[inverted_kb2])?;
 
         // Read back our own echo (K-Line is half-duplex)
         let mut echo = [0u8; 1];
-        if let Ok(n) = self.ftdi.read(&mut echo, 20) {
+        if let Ok(n) = self.transport.read(&mut echo, 20) {
             if n > 0 && echo[0] != inverted_kb2 {
                 warn!("Echo mismatch: sent 0x{:02X}, got 0x{:02X}", inverted_kb2, echo[0]);
+                METRICS.record_echo_mismatch();
             }
         }
 
         // Step 5: Receive inverted init address (~0x33 = 0xCC)
         // W4 timing: 25-50ms
-        FtdiConnection::delay_ms(25);
+        delay_ms(25);
 
-        let response = self.ftdi.read_exact(1, 100)?[0];
+        let response = self.transport.read_exact(1, 100)?[0];
         let expected = !INIT_ADDRESS; // 0xCC
 
         if response != expected {
@@ -188,32 +217,40 @@ impl KLine {
     /// 3. Send StartCommunication request (0x81)
     /// 4. Receive positive response (0xC1)
     pub fn init_fast(&mut self, address: u8) -> Result<InitResult> {
+        let start = Instant::now();
+        let result = self.fast_init_sequence(address);
+        METRICS.record_init("fast", matches!(result, Ok(ref r) if r.success), start.elapsed());
+        result
+    }
+
+    fn fast_init_sequence(&mut self, address: u8) -> Result<InitResult> {
         info!("Starting fast initialization for ECU 0x{:02X}", address);
         self.ecu_address = address;
 
         // Ensure K-Line configuration
-        self.ftdi.configure_kline()?;
-        self.ftdi.purge()?;
+        self.transport.configure_kline()?;
+        self.transport.purge()?;
 
         // Step 1: Send 30ms break (TiniL)
         // ISO 14230 specifies TiniL = 25-50ms, using 30ms for better compatibility
-        self.ftdi.send_break(30)?;
+        self.transport.send_break(30)?;
 
         // Step 2: Wait 25ms (TWup - Wake-up time)
-        FtdiConnection::delay_ms(25);
+        delay_ms(25);
 
         // Step 3: Send StartCommunication (0x81)
         let start_comm = KwpMessage::new(self.tester_address, address, vec![0x81]);
         let bytes = start_comm.to_bytes();
 
         debug!("TX StartCommunication: {:02X?}", bytes);
-        self.ftdi.write(&bytes)?;
+        self.transport.write(&bytes)?;
 
         // Read back our own transmission (K-Line is half-duplex)
         let mut echo = vec![0u8; bytes.len()];
-        if let Ok(n) = self.ftdi.read(&mut echo, 100) {
+        if let Ok(n) = self.transport.read(&mut echo, 100) {
             if n > 0 && echo[..n] != bytes[..n.min(bytes.len())] {
                 warn!("Echo mismatch in fast init");
+                METRICS.record_echo_mismatch();
             }
         }
 
@@ -222,7 +259,7 @@ impl KLine {
 
         // Read response
         let mut response_buf = vec![0u8; 32];
-        let read = self.ftdi.read(&mut response_buf, 200)?;
+        let read = self.transport.read(&mut response_buf, 200)?;
 
         if read == 0 {
             warn!("No response to StartCommunication");
@@ -296,7 +333,7 @@ impl KLine {
             if elapsed < effective_p3min {
                 let wait_time = effective_p3min - elapsed;
                 debug!("P3min: waiting {}ms before next request", wait_time);
-                FtdiConnection::delay_ms(wait_time);
+                delay_ms(wait_time);
             }
         }
 
@@ -310,23 +347,24 @@ impl KLine {
         debug!("TX: {:02X?}", bytes);
 
         // Purge any stale data
-        self.ftdi.purge()?;
+        self.transport.purge()?;
 
         // Send request
         let start = Instant::now();
-        self.ftdi.write(&bytes)?;
+        self.transport.write(&bytes)?;
 
         // Read back echo (K-Line half-duplex)
         let mut echo = vec![0u8; bytes.len()];
-        if let Ok(n) = self.ftdi.read(&mut echo, 50) {
+        if let Ok(n) = self.transport.read(&mut echo, 50) {
             if n > 0 && echo[..n] != bytes[..n] {
                 warn!("Echo mismatch in send_request");
+                METRICS.record_echo_mismatch();
             }
         }
 
         // Read response with timeout (P2 timing handled by ECU)
         let mut response_buf = vec![0u8; 256];
-        let read = self.ftdi.read(&mut response_buf, 500)?;
+        let read = self.transport.read(&mut response_buf, 500)?;
 
         // Record completion time for P3min calculation
         self.last_request_time = Some(Instant::now());
@@ -335,14 +373,45 @@ impl KLine {
         debug!("Response received in {}ms", latency);
 
         if read == 0 {
+            METRICS.record_request(service, RequestResult::Timeout, start.elapsed());
             return Err(anyhow!("No response from ECU (timeout)"));
         }
 
         let response_data = &response_buf[..read];
         debug!("RX: {:02X?}", response_data);
 
-        KwpResponse::parse(response_data)
-            .ok_or_else(|| anyhow!("Failed to parse response"))
+        let Some(response) = KwpResponse::parse(response_data) else {
+            METRICS.record_request(service, RequestResult::Error, start.elapsed());
+            return Err(anyhow!("Failed to parse response"));
+        };
+
+        if response.is_negative() {
+            METRICS.record_request(service, RequestResult::Negative, start.elapsed());
+            if let Some(nrc) = response.error_code() {
+                METRICS.record_nrc(service, nrc);
+            }
+        } else {
+            METRICS.record_request(service, RequestResult::Positive, start.elapsed());
+        }
+
+        Ok(response)
+    }
+
+    /// Receive one further response without sending a request
+    ///
+    /// For responsePending and answers split over several messages.
+    pub fn receive_response(&mut self) -> Result<KwpResponse> {
+        let mut response_buf = vec![0u8; 256];
+        let read = self.transport.read(&mut response_buf, 500)?;
+        self.last_request_time = Some(Instant::now());
+
+        if read == 0 {
+            return Err(anyhow!("No response from ECU (timeout)"));
+        }
+
+        let response_data = &response_buf[..read];
+        debug!("RX: {:02X?}", response_data);
+        KwpResponse::parse(response_data).ok_or_else(|| anyhow!("Failed to parse response"))
     }
 
     /// Send TesterPresent to keep connection alive
@@ -372,52 +441,61 @@ impl KLine {
         }
     }
 
-    /// Read DTCs (Diagnostic Trouble Codes)
-    pub fn read_dtcs(&mut self) -> Result<Vec<(u16, u8)>> {
-        // Service 0x18 = ReadDTCByStatus
-        // Sub-function 0x00 = Report DTC by status mask
-        // Status mask 0xFF = All DTCs
-        let response = self.send_request(0x18, &[0x00, 0xFF])?;
-
-        if response.service != 0x58 {
-            if response.service == 0x7F {
-                return Err(anyhow!(
-                    "Negative response: 0x{:02X}",
-                    response.data.get(1).copied().unwrap_or(0)
-                ));
-            }
-            return Err(anyhow!("Unexpected response service: 0x{:02X}", response.service));
+    /// Send StopCommunication (0x82) to end the diagnostic session
+    /// Returns Ok(true) if the ECU acknowledged with 0xC2
+    pub fn stop_communication(&mut self) -> Result<bool> {
+        if !self.initialized {
+            return Ok(false);
         }
 
-        // Parse DTCs from response
-        // Format: [count, high1, low1, status1, high2, low2, status2, ...]
-        let mut dtcs = Vec::new();
-        let data = &response.data;
-
-        if data.len() >= 2 {
-            let mut i = 1; // Skip count byte
-            while i + 2 < data.len() {
-                let high = data[i];
-                let low = data[i + 1];
-                let status = data[i + 2];
-
-                if high != 0 || low != 0 {
-                    let dtc_code = ((high as u16) << 8) | (low as u16);
-                    dtcs.push((dtc_code, status));
-                }
+        let response = self.send_request(0x82, &[])?;
+        self.initialized = false;
+        self.key_bytes = None;
 
-                i += 3;
-            }
+        let success = response.service == 0xC2;
+        if !success {
+            warn!("StopCommunication: unexpected response 0x{:02X}", response.service);
         }
+        Ok(success)
+    }
 
-        Ok(dtcs)
+    /// Read DTCs (Diagnostic Trouble Codes)
+    ///
+    /// KWP2000 ReadDTCByStatus, falling back to UDS if the ECU rejects it.
+    pub fn read_dtcs(&mut self) -> Result<DtcReadout> {
+        dtc_service::read_dtcs(self, DtcDialect::for_transport(true)).map_err(|e| anyhow!(e))
+    }
+
+    /// Clear all DTCs, then re-read to report which faults came back
+    pub fn clear_dtcs(&mut self) -> Result<ClearSummary> {
+        dtc_service::clear_and_verify(self, DtcDialect::for_transport(true), DtcGroup::All)
+            .map_err(|e| anyhow!(e))
     }
 
-    /// Clear DTCs
-    pub fn clear_dtcs(&mut self) -> Result<bool> {
-        // Service 0x14 = ClearDiagnosticInformation
-        let response = self.send_request(0x14, &[0xFF, 0x00])?;
-        Ok(response.service == 0x54)
+    /// Read the OBD-II emissions readiness report (MIL, monitors, Mode 03/07/0A)
+    pub fn read_emissions_report(&mut self) -> Result<EmissionsReport> {
+        obd_service::read_emissions_report(self).map_err(|e| anyhow!(e))
+    }
+
+    /// Clear emission DTCs and reset readiness monitors (OBD Mode 04)
+    pub fn clear_emissions_dtcs(&mut self) -> Result<()> {
+        obd_service::clear_emissions_dtcs(self).map_err(|e| anyhow!(e))
+    }
+
+    /// Mode 01 PIDs the ECU announces in its supported-PID bitmaps
+    pub fn read_supported_pids(&mut self) -> Result<Vec<u8>> {
+        obd_service::read_supported_pids(self).map_err(|e| anyhow!(e))
+    }
+
+    /// Read a Mode 02 freeze frame; `fallback_pids` are tried if the ECU
+    /// has no Mode 02 bitmap
+    pub fn read_freeze_frame(&mut self, frame: u8, fallback_pids: &[u8]) -> Result<FreezeFrame> {
+        obd_service::read_freeze_frame(self, frame, fallback_pids).map_err(|e| anyhow!(e))
+    }
+
+    /// Read OBD-II Mode 09 vehicle information (VIN, CALID, CVN, ECU name, IPT)
+    pub fn read_vehicle_info(&mut self) -> Result<ObdVehicleInfo> {
+        obd_service::read_vehicle_info(self).map_err(|e| anyhow!(e))
     }
 
     /// Read OBD-II standard PID (Service 0x01 - Request Current Powertrain Data)
@@ -474,9 +552,14 @@ impl KLine {
         self.initialized
     }
 
-    /// Get connection reference
-    pub fn ftdi(&mut self) -> &mut FtdiConnection {
-        &mut self.ftdi
+    /// Get the address of the ECU the line was last initialized to
+    pub fn ecu_address(&self) -> u8 {
+        self.ecu_address
+    }
+
+    /// Get transport reference
+    pub fn transport(&mut self) -> &mut dyn Transport {
+        self.transport.as_mut()
     }
 }
 
@@ -532,3 +615,25 @@ impl From<u8> for DtcStatus {
         }
     }
 }
+
+impl DtcTransport for KLine {
+    fn request(&mut self, data: &[u8]) -> std::result::Result<Vec<u8>, String> {
+        let (&service, params) = data.split_first().ok_or_else(|| "Empty request".to_string())?;
+        self.send_request(service, params)
+            .map(response_bytes)
+            .map_err(|e| e.to_string())
+    }
+
+    fn receive(&mut self) -> std::result::Result<Vec<u8>, String> {
+        self.receive_response()
+            .map(response_bytes)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Response as service ID followed by data
+fn response_bytes(response: KwpResponse) -> Vec<u8> {
+    let mut bytes = vec![response.service];
+    bytes.extend(response.data);
+    bytes
+}
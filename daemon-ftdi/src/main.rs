@@ -3,13 +3,24 @@
 //! This daemon provides microsecond-level timing control for K-Line
 //! communication with BMW ECUs using FTDI D2XX direct drivers.
 
+mod alarms;
+mod channels;
+#[cfg(feature = "d2xx")]
 mod ftdi;
+mod fault_codes;
 mod kline;
 mod kwp2000;
+mod metrics;
+mod serial;
+mod services;
+mod supervisor;
+mod transport;
 mod websocket;
 
 use anyhow::Result;
-use tracing::{info, Level};
+use std::sync::Arc;
+use tokio::sync::{broadcast, Mutex};
+use tracing::{info, warn, Level};
 use tracing_subscriber::FmtSubscriber;
 
 #[tokio::main]
@@ -28,27 +39,77 @@ async fn main() -> Result<()> {
     println!();
 
     // List available FTDI devices
-    info!("Scanning for FTDI devices...");
-    let devices = ftdi::list_devices()?;
+    #[cfg(feature = "d2xx")]
+    let devices = {
+        info!("Scanning for FTDI devices...");
+        ftdi::list_devices().unwrap_or_else(|e| {
+            warn!("D2XX unavailable ({}), falling back to serial ports", e);
+            Vec::new()
+        })
+    };
+    #[cfg(not(feature = "d2xx"))]
+    let devices: Vec<()> = Vec::new();
 
-    if devices.is_empty() {
-        println!("⚠️  No FTDI devices found!");
+    // ftdi_sio-bound or non-FTDI cables show up as tty devices instead
+    info!("Scanning for serial ports...");
+    let ports = serial::list_ports().unwrap_or_else(|e| {
+        warn!("Failed to list serial ports: {}", e);
+        Vec::new()
+    });
+
+    if devices.is_empty() && ports.is_empty() {
+        println!("⚠️  No FTDI devices or serial ports found!");
         println!("   Make sure your K+DCAN cable is connected.");
         println!("   Install FTDI D2XX drivers from: https://ftdichip.com/drivers/d2xx-drivers/");
         return Ok(());
     }
 
-    println!("Found {} FTDI device(s):", devices.len());
-    for (i, dev) in devices.iter().enumerate() {
-        println!("  [{}] {} - {}", i, dev.description, dev.serial_number);
+    #[cfg(feature = "d2xx")]
+    if !devices.is_empty() {
+        println!("Found {} FTDI device(s):", devices.len());
+        for (i, dev) in devices.iter().enumerate() {
+            println!("  [{}] {} - {}", i, dev.description, dev.serial_number);
+        }
+    }
+    if !ports.is_empty() {
+        println!("Found {} serial port(s):", ports.len());
+        for port in &ports {
+            println!(
+                "  {} - {} {}",
+                port.path,
+                port.description,
+                port.serial_number.as_deref().unwrap_or("")
+            );
+        }
     }
     println!();
 
+    let state: websocket::SharedState = Arc::new(Mutex::new(websocket::AppState::new()));
+    let (events, _) = broadcast::channel(16);
+
+    // Watch for USB hot-unplug and reconnect by serial number
+    tokio::spawn(supervisor::run(Arc::clone(&state), events.clone()));
+
+    // Prometheus scrape endpoint, on its own port so scrapes bypass the WS limits
+    let metrics_addr =
+        std::env::var("BMW_DIAG_METRICS_ADDR").unwrap_or_else(|_| metrics::DEFAULT_ADDR.to_string());
+    tokio::spawn(async move {
+        if let Err(e) = metrics::run_server(&metrics_addr, websocket::active_connections).await {
+            warn!("Metrics endpoint unavailable: {}", e);
+        }
+    });
+
     // Start WebSocket server
     let port = 3003;
     info!("Starting WebSocket server on port {}...", port);
 
-    websocket::run_server(port).await?;
+    tokio::select! {
+        result = websocket::run_server(port, Arc::clone(&state), events.clone()) => result?,
+        _ = supervisor::shutdown_signal() => {
+            info!("Shutting down...");
+            supervisor::shutdown(state, events).await;
+        }
+    }
 
     Ok(())
 }
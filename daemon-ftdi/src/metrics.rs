@@ -0,0 +1,367 @@
+//! Prometheus Metrics
+//!
+//! Link health counters for bench rigs: KWP2000 requests by service and
+//! result, NRCs, timeouts, echo mismatches, latencies and init success
+//! per method. Served in the Prometheus text format on `/metrics`.
+
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info, warn};
+
+/// Default listen address, override with `BMW_DIAG_METRICS_ADDR`
+pub const DEFAULT_ADDR: &str = "127.0.0.1:3004";
+
+/// Histogram bucket upper bounds in seconds
+///
+/// K-Line round trips are 20-600ms, 5-baud init alone takes over 2s.
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Outcome of a KWP2000 request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RequestResult {
+    Positive,
+    Negative,
+    Timeout,
+    Error,
+}
+
+impl RequestResult {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Positive => "positive",
+            Self::Negative => "negative",
+            Self::Timeout => "timeout",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// Cumulative histogram for one label set
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        for (count, bound) in self.buckets.iter().zip(BUCKETS) {
+            let _ = writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, bound, count);
+        }
+        let _ = writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, self.count);
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, self.count);
+    }
+}
+
+/// All daemon metrics
+///
+/// Label sets are small and bounded (service IDs, NRCs, command names
+/// that passed deserialization), so plain maps behind a mutex are enough.
+#[derive(Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<(u8, RequestResult), u64>>,
+    request_latency: Mutex<BTreeMap<u8, Histogram>>,
+    nrcs: Mutex<BTreeMap<(u8, u8), u64>>,
+    echo_mismatches: AtomicU64,
+    inits: Mutex<BTreeMap<(&'static str, bool), u64>>,
+    init_latency: Mutex<BTreeMap<&'static str, Histogram>>,
+    commands: Mutex<BTreeMap<(String, bool), u64>>,
+    command_latency: Mutex<BTreeMap<String, Histogram>>,
+    rate_limited: AtomicU64,
+    connections_rejected: AtomicU64,
+}
+
+/// Global registry
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+impl Metrics {
+    /// Record a completed KWP2000 request (`service` is the request SID)
+    pub fn record_request(&self, service: u8, result: RequestResult, elapsed: Duration) {
+        *self.requests.lock().unwrap().entry((service, result)).or_default() += 1;
+        if result != RequestResult::Timeout {
+            self.request_latency
+                .lock()
+                .unwrap()
+                .entry(service)
+                .or_default()
+                .observe(elapsed.as_secs_f64());
+        }
+    }
+
+    /// Record a negative response code
+    pub fn record_nrc(&self, service: u8, nrc: u8) {
+        *self.nrcs.lock().unwrap().entry((service, nrc)).or_default() += 1;
+    }
+
+    /// Our own echo on the half-duplex line did not match what we sent
+    pub fn record_echo_mismatch(&self) {
+        self.echo_mismatches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record an init attempt (`method` is "fast" or "5baud")
+    pub fn record_init(&self, method: &'static str, success: bool, elapsed: Duration) {
+        *self.inits.lock().unwrap().entry((method, success)).or_default() += 1;
+        self.init_latency
+            .lock()
+            .unwrap()
+            .entry(method)
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Record a WebSocket command and its reported latency
+    pub fn record_command(&self, command: &str, success: bool, latency_us: Option<u64>) {
+        *self
+            .commands
+            .lock()
+            .unwrap()
+            .entry((command.to_string(), success))
+            .or_default() += 1;
+
+        if let Some(us) = latency_us {
+            self.command_latency
+                .lock()
+                .unwrap()
+                .entry(command.to_string())
+                .or_default()
+                .observe(us as f64 / 1_000_000.0);
+        }
+    }
+
+    pub fn record_rate_limited(&self) {
+        self.rate_limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_connection_rejected(&self) {
+        self.connections_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Render everything in the Prometheus text exposition format
+    pub fn render(&self, active_connections: usize) -> String {
+        let mut out = String::new();
+
+        header(&mut out, "bmw_kwp_requests_total", "counter", "KWP2000 requests by service and result");
+        for ((service, result), count) in self.requests.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "bmw_kwp_requests_total{{service=\"0x{:02X}\",result=\"{}\"}} {}",
+                service,
+                result.as_str(),
+                count
+            );
+        }
+
+        let timeouts: u64 = self
+            .requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|((_, result), _)| *result == RequestResult::Timeout)
+            .map(|(_, count)| count)
+            .sum();
+        header(&mut out, "bmw_kwp_timeouts_total", "counter", "KWP2000 requests without any ECU response");
+        let _ = writeln!(out, "bmw_kwp_timeouts_total {}", timeouts);
+
+        header(&mut out, "bmw_kwp_request_duration_seconds", "histogram", "KWP2000 request to response time");
+        for (service, histogram) in self.request_latency.lock().unwrap().iter() {
+            histogram.render(
+                &mut out,
+                "bmw_kwp_request_duration_seconds",
+                &format!("service=\"0x{:02X}\"", service),
+            );
+        }
+
+        header(&mut out, "bmw_kwp_negative_responses_total", "counter", "Negative response codes by service and NRC");
+        for ((service, nrc), count) in self.nrcs.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "bmw_kwp_negative_responses_total{{service=\"0x{:02X}\",nrc=\"0x{:02X}\"}} {}",
+                service, nrc, count
+            );
+        }
+
+        header(&mut out, "bmw_kline_echo_mismatches_total", "counter", "Half-duplex echo did not match the transmitted bytes");
+        let _ = writeln!(
+            out,
+            "bmw_kline_echo_mismatches_total {}",
+            self.echo_mismatches.load(Ordering::Relaxed)
+        );
+
+        header(&mut out, "bmw_kline_inits_total", "counter", "ECU initializations by method and result");
+        for ((method, success), count) in self.inits.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "bmw_kline_inits_total{{method=\"{}\",result=\"{}\"}} {}",
+                method,
+                if *success { "success" } else { "failure" },
+                count
+            );
+        }
+
+        header(&mut out, "bmw_kline_init_duration_seconds", "histogram", "ECU initialization time by method");
+        for (method, histogram) in self.init_latency.lock().unwrap().iter() {
+            histogram.render(
+                &mut out,
+                "bmw_kline_init_duration_seconds",
+                &format!("method=\"{}\"", method),
+            );
+        }
+
+        header(&mut out, "bmw_ws_commands_total", "counter", "WebSocket commands by name and result");
+        for ((command, success), count) in self.commands.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "bmw_ws_commands_total{{command=\"{}\",result=\"{}\"}} {}",
+                command,
+                if *success { "success" } else { "error" },
+                count
+            );
+        }
+
+        header(&mut out, "bmw_ws_command_duration_seconds", "histogram", "Latency reported to clients (latency_us)");
+        for (command, histogram) in self.command_latency.lock().unwrap().iter() {
+            histogram.render(
+                &mut out,
+                "bmw_ws_command_duration_seconds",
+                &format!("command=\"{}\"", command),
+            );
+        }
+
+        header(&mut out, "bmw_ws_active_connections", "gauge", "Open WebSocket connections");
+        let _ = writeln!(out, "bmw_ws_active_connections {}", active_connections);
+
+        header(&mut out, "bmw_ws_rate_limited_total", "counter", "Commands rejected by the per-connection rate limit");
+        let _ = writeln!(
+            out,
+            "bmw_ws_rate_limited_total {}",
+            self.rate_limited.load(Ordering::Relaxed)
+        );
+
+        header(&mut out, "bmw_ws_connections_rejected_total", "counter", "Connections rejected at the connection limit");
+        let _ = writeln!(
+            out,
+            "bmw_ws_connections_rejected_total {}",
+            self.connections_rejected.load(Ordering::Relaxed)
+        );
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Serve `GET /metrics` over plain HTTP
+///
+/// Kept off the WebSocket port so scrapes never count against the
+/// connection limit or the rate limit.
+pub async fn run_server(addr: &str, active_connections: fn() -> usize) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Metrics available on http://{}/metrics", addr);
+
+    while let Ok((stream, peer)) = listener.accept().await {
+        tokio::spawn(async move {
+            if let Err(e) = handle_scrape(stream, active_connections).await {
+                debug!("Metrics request from {} failed: {}", peer, e);
+            }
+        });
+    }
+
+    warn!("Metrics listener stopped");
+    Ok(())
+}
+
+async fn handle_scrape(mut stream: TcpStream, active_connections: fn() -> usize) -> Result<()> {
+    // Only the request line matters; cap what we read from a client
+    let mut buf = vec![0u8; 1024];
+    let mut len = 0;
+    let read = async {
+        while len < buf.len() && !buf[..len].windows(4).any(|w| w == b"\r\n\r\n") {
+            let n = stream.read(&mut buf[len..]).await?;
+            if n == 0 {
+                break;
+            }
+            len += n;
+        }
+        Ok::<_, std::io::Error>(())
+    };
+    tokio::time::timeout(Duration::from_secs(5), read).await??;
+
+    let request = String::from_utf8_lossy(&buf[..len]);
+    let mut parts = request.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => (
+            "200 OK",
+            "text/plain; version=0.0.4",
+            METRICS.render(active_connections()),
+        ),
+        _ => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let mut histogram = Histogram::default();
+        histogram.observe(0.004);
+        histogram.observe(0.2);
+        histogram.observe(20.0);
+
+        assert_eq!(histogram.buckets[0], 1); // le=0.005
+        assert_eq!(histogram.buckets[5], 2); // le=0.25
+        assert_eq!(histogram.buckets[BUCKETS.len() - 1], 2); // le=10
+        assert_eq!(histogram.count, 3);
+    }
+
+    #[test]
+    fn test_render_counts_timeouts_and_nrcs() {
+        let metrics = Metrics::default();
+        metrics.record_request(0x31, RequestResult::Negative, Duration::from_millis(40));
+        metrics.record_nrc(0x31, 0x22);
+        metrics.record_request(0x22, RequestResult::Timeout, Duration::from_millis(550));
+        metrics.record_init("fast", true, Duration::from_millis(120));
+
+        let text = metrics.render(2);
+        assert!(text.contains("bmw_kwp_requests_total{service=\"0x31\",result=\"negative\"} 1"));
+        assert!(text.contains("bmw_kwp_negative_responses_total{service=\"0x31\",nrc=\"0x22\"} 1"));
+        assert!(text.contains("bmw_kwp_timeouts_total 1"));
+        assert!(text.contains("bmw_kline_inits_total{method=\"fast\",result=\"success\"} 1"));
+        assert!(text.contains("bmw_ws_active_connections 2"));
+        // Timeouts carry no latency sample
+        assert!(!text.contains("bmw_kwp_request_duration_seconds_count{service=\"0x22\"}"));
+    }
+}
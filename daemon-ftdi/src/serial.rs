@@ -0,0 +1,370 @@
+//! Serial Port (termios) Backend
+//!
+//! Fallback transport over the kernel tty driver (`/dev/ttyUSB*`,
+//! `/dev/ttyACM*`) for when D2XX cannot claim the device, e.g. because
+//! `ftdi_sio` is bound, or when the cable is not FTDI-based at all.
+//!
+//! Timing is less precise than D2XX: break and DTR are driven through
+//! ioctls, and on FTDI chips RX latency is bounded by the `ftdi_sio`
+//! latency timer (16ms by default). We try to lower it to 1ms and report
+//! what we actually got through `timing_precision()`.
+
+use crate::transport::{delay_ms, five_baud_bits, Backend, TimingPrecision, Transport};
+use anyhow::{anyhow, Result};
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, SerialPortType, StopBits};
+use std::io::{ErrorKind, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// K-Line baud rate
+const KLINE_BAUD: u32 = 10400;
+
+/// Serial port information
+#[derive(Debug, Clone)]
+pub struct SerialPortInfo {
+    pub path: String,
+    pub description: String,
+    pub serial_number: Option<String>,
+}
+
+/// List USB serial ports that could carry a K-Line cable
+pub fn list_ports() -> Result<Vec<SerialPortInfo>> {
+    let ports = serialport::available_ports()?;
+
+    Ok(ports
+        .into_iter()
+        .filter(|p| p.port_name.contains("ttyUSB") || p.port_name.contains("ttyACM"))
+        .map(|p| {
+            let (description, serial_number) = match p.port_type {
+                SerialPortType::UsbPort(usb) => (
+                    usb.product
+                        .unwrap_or_else(|| format!("USB {:04X}:{:04X}", usb.vid, usb.pid)),
+                    usb.serial_number,
+                ),
+                _ => ("Serial port".to_string(), None),
+            };
+            SerialPortInfo {
+                path: p.port_name,
+                description,
+                serial_number,
+            }
+        })
+        .collect())
+}
+
+/// K-Line connection over a tty device
+pub struct SerialConnection {
+    port: Option<Box<dyn SerialPort>>,
+    path: String,
+    /// ftdi_sio latency timer in ms, None if not an FTDI chip
+    latency_timer_ms: Option<u64>,
+    last_break_us: Option<u64>,
+    last_5baud_us: Option<u64>,
+}
+
+impl SerialConnection {
+    /// Open a tty device (e.g. /dev/ttyUSB0)
+    pub fn open(path: &str) -> Result<Self> {
+        info!("Opening serial port {}...", path);
+
+        let port = serialport::new(path, KLINE_BAUD)
+            .data_bits(DataBits::Eight)
+            .stop_bits(StopBits::One)
+            .parity(Parity::None)
+            .flow_control(FlowControl::None)
+            .timeout(Duration::from_millis(1000))
+            .open()
+            .map_err(|e| anyhow!("Failed to open {}: {}", path, e))?;
+
+        let mut conn = Self::from_port(port, path);
+        conn.latency_timer_ms = set_low_latency(path);
+        Ok(conn)
+    }
+
+    /// Wrap an already opened port (used for pseudo-terminals in tests)
+    pub fn from_port(port: Box<dyn SerialPort>, path: &str) -> Self {
+        Self {
+            port: Some(port),
+            path: path.to_string(),
+            latency_timer_ms: None,
+            last_break_us: None,
+            last_5baud_us: None,
+        }
+    }
+
+    fn port(&mut self) -> Result<&mut Box<dyn SerialPort>> {
+        self.port
+            .as_mut()
+            .ok_or_else(|| anyhow!("Serial port {} is closed", self.path))
+    }
+}
+
+/// Lower the ftdi_sio latency timer to 1ms
+///
+/// Returns the value in effect afterwards, or None if the device has no
+/// latency timer (non-FTDI chip, pty). Writing needs root or a udev rule.
+fn set_low_latency(path: &str) -> Option<u64> {
+    let name = Path::new(path).file_name()?.to_str()?;
+    let sysfs = format!("/sys/bus/usb-serial/devices/{}/latency_timer", name);
+
+    if let Err(e) = std::fs::write(&sysfs, "1") {
+        debug!("Could not set {} to 1ms: {}", sysfs, e);
+    }
+
+    let value = std::fs::read_to_string(&sysfs).ok()?.trim().parse().ok()?;
+    if value > 1 {
+        warn!(
+            "{} latency timer is {}ms; responses may be delayed (write 1 to {})",
+            name, value, sysfs
+        );
+    }
+    Some(value)
+}
+
+impl Transport for SerialConnection {
+    fn configure_kline(&mut self) -> Result<()> {
+        info!("Configuring for K-Line (10400 baud, 8N1)");
+
+        let port = self.port()?;
+        port.set_baud_rate(KLINE_BAUD)?;
+        port.set_data_bits(DataBits::Eight)?;
+        port.set_stop_bits(StopBits::One)?;
+        port.set_parity(Parity::None)?;
+        port.set_flow_control(FlowControl::None)?;
+
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<usize> {
+        debug!("TX: {:02X?}", data);
+        let port = self.port()?;
+        port.write_all(data)?;
+        port.flush()?;
+        Ok(data.len())
+    }
+
+    fn read(&mut self, buffer: &mut [u8], timeout_ms: u64) -> Result<usize> {
+        let start = Instant::now();
+        let timeout = Duration::from_millis(timeout_ms);
+        let mut total_read = 0;
+        let port = self.port()?;
+
+        while total_read < buffer.len() {
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                break;
+            }
+            port.set_timeout(timeout - elapsed)?;
+
+            match port.read(&mut buffer[total_read..]) {
+                Ok(0) => break,
+                Ok(n) => total_read += n,
+                Err(e) if e.kind() == ErrorKind::TimedOut => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        if total_read > 0 {
+            debug!("RX: {:02X?}", &buffer[..total_read]);
+        }
+
+        Ok(total_read)
+    }
+
+    fn purge(&mut self) -> Result<()> {
+        self.port()?.clear(ClearBuffer::All)?;
+        Ok(())
+    }
+
+    /// Break via TIOCSBRK/TIOCCBRK
+    fn send_break(&mut self, duration_ms: u64) -> Result<()> {
+        debug!("Sending break signal for {}ms", duration_ms);
+        let start = Instant::now();
+        {
+            let port = self.port()?;
+            port.set_break()?;
+            delay_ms(duration_ms);
+            port.clear_break()?;
+        }
+        self.last_break_us = Some(start.elapsed().as_micros() as u64);
+        Ok(())
+    }
+
+    /// 5-baud init by bit-banging DTR
+    ///
+    /// On K+DCAN cables asserting DTR pulls the K-Line low (same wiring the
+    /// Tauri app relies on). Bit edges are scheduled against absolute
+    /// deadlines so sleep jitter does not accumulate over the 2 seconds.
+    fn send_5baud(&mut self, byte: u8) -> Result<()> {
+        info!("Sending 0x{:02X} at 5 baud via DTR...", byte);
+
+        let bits = five_baud_bits(byte);
+        let start = Instant::now();
+        {
+            let port = self.port()?;
+
+            for (i, high) in bits.iter().enumerate() {
+                port.write_data_terminal_ready(!high)?;
+
+                let deadline = Duration::from_millis(200 * (i as u64 + 1));
+                let elapsed = start.elapsed();
+                if deadline > elapsed {
+                    delay_ms((deadline - elapsed).as_millis() as u64);
+                }
+            }
+
+            // Release the line (idle high)
+            port.write_data_terminal_ready(false)?;
+        }
+        self.last_5baud_us = Some(start.elapsed().as_micros() as u64);
+
+        self.configure_kline()?;
+
+        info!("5-baud transmission complete");
+        Ok(())
+    }
+
+    /// The tty returns EIO once the USB device is gone
+    fn is_alive(&mut self) -> bool {
+        let path_exists = Path::new(&self.path).exists();
+        match self.port.as_ref() {
+            Some(port) => match port.bytes_to_read() {
+                Ok(_) if path_exists => true,
+                Ok(_) => false,
+                Err(e) => {
+                    warn!("Serial port {} not responding: {}", self.path, e);
+                    false
+                }
+            },
+            None => false,
+        }
+    }
+
+    fn close(&mut self) -> Result<()> {
+        if self.port.take().is_some() {
+            info!("Closing serial port {}", self.path);
+        }
+        Ok(())
+    }
+
+    fn timing_precision(&self) -> TimingPrecision {
+        let mut notes = vec![
+            "Break and DTR are set with ioctls; edges depend on USB control transfers and the kernel scheduler".to_string(),
+            "5-baud init is bit-banged on DTR, which requires a cable that keys K-Line from DTR".to_string(),
+        ];
+        match self.latency_timer_ms {
+            Some(1) => {}
+            Some(ms) => notes.push(format!(
+                "ftdi_sio latency timer is {}ms, responses arrive in {}ms chunks",
+                ms, ms
+            )),
+            None => notes.push("RX latency of this adapter is unknown".to_string()),
+        }
+
+        TimingPrecision {
+            backend: Backend::Serial,
+            // One USB control transfer plus a context switch, in practice 1-4ms
+            transition_resolution_us: 4000,
+            rx_latency_ms: self.latency_timer_ms,
+            last_break_us: self.last_break_us,
+            last_5baud_us: self.last_5baud_us,
+            notes,
+        }
+    }
+}
+
+impl Drop for SerialConnection {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kline::KLine;
+    use crate::kwp2000::KwpMessage;
+    use serialport::TTYPort;
+    use std::thread;
+
+    /// Minimal ECU on the master side of a pty: echoes every request
+    /// (K-Line is half-duplex) and answers with the given responses.
+    ///
+    /// The master is handed back through the join handle: closing it
+    /// hangs up the slave, which the backend reports as a lost device.
+    fn spawn_ecu(mut master: TTYPort, responses: Vec<Vec<u8>>) -> thread::JoinHandle<TTYPort> {
+        thread::spawn(move || {
+            master.set_timeout(Duration::from_secs(2)).unwrap();
+            for response in responses {
+                let mut header = [0u8; 3];
+                master.read_exact(&mut header).unwrap();
+                let len = (header[0] & 0x3F) as usize;
+                let mut rest = vec![0u8; len + 1];
+                master.read_exact(&mut rest).unwrap();
+
+                let mut echo = header.to_vec();
+                echo.extend_from_slice(&rest);
+                master.write_all(&echo).unwrap();
+
+                let bytes = KwpMessage::new(0x12, 0xF1, response).to_bytes();
+                master.write_all(&bytes).unwrap();
+            }
+            master
+        })
+    }
+
+    fn pty_kline() -> (KLine, TTYPort) {
+        let (master, slave) = TTYPort::pair().expect("pty pair");
+        let path = slave.name().unwrap_or_default();
+        let conn = SerialConnection::from_port(Box::new(slave), &path);
+        (KLine::new(Box::new(conn)), master)
+    }
+
+    #[test]
+    fn test_fast_init_over_pty() {
+        let (mut kline, master) = pty_kline();
+        let ecu = spawn_ecu(master, vec![vec![0xC1, 0xEF, 0x8F]]);
+
+        let result = kline.init_fast(0x12).unwrap();
+        ecu.join().unwrap();
+
+        assert!(result.success);
+        assert_eq!(result.key_bytes, Some([0xEF, 0x8F]));
+        assert!(kline.is_initialized());
+
+        let timing = kline.transport().timing_precision();
+        assert_eq!(timing.backend, Backend::Serial);
+        assert!(timing.last_break_us.unwrap() >= 30_000);
+    }
+
+    #[test]
+    fn test_request_round_trip_over_pty() {
+        let (mut kline, master) = pty_kline();
+        let ecu = spawn_ecu(
+            master,
+            vec![vec![0xC1, 0xEF, 0x8F], vec![0x58, 0x01, 0x01, 0x23, 0x08]],
+        );
+
+        assert!(kline.init_fast(0x12).unwrap().success);
+        let readout = kline.read_dtcs().unwrap();
+        ecu.join().unwrap();
+
+        let dtcs: Vec<_> = readout.records.iter().map(|r| (r.code, r.status)).collect();
+        assert_eq!(dtcs, vec![(0x0123, 0x08)]);
+    }
+
+    #[test]
+    fn test_closed_port_is_not_alive() {
+        let (master, slave) = TTYPort::pair().expect("pty pair");
+        let path = slave.name().unwrap_or_default();
+        let mut conn = SerialConnection::from_port(Box::new(slave), &path);
+
+        assert!(conn.is_alive());
+        conn.close().unwrap();
+        assert!(!conn.is_alive());
+        assert!(conn.write(&[0x00]).is_err());
+        drop(master);
+    }
+}
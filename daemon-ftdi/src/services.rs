@@ -0,0 +1,514 @@
+//! BMW ECU Service Functions
+//!
+//! DPF, DSC, KOMBI, FRM and EGS functions on top of `KLine`, mirroring
+//! the Tauri app's `bmw_commands.rs` so both frontends get the same
+//! request and response shapes.
+
+use crate::kline::{EcuAddress, KLine};
+use crate::kwp2000::KwpResponse;
+use anyhow::{anyhow, Result};
+use bmw_diag_core::routine::RoutineStatus;
+use serde::Serialize;
+use tracing::{info, warn};
+
+/// RoutineControl (0x31) sub-functions
+pub mod routine {
+    pub const START: u8 = 0x01;
+    pub const STOP: u8 = 0x02;
+}
+
+/// DPF routine IDs (DDE)
+pub mod dpf_routines {
+    pub const RESET_ASH_LOADING: u16 = 0xA091;
+    pub const RESET_LEARNED_VALUES: u16 = 0xA092;
+    pub const NEW_DPF_INSTALLED: u16 = 0xA093;
+    pub const START_FORCED_REGEN: u16 = 0xA094;
+    pub const STOP_FORCED_REGEN: u16 = 0xA095;
+
+    /// Alternative routine IDs (some DDE versions use these)
+    pub mod alt {
+        pub const RESET_ASH: u16 = 0x0061;
+        pub const RESET_ADAPTATION: u16 = 0x0062;
+        pub const NEW_DPF: u16 = 0x0063;
+        pub const FORCED_REGEN: u16 = 0x0064;
+    }
+}
+
+/// DPF Data Identifiers (DDE)
+pub mod dpf_dids {
+    pub const SOOT_LOADING: u16 = 0xAB10;
+    pub const ASH_LOADING: u16 = 0xAB11;
+    pub const DIFFERENTIAL_PRESSURE: u16 = 0xAB12;
+    pub const TEMP_BEFORE_DPF: u16 = 0xAB13;
+    pub const TEMP_AFTER_DPF: u16 = 0xAB14;
+    pub const DISTANCE_SINCE_REGEN: u16 = 0xAB15;
+    pub const REGEN_COUNT: u16 = 0xAB16;
+    pub const REGEN_STATUS: u16 = 0xAB17;
+}
+
+/// Routine result (same shape as the app's `DpfRoutineResult`)
+#[derive(Debug, Clone, Serialize)]
+pub struct DpfRoutineResult {
+    pub success: bool,
+    pub routine_id: u16,
+    pub status: String,
+    pub data: Vec<u8>,
+}
+
+impl DpfRoutineResult {
+    fn failed(routine_id: u16, status: String) -> Self {
+        Self {
+            success: false,
+            routine_id,
+            status,
+            data: vec![],
+        }
+    }
+}
+
+/// DPF status information
+#[derive(Debug, Clone, Serialize)]
+pub struct DpfStatus {
+    pub soot_loading_percent: Option<f32>,
+    pub ash_loading_grams: Option<f32>,
+    pub differential_pressure_mbar: Option<f32>,
+    pub temp_before_dpf: Option<f32>,
+    pub temp_after_dpf: Option<f32>,
+    pub distance_since_regen_km: Option<f32>,
+    pub regen_count: Option<u32>,
+    pub regen_active: bool,
+}
+
+/// Wheel speed sensor data
+#[derive(Debug, Clone, Serialize)]
+pub struct WheelSpeedData {
+    pub front_left: f32,
+    pub front_right: f32,
+    pub rear_left: f32,
+    pub rear_right: f32,
+    pub timestamp: u64,
+}
+
+/// Service interval info
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceInfo {
+    pub oil_service_km: Option<i32>,
+    pub oil_service_days: Option<i32>,
+    pub inspection_km: Option<i32>,
+    pub inspection_days: Option<i32>,
+    pub brake_fluid_months: Option<i32>,
+}
+
+/// Lamp status
+#[derive(Debug, Clone, Serialize)]
+pub struct LampStatus {
+    pub front_left_low: bool,
+    pub front_right_low: bool,
+    pub front_left_high: bool,
+    pub front_right_high: bool,
+    pub rear_left: bool,
+    pub rear_right: bool,
+    pub brake_left: bool,
+    pub brake_right: bool,
+    pub brake_center: bool,
+    pub turn_front_left: bool,
+    pub turn_front_right: bool,
+    pub turn_rear_left: bool,
+    pub turn_rear_right: bool,
+    pub fog_front_left: bool,
+    pub fog_front_right: bool,
+    pub fog_rear: bool,
+    pub reverse_left: bool,
+    pub reverse_right: bool,
+}
+
+/// Make sure the K-Line is initialized to `address`
+///
+/// Re-initializes (fast init, then 5-baud) if the line is talking to a
+/// different ECU. Returns `Some(fast)` when an init was performed so the
+/// caller can remember it for reconnects.
+pub fn ensure_ecu(kline: &mut KLine, address: u8) -> Result<Option<bool>> {
+    if kline.is_initialized() && kline.ecu_address() == address {
+        return Ok(None);
+    }
+
+    info!("Switching K-Line to ECU 0x{:02X}", address);
+    if kline.init_fast(address)?.success {
+        return Ok(Some(true));
+    }
+
+    warn!("Fast init to 0x{:02X} failed, trying 5-baud init", address);
+    if kline.init_5baud(address)?.success {
+        return Ok(Some(false));
+    }
+
+    Err(anyhow!("ECU 0x{:02X} not responding", address))
+}
+
+/// Describe a negative response as the app does
+fn nrc_text(response: &KwpResponse) -> String {
+    let nrc = response.data.get(1).copied().unwrap_or(0);
+    format!(
+        "{} (0x{:02X})",
+        response.error_description().unwrap_or("Unknown error"),
+        nrc
+    )
+}
+
+/// Read a DID with ReadDataByIdentifier (0x22), None on any failure
+fn read_did(kline: &mut KLine, did: u16) -> Option<Vec<u8>> {
+    match kline.send_request(0x22, &[(did >> 8) as u8, (did & 0xFF) as u8]) {
+        Ok(response) if response.service == 0x62 && response.data.len() >= 2 => {
+            Some(response.data[2..].to_vec())
+        }
+        Ok(response) => {
+            warn!("DID 0x{:04X}: unexpected response 0x{:02X}", did, response.service);
+            None
+        }
+        Err(e) => {
+            warn!("DID 0x{:04X}: {}", did, e);
+            None
+        }
+    }
+}
+
+fn be_u16(data: &[u8]) -> Option<u16> {
+    (data.len() >= 2).then(|| ((data[0] as u16) << 8) | data[1] as u16)
+}
+
+/// Execute a RoutineControl (0x31) without parameters for the DPF/body routines
+fn execute_routine(kline: &mut KLine, routine_id: u16, sub_function: u8) -> DpfRoutineResult {
+    routine_control(kline, routine_id, sub_function, &[])
+}
+
+/// Generic RoutineControl (0x31) with optional parameters
+pub fn routine_control(
+    kline: &mut KLine,
+    routine_id: u16,
+    sub_function: u8,
+    params: &[u8],
+) -> DpfRoutineResult {
+    info!(
+        "Executing routine 0x{:04X} sub-function 0x{:02X} on ECU 0x{:02X}",
+        routine_id,
+        sub_function,
+        kline.ecu_address()
+    );
+
+    let mut request = vec![sub_function, (routine_id >> 8) as u8, (routine_id & 0xFF) as u8];
+    request.extend_from_slice(params);
+
+    match kline.send_request(0x31, &request) {
+        Ok(response) => routine_result(routine_id, &response),
+        Err(e) => from_status(routine_id, RoutineStatus::failed(&e.to_string())),
+    }
+}
+
+/// Interpret the ECU's answer to a RoutineControl request
+fn routine_result(routine_id: u16, response: &KwpResponse) -> DpfRoutineResult {
+    let mut bytes = vec![response.service];
+    bytes.extend_from_slice(&response.data);
+    from_status(routine_id, RoutineStatus::from_response(&bytes))
+}
+
+fn from_status(routine_id: u16, result: RoutineStatus) -> DpfRoutineResult {
+    DpfRoutineResult {
+        success: result.success,
+        routine_id,
+        status: result.status,
+        data: result.data,
+    }
+}
+
+/// Start a routine, retrying with the alternative ID if the first is rejected
+fn start_with_fallback(kline: &mut KLine, primary: u16, alternative: u16) -> DpfRoutineResult {
+    let result = execute_routine(kline, primary, routine::START);
+    if result.success {
+        return result;
+    }
+
+    info!("Primary routine failed, trying alternative ID");
+    execute_routine(kline, alternative, routine::START)
+}
+
+/// Start the first routine in `ids` the ECU accepts
+fn start_first_supported(kline: &mut KLine, ids: &[u16], not_supported: &str) -> DpfRoutineResult {
+    for &routine_id in ids {
+        let result = execute_routine(kline, routine_id, routine::START);
+        if result.success {
+            return result;
+        }
+    }
+
+    DpfRoutineResult::failed(0, not_supported.to_string())
+}
+
+// ============================================================================
+// DPF (DDE) - ECU Address 0x12
+// ============================================================================
+
+/// Default DPF target (DDE shares the DME address)
+pub const DPF_DEFAULT_ADDRESS: u8 = EcuAddress::DME as u8;
+
+/// Reset DPF soot/ash loading counter
+pub fn dpf_reset_ash(kline: &mut KLine) -> DpfRoutineResult {
+    start_with_fallback(kline, dpf_routines::RESET_ASH_LOADING, dpf_routines::alt::RESET_ASH)
+}
+
+/// Reset DPF learned/adaptation values
+pub fn dpf_reset_learned(kline: &mut KLine) -> DpfRoutineResult {
+    start_with_fallback(
+        kline,
+        dpf_routines::RESET_LEARNED_VALUES,
+        dpf_routines::alt::RESET_ADAPTATION,
+    )
+}
+
+/// Register new DPF installed
+pub fn dpf_new_installed(kline: &mut KLine) -> DpfRoutineResult {
+    start_with_fallback(kline, dpf_routines::NEW_DPF_INSTALLED, dpf_routines::alt::NEW_DPF)
+}
+
+/// Start forced DPF regeneration
+/// WARNING: Vehicle must be stationary with engine running!
+pub fn dpf_start_regen(kline: &mut KLine) -> DpfRoutineResult {
+    warn!("Starting forced DPF regeneration - vehicle must be stationary, engine running");
+    start_with_fallback(
+        kline,
+        dpf_routines::START_FORCED_REGEN,
+        dpf_routines::alt::FORCED_REGEN,
+    )
+}
+
+/// Stop forced DPF regeneration
+pub fn dpf_stop_regen(kline: &mut KLine) -> DpfRoutineResult {
+    execute_routine(kline, dpf_routines::STOP_FORCED_REGEN, routine::STOP)
+}
+
+/// Read DPF status information
+pub fn dpf_read_status(kline: &mut KLine) -> DpfStatus {
+    let temp = |data: Vec<u8>| be_u16(&data).map(|raw| raw as i16 as f32 * 0.1 - 40.0);
+
+    DpfStatus {
+        soot_loading_percent: read_did(kline, dpf_dids::SOOT_LOADING)
+            .and_then(|d| d.first().map(|&b| b as f32 * 100.0 / 255.0)),
+        ash_loading_grams: read_did(kline, dpf_dids::ASH_LOADING)
+            .and_then(|d| be_u16(&d))
+            .map(|raw| raw as f32),
+        differential_pressure_mbar: read_did(kline, dpf_dids::DIFFERENTIAL_PRESSURE)
+            .and_then(|d| be_u16(&d))
+            .map(|raw| raw as f32 * 0.1),
+        temp_before_dpf: read_did(kline, dpf_dids::TEMP_BEFORE_DPF).and_then(temp),
+        temp_after_dpf: read_did(kline, dpf_dids::TEMP_AFTER_DPF).and_then(temp),
+        distance_since_regen_km: read_did(kline, dpf_dids::DISTANCE_SINCE_REGEN)
+            .and_then(|d| be_u16(&d))
+            .map(|raw| raw as f32),
+        regen_count: read_did(kline, dpf_dids::REGEN_COUNT)
+            .and_then(|d| be_u16(&d))
+            .map(|raw| raw as u32),
+        regen_active: read_did(kline, dpf_dids::REGEN_STATUS)
+            .and_then(|d| d.first().copied())
+            .map(|b| b != 0)
+            .unwrap_or(false),
+    }
+}
+
+// ============================================================================
+// DSC (Dynamic Stability Control) - ECU Address 0x44
+// ============================================================================
+
+/// Read wheel speed sensors (DIDs 0x4001-0x4004, 0.01 km/h)
+pub fn dsc_read_wheel_speeds(kline: &mut KLine) -> WheelSpeedData {
+    let mut speeds = [0.0f32; 4];
+
+    for (i, did) in (0x4001u16..=0x4004).enumerate() {
+        if let Some(raw) = read_did(kline, did).and_then(|d| be_u16(&d)) {
+            speeds[i] = raw as f32 * 0.01;
+        }
+    }
+
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+
+    WheelSpeedData {
+        front_left: speeds[0],
+        front_right: speeds[1],
+        rear_left: speeds[2],
+        rear_right: speeds[3],
+        timestamp,
+    }
+}
+
+/// Start ABS brake bleed routine for "FL", "FR", "RL", "RR" or "ALL"
+/// Requires extended session and security access
+pub fn dsc_bleed_brakes(kline: &mut KLine, corner: &str) -> Result<DpfRoutineResult> {
+    let routine_id: u16 = match corner {
+        "FL" => 0xFF01,
+        "FR" => 0xFF02,
+        "RL" => 0xFF03,
+        "RR" => 0xFF04,
+        "ALL" => 0xFF00,
+        _ => return Err(anyhow!("Invalid corner: {}", corner)),
+    };
+
+    warn!("Starting ABS bleed routine for {} on DSC", corner);
+    Ok(execute_routine(kline, routine_id, routine::START))
+}
+
+// ============================================================================
+// KOMBI (Instrument Cluster) - ECU Address 0x60
+// ============================================================================
+
+/// Read service intervals (DIDs 0x6001-0x6004)
+pub fn kombi_read_service(kline: &mut KLine) -> ServiceInfo {
+    let km = |data: Vec<u8>| be_u16(&data).map(|raw| raw as i32 * 100); // 100km units
+    let days = |data: Vec<u8>| be_u16(&data).map(|raw| raw as i16 as i32);
+
+    ServiceInfo {
+        oil_service_km: read_did(kline, 0x6001).and_then(km),
+        oil_service_days: read_did(kline, 0x6002).and_then(days),
+        inspection_km: read_did(kline, 0x6003).and_then(km),
+        inspection_days: read_did(kline, 0x6004).and_then(days),
+        brake_fluid_months: None,
+    }
+}
+
+/// Reset service interval: "oil", "inspection", "brake_fluid" or "all"
+pub fn kombi_reset_service(kline: &mut KLine, service_type: &str) -> Result<DpfRoutineResult> {
+    let routine_id: u16 = match service_type {
+        "oil" => 0xAB01,
+        "inspection" => 0xAB02,
+        "brake_fluid" => 0xAB03,
+        "all" => 0xAB00,
+        _ => return Err(anyhow!("Invalid service type: {}", service_type)),
+    };
+
+    info!("Resetting {} service on KOMBI", service_type);
+    Ok(execute_routine(kline, routine_id, routine::START))
+}
+
+// ============================================================================
+// FRM (Footwell Module - Lights) - ECU Address 0x68
+// ============================================================================
+
+/// Read lamp status bitfield (DID 0x6800)
+pub fn frm_read_lamp_status(kline: &mut KLine) -> Result<LampStatus> {
+    let data = read_did(kline, 0x6800).ok_or_else(|| anyhow!("Invalid response from FRM"))?;
+
+    // Byte 0: Front lights, Byte 1: Rear lights, Byte 2: Turn signals, Byte 3: Misc
+    let front = data.first().copied().unwrap_or(0);
+    let rear = data.get(1).copied().unwrap_or(0);
+    let turn = data.get(2).copied().unwrap_or(0);
+    let misc = data.get(3).copied().unwrap_or(0);
+
+    Ok(LampStatus {
+        front_left_low: (front & 0x01) != 0,
+        front_right_low: (front & 0x02) != 0,
+        front_left_high: (front & 0x04) != 0,
+        front_right_high: (front & 0x08) != 0,
+        fog_front_left: (front & 0x10) != 0,
+        fog_front_right: (front & 0x20) != 0,
+        rear_left: (rear & 0x01) != 0,
+        rear_right: (rear & 0x02) != 0,
+        brake_left: (rear & 0x04) != 0,
+        brake_right: (rear & 0x08) != 0,
+        brake_center: (rear & 0x10) != 0,
+        fog_rear: (rear & 0x20) != 0,
+        turn_front_left: (turn & 0x01) != 0,
+        turn_front_right: (turn & 0x02) != 0,
+        turn_rear_left: (turn & 0x04) != 0,
+        turn_rear_right: (turn & 0x08) != 0,
+        reverse_left: (misc & 0x01) != 0,
+        reverse_right: (misc & 0x02) != 0,
+    })
+}
+
+/// Run lamp test (flash all lights)
+pub fn frm_lamp_test(kline: &mut KLine) -> DpfRoutineResult {
+    info!("Starting lamp test on FRM");
+    execute_routine(kline, 0xF001, routine::START)
+}
+
+/// Switch a lamp on, or return control to the FRM (IO control 0x2F)
+pub fn frm_control_lamp(kline: &mut KLine, lamp_id: u8, on: bool) -> Result<String> {
+    let control_param = if on { 0x03 } else { 0x00 }; // 0x03 = ON, 0x00 = Return control
+    let response = kline.send_request(0x2F, &[0x68, lamp_id, control_param])?;
+
+    if response.service == 0x6F {
+        Ok(format!("Lamp {} {}", lamp_id, if on { "ON" } else { "OFF" }))
+    } else if response.is_negative() {
+        Err(anyhow!("Control failed: {}", nrc_text(&response)))
+    } else {
+        Err(anyhow!("Unexpected response: 0x{:02X}", response.service))
+    }
+}
+
+// ============================================================================
+// EGS (Electronic Gearbox Control) - ECU Address 0x18
+// ============================================================================
+
+/// Reset transmission adaptations
+/// Requires extended session and possibly security access
+pub fn egs_reset_adaptations(kline: &mut KLine) -> DpfRoutineResult {
+    info!("Resetting EGS adaptations");
+    start_first_supported(
+        kline,
+        &[0xFF01, 0xAB01, 0x0001],
+        "Reset adaptation routine not supported",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kwp2000::KwpMessage;
+
+    /// Frame `payload` as the DDE would answer the tester and parse it back
+    fn response(payload: &[u8]) -> KwpResponse {
+        let bytes = KwpMessage::new(0x12, 0xF1, payload.to_vec()).to_bytes();
+        KwpResponse::parse(&bytes).expect("valid frame")
+    }
+
+    #[test]
+    fn test_routine_ok() {
+        let result = routine_result(0xA094, &response(&[0x71, 0xA0, 0x94]));
+        assert!(result.success);
+        assert_eq!(result.routine_id, 0xA094);
+        assert_eq!(result.status, "OK");
+        assert!(result.data.is_empty());
+    }
+
+    #[test]
+    fn test_routine_results_data() {
+        let result = routine_result(0xA094, &response(&[0x71, 0xA0, 0x94, 0x02, 0x5A]));
+        assert!(result.success);
+        assert_eq!(result.status, "OK");
+        assert_eq!(result.data, vec![0x02, 0x5A]);
+    }
+
+    #[test]
+    fn test_routine_negative_response() {
+        // Conditions not correct, e.g. engine not running for a regen
+        let result = routine_result(0xA094, &response(&[0x7F, 0x31, 0x22]));
+        assert!(!result.success);
+        assert_eq!(result.status, "Conditions not correct (0x22)");
+        assert!(result.data.is_empty());
+    }
+
+    #[test]
+    fn test_routine_unexpected_response() {
+        let result = routine_result(0xF001, &response(&[0x62, 0xF0, 0x01]));
+        assert!(!result.success);
+        assert_eq!(result.status, "Unexpected: [62, F0, 01]");
+    }
+
+    #[test]
+    fn test_routine_request_failed() {
+        let result = from_status(0xF001, RoutineStatus::failed("No response"));
+        assert!(!result.success);
+        assert_eq!(result.routine_id, 0xF001);
+        assert_eq!(result.status, "Failed: No response");
+    }
+}
@@ -0,0 +1,218 @@
+//! Device Supervisor
+//!
+//! Watches the open device (D2XX or tty) for USB hot-unplug, notifies
+//! connected WebSocket clients, and re-opens the same serial number when the cable
+//! comes back (re-initializing the last ECU). Also owns the shutdown path
+//! so the ECU session is closed with StopCommunication on SIGINT/SIGTERM.
+
+use crate::kline::KLine;
+use crate::transport::{self, Backend};
+use crate::websocket::{self, SharedState, WsEvent};
+
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+/// How often the device is probed
+const POLL_INTERVAL: Duration = Duration::from_millis(1000);
+/// How long shutdown waits for clients to receive the shutdown event
+const SHUTDOWN_NOTIFY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Run the supervisor loop forever
+pub async fn run(state: SharedState, events: broadcast::Sender<WsEvent>) {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    loop {
+        interval.tick().await;
+
+        let (backend, serial, last_init) = {
+            let mut state = state.lock().await;
+
+            // Connected: make sure the device is still there
+            if let Some(ref mut kline) = state.kline {
+                if kline.transport().is_alive() {
+                    continue;
+                }
+
+                warn!("Device lost (cable unplugged?)");
+                state.kline = None;
+                state.device_lost = true;
+
+                let _ = events.send(WsEvent::new(
+                    "device_lost",
+                    serde_json::json!({
+                        "device": state.connected_device,
+                        "serial": state.device_serial,
+                        "will_reconnect": state.device_serial.is_some()
+                    }),
+                ));
+                continue;
+            }
+
+            // Lost: wait for the same serial number to reappear
+            if !state.device_lost {
+                continue;
+            }
+            match (state.backend, state.device_serial.clone()) {
+                (Some(backend), Some(serial)) => (backend, serial, state.last_init),
+                _ => continue,
+            }
+        };
+
+        // Re-opening and a 5-baud init block for seconds; run them without
+        // the state lock so clients are not stalled meanwhile
+        let reopen_serial = serial.clone();
+        let reconnected =
+            tokio::task::spawn_blocking(move || reconnect(backend, &reopen_serial, last_init))
+                .await;
+        let (mut kline, reinit) = match reconnected {
+            Ok(Some(reconnected)) => reconnected,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("Reconnect to {} failed: {}", serial, e);
+                continue;
+            }
+        };
+
+        let mut state = state.lock().await;
+
+        // A client may have connected or disconnected in the meantime
+        let still_lost = state.device_lost
+            && state.backend == Some(backend)
+            && state.device_serial.as_deref() == Some(serial.as_str());
+        if !still_lost {
+            info!("Device state changed while reconnecting, closing {}", serial);
+            if let Err(e) = kline.transport().close() {
+                warn!("Failed to close device: {}", e);
+            }
+            continue;
+        }
+
+        state.kline = Some(kline);
+        state.device_lost = false;
+
+        let _ = events.send(WsEvent::new(
+            "device_reconnected",
+            serde_json::json!({
+                "device": state.connected_device,
+                "serial": serial,
+                "ecu": reinit
+            }),
+        ));
+    }
+}
+
+/// Re-open the device and re-initialize the ECU of `last_init` (blocking)
+///
+/// Returns the line and the re-init outcome for the reconnect event, or
+/// None while the device is not back yet.
+fn reconnect(
+    backend: Backend,
+    serial: &str,
+    last_init: Option<(u8, bool)>,
+) -> Option<(KLine, Option<serde_json::Value>)> {
+    let transport = match transport::reopen(backend, serial) {
+        Ok(Some(transport)) => transport,
+        Ok(None) => return None,
+        Err(e) => {
+            // Device may still be enumerating; try again next tick
+            warn!("Reconnect to {} failed: {}", serial, e);
+            return None;
+        }
+    };
+    info!("Device {} is back, reconnected", serial);
+
+    let mut kline = KLine::new(transport);
+
+    // Re-initialize the ECU the client was talking to
+    let reinit = last_init.map(|(address, fast)| {
+        let result = if fast {
+            kline.init_fast(address)
+        } else {
+            kline.init_5baud(address)
+        };
+        let initialized = matches!(result, Ok(ref r) if r.success);
+        if !initialized {
+            warn!("Re-init of ECU 0x{:02X} after reconnect failed", address);
+        }
+        serde_json::json!({
+            "address": format!("0x{:02X}", address),
+            "fast": fast,
+            "initialized": initialized
+        })
+    });
+
+    Some((kline, reinit))
+}
+
+/// Wait for SIGINT (Ctrl-C) or SIGTERM
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sig) => {
+                sig.recv().await;
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = ctrl_c => info!("Received SIGINT"),
+        _ = terminate => info!("Received SIGTERM"),
+    }
+}
+
+/// Close the ECU session and the device
+///
+/// Sends StopCommunication (0x82) if an ECU is initialized so it leaves
+/// the diagnostic session instead of waiting for the P3max timeout.
+pub async fn shutdown(state: SharedState, events: broadcast::Sender<WsEvent>) {
+    // Each client forwards the event, closes its socket and drops out of the
+    // connection count; wait for that before the runtime goes away
+    let _ = events.send(WsEvent::new("shutdown", serde_json::json!({})));
+    let deadline = tokio::time::Instant::now() + SHUTDOWN_NOTIFY_TIMEOUT;
+    while websocket::active_connections() > 0 {
+        if tokio::time::Instant::now() >= deadline {
+            warn!(
+                "{} client(s) did not take the shutdown event in time",
+                websocket::active_connections()
+            );
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let mut state = state.lock().await;
+
+    if let Some(mut kline) = state.kline.take() {
+        if kline.is_initialized() {
+            let address = kline.ecu_address();
+            match kline.stop_communication() {
+                Ok(true) => info!("StopCommunication acknowledged by ECU 0x{:02X}", address),
+                Ok(false) => warn!("ECU 0x{:02X} rejected StopCommunication", address),
+                Err(e) => warn!("StopCommunication to ECU 0x{:02X} failed: {}", address, e),
+            }
+        }
+
+        if let Err(e) = kline.transport().close() {
+            warn!("Failed to close device: {}", e);
+        }
+    }
+
+    state.connected_device = None;
+    state.backend = None;
+    state.device_serial = None;
+    state.last_init = None;
+    info!("Daemon stopped");
+}
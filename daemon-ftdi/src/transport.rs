@@ -0,0 +1,178 @@
+//! K-Line Transport Abstraction
+//!
+//! The K-Line protocol code only needs a handful of line operations:
+//! byte I/O, a break for fast init and a way to clock out the 5-baud
+//! address. This trait lets it run over the FTDI D2XX driver or over a
+//! plain `/dev/ttyUSB*` serial port when D2XX cannot claim the device.
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// Which driver a transport is using
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// FTDI D2XX direct driver (libftd2xx)
+    D2xx,
+    /// Kernel tty driver (ftdi_sio, ch341, pl2303...) via termios
+    Serial,
+}
+
+/// What the backend can actually guarantee about line timing
+///
+/// Reported to clients so they know how far to trust init timing.
+#[derive(Debug, Clone, Serialize)]
+pub struct TimingPrecision {
+    pub backend: Backend,
+    /// Granularity of line transitions we drive (break, 5-baud bits)
+    pub transition_resolution_us: u64,
+    /// Worst-case delay before received bytes reach us
+    pub rx_latency_ms: Option<u64>,
+    /// Measured length of the last fast-init break, if one was sent
+    pub last_break_us: Option<u64>,
+    /// Measured length of the last 5-baud byte (ideal: 2,000,000us)
+    pub last_5baud_us: Option<u64>,
+    /// Human-readable caveats
+    pub notes: Vec<String>,
+}
+
+/// Byte-level K-Line transport
+pub trait Transport: Send {
+    /// Configure for K-Line communication (10400 baud, 8N1)
+    fn configure_kline(&mut self) -> Result<()>;
+
+    /// Write bytes
+    fn write(&mut self, data: &[u8]) -> Result<usize>;
+
+    /// Read bytes with timeout, returns the number of bytes read
+    fn read(&mut self, buffer: &mut [u8], timeout_ms: u64) -> Result<usize>;
+
+    /// Purge RX and TX buffers
+    fn purge(&mut self) -> Result<()>;
+
+    /// Hold the line low for `duration_ms` (fast init TiniL)
+    fn send_break(&mut self, duration_ms: u64) -> Result<()>;
+
+    /// Send byte at 5 baud (ISO 9141-2 slow init), then restore 10400 baud
+    fn send_5baud(&mut self, byte: u8) -> Result<()>;
+
+    /// Probe the device, false once it has been unplugged
+    fn is_alive(&mut self) -> bool;
+
+    /// Close the connection
+    fn close(&mut self) -> Result<()>;
+
+    /// Timing guarantees of this transport
+    fn timing_precision(&self) -> TimingPrecision;
+
+    /// Read exact number of bytes with timeout
+    fn read_exact(&mut self, length: usize, timeout_ms: u64) -> Result<Vec<u8>> {
+        let mut buffer = vec![0u8; length];
+        let read = self.read(&mut buffer, timeout_ms)?;
+
+        if read < length {
+            return Err(anyhow!(
+                "Timeout: expected {} bytes, got {}",
+                length,
+                read
+            ));
+        }
+
+        Ok(buffer)
+    }
+}
+
+/// Bits clocked out for a 5-baud init byte, in line order
+///
+/// ISO 9141-2 format: START + 7 data bits (LSB first) + ODD PARITY + STOP.
+/// `true` means the line is high (idle/recessive).
+pub fn five_baud_bits(byte: u8) -> [bool; 10] {
+    let data_bits = byte & 0x7F;
+    let parity_high = data_bits.count_ones() & 1 == 0; // Odd parity
+
+    let mut bits = [false; 10];
+    // bits[0] is the start bit (low)
+    for (i, bit) in bits.iter_mut().enumerate().skip(1).take(7) {
+        *bit = (data_bits >> (i - 1)) & 0x01 != 0;
+    }
+    bits[8] = parity_high;
+    bits[9] = true; // Stop bit
+    bits
+}
+
+/// High-precision delay for milliseconds
+/// Uses thread::sleep for bulk of delay, spin-wait only for final precision
+pub fn delay_ms(ms: u64) {
+    let start = Instant::now();
+    let target = Duration::from_millis(ms);
+
+    // For delays > 2ms, sleep for most of it
+    if ms > 2 {
+        let sleep_time = Duration::from_millis(ms.saturating_sub(1));
+        std::thread::sleep(sleep_time);
+    }
+
+    // Spin-wait for final millisecond precision
+    while start.elapsed() < target {
+        std::hint::spin_loop();
+    }
+}
+
+/// Re-open a device by USB serial number on the given backend
+///
+/// Returns Ok(None) while the device is not (yet) present.
+pub fn reopen(backend: Backend, serial: &str) -> Result<Option<Box<dyn Transport>>> {
+    match backend {
+        #[cfg(feature = "d2xx")]
+        Backend::D2xx => {
+            let present = crate::ftdi::list_devices()?
+                .iter()
+                .any(|d| d.serial_number == serial);
+            if !present {
+                return Ok(None);
+            }
+            let ftdi = crate::ftdi::FtdiConnection::open_by_serial(serial)?;
+            Ok(Some(Box::new(ftdi)))
+        }
+        #[cfg(not(feature = "d2xx"))]
+        Backend::D2xx => Err(anyhow!("Built without D2XX support")),
+        Backend::Serial => {
+            let port = crate::serial::list_ports()?
+                .into_iter()
+                .find(|p| p.serial_number.as_deref() == Some(serial));
+            match port {
+                Some(port) => {
+                    let conn = crate::serial::SerialConnection::open(&port.path)?;
+                    Ok(Some(Box::new(conn)))
+                }
+                None => Ok(None),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_five_baud_bits_for_0x33() {
+        // 0x33 = 011 0011 -> four ones, so odd parity bit is 1
+        let bits = five_baud_bits(0x33);
+        assert_eq!(
+            bits,
+            [false, true, true, false, false, true, true, false, true, true]
+        );
+    }
+
+    #[test]
+    fn test_five_baud_bits_parity() {
+        // 0x01 has one set bit, parity bit must be 0
+        let bits = five_baud_bits(0x01);
+        assert!(!bits[0]);
+        assert!(bits[1]);
+        assert!(!bits[8]);
+        assert!(bits[9]);
+    }
+}
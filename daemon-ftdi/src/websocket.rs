@@ -3,10 +3,19 @@
 //! Provides a WebSocket API for the web dashboard to communicate
 //! with the FTDI daemon.
 
+#[cfg(feature = "d2xx")]
 use crate::ftdi::{self, FtdiConnection};
-use crate::kline::{self, KLine};
+use crate::alarms::{self, AlarmEvent, AlarmWatch};
+use crate::channels;
+use crate::fault_codes;
+use crate::kline::{self, EcuAddress, KLine};
+use crate::metrics::METRICS;
+use crate::serial::{self, SerialConnection};
+use crate::services;
+use crate::transport::Backend;
 
 use anyhow::Result;
+use bmw_diag_core::alarms::AlarmRule;
 use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -14,7 +23,7 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Instant;
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::Mutex;
+use tokio::sync::{broadcast, Mutex};
 use tokio_tungstenite::{accept_async, tungstenite::Message};
 use tracing::{debug, error, info, warn};
 
@@ -27,10 +36,73 @@ const MAX_COMMANDS_PER_SECOND: usize = 20;
 /// Global connection counter
 static ACTIVE_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
 
+/// Number of open WebSocket connections (exported as a metrics gauge)
+pub fn active_connections() -> usize {
+    ACTIVE_CONNECTIONS.load(Ordering::SeqCst)
+}
+
 /// Global state shared between connections
-struct AppState {
-    kline: Option<KLine>,
-    connected_device: Option<String>,
+pub struct AppState {
+    pub kline: Option<KLine>,
+    pub connected_device: Option<String>,
+    /// Driver the open device is using
+    pub backend: Option<Backend>,
+    /// Serial number of the open device, used to reconnect after unplug
+    pub device_serial: Option<String>,
+    /// Last successful ECU init (address, fast), replayed after reconnect
+    pub last_init: Option<(u8, bool)>,
+    /// ECU software variant given with init_ecu, selects fault texts
+    pub ecu_variant: Option<String>,
+    /// Device vanished and the supervisor is waiting for it to come back
+    pub device_lost: bool,
+    /// Mode 01 PIDs each ECU reported as supported, by K-Line address
+    pub supported_pids: HashMap<u8, Vec<u8>>,
+    /// Alarm rules on polled values; kept across devices
+    pub alarms: AlarmWatch,
+}
+
+impl AppState {
+    pub fn new() -> Self {
+        Self {
+            kline: None,
+            connected_device: None,
+            backend: None,
+            device_serial: None,
+            last_init: None,
+            ecu_variant: None,
+            device_lost: false,
+            supported_pids: HashMap::new(),
+            alarms: AlarmWatch::default(),
+        }
+    }
+
+    /// Forget the current device (explicit disconnect or new connect)
+    fn reset(&mut self) {
+        self.kline = None;
+        self.connected_device = None;
+        self.backend = None;
+        self.device_serial = None;
+        self.last_init = None;
+        self.ecu_variant = None;
+        self.device_lost = false;
+        self.supported_pids.clear();
+    }
+}
+
+/// State handle shared by the server, its connections and the supervisor
+pub type SharedState = Arc<Mutex<AppState>>;
+
+/// Unsolicited notification pushed to every client
+#[derive(Debug, Clone, Serialize)]
+pub struct WsEvent {
+    event: &'static str,
+    data: serde_json::Value,
+}
+
+impl WsEvent {
+    pub fn new(event: &'static str, data: serde_json::Value) -> Self {
+        Self { event, data }
+    }
 }
 
 /// WebSocket command from client
@@ -43,11 +115,20 @@ enum WsCommand {
     #[serde(rename = "connect")]
     Connect { device_index: i32 },
 
+    /// Connect through the kernel tty driver (e.g. /dev/ttyUSB0)
+    #[serde(rename = "connect_serial")]
+    ConnectSerial { path: String },
+
     #[serde(rename = "disconnect")]
     Disconnect,
 
+    /// `variant` (e.g. "MSV70") selects variant-specific fault texts
     #[serde(rename = "init_ecu")]
-    InitEcu { address: u8, fast: bool },
+    InitEcu {
+        address: u8,
+        fast: bool,
+        variant: Option<String>,
+    },
 
     #[serde(rename = "read_dtcs")]
     ReadDtcs,
@@ -55,6 +136,26 @@ enum WsCommand {
     #[serde(rename = "clear_dtcs")]
     ClearDtcs,
 
+    /// OBD-II emissions readiness report (MIL, monitors, Mode 03/07/0A)
+    #[serde(rename = "read_emissions")]
+    ReadEmissions,
+
+    /// OBD-II Mode 04: clear emission DTCs, resets readiness monitors
+    #[serde(rename = "clear_emissions_dtcs")]
+    ClearEmissionsDtcs,
+
+    /// OBD-II Mode 09: VIN, calibration IDs, CVNs, ECU name, IPT
+    #[serde(rename = "read_vehicle_info")]
+    ReadVehicleInfo,
+
+    /// Query the supported-PID bitmaps of the current ECU (cached)
+    #[serde(rename = "discover_pids")]
+    DiscoverPids { refresh: Option<bool> },
+
+    /// OBD-II Mode 02 freeze frame (default frame 0)
+    #[serde(rename = "read_freeze_frame")]
+    ReadFreezeFrame { frame: Option<u8> },
+
     #[serde(rename = "read_pid")]
     ReadPid { pid: u8 },
 
@@ -82,6 +183,68 @@ enum WsCommand {
 
     #[serde(rename = "status")]
     Status,
+
+    /// Read DPF status (DDE, default 0x12)
+    #[serde(rename = "dpf_read_status")]
+    DpfReadStatus { target_address: Option<u8> },
+
+    #[serde(rename = "dpf_reset_ash")]
+    DpfResetAsh { target_address: Option<u8> },
+
+    #[serde(rename = "dpf_reset_learned")]
+    DpfResetLearned { target_address: Option<u8> },
+
+    #[serde(rename = "dpf_new_installed")]
+    DpfNewInstalled { target_address: Option<u8> },
+
+    /// Forced regeneration - vehicle stationary, engine running
+    #[serde(rename = "dpf_start_regen")]
+    DpfStartRegen { target_address: Option<u8> },
+
+    #[serde(rename = "dpf_stop_regen")]
+    DpfStopRegen { target_address: Option<u8> },
+
+    /// Generic RoutineControl (0x31)
+    #[serde(rename = "routine_control")]
+    RoutineControl {
+        target_address: Option<u8>,
+        routine_id: u16,
+        sub_function: u8,
+        data: Option<Vec<u8>>,
+    },
+
+    #[serde(rename = "dsc_read_wheel_speeds")]
+    DscReadWheelSpeeds,
+
+    /// ABS bleeding: corner is "FL", "FR", "RL", "RR" or "ALL"
+    #[serde(rename = "dsc_bleed_brakes")]
+    DscBleedBrakes { corner: String },
+
+    #[serde(rename = "kombi_read_service")]
+    KombiReadService,
+
+    /// service_type is "oil", "inspection", "brake_fluid" or "all"
+    #[serde(rename = "kombi_reset_service")]
+    KombiResetService { service_type: String },
+
+    #[serde(rename = "frm_read_lamp_status")]
+    FrmReadLampStatus,
+
+    #[serde(rename = "frm_lamp_test")]
+    FrmLampTest,
+
+    #[serde(rename = "frm_control_lamp")]
+    FrmControlLamp { lamp_id: u8, on: bool },
+
+    #[serde(rename = "egs_reset_adaptations")]
+    EgsResetAdaptations,
+
+    /// Replace the alarm rules checked against polled values
+    #[serde(rename = "set_alarm_rules")]
+    SetAlarmRules { rules: Vec<AlarmRule> },
+
+    #[serde(rename = "get_alarm_rules")]
+    GetAlarmRules,
 }
 
 /// WebSocket response to client
@@ -126,7 +289,11 @@ impl WsResponse {
 }
 
 /// Run the WebSocket server
-pub async fn run_server(port: u16) -> Result<()> {
+pub async fn run_server(
+    port: u16,
+    state: SharedState,
+    events: broadcast::Sender<WsEvent>,
+) -> Result<()> {
     let addr = format!("127.0.0.1:{}", port);
     let listener = TcpListener::bind(&addr).await?;
 
@@ -140,24 +307,26 @@ pub async fn run_server(port: u16) -> Result<()> {
     println!("║  Commands available:                                  ║");
     println!("║    - list_devices: List FTDI devices                  ║");
     println!("║    - connect: Connect to device                       ║");
+    println!("║    - connect_serial: Connect via /dev/ttyUSB*         ║");
     println!("║    - init_ecu: Initialize K-Line to ECU               ║");
     println!("║    - read_dtcs: Read diagnostic trouble codes         ║");
-    println!("║    - clear_dtcs: Clear all DTCs                       ║");
+    println!("║    - clear_dtcs: Clear all DTCs and re-read           ║");
+    println!("║    - read_emissions: OBD readiness and emission DTCs  ║");
+    println!("║    - read_vehicle_info: OBD VIN, CALID, CVN           ║");
+    println!("║    - discover_pids: Query supported OBD PIDs          ║");
+    println!("║    - read_freeze_frame: OBD Mode 02 freeze frame      ║");
     println!("║    - read_pid: Read single PID value                  ║");
     println!("║    - read_pids: Read multiple PIDs                    ║");
+    println!("║    - dpf_*, dsc_*, kombi_*, frm_*, egs_*: services    ║");
     println!("╚═══════════════════════════════════════════════════════╝");
     println!();
 
-    let state = Arc::new(Mutex::new(AppState {
-        kline: None,
-        connected_device: None,
-    }));
-
     while let Ok((stream, addr)) = listener.accept().await {
         // Check connection limit
         let current = ACTIVE_CONNECTIONS.load(Ordering::SeqCst);
         if current >= MAX_CONNECTIONS {
             warn!("Connection rejected from {}: max connections ({}) reached", addr, MAX_CONNECTIONS);
+            METRICS.record_connection_rejected();
             drop(stream); // Close the connection
             continue;
         }
@@ -167,8 +336,9 @@ pub async fn run_server(port: u16) -> Result<()> {
         info!("New connection from: {} (active: {})", addr, current + 1);
 
         let state = Arc::clone(&state);
+        let events = events.clone();
         tokio::spawn(async move {
-            if let Err(e) = handle_connection(stream, state).await {
+            if let Err(e) = handle_connection(stream, state, events).await {
                 error!("Connection error: {}", e);
             }
             // Decrement connection counter when done
@@ -180,7 +350,12 @@ pub async fn run_server(port: u16) -> Result<()> {
     Ok(())
 }
 
-async fn handle_connection(stream: TcpStream, state: Arc<Mutex<AppState>>) -> Result<()> {
+async fn handle_connection(
+    stream: TcpStream,
+    state: SharedState,
+    events: broadcast::Sender<WsEvent>,
+) -> Result<()> {
+    let mut received = events.subscribe();
     let ws_stream = accept_async(stream).await?;
     let (mut write, mut read) = ws_stream.split();
 
@@ -202,7 +377,31 @@ async fn handle_connection(stream: TcpStream, state: Arc<Mutex<AppState>>) -> Re
     let mut command_count = 0usize;
     let mut rate_limit_start = Instant::now();
 
-    while let Some(msg) = read.next().await {
+    loop {
+        let msg = tokio::select! {
+            msg = read.next() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            event = received.recv() => {
+                match event {
+                    Ok(event) => {
+                        let json = serde_json::to_string(&event)?;
+                        write.send(Message::Text(json)).await?;
+                        if event.event == "shutdown" {
+                            write.send(Message::Close(None)).await?;
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("Client lagged behind, {} events dropped", n);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+                continue;
+            }
+        };
+
         match msg {
             Ok(Message::Text(text)) => {
                 // Rate limiting check
@@ -216,6 +415,7 @@ async fn handle_connection(stream: TcpStream, state: Arc<Mutex<AppState>>) -> Re
                 command_count += 1;
                 if command_count > MAX_COMMANDS_PER_SECOND {
                     warn!("Rate limit exceeded: {} commands/sec", command_count);
+                    METRICS.record_rate_limited();
                     let response = WsResponse::error("Rate limit exceeded. Max 20 commands/second.");
                     let json = serde_json::to_string(&response)?;
                     write.send(Message::Text(json)).await?;
@@ -224,8 +424,19 @@ async fn handle_connection(stream: TcpStream, state: Arc<Mutex<AppState>>) -> Re
 
                 debug!("Received: {}", text);
 
-                let response = match serde_json::from_str::<WsCommand>(&text) {
-                    Ok(cmd) => process_command(cmd, &state).await,
+                let response = match serde_json::from_str::<serde_json::Value>(&text) {
+                    Ok(value) => {
+                        // Only names of valid commands become metric labels
+                        let name = value["cmd"].as_str().unwrap_or_default().to_string();
+                        match serde_json::from_value::<WsCommand>(value) {
+                            Ok(cmd) => {
+                                let response = process_command(cmd, &state, &events).await;
+                                METRICS.record_command(&name, response.success, response.latency_us);
+                                response
+                            }
+                            Err(e) => WsResponse::error(&format!("Invalid command: {}", e)),
+                        }
+                    }
                     Err(e) => WsResponse::error(&format!("Invalid command: {}", e)),
                 };
 
@@ -251,30 +462,58 @@ async fn handle_connection(stream: TcpStream, state: Arc<Mutex<AppState>>) -> Re
     Ok(())
 }
 
-async fn process_command(cmd: WsCommand, state: &Arc<Mutex<AppState>>) -> WsResponse {
+async fn process_command(
+    cmd: WsCommand,
+    state: &SharedState,
+    events: &broadcast::Sender<WsEvent>,
+) -> WsResponse {
     let start = Instant::now();
 
     match cmd {
         WsCommand::ListDevices => {
-            match ftdi::list_devices() {
-                Ok(devices) => {
-                    let device_list: Vec<_> = devices
+            #[cfg(feature = "d2xx")]
+            let devices = match ftdi::list_devices() {
+                Ok(devices) => devices
+                    .iter()
+                    .map(|d| {
+                        serde_json::json!({
+                            "index": d.index,
+                            "description": d.description,
+                            "serial": d.serial_number
+                        })
+                    })
+                    .collect::<Vec<_>>(),
+                Err(e) => {
+                    warn!("D2XX device list failed: {}", e);
+                    Vec::new()
+                }
+            };
+            #[cfg(not(feature = "d2xx"))]
+            let devices: Vec<serde_json::Value> = Vec::new();
+
+            match serial::list_ports() {
+                Ok(ports) => {
+                    let port_list: Vec<_> = ports
                         .iter()
-                        .map(|d| {
+                        .map(|p| {
                             serde_json::json!({
-                                "index": d.index,
-                                "description": d.description,
-                                "serial": d.serial_number
+                                "path": p.path,
+                                "description": p.description,
+                                "serial": p.serial_number
                             })
                         })
                         .collect();
 
-                    WsResponse::success(serde_json::json!({ "devices": device_list }))
+                    WsResponse::success(serde_json::json!({
+                        "devices": devices,
+                        "serial_ports": port_list
+                    }))
                 }
                 Err(e) => WsResponse::error(&format!("Failed to list devices: {}", e)),
             }
         }
 
+        #[cfg(feature = "d2xx")]
         WsCommand::Connect { device_index } => {
             // Validate device index
             if device_index < 0 {
@@ -284,20 +523,72 @@ async fn process_command(cmd: WsCommand, state: &Arc<Mutex<AppState>>) -> WsResp
             let mut state = state.lock().await;
 
             // Disconnect existing connection
-            state.kline = None;
-            state.connected_device = None;
+            state.reset();
+
+            // Remember the serial number so the supervisor can find the
+            // device again after a hot-unplug (indexes are not stable)
+            let serial = ftdi::list_devices()
+                .ok()
+                .and_then(|devices| devices.into_iter().nth(device_index as usize))
+                .map(|d| d.serial_number)
+                .filter(|s| !s.is_empty());
 
             match FtdiConnection::open(device_index) {
                 Ok(ftdi) => {
-                    let kline = KLine::new(ftdi);
+                    let kline = KLine::new(Box::new(ftdi));
                     state.kline = Some(kline);
                     state.connected_device = Some(format!("Device {}", device_index));
+                    state.backend = Some(Backend::D2xx);
+                    state.device_serial = serial;
+
+                    let latency = start.elapsed().as_micros() as u64;
+                    WsResponse::success_with_latency(
+                        serde_json::json!({
+                            "connected": true,
+                            "device": device_index,
+                            "backend": Backend::D2xx
+                        }),
+                        latency,
+                    )
+                }
+                Err(e) => WsResponse::error(&format!(
+                    "Failed to connect: {} (if ftdi_sio owns the device, use connect_serial)",
+                    e
+                )),
+            }
+        }
+
+        #[cfg(not(feature = "d2xx"))]
+        WsCommand::Connect { .. } => {
+            WsResponse::error("Daemon built without D2XX support, use connect_serial")
+        }
+
+        WsCommand::ConnectSerial { path } => {
+            let mut state = state.lock().await;
+
+            // Disconnect existing connection
+            state.reset();
+
+            // Same as D2XX: reconnect by USB serial, tty names can change
+            let serial = serial::list_ports()
+                .ok()
+                .and_then(|ports| ports.into_iter().find(|p| p.path == path))
+                .and_then(|p| p.serial_number);
+
+            match SerialConnection::open(&path) {
+                Ok(conn) => {
+                    let kline = KLine::new(Box::new(conn));
+                    state.kline = Some(kline);
+                    state.connected_device = Some(path.clone());
+                    state.backend = Some(Backend::Serial);
+                    state.device_serial = serial;
 
                     let latency = start.elapsed().as_micros() as u64;
                     WsResponse::success_with_latency(
                         serde_json::json!({
                             "connected": true,
-                            "device": device_index
+                            "device": path,
+                            "backend": Backend::Serial
                         }),
                         latency,
                     )
@@ -308,12 +599,15 @@ async fn process_command(cmd: WsCommand, state: &Arc<Mutex<AppState>>) -> WsResp
 
         WsCommand::Disconnect => {
             let mut state = state.lock().await;
-            state.kline = None;
-            state.connected_device = None;
+            state.reset();
             WsResponse::success(serde_json::json!({ "disconnected": true }))
         }
 
-        WsCommand::InitEcu { address, fast } => {
+        WsCommand::InitEcu {
+            address,
+            fast,
+            variant,
+        } => {
             let mut state = state.lock().await;
 
             if let Some(ref mut kline) = state.kline {
@@ -323,15 +617,22 @@ async fn process_command(cmd: WsCommand, state: &Arc<Mutex<AppState>>) -> WsResp
                     kline.init_5baud(address)
                 };
 
+                let timing = kline.transport().timing_precision();
+
                 match result {
                     Ok(init_result) => {
+                        if init_result.success {
+                            state.last_init = Some((address, fast));
+                            state.ecu_variant = variant;
+                        }
                         let latency = start.elapsed().as_micros() as u64;
                         WsResponse::success_with_latency(
                             serde_json::json!({
                                 "initialized": init_result.success,
                                 "key_bytes": init_result.key_bytes.map(|kb| format!("{:02X} {:02X}", kb[0], kb[1])),
                                 "p2_max_ms": init_result.timing_p2_max,
-                                "p3_min_ms": init_result.timing_p3_min
+                                "p3_min_ms": init_result.timing_p3_min,
+                                "timing": timing
                             }),
                             latency,
                         )
@@ -339,37 +640,57 @@ async fn process_command(cmd: WsCommand, state: &Arc<Mutex<AppState>>) -> WsResp
                     Err(e) => WsResponse::error(&format!("Init failed: {}", e)),
                 }
             } else {
-                WsResponse::error("Not connected to FTDI device")
+                WsResponse::error("Not connected to a device")
             }
         }
 
         WsCommand::ReadDtcs => {
             let mut state = state.lock().await;
+            let variant = state.ecu_variant.clone();
 
             if let Some(ref mut kline) = state.kline {
+                let ecus = kline::ecu_ids(kline.ecu_address());
                 match kline.read_dtcs() {
-                    Ok(dtcs) => {
+                    Ok(readout) => {
                         let latency = start.elapsed().as_micros() as u64;
-                        let dtc_list: Vec<_> = dtcs
+                        let dtc_list: Vec<_> = readout
+                            .records
                             .iter()
-                            .map(|(code, status)| {
-                                let decoded = kline::decode_dtc(*code);
-                                let status_flags = kline::DtcStatus::from(*status);
-                                serde_json::json!({
-                                    "code": decoded,
-                                    "raw": format!("{:04X}", code),
-                                    "status": status,
+                            .map(|record| {
+                                let status_flags = kline::DtcStatus::from(record.uds_status());
+                                let fault_code = record.fault_code();
+                                let mut dtc = serde_json::json!({
+                                    "code": kline::decode_dtc(record.short_code()),
+                                    "raw": fault_code.to_string(),
+                                    "raw_bytes": record.raw_bytes(),
+                                    "status": record.status,
                                     "confirmed": status_flags.confirmed,
                                     "pending": status_flags.pending,
                                     "test_failed": status_flags.test_failed
-                                })
+                                });
+                                // BMW code, SAE equivalent and texts for this ECU
+                                if let (Some(dtc), serde_json::Value::Object(fault)) = (
+                                    dtc.as_object_mut(),
+                                    fault_codes::describe(ecus, variant.as_deref(), fault_code),
+                                ) {
+                                    dtc.extend(fault);
+                                }
+                                dtc
                             })
                             .collect();
 
+                        let fault_db = fault_codes::FAULT_DB
+                            .read()
+                            .map(|db| db.versions().to_vec())
+                            .unwrap_or_default();
+
                         WsResponse::success_with_latency(
                             serde_json::json!({
-                                "count": dtcs.len(),
-                                "dtcs": dtc_list
+                                "count": dtc_list.len(),
+                                "dtcs": dtc_list,
+                                "dialect": readout.dialect,
+                                "ecu_variant": variant,
+                                "fault_db": fault_db
                             }),
                             latency,
                         )
@@ -386,10 +707,10 @@ async fn process_command(cmd: WsCommand, state: &Arc<Mutex<AppState>>) -> WsResp
 
             if let Some(ref mut kline) = state.kline {
                 match kline.clear_dtcs() {
-                    Ok(success) => {
+                    Ok(summary) => {
                         let latency = start.elapsed().as_micros() as u64;
                         WsResponse::success_with_latency(
-                            serde_json::json!({ "cleared": success }),
+                            serde_json::json!({ "cleared": true, "summary": summary }),
                             latency,
                         )
                     }
@@ -400,6 +721,132 @@ async fn process_command(cmd: WsCommand, state: &Arc<Mutex<AppState>>) -> WsResp
             }
         }
 
+        WsCommand::ReadEmissions => {
+            let mut state = state.lock().await;
+
+            if let Some(ref mut kline) = state.kline {
+                match kline.read_emissions_report() {
+                    Ok(report) => {
+                        let latency = start.elapsed().as_micros() as u64;
+                        WsResponse::success_with_latency(serde_json::json!(report), latency)
+                    }
+                    Err(e) => WsResponse::error(&format!("Read emissions failed: {}", e)),
+                }
+            } else {
+                WsResponse::error("Not connected")
+            }
+        }
+
+        WsCommand::ClearEmissionsDtcs => {
+            let mut state = state.lock().await;
+
+            if let Some(ref mut kline) = state.kline {
+                match kline.clear_emissions_dtcs() {
+                    Ok(()) => {
+                        let latency = start.elapsed().as_micros() as u64;
+                        WsResponse::success_with_latency(
+                            serde_json::json!({ "cleared": true }),
+                            latency,
+                        )
+                    }
+                    Err(e) => WsResponse::error(&format!("Clear emission DTCs failed: {}", e)),
+                }
+            } else {
+                WsResponse::error("Not connected")
+            }
+        }
+
+        WsCommand::ReadVehicleInfo => {
+            let mut state = state.lock().await;
+
+            if let Some(ref mut kline) = state.kline {
+                match kline.read_vehicle_info() {
+                    Ok(info) => {
+                        let latency = start.elapsed().as_micros() as u64;
+                        WsResponse::success_with_latency(serde_json::json!(info), latency)
+                    }
+                    Err(e) => WsResponse::error(&format!("Read vehicle info failed: {}", e)),
+                }
+            } else {
+                WsResponse::error("Not connected")
+            }
+        }
+
+        WsCommand::DiscoverPids { refresh } => {
+            let mut state = state.lock().await;
+            let state = &mut *state;
+
+            if let Some(ref mut kline) = state.kline {
+                let address = kline.ecu_address();
+                let cached = state.supported_pids.get(&address).cloned();
+
+                let result = match cached {
+                    Some(pids) if !refresh.unwrap_or(false) => Ok(pids),
+                    _ => kline.read_supported_pids(),
+                };
+                match result {
+                    Ok(pids) => {
+                        let latency = start.elapsed().as_micros() as u64;
+                        state.supported_pids.insert(address, pids.clone());
+                        WsResponse::success_with_latency(
+                            serde_json::json!({
+                                "ecu_address": format!("0x{:02X}", address),
+                                "pids": pids.iter().map(|pid| format!("0x{:02X}", pid)).collect::<Vec<_>>()
+                            }),
+                            latency,
+                        )
+                    }
+                    Err(e) => WsResponse::error(&format!("PID discovery failed: {}", e)),
+                }
+            } else {
+                WsResponse::error("Not connected")
+            }
+        }
+
+        WsCommand::ReadFreezeFrame { frame } => {
+            let mut state = state.lock().await;
+            let state = &mut *state;
+
+            if let Some(ref mut kline) = state.kline {
+                let fallback = state
+                    .supported_pids
+                    .get(&kline.ecu_address())
+                    .cloned()
+                    .unwrap_or_else(|| FREEZE_FRAME_PIDS.to_vec());
+
+                match kline.read_freeze_frame(frame.unwrap_or(0), &fallback) {
+                    Ok(freeze_frame) => {
+                        let latency = start.elapsed().as_micros() as u64;
+                        let values: Vec<_> = freeze_frame
+                            .values
+                            .iter()
+                            .map(|value| {
+                                let (decoded, unit) = calculate_pid_value(value.pid, &value.data);
+                                serde_json::json!({
+                                    "pid": format!("0x{:02X}", value.pid),
+                                    "raw": value.data.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" "),
+                                    "value": decoded,
+                                    "unit": unit
+                                })
+                            })
+                            .collect();
+
+                        WsResponse::success_with_latency(
+                            serde_json::json!({
+                                "frame": freeze_frame.frame,
+                                "dtc": freeze_frame.dtc,
+                                "values": values
+                            }),
+                            latency,
+                        )
+                    }
+                    Err(e) => WsResponse::error(&format!("Read freeze frame failed: {}", e)),
+                }
+            } else {
+                WsResponse::error("Not connected")
+            }
+        }
+
         WsCommand::ReadPid { pid } => {
             let mut state = state.lock().await;
 
@@ -410,6 +857,13 @@ async fn process_command(cmd: WsCommand, state: &Arc<Mutex<AppState>>) -> WsResp
 
                         // Calculate value based on PID
                         let value = calculate_pid_value(pid, &data);
+                        let fired = state.alarms.update(
+                            &alarms::pid_names(pid),
+                            value.0,
+                            &value.1,
+                            alarms::now_ms(),
+                        );
+                        publish_alarms(events, fired);
 
                         WsResponse::success_with_latency(
                             serde_json::json!({
@@ -444,6 +898,7 @@ async fn process_command(cmd: WsCommand, state: &Arc<Mutex<AppState>>) -> WsResp
             if let Some(ref mut kline) = state.kline {
                 let mut results = HashMap::new();
                 let mut total_latency = 0u64;
+                let mut readings = Vec::new();
 
                 for pid in pid_list {
                     let pid_start = Instant::now();
@@ -461,6 +916,7 @@ async fn process_command(cmd: WsCommand, state: &Arc<Mutex<AppState>>) -> WsResp
                                     "latency_us": latency
                                 }),
                             );
+                            readings.push((pid, value));
                         }
                         Err(e) => {
                             results.insert(
@@ -471,6 +927,13 @@ async fn process_command(cmd: WsCommand, state: &Arc<Mutex<AppState>>) -> WsResp
                     }
                 }
 
+                let now = alarms::now_ms();
+                for (pid, (value, unit)) in readings {
+                    let names = alarms::pid_names(pid);
+                    let fired = state.alarms.update(&names, value, &unit, now);
+                    publish_alarms(events, fired);
+                }
+
                 WsResponse::success_with_latency(
                     serde_json::json!({
                         "pids": results,
@@ -503,18 +966,30 @@ async fn process_command(cmd: WsCommand, state: &Arc<Mutex<AppState>>) -> WsResp
         }
 
         WsCommand::Status => {
-            let state = state.lock().await;
+            let mut state = state.lock().await;
             let connected = state.kline.is_some();
             let initialized = state
                 .kline
                 .as_ref()
                 .map(|k| k.is_initialized())
                 .unwrap_or(false);
+            let timing = state
+                .kline
+                .as_mut()
+                .map(|k| k.transport().timing_precision());
 
             WsResponse::success(serde_json::json!({
                 "connected": connected,
                 "initialized": initialized,
-                "device": state.connected_device
+                "device": state.connected_device,
+                "backend": state.backend,
+                "timing": timing,
+                "serial": state.device_serial,
+                "device_lost": state.device_lost,
+                "channel_db": channels::CHANNEL_DB
+                    .read()
+                    .map(|db| db.versions().to_vec())
+                    .unwrap_or_default()
             }))
         }
 
@@ -526,6 +1001,13 @@ async fn process_command(cmd: WsCommand, state: &Arc<Mutex<AppState>>) -> WsResp
                     Ok(data) => {
                         let latency = start.elapsed().as_micros() as u64;
                         let value = calculate_bmw_pid_value(pid, &data);
+                        let fired = state.alarms.update(
+                            &alarms::local_id_names(pid),
+                            value.0,
+                            &value.1,
+                            alarms::now_ms(),
+                        );
+                        publish_alarms(events, fired);
 
                         WsResponse::success_with_latency(
                             serde_json::json!({
@@ -559,6 +1041,7 @@ async fn process_command(cmd: WsCommand, state: &Arc<Mutex<AppState>>) -> WsResp
             if let Some(ref mut kline) = state.kline {
                 let mut results = HashMap::new();
                 let mut total_latency = 0u64;
+                let mut readings = Vec::new();
 
                 for pid in pid_list {
                     let pid_start = Instant::now();
@@ -576,6 +1059,7 @@ async fn process_command(cmd: WsCommand, state: &Arc<Mutex<AppState>>) -> WsResp
                                     "latency_us": latency
                                 }),
                             );
+                            readings.push((pid, value));
                         }
                         Err(e) => {
                             results.insert(
@@ -586,6 +1070,13 @@ async fn process_command(cmd: WsCommand, state: &Arc<Mutex<AppState>>) -> WsResp
                     }
                 }
 
+                let now = alarms::now_ms();
+                for (pid, (value, unit)) in readings {
+                    let names = alarms::local_id_names(pid);
+                    let fired = state.alarms.update(&names, value, &unit, now);
+                    publish_alarms(events, fired);
+                }
+
                 WsResponse::success_with_latency(
                     serde_json::json!({
                         "pids": results,
@@ -622,6 +1113,7 @@ async fn process_command(cmd: WsCommand, state: &Arc<Mutex<AppState>>) -> WsResp
                 let mut results = serde_json::Map::new();
                 let mut total_latency = 0u64;
                 let mut errors = Vec::new();
+                let mut readings = Vec::new();
 
                 for pid in engine_pids {
                     let pid_start = Instant::now();
@@ -640,6 +1132,7 @@ async fn process_command(cmd: WsCommand, state: &Arc<Mutex<AppState>>) -> WsResp
                                     "unit": unit
                                 }),
                             );
+                            readings.push((pid, value, unit));
                         }
                         Err(e) => {
                             errors.push(format!("PID 0x{:02X}: {}", pid, e));
@@ -647,6 +1140,13 @@ async fn process_command(cmd: WsCommand, state: &Arc<Mutex<AppState>>) -> WsResp
                     }
                 }
 
+                let now = alarms::now_ms();
+                for (pid, value, unit) in readings {
+                    let names = alarms::pid_names(pid);
+                    let fired = state.alarms.update(&names, value, &unit, now);
+                    publish_alarms(events, fired);
+                }
+
                 WsResponse::success_with_latency(
                     serde_json::json!({
                         "engine": results,
@@ -720,127 +1220,172 @@ async fn process_command(cmd: WsCommand, state: &Arc<Mutex<AppState>>) -> WsResp
                 WsResponse::error("Not connected")
             }
         }
-    }
-}
 
-/// Calculate PID value from raw bytes
-fn calculate_pid_value(pid: u8, data: &[u8]) -> (f64, &'static str) {
-    match pid {
-        0x0C => {
-            // RPM: ((A * 256) + B) / 4
-            if data.len() >= 2 {
-                let rpm = ((data[0] as f64 * 256.0) + data[1] as f64) / 4.0;
-                (rpm, "RPM")
-            } else {
-                (0.0, "RPM")
-            }
+        WsCommand::DpfReadStatus { target_address } => {
+            let target = target_address.unwrap_or(services::DPF_DEFAULT_ADDRESS);
+            with_ecu(state, target, start, |k| Ok(services::dpf_read_status(k))).await
         }
-        0x05 => {
-            // Coolant temp: A - 40
-            if !data.is_empty() {
-                let temp = data[0] as f64 - 40.0;
-                (temp, "°C")
-            } else {
-                (0.0, "°C")
-            }
+
+        WsCommand::DpfResetAsh { target_address } => {
+            let target = target_address.unwrap_or(services::DPF_DEFAULT_ADDRESS);
+            with_ecu(state, target, start, |k| Ok(services::dpf_reset_ash(k))).await
         }
-        0x0D => {
-            // Vehicle speed: A
-            if !data.is_empty() {
-                (data[0] as f64, "km/h")
-            } else {
-                (0.0, "km/h")
-            }
+
+        WsCommand::DpfResetLearned { target_address } => {
+            let target = target_address.unwrap_or(services::DPF_DEFAULT_ADDRESS);
+            with_ecu(state, target, start, |k| Ok(services::dpf_reset_learned(k))).await
         }
-        0x11 => {
-            // Throttle position: (A * 100) / 255
-            if !data.is_empty() {
-                let throttle = (data[0] as f64 * 100.0) / 255.0;
-                (throttle, "%")
-            } else {
-                (0.0, "%")
-            }
+
+        WsCommand::DpfNewInstalled { target_address } => {
+            let target = target_address.unwrap_or(services::DPF_DEFAULT_ADDRESS);
+            with_ecu(state, target, start, |k| Ok(services::dpf_new_installed(k))).await
         }
-        0x04 => {
-            // Engine load: (A * 100) / 255
-            if !data.is_empty() {
-                let load = (data[0] as f64 * 100.0) / 255.0;
-                (load, "%")
-            } else {
-                (0.0, "%")
-            }
+
+        WsCommand::DpfStartRegen { target_address } => {
+            let target = target_address.unwrap_or(services::DPF_DEFAULT_ADDRESS);
+            with_ecu(state, target, start, |k| Ok(services::dpf_start_regen(k))).await
         }
-        0x0F => {
-            // Intake air temp: A - 40
-            if !data.is_empty() {
-                (data[0] as f64 - 40.0, "°C")
-            } else {
-                (0.0, "°C")
-            }
+
+        WsCommand::DpfStopRegen { target_address } => {
+            let target = target_address.unwrap_or(services::DPF_DEFAULT_ADDRESS);
+            with_ecu(state, target, start, |k| Ok(services::dpf_stop_regen(k))).await
         }
-        0x42 => {
-            // Battery voltage: ((A * 256) + B) / 1000
-            if data.len() >= 2 {
-                let voltage = ((data[0] as f64 * 256.0) + data[1] as f64) / 1000.0;
-                (voltage, "V")
-            } else {
-                (0.0, "V")
-            }
+
+        WsCommand::RoutineControl {
+            target_address,
+            routine_id,
+            sub_function,
+            data,
+        } => {
+            let target = target_address.unwrap_or(services::DPF_DEFAULT_ADDRESS);
+            let params = data.unwrap_or_default();
+            with_ecu(state, target, start, |k| {
+                Ok(services::routine_control(k, routine_id, sub_function, &params))
+            })
+            .await
         }
-        0x0B => {
-            // Intake manifold absolute pressure: A (kPa)
-            if !data.is_empty() {
-                (data[0] as f64, "kPa")
-            } else {
-                (0.0, "kPa")
-            }
+
+        WsCommand::DscReadWheelSpeeds => {
+            with_ecu(state, EcuAddress::DSC as u8, start, |k| {
+                Ok(services::dsc_read_wheel_speeds(k))
+            })
+            .await
         }
-        0x0E => {
-            // Timing advance: (A / 2) - 64
-            if !data.is_empty() {
-                let advance = (data[0] as f64 / 2.0) - 64.0;
-                (advance, "°")
-            } else {
-                (0.0, "°")
-            }
+
+        WsCommand::DscBleedBrakes { corner } => {
+            with_ecu(state, EcuAddress::DSC as u8, start, |k| {
+                services::dsc_bleed_brakes(k, &corner)
+            })
+            .await
         }
-        0x10 => {
-            // MAF air flow rate: ((A * 256) + B) / 100
-            if data.len() >= 2 {
-                let maf = ((data[0] as f64 * 256.0) + data[1] as f64) / 100.0;
-                (maf, "g/s")
-            } else {
-                (0.0, "g/s")
-            }
+
+        WsCommand::KombiReadService => {
+            with_ecu(state, EcuAddress::KOMBI as u8, start, |k| {
+                Ok(services::kombi_read_service(k))
+            })
+            .await
         }
-        0x5C => {
-            // Engine oil temperature: A - 40
-            if !data.is_empty() {
-                (data[0] as f64 - 40.0, "°C")
-            } else {
-                (0.0, "°C")
-            }
+
+        WsCommand::KombiResetService { service_type } => {
+            with_ecu(state, EcuAddress::KOMBI as u8, start, |k| {
+                services::kombi_reset_service(k, &service_type)
+            })
+            .await
         }
-        0x06 | 0x07 | 0x08 | 0x09 => {
-            // Fuel trims: (A - 128) * 100 / 128
-            if !data.is_empty() {
-                let trim = ((data[0] as f64 - 128.0) * 100.0) / 128.0;
-                (trim, "%")
-            } else {
-                (0.0, "%")
-            }
+
+        WsCommand::FrmReadLampStatus => {
+            with_ecu(state, EcuAddress::FRM as u8, start, services::frm_read_lamp_status).await
         }
-        _ => {
-            // Unknown PID - return raw value
-            if !data.is_empty() {
-                (data[0] as f64, "raw")
-            } else {
-                (0.0, "raw")
+
+        WsCommand::FrmLampTest => {
+            with_ecu(state, EcuAddress::FRM as u8, start, |k| Ok(services::frm_lamp_test(k))).await
+        }
+
+        WsCommand::FrmControlLamp { lamp_id, on } => {
+            with_ecu(state, EcuAddress::FRM as u8, start, |k| {
+                services::frm_control_lamp(k, lamp_id, on)
+            })
+            .await
+        }
+
+        WsCommand::EgsResetAdaptations => {
+            with_ecu(state, EcuAddress::EGS as u8, start, |k| {
+                Ok(services::egs_reset_adaptations(k))
+            })
+            .await
+        }
+
+        WsCommand::SetAlarmRules { rules } => {
+            let mut state = state.lock().await;
+            let count = rules.len();
+            match state.alarms.set_rules(rules) {
+                Ok(()) => {
+                    info!("{} alarm rule(s) set", count);
+                    WsResponse::success(serde_json::json!({ "rules": count }))
+                }
+                Err(e) => WsResponse::error(&e.to_string()),
             }
         }
+
+        WsCommand::GetAlarmRules => {
+            let state = state.lock().await;
+            WsResponse::success(serde_json::json!({ "rules": state.alarms.rules() }))
+        }
+    }
+}
+
+/// Push alarms raised or cleared to every client
+fn publish_alarms(events: &broadcast::Sender<WsEvent>, fired: Vec<AlarmEvent>) {
+    for alarm in fired {
+        info!(
+            "Alarm {} {:?} at {} {}",
+            alarm.rule, alarm.state, alarm.value, alarm.unit
+        );
+        let _ = events.send(WsEvent::new("alarm", serde_json::json!(alarm)));
+    }
+}
+
+/// Run an ECU service on `address`, initializing the K-Line to it first
+///
+/// The result is serialized as-is so responses match the Tauri commands.
+async fn with_ecu<T, F>(state: &SharedState, address: u8, start: Instant, service: F) -> WsResponse
+where
+    T: Serialize,
+    F: FnOnce(&mut KLine) -> Result<T>,
+{
+    let mut state = state.lock().await;
+
+    let Some(kline) = state.kline.as_mut() else {
+        return WsResponse::error("Not connected");
+    };
+
+    let init = match services::ensure_ecu(kline, address) {
+        Ok(init) => init,
+        Err(e) => return WsResponse::error(&format!("Init failed: {}", e)),
+    };
+    let result = service(kline);
+
+    if let Some(fast) = init {
+        state.last_init = Some((address, fast));
+    }
+
+    match result.and_then(|value| Ok(serde_json::to_value(value)?)) {
+        Ok(data) => {
+            let latency = start.elapsed().as_micros() as u64;
+            WsResponse::success_with_latency(data, latency)
+        }
+        Err(e) => WsResponse::error(&e.to_string()),
     }
 }
 
+/// Freeze frame PIDs tried when neither Mode 02 nor discovery lists them
+const FREEZE_FRAME_PIDS: [u8; 8] = [0x04, 0x05, 0x0B, 0x0C, 0x0D, 0x0F, 0x10, 0x11];
+
+/// Calculate PID value from raw bytes
+fn calculate_pid_value(pid: u8, data: &[u8]) -> (f64, String) {
+    channels::scale(channels::OBD, channels::SERVICE_MODE_01, pid, data)
+}
+
 /// Get human-readable name for OBD-II PID
 fn get_pid_name(pid: u8) -> &'static str {
     match pid {
@@ -864,188 +1409,13 @@ fn get_pid_name(pid: u8) -> &'static str {
 }
 
 /// Calculate BMW manufacturer-specific PID value
-fn calculate_bmw_pid_value(pid: u8, data: &[u8]) -> (f64, &'static str) {
-    match pid {
-        // Temperatures (typically: value - 48 or value - 40)
-        0x10 | 0x11 | 0x12 | 0x13 => {
-            if !data.is_empty() {
-                (data[0] as f64 - 48.0, "°C")
-            } else {
-                (0.0, "°C")
-            }
-        }
-        // RPM (typically: ((A * 256) + B) or A * 40)
-        0x20 => {
-            if data.len() >= 2 {
-                let rpm = (data[0] as f64 * 256.0) + data[1] as f64;
-                (rpm, "RPM")
-            } else if !data.is_empty() {
-                (data[0] as f64 * 40.0, "RPM")
-            } else {
-                (0.0, "RPM")
-            }
-        }
-        // Percentages (throttle, load, etc.)
-        0x21 | 0x30 | 0x31 => {
-            if !data.is_empty() {
-                let pct = (data[0] as f64 * 100.0) / 255.0;
-                (pct, "%")
-            } else {
-                (0.0, "%")
-            }
-        }
-        // Speed
-        0x22 => {
-            if !data.is_empty() {
-                (data[0] as f64, "km/h")
-            } else {
-                (0.0, "km/h")
-            }
-        }
-        // Angles (ignition, VANOS)
-        0x40..=0x46 | 0x80..=0x83 => {
-            if !data.is_empty() {
-                let angle = (data[0] as f64 * 0.75) - 24.0; // Typical BMW scaling
-                (angle, "°")
-            } else {
-                (0.0, "°")
-            }
-        }
-        // Voltages
-        0xA0 | 0xA1 => {
-            if data.len() >= 2 {
-                let voltage = ((data[0] as f64 * 256.0) + data[1] as f64) / 1000.0;
-                (voltage, "V")
-            } else if !data.is_empty() {
-                (data[0] as f64 / 10.0, "V")
-            } else {
-                (0.0, "V")
-            }
-        }
-        // Injection time (ms)
-        0x50 => {
-            if data.len() >= 2 {
-                let ms = ((data[0] as f64 * 256.0) + data[1] as f64) / 1000.0;
-                (ms, "ms")
-            } else {
-                (0.0, "ms")
-            }
-        }
-        // Lambda
-        0x60..=0x63 => {
-            if data.len() >= 2 {
-                let lambda = ((data[0] as f64 * 256.0) + data[1] as f64) / 32768.0;
-                (lambda, "λ")
-            } else if !data.is_empty() {
-                (data[0] as f64 / 128.0, "λ")
-            } else {
-                (0.0, "λ")
-            }
-        }
-        _ => {
-            // Unknown - return raw
-            if !data.is_empty() {
-                (data[0] as f64, "raw")
-            } else {
-                (0.0, "raw")
-            }
-        }
-    }
+fn calculate_bmw_pid_value(pid: u8, data: &[u8]) -> (f64, String) {
+    channels::scale(channels::DME, channels::SERVICE_LOCAL_ID, pid, data)
 }
 
 /// Calculate transmission-specific PID value
-fn calculate_transmission_value(pid: u8, data: &[u8]) -> (f64, &'static str) {
-    match pid {
-        // Current gear (0=N, 1-6=gears, 7=R)
-        0x01 => {
-            if !data.is_empty() {
-                let gear = data[0] as f64;
-                (gear, "gear")
-            } else {
-                (0.0, "gear")
-            }
-        }
-        // Target gear
-        0x02 => {
-            if !data.is_empty() {
-                (data[0] as f64, "gear")
-            } else {
-                (0.0, "gear")
-            }
-        }
-        // Gear selector (P=0, R=1, N=2, D=3, S=4, M=5)
-        0x03 => {
-            if !data.is_empty() {
-                (data[0] as f64, "pos")
-            } else {
-                (0.0, "pos")
-            }
-        }
-        // Shaft speeds (RPM)
-        0x10 | 0x11 | 0x12 | 0x13 => {
-            if data.len() >= 2 {
-                let rpm = (data[0] as f64 * 256.0) + data[1] as f64;
-                (rpm, "RPM")
-            } else if !data.is_empty() {
-                (data[0] as f64 * 40.0, "RPM")
-            } else {
-                (0.0, "RPM")
-            }
-        }
-        // Temperature
-        0x20 | 0x21 => {
-            if !data.is_empty() {
-                (data[0] as f64 - 40.0, "°C")
-            } else {
-                (0.0, "°C")
-            }
-        }
-        // Pressure (bar)
-        0x30 | 0x31 | 0x32 => {
-            if data.len() >= 2 {
-                let pressure = ((data[0] as f64 * 256.0) + data[1] as f64) / 100.0;
-                (pressure, "bar")
-            } else if !data.is_empty() {
-                (data[0] as f64 / 10.0, "bar")
-            } else {
-                (0.0, "bar")
-            }
-        }
-        // Torque (Nm)
-        0x40 | 0x41 => {
-            if data.len() >= 2 {
-                let torque = ((data[0] as f64 * 256.0) + data[1] as f64) - 500.0;
-                (torque, "Nm")
-            } else if !data.is_empty() {
-                (data[0] as f64 * 4.0, "Nm")
-            } else {
-                (0.0, "Nm")
-            }
-        }
-        // Lockup status (0=open, 1=slipping, 2=locked)
-        0x50 => {
-            if !data.is_empty() {
-                (data[0] as f64, "status")
-            } else {
-                (0.0, "status")
-            }
-        }
-        // Driving program (0=Normal, 1=Sport, 2=Manual)
-        0x70 => {
-            if !data.is_empty() {
-                (data[0] as f64, "mode")
-            } else {
-                (0.0, "mode")
-            }
-        }
-        _ => {
-            if !data.is_empty() {
-                (data[0] as f64, "raw")
-            } else {
-                (0.0, "raw")
-            }
-        }
-    }
+fn calculate_transmission_value(pid: u8, data: &[u8]) -> (f64, String) {
+    channels::scale(channels::EGS, channels::SERVICE_LOCAL_ID, pid, data)
 }
 
 /// Get human-readable name for transmission PID